@@ -2,6 +2,13 @@ extern crate lorikeet_genome;
 
 use lorikeet_genome::*;
 use lorikeet_genome::estimation::contig;
+use lorikeet_genome::estimation::lorikeet_config::LorikeetConfig;
+use lorikeet_genome::estimation::variant_annotation::ANNOTATION_HEADER;
+use lorikeet_genome::estimation::read_filter::*;
+use lorikeet_genome::estimation::alignment_format::*;
+use lorikeet_genome::estimation::run_config::*;
+use lorikeet_genome::estimation::result_cache::*;
+use lorikeet_genome::estimation::sample_sheet::*;
 use lorikeet_genome::external_command_checker;
 use lorikeet_genome::cli::*;
 
@@ -24,8 +31,8 @@ use std::env;
 use std::str;
 use std::process;
 use std::collections::BTreeMap;
-use std::process::Stdio;
 use std::fs::File;
+use std::io::Write;
 use std::path::Path;
 
 extern crate clap;
@@ -50,7 +57,7 @@ const DEFAULT_MAPPING_SOFTWARE_ENUM: MappingProgram = MappingProgram::MINIMAP2_S
 
 fn main(){
     let mut app = build_cli();
-    let matches = app.clone().get_matches();
+    let matches = get_matches_with_sample_sheet(&app, env::args().collect());
     set_log_level(&matches, false);
 
     match matches.subcommand_name() {
@@ -68,20 +75,23 @@ fn main(){
             if bam_files.len() != output_bam_files.len() {
                 panic!("The number of input BAM files must be the same as the number output")
             }
+            write_run_record(m, "filter");
 
             let filter_params = FilterParameters::generate_from_clap(m);
+            let read_filter_params = ReadFilterParameters::generate_from_clap(m);
+            let reference = m.value_of("reference");
+            let output_format = match m.is_present("output-cram") {
+                true => AlignmentFormat::Cram,
+                false => AlignmentFormat::Bam,
+            };
 
             let num_threads = value_t!(m.value_of("threads"), u16).unwrap();
 
             for (bam, output) in bam_files.iter().zip(output_bam_files.iter()) {
-                let reader = bam::Reader::from_path(bam).expect(
-                    &format!("Unable to find BAM file {}", bam));
+                let mut stats = FilterStats::new(bam, output);
+                let reader = open_reader(bam, reference);
                 let header = bam::header::Header::from_template(reader.header());
-                let mut writer = bam::Writer::from_path(
-                    output,
-                    &header,
-                    rust_htslib::bam::Format::BAM)
-                    .expect(&format!("Failed to write BAM file {}", output));
+                let mut writer = create_writer(output, &header, output_format, reference);
                 writer
                     .set_threads(num_threads as usize)
                     .expect("Failed to set num threads in writer");
@@ -96,11 +106,74 @@ fn main(){
                     filter_params.min_aligned_percent_pair,
                     !m.is_present("inverse"));
 
+                let mut duplicates = DuplicateTracker::default();
+                let mut pairs = PairFilter::default();
+                let mut passed_thresholds = 0;
+                let remove_duplicates = read_filter_params.remove_duplicates;
+                // Removes an alignment, or both mates of a pair, as duplicates when they
+                // passed every other criterion and the first of them is a duplicate
+                let mut decide = |alignments: &[&bam::Record], criterion: Option<FilterCriterion>| {
+                    if criterion.is_some() || !remove_duplicates {
+                        return criterion
+                    }
+                    let first = alignments[0];
+                    let cigar = first.cigar();
+                    let flagged = alignments.iter().any(|record| record.is_duplicate());
+                    if flagged || duplicates.is_duplicate(
+                        first.tid(),
+                        unclipped_five_prime(first.pos() as i64, &cigar, first.is_reverse()),
+                        first.is_reverse(), first.mtid(), first.mpos() as i64) {
+                        Some(FilterCriterion::Duplicate)
+                    } else {
+                        None
+                    }
+                };
+                // Writes the alignments that did not fail a criterion, in the order they
+                // were read so the output stays sorted
+                let mut write_alignments = |alignments: Vec<(bam::Record, Option<FilterCriterion>)>,
+                                            stats: &mut FilterStats| {
+                    for (record, criterion) in alignments.iter() {
+                        match *criterion {
+                            Some(criterion) => stats.remove(criterion),
+                            None => {
+                                debug!("Writing.. {:?}", record.qname());
+                                writer.write(record).expect("Failed to write BAM record");
+                                stats.output_alignments += 1;
+                            },
+                        }
+                    }
+                };
                 let mut record = bam::record::Record::new();
                 while filtered.read(&mut record).is_ok() {
-                    debug!("Writing.. {:?}", record.qname());
-                    writer.write(&record).expect("Failed to write BAM record");
+                    if !record.is_secondary() && !record.is_supplementary() {
+                        passed_thresholds += 1;
+                    }
+                    let edit_distance = match record.aux(b"NM") {
+                        Some(bam::record::Aux::Integer(distance)) => Some(distance),
+                        _ => None,
+                    };
+                    let criterion = failed_criterion(
+                        &read_filter_params,
+                        record.mapq(),
+                        edit_distance,
+                        &record.cigar(),
+                        record.qual());
+                    // Secondary and supplementary alignments are filtered on their own
+                    let has_mapped_mate = record.is_paired() && !record.is_mate_unmapped()
+                        && !record.is_secondary() && !record.is_supplementary();
+                    let decided = pairs.add(
+                        record.qname(), has_mapped_mate, record.clone(), criterion, &mut decide);
+                    write_alignments(decided, &mut stats);
                 }
+                write_alignments(pairs.finish(&mut decide), &mut stats);
+                // coverm's filter counts the primary alignments it reads
+                stats.input_alignments = filtered.num_detected_primary_alignments;
+                stats.removed_identity_and_length = stats.input_alignments
+                    .saturating_sub(passed_thresholds);
+                let stats_prefix = Path::new(output).with_extension("filter_stats");
+                stats.write(stats_prefix.to_str().expect("Unable to parse output file name"));
+                info!("Kept {} of {} alignments from {}",
+                      stats.output_alignments, stats.input_alignments, bam);
             }
         },
         Some("polymorph") => {
@@ -112,15 +185,19 @@ fn main(){
                 process::exit(1);
             }
             set_log_level(m, true);
+            check_cram_inputs(m);
+            let m = &map_reads_into_result_cache(&app, m, mode);
             let filter_params = FilterParameters::generate_from_clap(m);
             let threads = m.value_of("threads").unwrap().parse().unwrap();
             rayon::ThreadPoolBuilder::new().num_threads(threads).build_global().unwrap();
+            write_run_record(m, mode);
 
             if m.is_present("bam-files") {
                 let bam_files: Vec<&str> = m.values_of("bam-files").unwrap().collect();
                 if filter_params.doing_filtering() {
-                    let bam_readers = bam_generator::generate_filtered_bam_readers_from_bam_files(
+                    let bam_readers = generate_filtered_alignment_readers(
                         bam_files,
+                        m.value_of("reference"),
                         filter_params.flag_filters.clone(),
                         filter_params.min_aligned_length_single,
                         filter_params.min_percent_identity_single,
@@ -146,8 +223,8 @@ fn main(){
                                filter_params.flag_filters,
                                None);
                 } else {
-                    let bam_readers = bam_generator::generate_named_bam_readers_from_bam_files(
-                        bam_files);
+                    let bam_readers = generate_named_alignment_readers(
+                        bam_files, m.value_of("reference"));
                     run_pileup(m,
                                mode,
                                &mut estimators,
@@ -223,20 +300,25 @@ fn main(){
                 process::exit(1);
             }
             set_log_level(m, true);
+            check_cram_inputs(m);
+            let m = &map_reads_into_result_cache(&app, m, mode);
+            let m = &map_long_reads(&app, m, mode);
             let filter_params = FilterParameters::generate_from_clap(m);
             let threads = m.value_of("threads").unwrap().parse().unwrap();
             rayon::ThreadPoolBuilder::new().num_threads(threads).build_global().unwrap();
+            write_run_record(m, mode);
             let mut long_readers = vec!();
             if m.is_present("longread-bam-files") {
                 let longreads = m.values_of("longread-bam-files").unwrap().collect();
-                long_readers = bam_generator::generate_named_bam_readers_from_bam_files(
-                    longreads);
+                long_readers = generate_named_alignment_readers(
+                    longreads, m.value_of("reference"));
             };
             if m.is_present("bam-files") {
                 let bam_files: Vec<&str> = m.values_of("bam-files").unwrap().collect();
                 if filter_params.doing_filtering() {
-                    let bam_readers = bam_generator::generate_filtered_bam_readers_from_bam_files(
+                    let bam_readers = generate_filtered_alignment_readers(
                         bam_files,
+                        m.value_of("reference"),
                         filter_params.flag_filters.clone(),
                         filter_params.min_aligned_length_single,
                         filter_params.min_percent_identity_single,
@@ -260,8 +342,8 @@ fn main(){
                                        bam_readers,
                                        filter_params.flag_filters, None);
                 } else {
-                    let bam_readers = bam_generator::generate_named_bam_readers_from_bam_files(
-                        bam_files);
+                    let bam_readers = generate_named_alignment_readers(
+                        bam_files, m.value_of("reference"));
                     run_pileup(m,
                                        mode,
                                        &mut estimators,
@@ -332,22 +414,34 @@ fn main(){
                 process::exit(1);
             }
             set_log_level(m, true);
+            check_cram_inputs(m);
+            let m = &map_reads_into_result_cache(&app, m, mode);
+            let m = &map_long_reads(&app, m, mode);
             let filter_params = FilterParameters::generate_from_clap(m);
             let threads = m.value_of("threads").unwrap().parse().unwrap();
             rayon::ThreadPoolBuilder::new().num_threads(threads).build_global().unwrap();
-            
+            write_run_record(m, mode);
+
+            // Reads are assigned in a second pass, so the BAM files must be kept on disk
+            if m.is_present("assign-reads") && !m.is_present("bam-files")
+                && !m.is_present("longread-bam-files") {
+                eprintln!("--assign-reads requires --bam-files or --longread-bam-files");
+                process::exit(1);
+            }
+
             let mut long_readers = vec!();
             if m.is_present("longread-bam-files") {
                 let longreads = m.values_of("longread-bam-files").unwrap().collect();
-                long_readers = bam_generator::generate_named_bam_readers_from_bam_files(
-                    longreads);
+                long_readers = generate_named_alignment_readers(
+                    longreads, m.value_of("reference"));
             };
 
             if m.is_present("bam-files") {
                 let bam_files: Vec<&str> = m.values_of("bam-files").unwrap().collect();
                 if filter_params.doing_filtering() {
-                    let mut bam_readers = bam_generator::generate_filtered_bam_readers_from_bam_files(
+                    let mut bam_readers = generate_filtered_alignment_readers(
                             bam_files,
+                            m.value_of("reference"),
                             filter_params.flag_filters.clone(),
                             filter_params.min_aligned_length_single,
                             filter_params.min_percent_identity_single,
@@ -372,8 +466,8 @@ fn main(){
                                filter_params.flag_filters, Some(long_readers))
 
                 } else {
-                    let mut bam_readers = bam_generator::generate_named_bam_readers_from_bam_files(
-                        bam_files);
+                    let mut bam_readers = generate_named_alignment_readers(
+                        bam_files, m.value_of("reference"));
                     
                     run_pileup(m,
                                mode,
@@ -447,15 +541,19 @@ fn main(){
                 process::exit(1);
             }
             set_log_level(m, true);
+            check_cram_inputs(m);
+            let m = &map_reads_into_result_cache(&app, m, mode);
             let filter_params = FilterParameters::generate_from_clap(m);
             let threads = m.value_of("threads").unwrap().parse().unwrap();
             rayon::ThreadPoolBuilder::new().num_threads(threads).build_global().unwrap();
+            write_run_record(m, mode);
             if m.is_present("bam-files") {
                 let bam_files: Vec<&str> = m.values_of("bam-files").unwrap().collect();
 
                 if filter_params.doing_filtering() {
-                    let mut bam_readers = bam_generator::generate_filtered_bam_readers_from_bam_files(
+                    let mut bam_readers = generate_filtered_alignment_readers(
                         bam_files,
+                        m.value_of("reference"),
                         filter_params.flag_filters.clone(),
                         filter_params.min_aligned_length_single,
                         filter_params.min_percent_identity_single,
@@ -479,8 +577,8 @@ fn main(){
                                bam_readers,
                                filter_params.flag_filters, None);
                 } else {
-                    let mut bam_readers = bam_generator::generate_named_bam_readers_from_bam_files(
-                        bam_files);
+                    let mut bam_readers = generate_named_alignment_readers(
+                        bam_files, m.value_of("reference"));
                     run_pileup(m,
                                mode,
                                &mut estimators,
@@ -551,25 +649,49 @@ fn main(){
             let mode = "polish";
             let mut estimators = EstimatorsAndTaker::generate_from_clap(m);
             if m.is_present("full-help") {
-                println!("{}", polymorph_full_help());
+                println!("{}", polish_full_help());
                 process::exit(1);
             }
             set_log_level(m, true);
+            check_cram_inputs(m);
+            let m = &map_reads_into_result_cache(&app, m, mode);
+            let m = &map_long_reads(&app, m, mode);
             let filter_params = FilterParameters::generate_from_clap(m);
             let threads = m.value_of("threads").unwrap().parse().unwrap();
             rayon::ThreadPoolBuilder::new().num_threads(threads).build_global().unwrap();
+            write_run_record(m, mode);
             let mut long_readers = vec!();
             if m.is_present("longread-bam-files") {
                 let longreads = m.values_of("longread-bam-files").unwrap().collect();
-                long_readers = bam_generator::generate_named_bam_readers_from_bam_files(
-                    longreads);
+                long_readers = generate_named_alignment_readers(
+                    longreads, m.value_of("reference"));
             };
 
-            if m.is_present("bam-file") {
+            let polish_rounds = value_t!(m.value_of("polish-rounds"), usize).unwrap();
+            if polish_rounds == 0 {
+                panic!("--polish-rounds must be at least 1");
+            }
+            if polish_rounds > 1 {
+                // Reads are remapped to each round's assembly, so BAM files cannot be reused
+                if m.is_present("bam-file") || m.is_present("longread-bam-files")
+                    || m.is_present("sharded") {
+                    eprintln!("--polish-rounds greater than 1 requires reads to map rather than \
+                               BAM files, so cannot be used for hybrid polishing with \
+                               --longread-bam-files, and cannot be used with --sharded");
+                    process::exit(1);
+                }
+                external_command_checker::check_for_samtools();
+                polish_iteratively(m,
+                                   &mut estimators,
+                                   &filter_params,
+                                   parse_mapping_program(&m),
+                                   polish_rounds);
+            } else if m.is_present("bam-file") {
                 let bam_files: Vec<&str> = m.values_of("bam-file").unwrap().collect();
                 if filter_params.doing_filtering() {
-                    let bam_readers = bam_generator::generate_filtered_bam_readers_from_bam_files(
+                    let bam_readers = generate_filtered_alignment_readers(
                         bam_files,
+                        m.value_of("reference"),
                         filter_params.flag_filters.clone(),
                         filter_params.min_aligned_length_single,
                         filter_params.min_percent_identity_single,
@@ -583,8 +705,8 @@ fn main(){
                                bam_readers,
                                filter_params.flag_filters, Some(long_readers));
                 } else {
-                    let bam_readers = bam_generator::generate_named_bam_readers_from_bam_files(
-                        bam_files);
+                    let bam_readers = generate_named_alignment_readers(
+                        bam_files, m.value_of("reference"));
                     run_pileup(m,
                                mode,
                                &mut estimators,
@@ -948,8 +1070,8 @@ fn get_sharded_bam_readers<'a, 'b, T>(
         T: GenomeExclusion,
 {
     // Check the output BAM directory actually exists and is writeable
-    if m.is_present("bam-file-cache-directory") {
-        setup_bam_cache_directory(m.value_of("bam-file-cache-directory").unwrap());
+    if m.is_present("outdir") {
+        setup_bam_cache_directory(m.value_of("outdir").unwrap());
     }
     let discard_unmapped = m.is_present("discard-unmapped");
     let sort_threads = m.value_of("threads").unwrap().parse::<i32>().unwrap();
@@ -974,11 +1096,11 @@ fn get_sharded_bam_readers<'a, 'b, T>(
         };
         let bam_file_cache = |naming_readset| -> Option<String> {
             let bam_file_cache_path;
-            match m.is_present("bam-file-cache-directory") {
+            match m.is_present("outdir") {
                 false => None,
                 true => {
                     bam_file_cache_path = generate_cached_bam_file_name(
-                        m.value_of("bam-file-cache-directory").unwrap(),
+                        m.value_of("outdir").unwrap(),
                         match reference_tempfile {
                             Some(_) => CONCATENATED_REFERENCE_CACHE_STEM,
                             None => reference,
@@ -1042,8 +1164,8 @@ fn get_streamed_bam_readers<'a>(
     reference_tempfile: &'a Option<NamedTempFile>,
 ) -> Vec<BamGeneratorSet<StreamingNamedBamReaderGenerator>> {
     // Check the output BAM directory actually exists and is writeable
-    if m.is_present("bam-file-cache-directory") {
-        setup_bam_cache_directory(m.value_of("bam-file-cache-directory").unwrap());
+    if m.is_present("outdir") {
+        setup_bam_cache_directory(m.value_of("outdir").unwrap());
     }
     let discard_unmapped = m.is_present("discard-unmapped");
 
@@ -1056,11 +1178,11 @@ fn get_streamed_bam_readers<'a>(
         let reference = reference_wise_params.reference;
         let bam_file_cache = |naming_readset| -> Option<String> {
             let bam_file_cache_path;
-            match m.is_present("bam-file-cache-directory") {
+            match m.is_present("outdir") {
                 false => None,
                 true => {
                     bam_file_cache_path = generate_cached_bam_file_name(
-                        m.value_of("bam-file-cache-directory").unwrap(),
+                        m.value_of("outdir").unwrap(),
                         match reference_tempfile {
                             Some(_) => CONCATENATED_REFERENCE_CACHE_STEM,
                             None => reference,
@@ -1104,6 +1226,189 @@ fn get_streamed_bam_readers<'a>(
     }
     return generator_set;
 }
+/// With --result-cache, maps each read set into the cache unless an earlier run mapped the
+/// same reads to the same reference with the same mapper and options, and returns matches
+/// with the reads replaced by the cached BAM files. Sharded runs and polishing over several
+/// rounds map their reads as usual
+fn map_reads_into_result_cache(
+    app: &App<'static, 'static>,
+    m: &clap::ArgMatches<'static>,
+    mode: &str) -> clap::ArgMatches<'static> {
+    let bam_argument = if mode == "polish" { "bam-file" } else { "bam-files" };
+    if !m.is_present("result-cache") || m.is_present(bam_argument) || m.is_present("sharded")
+        || (mode == "polish" && m.value_of("polish-rounds") != Some("1")) {
+        return m.clone()
+    }
+    let cache = ResultCache::new(m.value_of("result-cache").unwrap());
+    let mapping_program = parse_mapping_program(&m);
+    external_command_checker::check_for_samtools();
+    let discard_unmapped = m.is_present("discard-unmapped");
+
+    // Key, cached BAM file name and sample name of each read set, in the order they are mapped
+    let mut read_sets = vec!();
+    for reference_wise_params in MappingParameters::generate_from_clap(&m, mapping_program, &None) {
+        let reference = reference_wise_params.reference;
+        let reference_digest = ResultCache::input_digest(reference);
+        for p in reference_wise_params {
+            let key = ResultCache::key(&[
+                &reference_digest,
+                &ResultCache::input_digest(p.read1),
+                &p.read2.map(ResultCache::input_digest).unwrap_or_default(),
+                &format!("{:?}", p.read_format),
+                m.value_of("mapper").unwrap(),
+                p.mapping_options.unwrap_or(""),
+                &discard_unmapped.to_string()]);
+            let bam_name = Path::new(&generate_cached_bam_file_name(".", reference, p.read1))
+                .file_name().unwrap().to_string_lossy().to_string();
+            read_sets.push((key, bam_name, mapped_stoit_name(reference, p.read1)));
+        }
+    }
+
+    let mut read_set_idx = 0;
+    for reference_wise_params in MappingParameters::generate_from_clap(&m, mapping_program, &None) {
+        let reference = reference_wise_params.reference;
+        let uncached = read_sets[read_set_idx..read_set_idx + reference_wise_params.len()].iter()
+            .any(|(key, _, _)| cache.get(key).is_none());
+        let index = match uncached {
+            true => setup_mapping_index(&reference_wise_params, &m, mapping_program),
+            false => None,
+        };
+        for p in reference_wise_params {
+            let (key, bam_name, _) = &read_sets[read_set_idx];
+            read_set_idx += 1;
+            if cache.get(key).is_some() {
+                info!("Using cached mapping of {} to {}", p.read1, reference);
+                continue
+            }
+            info!("Mapping {} to {} into the result cache", p.read1, reference);
+            let partial = cache.partial(key);
+            let bam_path = entry_file(&partial, bam_name);
+            let generator = bam_generator::generate_named_bam_readers_from_reads(
+                mapping_program,
+                match index {
+                    Some(ref index) => index.index_path(),
+                    None => reference,
+                },
+                p.read1,
+                p.read2,
+                p.read_format.clone(),
+                p.threads,
+                Some(bam_path.as_str()),
+                discard_unmapped,
+                p.mapping_options,
+                true,
+            );
+            write_mapped_bam(generator, &bam_path);
+            cache.commit(key, &partial);
+        }
+    }
+
+    let bam_files: Vec<String> = read_sets.iter()
+        .map(|(key, bam_name, _)| entry_file(&cache.get(key).unwrap(), bam_name))
+        .collect();
+    let stoit_names: Vec<String> = read_sets.into_iter()
+        .map(|(_, _, stoit_name)| stoit_name)
+        .collect();
+    matches_with_bam_files(app, m, mode, READ_ARGUMENTS, bam_argument, &bam_files, &stoit_names)
+}
+
+/// Sample name of reads mapped to a reference, as named by coverm when mapping them during
+/// the run
+fn mapped_stoit_name(reference: &str, read1: &str) -> String {
+    format!("{}/{}",
+            Path::new(reference).file_name().expect("Unable to convert reference to file name")
+                .to_string_lossy(),
+            Path::new(read1).file_name().expect("Unable to convert read1 name to file name")
+                .to_string_lossy())
+}
+
+/// Maps the reads of --longreads, such as the long read libraries of a sample sheet, with the
+/// minimap2 preset of their technology into --bam-file-cache-directory, and returns matches
+/// with the reads replaced by the BAM files they were mapped to. Reads outside a sample sheet
+/// are taken to be from the technology of --mapper, or to be nanopore reads
+fn map_long_reads(
+    app: &App<'static, 'static>,
+    m: &clap::ArgMatches<'static>,
+    mode: &str) -> clap::ArgMatches<'static> {
+    if !m.is_present("longreads") {
+        return m.clone()
+    }
+    let outdir = match m.value_of("outdir") {
+        Some(outdir) => outdir,
+        None => {
+            error!("Long reads must be mapped into a --bam-file-cache-directory for variant \
+                    calling");
+            process::exit(1);
+        },
+    };
+    let references: Vec<&str> = m.values_of("reference").map(|references| references.collect())
+        .unwrap_or_default();
+    if references.len() != 1 {
+        error!("Long reads must be mapped to a single --reference");
+        process::exit(1);
+    }
+    setup_bam_cache_directory(outdir);
+    external_command_checker::check_for_minimap2();
+    external_command_checker::check_for_samtools();
+    let sheet = m.value_of("sample-sheet").map(SampleSheet::from_file);
+    let default_technology = match m.value_of("mapper") {
+        Some("minimap2-pb") => ReadTechnology::PacBio,
+        _ => ReadTechnology::Nanopore,
+    };
+    let reference = references[0];
+    let threads = m.value_of("threads").unwrap().parse::<u16>().unwrap();
+    let discard_unmapped = m.is_present("discard-unmapped");
+
+    let long_reads: Vec<&str> = m.values_of("longreads").unwrap().collect();
+    let bam_files: Vec<String> = long_reads.iter().map(|reads| {
+        let technology = sheet.as_ref().and_then(|sheet| sheet.long_read_technology(reads))
+            .unwrap_or(default_technology);
+        let mapping_program = match technology {
+            ReadTechnology::PacBio => MappingProgram::MINIMAP2_PB,
+            _ => MappingProgram::MINIMAP2_ONT,
+        };
+        let bam_path = generate_cached_bam_file_name(outdir, reference, reads);
+        info!("Mapping {} reads {} to {} into {}", technology.name(), reads, reference, bam_path);
+        let generator = bam_generator::generate_named_bam_readers_from_reads(
+            mapping_program,
+            reference,
+            reads,
+            None,
+            ReadFormat::Single,
+            threads,
+            Some(bam_path.as_str()),
+            discard_unmapped,
+            None,
+            true,
+        );
+        write_mapped_bam(generator, &bam_path);
+        bam_path
+    }).collect();
+    let stoit_names: Vec<String> = long_reads.iter()
+        .map(|reads| mapped_stoit_name(reference, reads))
+        .collect();
+    matches_with_bam_files(app, m, mode, &["longreads"], "longread-bam-files", &bam_files,
+                           &stoit_names)
+}
+
+/// Reads mapped reads to the end, which writes them to the generator's BAM file, and indexes
+/// the BAM file. The mapped reads are sorted, so indexing them lets variant calling use them
+/// as they are
+fn write_mapped_bam(generator: StreamingNamedBamReaderGenerator, bam_path: &str) {
+    let mut reader = generator.start();
+    let mut record = bam::Record::new();
+    while reader.read(&mut record).expect("Error while reading BAM record") {}
+    reader.finish();
+    let status = std::process::Command::new("samtools")
+        .arg("index")
+        .arg(bam_path)
+        .status()
+        .expect("Unable to execute samtools");
+    if !status.success() {
+        panic!("Failed to index mapped BAM file {}", bam_path);
+    }
+}
+
 fn generate_cached_bam_file_name(directory: &str, reference: &str, read1_path: &str) -> String {
     debug!("Constructing BAM file cache name in directory {}, reference {}, read1_path {}",
            directory, reference, read1_path);
@@ -1174,8 +1479,8 @@ fn get_streamed_filtered_bam_readers(
     filter_params: &FilterParameters,
 ) -> Vec<BamGeneratorSet<StreamingFilteredNamedBamReaderGenerator>> {
     // Check the output BAM directory actually exists and is writeable
-    if m.is_present("bam-file-cache-directory") {
-        setup_bam_cache_directory(m.value_of("bam-file-cache-directory").unwrap());
+    if m.is_present("outdir") {
+        setup_bam_cache_directory(m.value_of("outdir").unwrap());
     }
     let discard_unmapped = m.is_present("discard-unmapped");
 
@@ -1188,11 +1493,11 @@ fn get_streamed_filtered_bam_readers(
         let reference = reference_wise_params.reference;
         let bam_file_cache = |naming_readset| -> Option<String> {
             let bam_file_cache_path;
-            match m.is_present("bam-file-cache-directory") {
+            match m.is_present("outdir") {
                 false => None,
                 true => {
                     bam_file_cache_path = generate_cached_bam_file_name(
-                        m.value_of("bam-file-cache-directory").unwrap(),
+                        m.value_of("outdir").unwrap(),
                         match reference_tempfile {
                             Some(_) => CONCATENATED_REFERENCE_CACHE_STEM,
                             None => reference,
@@ -1243,27 +1548,6 @@ fn get_streamed_filtered_bam_readers(
     return generator_set;
 }
 
-fn generate_faidx(m: &clap::ArgMatches) -> bio::io::fasta::IndexedReader<File> {
-    external_command_checker::check_for_samtools();
-    info!("Generating reference index");
-    let cmd_string = format!(
-        "set -e -o pipefail; \
-                     samtools faidx {}",
-        m.value_of("reference").unwrap());
-    debug!("Queuing cmd_string: {}", cmd_string);
-
-    std::process::Command::new("bash")
-        .arg("-c")
-        .arg(&cmd_string)
-        .stdout(Stdio::piped())
-        .output()
-        .expect("Unable to execute bash");
-
-    let reference_path = Path::new(m.value_of("reference").unwrap());
-    return bio::io::fasta::IndexedReader::from_file(&reference_path).expect(
-        "Unable to generate index")
-}
-
 fn run_pileup<'a,
     R: bam_generator::NamedBamReader + Send,
     T: bam_generator::NamedBamReaderGenerator<R> + Send>(
@@ -1272,279 +1556,207 @@ fn run_pileup<'a,
     estimators: &mut EstimatorsAndTaker,
     bam_readers: Vec<T>,
     flag_filters: FlagFilter,
-    long_readers: Option<Vec<bam_generator::BamFileNamedReader>>) {
+    long_readers: Option<Vec<AlignmentFileNamedReader>>) {
     match mode {
-        "polymorph" => {
-            let print_zeros = !m.is_present("no-zeros");
-            let var_fraction = m.value_of("min-variant-depth").unwrap().parse().unwrap();
-
-            let mapq_threshold = m.value_of("mapq-threshold").unwrap().parse().unwrap();
-            let coverage_fold = m.value_of("coverage-fold").unwrap().parse().unwrap();
-            let method = m.value_of("method").unwrap();
-
-            let output_prefix = m.value_of("output-prefix").unwrap().to_string();
-            let include_indels = m.is_present("include-indels");
-
-            // Make sure we are dealing with a fresh file
-            let file_name = output_prefix.to_string()
-                + &".fna".to_owned();
-
-            let file_path = Path::new(&file_name);
-
-            File::create(file_path)
-                .expect("No Read or Write Permission in current directory");
-            let reference_path = Path::new(m.value_of("reference").unwrap());
-//            let index_path = reference_path.clone().to_owned() + ".fai";
-            let fasta_reader = match bio::io::fasta::IndexedReader::from_file(&reference_path) {
-                Ok(reader) => reader,
-                Err(_e) => generate_faidx(m),
-            };
-            let threads = m.value_of("threads").unwrap().parse().unwrap();
-
-
-            let contig_end_exclusion = value_t!(m.value_of("contig-end-exclusion"), u64).unwrap();
-            let min = value_t!(m.value_of("trim-min"), f32).unwrap();
-            let max = value_t!(m.value_of("trim-max"), f32).unwrap();
-            if min < 0.0 || min > 1.0 || max <= min || max > 1.0 {
-                panic!("error: Trim bounds must be between 0 and 1, and \
-                                    min must be less than max, found {} and {}", min, max);
-            }
-
-
-            info!("Beginning polymorph with {} bam readers and {} threads", bam_readers.len(), threads);
-            println!("sample\ttid\tpos\tvariant\treference\tvariant_depth\tdepth\tgenotypes\tvaf_cluster\tgenotype");
-            contig::pileup_variants(
-                m,
-                bam_readers,
-                long_readers,
-                mode,
-                &mut estimators.estimators,
-                fasta_reader,
-                print_zeros,
-                flag_filters,
-                mapq_threshold,
-                var_fraction,
-                min,
-                max,
-                contig_end_exclusion,
-                &output_prefix,
-                threads,
-                method,
-                coverage_fold,
-                include_indels,
-                false,
-                false);
+        "polish" => {
+            let output_prefix = polish_output_prefix(m);
+            run_polish(m,
+                       estimators,
+                       bam_readers,
+                       flag_filters,
+                       long_readers,
+                       m.value_of("reference").unwrap(),
+                       &output_prefix);
         },
-        "genotype" => {
-            let print_zeros = !m.is_present("no-zeros");
-            let var_fraction = m.value_of("min-variant-depth").unwrap().parse().unwrap();
-            let mapq_threshold = m.value_of("mapq-threshold").unwrap().parse().unwrap();
-            let coverage_fold = m.value_of("coverage-fold").unwrap().parse().unwrap();
-//            let kmer_size = m.value_of("kmer-size").unwrap().parse().unwrap();
-            let reference_path = Path::new(m.value_of("reference").unwrap());
-
-            let output_prefix = m.value_of("output-prefix").unwrap();
-            let include_indels = m.is_present("include-indels");
-            let include_soft_clipping = m.is_present("include-soft-clipping");
-
-            let threads = m.value_of("threads").unwrap().parse().unwrap();
-
-            let method = m.value_of("method").unwrap();
-
-            let contig_end_exclusion = value_t!(m.value_of("contig-end-exclusion"), u64).unwrap();
-            let min = value_t!(m.value_of("trim-min"), f32).unwrap();
-            let max = value_t!(m.value_of("trim-max"), f32).unwrap();
-            if min < 0.0 || min > 1.0 || max <= min || max > 1.0 {
-                panic!("error: Trim bounds must be between 0 and 1, and \
-                                    min must be less than max, found {} and {}", min, max);
+        "polymorph" | "genotype" | "summarize" | "evolve" => {
+            let mut config = LorikeetConfig::generate_from_clap(m, mode);
+            config.flag_filters = flag_filters;
+            let fasta_reader = contig::indexed_reference(&config.reference);
+
+            info!("Beginning {} with {} bam readers and {} threads",
+                  mode, bam_readers.len(), config.threads);
+            if mode == "polymorph" {
+                // Make sure we are dealing with a fresh file
+                let file_name = config.output_prefix.to_string()
+                    + &".fna".to_owned();
+                File::create(Path::new(&file_name))
+                    .expect("No Read or Write Permission in current directory");
+                println!("sample\ttid\tpos\tvariant\treference\tvariant_depth\tdepth\tgenotypes\tvaf_cluster\t{}",
+                         ANNOTATION_HEADER);
+            } else if mode == "evolve" {
+                println!("gene\tstart\tend\tframe\tstrand\tdnds\tposition\tvariant\treference\tabundance\tdepth\tinfo");
             }
-
-            let fasta_reader = match bio::io::fasta::IndexedReader::from_file(&reference_path){
-                Ok(reader) => reader,
-                Err(_e) => generate_faidx(m),
-            };
-
-            info!("Beginning summarize with {} bam readers and {} threads", bam_readers.len(), threads);
             contig::pileup_variants(
-                m,
-                bam_readers,
-                long_readers,
-                mode,
-                &mut estimators.estimators,
+                &config,
                 fasta_reader,
-                print_zeros,
-                flag_filters,
-                mapq_threshold,
-                var_fraction,
-                min,
-                max,
-                contig_end_exclusion,
-                output_prefix,
-                threads,
-                method,
-                coverage_fold,
-                include_indels,
-                include_soft_clipping,
-                m.is_present("longread-bam-files"));
-        },
-        "summarize" => {
-            let print_zeros = !m.is_present("no-zeros");
-            let var_fraction = m.value_of("min-variant-depth").unwrap().parse().unwrap();
-            let mapq_threshold = m.value_of("mapq-threshold").unwrap().parse().unwrap();
-            let coverage_fold = m.value_of("coverage-fold").unwrap().parse().unwrap();
-            let reference_path = Path::new(m.value_of("reference").unwrap());
-
-            let output_prefix = m.value_of("output-prefix").unwrap();
-            let include_indels = m.is_present("include-indels");
-
-            let threads = m.value_of("threads").unwrap().parse().unwrap();
-
-            let method = m.value_of("method").unwrap();
-
-            let contig_end_exclusion = value_t!(m.value_of("contig-end-exclusion"), u64).unwrap();
-            let min = value_t!(m.value_of("trim-min"), f32).unwrap();
-            let max = value_t!(m.value_of("trim-max"), f32).unwrap();
-            if min < 0.0 || min > 1.0 || max <= min || max > 1.0 {
-                panic!("error: Trim bounds must be between 0 and 1, and \
-                                    min must be less than max, found {} and {}", min, max);
-            }
-
-            let fasta_reader = match bio::io::fasta::IndexedReader::from_file(&reference_path){
-                Ok(reader) => reader,
-                Err(_e) => generate_faidx(m),
-            };
-
-            info!("Beginning summarize with {} bam readers and {} threads", bam_readers.len(), threads);
-            contig::pileup_variants(
-                m,
                 bam_readers,
                 long_readers,
                 mode,
-                &mut estimators.estimators,
-                fasta_reader,
-                print_zeros,
-                flag_filters,
-                mapq_threshold,
-                var_fraction,
-                min,
-                max,
-                contig_end_exclusion,
-                output_prefix,
-                threads,
-                method,
-                coverage_fold,
-                include_indels,
-                false,
-                false);
+                &mut estimators.estimators);
         },
-        "evolve" => {
-            let print_zeros = !m.is_present("no-zeros");
-            let var_fraction = m.value_of("min-variant-depth").unwrap().parse().unwrap();
-
-            let mapq_threshold = m.value_of("mapq-threshold").unwrap().parse().unwrap();
-            let coverage_fold = m.value_of("coverage-fold").unwrap().parse().unwrap();
-            let method = m.value_of("method").unwrap();
-
-            let reference_path = Path::new(m.value_of("reference").unwrap());
-//            let index_path = reference_path.clone().to_owned() + ".fai";
-            let fasta_reader = match bio::io::fasta::IndexedReader::from_file(&reference_path){
-                Ok(reader) => reader,
-                Err(_e) => generate_faidx(m),
-            };
-            let include_indels = m.is_present("include-indels");
-
-            let threads = m.value_of("threads").unwrap().parse().unwrap();
-
-            let contig_end_exclusion = value_t!(m.value_of("contig-end-exclusion"), u64).unwrap();
-            let min = value_t!(m.value_of("trim-min"), f32).unwrap();
-            let max = value_t!(m.value_of("trim-max"), f32).unwrap();
-            if min < 0.0 || min > 1.0 || max <= min || max > 1.0 {
-                panic!("error: Trim bounds must be between 0 and 1, and \
-                                    min must be less than max, found {} and {}", min, max);
-            }
+        _ => panic!("Unknown lorikeet mode"),
+    }
+    if m.is_present("cache-cram") {
+        if let Some(cache_directory) = m.value_of("outdir") {
+            external_command_checker::check_for_samtools();
+            let references: Vec<&str> = m.values_of("reference").unwrap().collect();
+            convert_cached_bams_to_cram(
+                cache_directory, &references, m.value_of("threads").unwrap().parse().unwrap());
+        }
+    }
+}
 
-            info!("Beginning evolve with {} bam readers and {} threads", bam_readers.len(), threads);
-            println!("gene\tstart\tend\tframe\tstrand\tdnds\tposition\tvariant\treference\tabundance\tdepth\tinfo");
-            contig::pileup_variants(
-                m,
-                bam_readers,
-                long_readers,
-                mode,
-                &mut estimators.estimators,
-                fasta_reader,
-                print_zeros,
-                flag_filters,
-                mapq_threshold,
-                var_fraction,
-                min,
-                max,
-                contig_end_exclusion,
-                "",
-                threads,
-                method,
-                coverage_fold,
-                include_indels,
-                false,
-                false);
+/// Writes the resolved options of a run, the lorikeet version and checksums of the inputs
+/// next to the run's outputs
+fn write_run_record(m: &clap::ArgMatches, mode: &str) {
+    let file_name = match mode {
+        "filter" => {
+            let output = m.values_of("output-bam-files").unwrap().next().unwrap();
+            Path::new(output).with_extension("run_config.json").to_str()
+                .expect("Unable to parse output file name").to_string()
         },
-        "polish" => {
-            let print_zeros = !m.is_present("no-zeros");
-            let var_fraction = m.value_of("min-variant-depth").unwrap().parse().unwrap();
-            let output_prefix = m.value_of("reference").unwrap().to_string();
-            let output_prefix = output_prefix
-                .split("..").last().unwrap()
-                .split("/").last().unwrap().split(".").next().unwrap();
-
-
-            let mapq_threshold = m.value_of("mapq-threshold").unwrap().parse().unwrap();
-            let coverage_fold = m.value_of("coverage-fold").unwrap().parse().unwrap();
-            let method = m.value_of("method").unwrap();
-            let include_indels = m.is_present("include-indels");
-
-            let reference_path = Path::new(m.value_of("reference").unwrap());
-//            let index_path = reference_path.clone().to_owned() + ".fai";
-            let fasta_reader = match bio::io::fasta::IndexedReader::from_file(&reference_path) {
-                Ok(reader) => reader,
-                Err(_e) => generate_faidx(m),
-            };
-            let threads = m.value_of("threads").unwrap().parse().unwrap();
+        "polish" => format!("{}_run_config.json", polish_output_prefix(m)),
+        _ => format!("{}_run_config.json", m.value_of("output-prefix").unwrap()),
+    };
+    RunRecord::new(m, mode).write(&file_name);
+}
 
+/// CRAM inputs are decoded against the reference set on their readers. coverm's sharded
+/// readers open the files themselves, so they can only read BAM files
+fn check_cram_inputs(m: &clap::ArgMatches) {
+    let cram_inputs = ["bam-files", "bam-file", "longread-bam-files"].iter()
+        .filter_map(|name| m.values_of(name))
+        .flatten()
+        .any(|path| AlignmentFormat::from_path(path) == AlignmentFormat::Cram);
+    if cram_inputs && m.is_present("sharded") {
+        error!("CRAM files cannot be read with --sharded, convert them to BAM files first");
+        process::exit(1);
+    }
+}
 
-            let contig_end_exclusion = value_t!(m.value_of("contig-end-exclusion"), u64).unwrap();
-            let min = value_t!(m.value_of("trim-min"), f32).unwrap();
-            let max = value_t!(m.value_of("trim-max"), f32).unwrap();
-            if min < 0.0 || min > 1.0 || max <= min || max > 1.0 {
-                panic!("error: Trim bounds must be between 0 and 1, and \
-                                    min must be less than max, found {} and {}", min, max);
-            }
+/// Polished assemblies are named after the reference being polished
+fn polish_output_prefix(m: &clap::ArgMatches) -> String {
+    m.value_of("reference").unwrap()
+        .split("..").last().unwrap()
+        .split("/").last().unwrap()
+        .split(".").next().unwrap().to_string()
+}
 
+/// Polish the given reference using the reads in bam_readers, which must be mapped to it.
+/// Returns the number of edits applied
+fn run_polish<
+    R: bam_generator::NamedBamReader + Send,
+    T: bam_generator::NamedBamReaderGenerator<R> + Send>(
+    m: &clap::ArgMatches,
+    estimators: &mut EstimatorsAndTaker,
+    bam_readers: Vec<T>,
+    flag_filters: FlagFilter,
+    long_readers: Option<Vec<AlignmentFileNamedReader>>,
+    reference: &str,
+    output_prefix: &str) -> usize {
+    // The reference changes with each polishing round
+    let mut config = LorikeetConfig::generate_from_clap(m, "polish");
+    config.reference = reference.to_string();
+    config.output_prefix = output_prefix.to_string();
+    config.flag_filters = flag_filters;
+    let fasta_reader = contig::indexed_reference(reference);
+
+    info!("Beginning polishing with {} bam readers and {} threads", bam_readers.len(), config.threads);
+    contig::pileup_variants(
+        &config,
+        fasta_reader,
+        bam_readers,
+        long_readers,
+        "polish",
+        &mut estimators.estimators)
+}
 
-            info!("Beginning polishing with {} bam readers and {} threads", bam_readers.len(), threads);
-            contig::pileup_variants(
+/// Alternate between polishing the assembly and mapping the reads to the polished assembly,
+/// stopping after `rounds` rounds or once a round applies no edits. The assembly and edits of
+/// each round are kept, the number of edits applied in each round is written to
+/// {prefix}_polish_rounds.tsv and the final assembly is copied to {prefix}_polished.fna
+fn polish_iteratively(
+    m: &clap::ArgMatches,
+    estimators: &mut EstimatorsAndTaker,
+    filter_params: &FilterParameters,
+    mapping_program: MappingProgram,
+    rounds: usize) {
+    let output_prefix = polish_output_prefix(m);
+    let mut reference = m.value_of("reference").unwrap().to_string();
+    // Reads are mapped to the original reference in the first round, and to a copy of the
+    // previous round's assembly after that
+    let mut reference_tempfile: Option<NamedTempFile> = None;
+    let mut edits_per_round = vec!();
+
+    for round in 1..(rounds + 1) {
+        info!("Beginning polishing round {} of at most {}", round, rounds);
+        let round_prefix = format!("{}_round{}", output_prefix, round);
+        let edits = if filter_params.doing_filtering() {
+            let generator_sets = get_streamed_filtered_bam_readers(
                 m,
-                bam_readers,
-                long_readers,
-                mode,
-                &mut estimators.estimators,
-                fasta_reader,
-                print_zeros,
-                flag_filters,
-                mapq_threshold,
-                var_fraction,
-                min,
-                max,
-                contig_end_exclusion,
-                &output_prefix,
-                threads,
-                method,
-                coverage_fold,
-                include_indels,
-                false,
-                false);
-        },
-        _ => panic!("Unknown lorikeet mode"),
+                mapping_program,
+                &reference_tempfile,
+                filter_params,
+            );
+            let mut all_generators = vec!();
+            let mut indices = vec!(); // Prevent indices from being dropped
+            for set in generator_sets {
+                indices.push(set.index);
+                for g in set.generators {
+                    all_generators.push(g)
+                }
+            }
+            run_polish(m,
+                       estimators,
+                       all_generators,
+                       filter_params.flag_filters.clone(),
+                       Some(vec!()),
+                       &reference,
+                       &round_prefix)
+        } else {
+            let generator_sets = get_streamed_bam_readers(m, mapping_program, &reference_tempfile);
+            let mut all_generators = vec!();
+            let mut indices = vec!(); // Prevent indices from being dropped
+            for set in generator_sets {
+                indices.push(set.index);
+                for g in set.generators {
+                    all_generators.push(g)
+                }
+            }
+            run_polish(m,
+                       estimators,
+                       all_generators,
+                       filter_params.flag_filters.clone(),
+                       Some(vec!()),
+                       &reference,
+                       &round_prefix)
+        };
+        info!("Polishing round {} applied {} edits", round, edits);
+        edits_per_round.push(edits);
+        reference = format!("{}_polished.fna", round_prefix);
+        if edits == 0 {
+            info!("No edits applied in round {}, polishing has converged", round);
+            break
+        } else if round < rounds {
+            let tempfile = NamedTempFile::new()
+                .expect("Unable to create temporary file for polished assembly");
+            std::fs::copy(&reference, tempfile.path())
+                .expect("Unable to copy polished assembly to temporary file");
+            reference_tempfile = Some(tempfile);
+        }
     }
 
+    let polished_file = format!("{}_polished.fna", output_prefix);
+    std::fs::copy(&reference, &polished_file)
+        .expect("Unable to copy final polished assembly");
+    let rounds_file = format!("{}_polish_rounds.tsv", output_prefix);
+    let mut rounds_file = File::create(Path::new(&rounds_file))
+        .expect("No Read or Write Permission in current directory");
+    writeln!(rounds_file, "round\tedits").expect("Unable to write to file");
+    for (round, edits) in edits_per_round.iter().enumerate() {
+        writeln!(rounds_file, "{}\t{}", round + 1, edits).expect("Unable to write to file");
+    }
+    info!("Polished assembly written to {} after {} rounds", polished_file, edits_per_round.len());
 }
 
 fn set_log_level(matches: &clap::ArgMatches, is_last: bool) {
@@ -15,15 +15,20 @@ const MAPPER_HELP: &'static str =
                                          [default: \"minimap2-sr\"]";
 
 pub fn filter_full_help() -> &'static str {
-    "lorikeet filter: Remove alignments with insufficient identity.
+    "lorikeet filter: Remove alignments with insufficient identity or quality.
 
 Only primary, non-supplementary alignments are considered, and output files
 are grouped by reference, but not sorted by position.
 
 Files (both required):
-   -b, --bam-files <PATH> ..             Path to reference-sorted BAM file(s)
+   -b, --bam-files <PATH> ..             Path to reference-sorted BAM or CRAM file(s)
    -o, --output-bam-files <PATH> ..      Path to corresponding output file(s)
 
+CRAM (optional):
+   -r, --reference <PATH>                FASTA file the CRAM files are encoded
+                                         against. Required for CRAM input or output.
+   --output-cram                         Write output files as CRAM rather than BAM.
+
 Thresholds:
    --min-read-aligned-length <INT>            Exclude reads with smaller numbers of
                                               aligned bases [default: 0]
@@ -44,6 +49,29 @@ Thresholds:
                                               Implies --proper-pairs-only. [default 0.0]
    --proper-pairs-only                        Require reads to be mapped as proper pairs
 
+Additional filters:
+   --min-mapq <INT>                           Exclude alignments with a lower mapping
+                                              quality. [default: 0]
+   --max-edit-distance <INT>                  Exclude alignments with a larger NM tag.
+                                              Alignments without an NM tag are kept.
+   --max-soft-clip-fraction <FLOAT>           Exclude reads with a larger fraction of
+                                              their bases soft clipped.
+   --remove-duplicates                        Exclude alignments marked as duplicates,
+                                              and alignments with the same unclipped
+                                              5' position, strand and mate position
+                                              as an earlier one.
+   --min-trimmed-length <INT>                 Exclude reads shorter than this after
+                                              trimming bases below --trim-quality from
+                                              both ends. [default: 0]
+   --trim-quality <INT>                       Base quality used when trimming reads for
+                                              --min-trimmed-length. [default: 20]
+
+  Alignments passing the thresholds are checked against the additional filters
+  in order, and counted against the first one they fail. Both mates of a pair
+  are removed when either fails. --inverse only applies to the thresholds. The
+  number of alignments removed by each criterion is written next to each output
+  file, as <output>.filter_stats.tsv and <output>.filter_stats.json
+
 Other:
    -t, --threads <INT>                   Number of threads for output compression
                                          [default 1]
@@ -52,6 +80,15 @@ Other:
                                          records may still be marked as mapped
                                          if they do not meet the thresholds.
                                          [default false]
+   --config <PATH>                       YAML file, or TOML file ending in .toml,
+                                         setting options by their long names.
+                                         Options on the command line take
+                                         precedence. Options for one subcommand
+                                         only can be put in a section named after
+                                         it. The resolved options, lorikeet
+                                         version and input file sizes, modification
+                                         times and checksums are written to
+                                         <output>.run_config.json
    --verbose                             Print extra debugging information
    -q, --quiet                           Unless there is an error, do not print
                                          log messages
@@ -60,6 +97,12 @@ Example usage:
 
   lorikeet filter -b in.bam -o out.bam --min-read-aligned-length 75
 
+  lorikeet filter -b in.bam -o out.bam --min-mapq 20 --max-edit-distance 10
+      --max-soft-clip-fraction 0.1 --remove-duplicates --min-trimmed-length 50
+
+  lorikeet filter -b in.cram -o out.cram -r assembly.fna --output-cram
+      --min-read-percent-identity 0.95
+
 Rhys J.P. Newell <r.newell near uq.edu.au>"
 }
 
@@ -75,6 +118,22 @@ Define mapping(s) (required):
                                          unless --sharded is specified, in which
                                          case they must be read name sorted (e.g.
                                          with samtools sort -n).
+                                         CRAM files are also accepted, and are
+                                         decoded with --reference.
+
+  Or define a sample sheet:
+   --sample-sheet <PATH>                 Tab separated file of sample name, read
+                                         technology (short, ont or pacbio), comma
+                                         separated read files or a BAM/CRAM file,
+                                         and optional group and stratum. Outputs
+                                         use these sample names, and the short and
+                                         long read libraries of a sample are
+                                         merged into one sample. Long read files
+                                         are mapped with the map-ont or map-pb
+                                         preset of minimap2 into
+                                         --bam-file-cache-directory, and PacBio
+                                         reads are called with the CCS preset of
+                                         sniffles.
 
   Or do mapping:
    -r, --reference <PATH> ..             FASTA file of contigs or BWA index stem
@@ -92,7 +151,19 @@ Define mapping(s) (required):
                                          <sample2_R1.fq.gz> <sample2_R2.fq.gz> ..
    --interleaved <PATH> ..               Interleaved FASTA/Q files(s) for mapping.
    --single <PATH> ..                    Unpaired FASTA/Q files(s) for mapping.
+   --gff, -g <PATH>                      GFF3, GTF, GenBank or EMBL file containing
+                                         gene locations present in reference genome.
+                                         Variants are annotated with their
+                                         consequence on overlapping genes.
    -d, --outdir                          Output directory
+   --result-cache <PATH>                 Directory of mapped reads and variant calls
+                                         to reuse across runs. Results are keyed by
+                                         the contents of the reference and reads or
+                                         BAM files along with the mapper and its
+                                         parameters, so are only reused for
+                                         identical inputs. Samples of mapped reads
+                                         keep their names when read back from the
+                                         cache.
 {}
    --minimap2-params PARAMS              Extra parameters to provide to minimap2,
                                          both indexing command (if used) and for
@@ -169,6 +240,9 @@ Other arguments (optional):
                                          [default: 0.5]
    --contig-end-exclusion                Exclude bases at the ends of reference
                                          sequences from calculation [default: 75]
+   --max-vaf-clusters <INT>              Maximum number of allele frequency modes fitted
+                                         to each sample. Each variant's vaf_cluster is
+                                         the mode it falls in. [default: 4]
    --trim-min FRACTION                   Remove this smallest fraction of positions
                                          when calculating trimmed_mean
                                          [default: 0.05]
@@ -178,6 +252,17 @@ Other arguments (optional):
    --no-zeros                            Omit printing of genomes that have zero
                                          coverage
    --discard-unmapped                    Exclude unmapped reads from cached BAM files.
+   --cache-cram                          Convert cached BAM files to CRAM files
+                                         encoded against --reference once finished.
+   --config <PATH>                       YAML file, or TOML file ending in .toml,
+                                         setting options by their long names.
+                                         Options on the command line take
+                                         precedence. Options for one subcommand
+                                         only can be put in a section named after
+                                         it. The resolved options, lorikeet
+                                         version and input file sizes, modification
+                                         times and checksums are written to
+                                         <output-prefix>_run_config.json
    -v, --verbose                         Print extra debugging information
    -q, --quiet                           Unless there is an error, do not print
                                          log messages
@@ -199,6 +284,22 @@ Define mapping(s) (required):
                                          unless --sharded is specified, in which
                                          case they must be read name sorted (e.g.
                                          with samtools sort -n).
+                                         CRAM files are also accepted, and are
+                                         decoded with --reference.
+
+  Or define a sample sheet:
+   --sample-sheet <PATH>                 Tab separated file of sample name, read
+                                         technology (short, ont or pacbio), comma
+                                         separated read files or a BAM/CRAM file,
+                                         and optional group and stratum. Outputs
+                                         use these sample names, and the short and
+                                         long read libraries of a sample are
+                                         merged into one sample. Long read files
+                                         are mapped with the map-ont or map-pb
+                                         preset of minimap2 into
+                                         --bam-file-cache-directory, and PacBio
+                                         reads are called with the CCS preset of
+                                         sniffles.
 
   Or do mapping:
    -r, --reference <PATH> ..             FASTA file of contigs or BWA index stem
@@ -216,13 +317,22 @@ Define mapping(s) (required):
                                          <sample2_R1.fq.gz> <sample2_R2.fq.gz> ..
    --interleaved <PATH> ..               Interleaved FASTA/Q files(s) for mapping.
    --single <PATH> ..                    Unpaired FASTA/Q files(s) for mapping.
-   --gff, -g <PATH>                      GFF3 file containing gene locations
-                                         present in reference genome.
-   --prodigal-params                     Paramaters passed onto prodigal to call
-                                         gene locations on reference genome.
-                                         -i and -f are already set. Only used if
-                                         not GFF file is not premade.
+   --gff, -g <PATH>                      GFF3, GTF, GenBank or EMBL file containing
+                                         gene locations present in reference genome.
+   --min-gene-length <INT>               Minimum length in bp of genes predicted
+                                         on the reference genome. Genes running
+                                         off the end of a contig are predicted as
+                                         partial genes. Only used if --gff is not
+                                         provided. [default: 90]
    -d, --outdir                          Output directory.
+   --result-cache <PATH>                 Directory of mapped reads and variant calls
+                                         to reuse across runs. Results are keyed by
+                                         the contents of the reference and reads or
+                                         BAM files along with the mapper and its
+                                         parameters, so are only reused for
+                                         identical inputs. Samples of mapped reads
+                                         keep their names when read back from the
+                                         cache.
 {}
    --minimap2-params PARAMS              Extra parameters to provide to minimap2,
                                          both indexing command (if used) and for
@@ -286,9 +396,13 @@ Other arguments (optional):
    -o, --output-prefix <STRING>          Output prefix for files. [default: output]
    -f, --min-variant-depth               Minimum depth threshold value a variant must occur at
                                          for it to be considered. [default: 10]
-   --output-format FORMAT                Shape of output: 'sparse' for long format,
-                                         'dense' for species-by-site.
-                                         [default: dense]
+   --output-format FORMAT                Format of the contig statistics and strain results:
+                                         'tsv' for tables, or 'json' for a single
+                                         schema-versioned <output-prefix>.json document
+                                         that also records the run parameters.
+                                         [default: tsv]
+   --html-report                         Also write a self-contained HTML report of the
+                                         run to <output-prefix>_report.html.
    --min-covered-fraction FRACTION       Contigs with less coverage than this
                                          reported as having zero coverage.
                                          [default: 0.0]
@@ -308,6 +422,17 @@ Other arguments (optional):
    --no-zeros                            Omit printing of genomes that have zero
                                          coverage
    --discard-unmapped                    Exclude unmapped reads from cached BAM files.
+   --cache-cram                          Convert cached BAM files to CRAM files
+                                         encoded against --reference once finished.
+   --config <PATH>                       YAML file, or TOML file ending in .toml,
+                                         setting options by their long names.
+                                         Options on the command line take
+                                         precedence. Options for one subcommand
+                                         only can be put in a section named after
+                                         it. The resolved options, lorikeet
+                                         version and input file sizes, modification
+                                         times and checksums are written to
+                                         <output-prefix>_run_config.json
    -v, --verbose                         Print extra debugging information
    -q, --quiet                           Unless there is an error, do not print
                                          log messages
@@ -330,6 +455,24 @@ Define mapping(s) (required):
                                          unless --sharded is specified, in which
                                          case they must be read name sorted (e.g.
                                          with samtools sort -n).
+                                         CRAM files are also accepted, and are
+                                         decoded with --reference.
+
+  Or define a sample sheet:
+   --sample-sheet <PATH>                 Tab separated file of sample name, read
+                                         technology (short, ont or pacbio), comma
+                                         separated read files or a BAM/CRAM file,
+                                         and optional group and stratum. Outputs
+                                         use these sample names, and the short and
+                                         long read libraries of a sample are
+                                         merged into one sample. Long read files
+                                         are mapped with the map-ont or map-pb
+                                         preset of minimap2 into
+                                         --bam-file-cache-directory, and PacBio
+                                         reads are called with the CCS preset of
+                                         sniffles.
+                                         Long read libraries must be BAM or CRAM
+                                         files.
 
   Or do mapping:
    -r, --reference <PATH> ..             FASTA file of contigs or BWA index stem
@@ -347,7 +490,19 @@ Define mapping(s) (required):
                                          <sample2_R1.fq.gz> <sample2_R2.fq.gz> ..
    --interleaved <PATH> ..               Interleaved FASTA/Q files(s) for mapping.
    --single <PATH> ..                    Unpaired FASTA/Q files(s) for mapping.
+   --gff, -g <PATH>                      GFF3, GTF, GenBank or EMBL file containing
+                                         gene locations present in reference genome.
+                                         Variants are annotated with their
+                                         consequence on overlapping genes.
    -d, --outdir                          Output directory
+   --result-cache <PATH>                 Directory of mapped reads and variant calls
+                                         to reuse across runs. Results are keyed by
+                                         the contents of the reference and reads or
+                                         BAM files along with the mapper and its
+                                         parameters, so are only reused for
+                                         identical inputs. Samples of mapped reads
+                                         keep their names when read back from the
+                                         cache.
 {}
    --minimap2-params PARAMS              Extra parameters to provide to minimap2,
                                          both indexing command (if used) and for
@@ -399,9 +554,13 @@ Other arguments (optional):
    -o, --output-prefix <STRING>          Output prefix for files. [default: output]
    -f, --min-variant-depth      Minimum depth threshold value a variant must occur at
                                          for it to be considered. [default: 10]
-   --output-format FORMAT                Shape of output: 'sparse' for long format,
-                                         'dense' for species-by-site.
-                                         [default: dense]
+   --output-format FORMAT                Format of the contig statistics and strain results:
+                                         'tsv' for tables, or 'json' for a single
+                                         schema-versioned <output-prefix>.json document
+                                         that also records the run parameters.
+                                         [default: tsv]
+   --html-report                         Also write a self-contained HTML report of the
+                                         run to <output-prefix>_report.html.
    --min-covered-fraction FRACTION       Contigs with less coverage than this
                                          reported as having zero coverage.
                                          [default: 0.0]
@@ -414,6 +573,54 @@ Other arguments (optional):
                                          [default: 0.5]
    --contig-end-exclusion                Exclude bases at the ends of reference
                                          sequences from calculation [default: 75]
+   --min-site-depth <INT>                Minimum read depth for a site to be included
+                                         in diversity statistics. Pairwise conANI,
+                                         popANI and FST between samples only use
+                                         sites passing this depth in both samples,
+                                         and alleles below it are masked with N in
+                                         the SNP alignment. [default: 5]
+   --min-breadth <FRACTION>              Minimum fraction of a contig, gene or window
+                                         passing --min-site-depth for diversity
+                                         statistics to be reported. [default: 0.5]
+   --window-size <INT>                   Size of the sliding windows diversity
+                                         statistics are reported for. [default: 1000]
+   --window-step <INT>                   Distance between the starts of consecutive
+                                         windows. [default: --window-size]
+   --min-linkage-reads <INT>             Minimum number of reads spanning a pair of
+                                         SNVs for linkage (r², D') between them to be
+                                         reported. Both reads of a pair count as
+                                         one read.
+                                         [default: 5]
+   --max-linkage-distance <INT>          Maximum distance between a pair of SNVs
+                                         for linkage to be calculated. [default: 5000]
+   --linkage-bin-size <INT>              Width of the distance bins used to summarise
+                                         linkage decay along each contig. [default: 100]
+   --sample-metadata <PATH>              Tab separated file of sample name, subject and
+                                         time point. When provided, the allele frequency
+                                         trajectory of each variant is reported for
+                                         each subject with more than one time point.
+                                         Sample names are matched exactly, or without
+                                         their directories and file extensions, and
+                                         every sample must be listed.
+   --sweep-frequency-change <FRACTION>   Minimum change in allele frequency across a
+                                         trajectory for a variant to be classed as
+                                         sweeping. [default: 0.5]
+   --min-gene-sweeps <INT>               Minimum number of sweeping or fixed variants
+                                         for a gene to be flagged as having clustered
+                                         sweeps. Requires --gff. [default: 3]
+   --sample-groups <PATH>                Tab separated file of sample name, group and
+                                         optional stratum. When provided, allele
+                                         frequencies are compared between each pair
+                                         of groups with a Cochran-Mantel-Haenszel
+                                         test. The reads of each group's samples
+                                         within a stratum are pooled into one table
+                                         of the test. Every sample must be listed.
+   --frequency-bins <INT>                Number of bins used for the allele frequency
+                                         histograms and site frequency spectra of each
+                                         sample. [default: 20]
+   --max-vaf-clusters <INT>              Maximum number of allele frequency modes fitted
+                                         to each sample when estimating the number of
+                                         co-existing strains. [default: 4]
    --trim-min FRACTION                   Remove this smallest fraction of positions
                                          when calculating trimmed_mean
                                          [default: 0.05]
@@ -423,6 +630,17 @@ Other arguments (optional):
    --no-zeros                            Omit printing of genomes that have zero
                                          coverage
    --discard-unmapped                    Exclude unmapped reads from cached BAM files.
+   --cache-cram                          Convert cached BAM files to CRAM files
+                                         encoded against --reference once finished.
+   --config <PATH>                       YAML file, or TOML file ending in .toml,
+                                         setting options by their long names.
+                                         Options on the command line take
+                                         precedence. Options for one subcommand
+                                         only can be put in a section named after
+                                         it. The resolved options, lorikeet
+                                         version and input file sizes, modification
+                                         times and checksums are written to
+                                         <output-prefix>_run_config.json
    -v, --verbose                         Print extra debugging information
    -q, --quiet                           Unless there is an error, do not print
                                          log messages
@@ -444,6 +662,24 @@ Define mapping(s) (required):
                                          unless --sharded is specified, in which
                                          case they must be read name sorted (e.g.
                                          with samtools sort -n).
+                                         CRAM files are also accepted, and are
+                                         decoded with --reference.
+
+  Or define a sample sheet:
+   --sample-sheet <PATH>                 Tab separated file of sample name, read
+                                         technology (short, ont or pacbio), comma
+                                         separated read files or a BAM/CRAM file,
+                                         and optional group and stratum. Outputs
+                                         use these sample names, and the short and
+                                         long read libraries of a sample are
+                                         merged into one sample. Long read files
+                                         are mapped with the map-ont or map-pb
+                                         preset of minimap2 into
+                                         --bam-file-cache-directory, and PacBio
+                                         reads are called with the CCS preset of
+                                         sniffles.
+                                         Long read libraries must be BAM or CRAM
+                                         files.
 
   Or do mapping:
    -r, --reference <PATH> ..             FASTA file of contigs or BWA index stem
@@ -461,7 +697,23 @@ Define mapping(s) (required):
                                          <sample2_R1.fq.gz> <sample2_R2.fq.gz> ..
    --interleaved <PATH> ..               Interleaved FASTA/Q files(s) for mapping.
    --single <PATH> ..                    Unpaired FASTA/Q files(s) for mapping.
+   --gff, -g <PATH>                      GFF3, GTF, GenBank or EMBL file containing
+                                         gene locations present in reference genome.
+                                         Variants are annotated with their
+                                         consequence on overlapping genes, and genes
+                                         are lifted onto each strain genome in
+                                         <output-prefix>_strain_<N>.gff, with genes
+                                         broken by frameshifts or premature stops
+                                         marked as pseudogenes.
    -d, --outdir                          Output directory
+   --result-cache <PATH>                 Directory of mapped reads and variant calls
+                                         to reuse across runs. Results are keyed by
+                                         the contents of the reference and reads or
+                                         BAM files along with the mapper and its
+                                         parameters, so are only reused for
+                                         identical inputs. Samples of mapped reads
+                                         keep their names when read back from the
+                                         cache.
 {}
    --minimap2-params PARAMS              Extra parameters to provide to minimap2,
                                          both indexing command (if used) and for
@@ -538,9 +790,13 @@ Other arguments (optional):
    --pts-max                             Maximum points as percentage in fuzzyDBSCAN algorithm.
                                          The fraction of points needed to be within e-max
                                          to begin border clustering.
-   --output-format FORMAT                Shape of output: 'sparse' for long format,
-                                         'dense' for species-by-site.
-                                         [default: dense]
+   --output-format FORMAT                Format of the contig statistics and strain results:
+                                         'tsv' for tables, or 'json' for a single
+                                         schema-versioned <output-prefix>.json document
+                                         that also records the run parameters.
+                                         [default: tsv]
+   --html-report                         Also write a self-contained HTML report of the
+                                         run to <output-prefix>_report.html.
    --min-covered-fraction FRACTION       Contigs with less coverage than this
                                          reported as having zero coverage.
                                          [default: 0.0]
@@ -551,6 +807,35 @@ Other arguments (optional):
                                          [default: 0.5]
    --contig-end-exclusion                Exclude bases at the ends of reference
                                          sequences from calculation [default: 75]
+   --min-site-depth <INT>                Minimum read depth for a sample's allele to
+                                         be included in the SNP alignment, otherwise
+                                         it is masked with N. [default: 5]
+   --assign-reads                        Assign each read to a strain by scoring it
+                                         against the alleles of each strain, and write
+                                         a copy of each BAM file to
+                                         <output-prefix>_<sample>_strains.bam with the
+                                         strain in the YS tag and its posterior
+                                         probability in the YP tag, or to
+                                         <output-prefix>_<sample>_long_strains.bam
+                                         for the long reads of a sample sheet sample
+                                         with short reads too. Reads covering no
+                                         site separating the strains are 'unassigned'.
+                                         Requires --bam-files or --longread-bam-files.
+   --assignment-error-rate <FLOAT>       Probability of a read showing a different
+                                         allele to its strain at a site. [default: 0.01]
+   --min-assignment-probability <FLOAT>  Minimum posterior probability for a read to
+                                         be assigned to a strain, otherwise it is
+                                         'ambiguous'. [default: 0.95]
+   --extract-strain-reads                Write the reads assigned to each strain, and
+                                         their mates, to FASTQ files pooled across
+                                         samples for reassembly. Requires
+                                         --assign-reads.
+   --ambiguous-reads <RULE>              Strains ambiguous reads are extracted for:
+                                         'best' for the most likely strain, 'all' for
+                                         every strain, or 'discard'. [default: discard]
+   --unassigned-reads <RULE>             Strains unassigned reads, which match the
+                                         reference and every strain, are extracted
+                                         for: 'all' or 'discard'. [default: all]
    --trim-min FRACTION                   Remove this smallest fraction of positions
                                          when calculating trimmed_mean
                                          [default: 0.05]
@@ -561,15 +846,167 @@ Other arguments (optional):
                                          coverage
 
    --discard-unmapped                    Exclude unmapped reads from cached BAM files.
+   --cache-cram                          Convert cached BAM files to CRAM files
+                                         encoded against --reference once finished.
+   --config <PATH>                       YAML file, or TOML file ending in .toml,
+                                         setting options by their long names.
+                                         Options on the command line take
+                                         precedence. Options for one subcommand
+                                         only can be put in a section named after
+                                         it. The resolved options, lorikeet
+                                         version and input file sizes, modification
+                                         times and checksums are written to
+                                         <output-prefix>_run_config.json
    -v, --verbose                         Print extra debugging information
    -q, --quiet                           Unless there is an error, do not print
                                          log messages
 
+  Each strain genome is written to <output-prefix>_strain_<N>.fna along with a
+  chain file, <output-prefix>_strain_<N>.chain, mapping reference coordinates
+  onto the strain's contigs. All strains are also combined into a GFA 1.0
+  variation graph, <output-prefix>_variation_graph.gfa, in which each reference
+  contig is split at variant sites, each allele is its own segment and each
+  contig and strain is a path. The graph can be viewed in Bandage or used as a
+  reference for graph mappers
+
+  With --assign-reads, the number of reads assigned to each strain in each sample
+  is written to <output-prefix>_read_assignments.tsv. With --extract-strain-reads,
+  paired reads of each strain are written to <output-prefix>_strain_<N>_R1.fastq
+  and <output-prefix>_strain_<N>_R2.fastq, unpaired reads and mates missing from
+  the BAM file to <output-prefix>_strain_<N>_single.fastq, and long reads to
+  <output-prefix>_strain_<N>_long.fastq
+
 Rhys J. P. Newell <r.newell near uq.edu.au>", MAPPER_HELP);
     }
     &GENOTYPE_HELP
 }
 
+pub fn polish_full_help() -> &'static str {
+    lazy_static! {
+        static ref POLISH_HELP: String = format!(
+    "lorikeet polish: Polish an assembly using variants pooled across samples
+
+Define mapping(s) (required):
+  Either define BAM:
+   -b, --bam-file <PATH> ..              Path to BAM file(s). These must be
+                                         reference sorted (e.g. with samtools sort)
+                                         unless --sharded is specified, in which
+                                         case they must be read name sorted (e.g.
+                                         with samtools sort -n).
+   -l, --longread-bam-files <PATH> ..    Path to BAM files(s) generated from longreads.
+                                         Must be reference sorted.
+                                         BAM and CRAM files given with -b and -l are
+                                         accepted, CRAM files being decoded with
+                                         --reference.
+
+  Or do mapping:
+   -r, --reference <PATH> ..             FASTA file of contigs to be polished
+   -t, --threads <INT>                   Number of threads for mapping / sorting
+   -1 <PATH> ..                          Forward FASTA/Q file(s) for mapping
+   -2 <PATH> ..                          Reverse FASTA/Q file(s) for mapping
+   -c, --coupled <PATH> <PATH> ..        One or more pairs of forward and reverse
+                                         FASTA/Q files for mapping in order
+                                         <sample1_R1.fq.gz> <sample1_R2.fq.gz>
+                                         <sample2_R1.fq.gz> <sample2_R2.fq.gz> ..
+   --interleaved <PATH> ..               Interleaved FASTA/Q files(s) for mapping.
+   --single <PATH> ..                    Unpaired FASTA/Q files(s) for mapping.
+   --result-cache <PATH>                 Directory of mapped reads and variant calls
+                                         to reuse across runs. Results are keyed by
+                                         the contents of the reference and reads or
+                                         BAM files along with the mapper and its
+                                         parameters, so are only reused for
+                                         identical inputs. Samples of mapped reads
+                                         keep their names when read back from the
+                                         cache.
+{}
+   --minimap2-params PARAMS              Extra parameters to provide to minimap2,
+                                         both indexing command (if used) and for
+                                         mapping. Note that usage of this parameter
+                                         has security implications if untrusted input
+                                         is specified. '-a' is always specified.
+                                         [default \"\"]
+   --bwa-params PARAMS                   Extra parameters to provide to BWA. Note
+                                         that usage of this parameter has security
+                                         implications if untrusted input is specified.
+                                         [default \"\"]
+
+Polishing (optional):
+   --min-polish-depth <INT>              Minimum short reads supporting an allele,
+                                         pooled across all samples, for it to replace
+                                         the reference. [default: 5]
+   --min-polish-frequency <FRACTION>     Minimum fraction of the pooled reads at a
+                                         site supporting an allele for it to replace
+                                         the reference. Only the best supported allele
+                                         at multi-allelic sites is considered.
+                                         [default: 0.5]
+   --mask-depth <INT>                    Replace bases covered by fewer pooled reads
+                                         than this with N.
+   --polish-rounds <INT>                 Maximum number of rounds of polishing. After
+                                         each round the reads are mapped to the
+                                         polished assembly and it is polished again,
+                                         stopping early once a round applies no edits.
+                                         Requires reads rather than BAM files when
+                                         greater than 1, so cannot be combined with
+                                         hybrid polishing. [default: 1]
+
+Hybrid polishing (optional):
+  When both short read and long read (-l) BAM files are given, alleles are called
+  separately from each technology and combined. Substitutions are taken from short
+  reads and insertions and deletions, including homopolymer corrections, from long
+  reads. As long reads are only taken as BAM files, which cannot be remapped to the
+  polished assembly, hybrid polishing runs a single round.
+   --min-long-polish-depth <INT>         Minimum long reads supporting an allele,
+                                         pooled across all samples, for it to replace
+                                         the reference. [default: 3]
+   --min-long-indel-length <INT>         Minimum length of insertions and deletions
+                                         called from long reads. [default: 1]
+   --polish-conflict <RULE>              How to resolve sites where short and long
+                                         reads disagree. One of:
+                                           trusted - Keep the allele from the technology
+                                                     trusted for that kind of edit
+                                           short   - Keep the short read allele
+                                           long    - Keep the long read allele
+                                           skip    - Leave the reference unchanged
+                                         [default: trusted]
+
+  The polished contigs are written to <reference>_polished.fna and every edit
+  made to the reference is written to <reference>_polish_edits.vcf, with a chain
+  file mapping reference coordinates onto the polished contigs written to
+  <reference>_polished.chain. When polishing over several rounds, the contigs,
+  edits and chain of each round are written with the prefix <reference>_round<N>,
+  relative to the previous round's contigs, and the number of edits applied in
+  each round to <reference>_polish_rounds.tsv
+
+Other arguments (optional):
+   -m, --method <METHOD>                 Method for calculating coverage.
+                                         [default: trimmed_mean]
+   -q, mapq-threshold <INT>              Mapping quality threshold used to verify
+                                         a variant. [default: 0]
+   -f, --min-variant-depth               Minimum depth threshold value a variant must occur at
+                                         for it to be considered. [default: 10]
+   --include-indels                      Flag indicating whether to attempt to calculate INDEL sites
+   --contig-end-exclusion                Exclude bases at the ends of reference
+                                         sequences from calculation [default: 75]
+   --cache-cram                          Convert cached BAM files to CRAM files
+                                         encoded against --reference once finished.
+   --config <PATH>                       YAML file, or TOML file ending in .toml,
+                                         setting options by their long names.
+                                         Options on the command line take
+                                         precedence. Options for one subcommand
+                                         only can be put in a section named after
+                                         it. The resolved options, lorikeet
+                                         version and input file sizes, modification
+                                         times and checksums are written to
+                                         <reference>_run_config.json
+   -v, --verbose                         Print extra debugging information
+   -q, --quiet                           Unless there is an error, do not print
+                                         log messages
+
+Rhys J. P. Newell <r.newell near uq.edu.au>", MAPPER_HELP);
+    }
+    &POLISH_HELP
+}
+
 pub fn build_cli() -> App<'static, 'static> {
     // specify _2 lazily because need to define it at runtime.
     lazy_static! {
@@ -642,6 +1079,10 @@ the unfiltered BAM files in the saved_bam_files folder:")
   lorikeet summarize --method metabat --bam-files my.bam --reference assembly.fna
     --bam-file-cache-directory saved_bam_files --threads 10
 
+{}
+
+  lorikeet summarize --sample-sheet samples.tsv --reference assembly.fna --threads 10
+
 See lorikeet summarize --full-help for further options and further detail.
 ",
             ansi_term::Colour::Green.paint(
@@ -653,6 +1094,8 @@ See lorikeet summarize --full-help for further options and further detail.
                 "Example: Map paired reads to a reference and generate contig stats across samples"),
             ansi_term::Colour::Purple.paint(
                 "Example: Summarizes contigs defined in reference from a sorted BAM file:"),
+            ansi_term::Colour::Purple.paint(
+                "Example: Summarizes contigs across the named samples of a sample sheet:"),
         ).to_string();
 
         static ref GENOTYPE_HELP: String = format!(
@@ -681,6 +1124,44 @@ See lorikeet genotype --full-help for further options and further detail.
                 "Example: Generate strain-level genotypes from read mappings compared to reference from a sorted BAM file:"),
         ).to_string();
 
+        static ref POLISH_HELP: String = format!(
+            "
+                            {}
+              {}
+
+{}
+
+  lorikeet polish --coupled read1.fastq.gz read2.fastq.gz --reference assembly.fna --threads 10
+
+{}
+
+  lorikeet polish --bam-file sample1.bam sample2.bam --reference assembly.fna
+    --min-polish-depth 10 --mask-depth 3
+
+{}
+
+  lorikeet polish --coupled read1.fastq.gz read2.fastq.gz --reference assembly.fna --polish-rounds 4
+
+{}
+
+  lorikeet polish --bam-file illumina.bam -l nanopore.bam --reference assembly.fna
+
+See lorikeet polish --full-help for further options and further detail.
+",
+            ansi_term::Colour::Green.paint(
+                "lorikeet polish"),
+            ansi_term::Colour::Green.paint(
+                "Polish an assembly using variants pooled across samples"),
+            ansi_term::Colour::Purple.paint(
+                "Example: Polish an assembly using reads mapped to it:"),
+            ansi_term::Colour::Purple.paint(
+                "Example: Polish an assembly from sorted BAM files, masking poorly covered bases:"),
+            ansi_term::Colour::Purple.paint(
+                "Example: Polish an assembly, remapping the reads after each round until no edits are made:"),
+            ansi_term::Colour::Purple.paint(
+                "Example: Polish an assembly using short read substitutions and long read indels:"),
+        ).to_string();
+
         static ref FILTER_HELP: String = format!(
             "
                             {}
@@ -696,6 +1177,16 @@ See lorikeet genotype --full-help for further options and further detail.
   lorikeet filter -b input.bam -o inverse_filtered.bam --inverse
     --min-read-percent-identity 0.95 --threads 16
 
+{}
+
+  lorikeet filter -b input.bam -o filtered.bam --min-mapq 20
+    --remove-duplicates --max-soft-clip-fraction 0.1
+
+{}
+
+  lorikeet filter -b input.cram -o filtered.cram -r assembly.fna --output-cram
+    --min-read-percent-identity 0.95
+
 See lorikeet filter --full-help for further options and further detail.
 ",
             ansi_term::Colour::Green.paint(
@@ -709,6 +1200,12 @@ See lorikeet filter --full-help for further options and further detail.
                  and those which do map at all. Note that the output BAM file will likely\n\
                  records that are still mapped, but align with < 95% identity. Use 16\n\
                  threads for output compression:"),
+            ansi_term::Colour::Purple.paint(
+                "Example: Remove duplicates and alignments with MAPQ below 20 or more than 10%\n\
+                 of bases soft clipped, reporting what each filter removed in\n\
+                 filtered.filter_stats.tsv:"),
+            ansi_term::Colour::Purple.paint(
+                "Example: Filter a CRAM file encoded against assembly.fna, writing a CRAM file:"),
         ).to_string();
     }
 
@@ -745,13 +1242,19 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                 .help(POLYMORPH_HELP.as_str())
                 .arg(Arg::with_name("full-help")
                     .long("full-help"))
+                .arg(Arg::with_name("config")
+                    .long("config")
+                    .takes_value(true))
+                .arg(Arg::with_name("sample-sheet")
+                    .long("sample-sheet")
+                    .takes_value(true))
                 .arg(Arg::with_name("bam-files")
                     .short("b")
                     .long("bam-files")
                     .multiple(true)
                     .takes_value(true)
                     .required_unless_one(
-                        &["read1","read2","coupled","interleaved","single","full-help"]))
+                        &["read1","read2","coupled","interleaved","single","sample-sheet","full-help"]))
                 .arg(Arg::with_name("sharded")
                     .long("sharded")
                     .required(false))
@@ -761,7 +1264,7 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .takes_value(true)
                     .requires("read2")
                     .required_unless_one(
-                        &["bam-files","coupled","interleaved","single","full-help"])
+                        &["bam-files","coupled","interleaved","single","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("read2")
                     .short("-2")
@@ -769,7 +1272,7 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .takes_value(true)
                     .requires("read1")
                     .required_unless_one(
-                        &["bam-files","coupled","interleaved","single","full-help"])
+                        &["bam-files","coupled","interleaved","single","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("coupled")
                     .short("-c")
@@ -777,22 +1280,26 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .multiple(true)
                     .takes_value(true)
                     .required_unless_one(
-                        &["bam-files","read1","interleaved","single","full-help"])
+                        &["bam-files","read1","interleaved","single","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("interleaved")
                     .long("interleaved")
                     .multiple(true)
                     .takes_value(true)
                     .required_unless_one(
-                        &["bam-files","read1","coupled","single","full-help"])
+                        &["bam-files","read1","coupled","single","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("single")
                     .long("single")
                     .multiple(true)
                     .takes_value(true)
                     .required_unless_one(
-                        &["bam-files","read1","coupled","interleaved","full-help"])
+                        &["bam-files","read1","coupled","interleaved","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
+                .arg(Arg::with_name("gff")
+                    .short("g")
+                    .long("gff")
+                    .takes_value(true))
                 .arg(Arg::with_name("reference")
                     .short("-r")
                     .long("reference")
@@ -804,6 +1311,9 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .long("bam-file-cache-directory")
                     .short("d")
                     .takes_value(true))
+                .arg(Arg::with_name("result-cache")
+                    .long("result-cache")
+                    .takes_value(true))
                 .arg(Arg::with_name("threads")
                     .short("-t")
                     .long("threads")
@@ -838,7 +1348,10 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                 )
                 .arg(Arg::with_name("discard-unmapped")
                     .long("discard-unmapped")
-                    .requires("bam-file-cache-directory"))
+                    .requires("outdir"))
+                .arg(Arg::with_name("cache-cram")
+                    .long("cache-cram")
+                    .requires("outdir"))
 
                 .arg(Arg::with_name("min-read-aligned-length")
                     .long("min-read-aligned-length")
@@ -866,6 +1379,9 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .long("output-prefix")
                     .short("o")
                     .default_value("output"))
+                .arg(Arg::with_name("max-vaf-clusters")
+                    .long("max-vaf-clusters")
+                    .default_value("4"))
                 .arg(Arg::with_name("method")
                     .short("m")
                     .long("method")
@@ -930,6 +1446,12 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                 .help(EVOLVE_HELP.as_str())
                 .arg(Arg::with_name("full-help")
                     .long("full-help"))
+                .arg(Arg::with_name("config")
+                    .long("config")
+                    .takes_value(true))
+                .arg(Arg::with_name("sample-sheet")
+                    .long("sample-sheet")
+                    .takes_value(true))
 
                 .arg(Arg::with_name("bam-files")
                     .short("b")
@@ -937,15 +1459,15 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .multiple(true)
                     .takes_value(true)
                     .required_unless_one(
-                        &["read1","read2","coupled","interleaved","single","full-help"]))
+                        &["read1","read2","coupled","interleaved","single","sample-sheet","full-help"]))
                 .arg(Arg::with_name("gff")
                     .short("g")
                     .long("gff")
                     .takes_value(true))
-                .arg(Arg::with_name("prodigal-params")
-                    .long("prodigal-params")
+                .arg(Arg::with_name("min-gene-length")
+                    .long("min-gene-length")
                     .takes_value(true)
-                    .conflicts_with("gff"))
+                    .default_value("90"))
                 .arg(Arg::with_name("sharded")
                     .long("sharded")
                     .required(false))
@@ -955,7 +1477,7 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .takes_value(true)
                     .requires("read2")
                     .required_unless_one(
-                        &["bam-files","coupled","interleaved","single","full-help"])
+                        &["bam-files","coupled","interleaved","single","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("read2")
                     .short("-2")
@@ -963,7 +1485,7 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .takes_value(true)
                     .requires("read1")
                     .required_unless_one(
-                        &["bam-files","coupled","interleaved","single","full-help"])
+                        &["bam-files","coupled","interleaved","single","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("coupled")
                     .short("-c")
@@ -971,21 +1493,21 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .multiple(true)
                     .takes_value(true)
                     .required_unless_one(
-                        &["bam-files","read1","interleaved","single","full-help"])
+                        &["bam-files","read1","interleaved","single","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("interleaved")
                     .long("interleaved")
                     .multiple(true)
                     .takes_value(true)
                     .required_unless_one(
-                        &["bam-files","read1","coupled","single","full-help"])
+                        &["bam-files","read1","coupled","single","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("single")
                     .long("single")
                     .multiple(true)
                     .takes_value(true)
                     .required_unless_one(
-                        &["bam-files","read1","coupled","interleaved","full-help"])
+                        &["bam-files","read1","coupled","interleaved","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("reference")
                     .short("-r")
@@ -996,6 +1518,9 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .long("bam-file-cache-directory")
                     .short("d")
                     .takes_value(true))
+                .arg(Arg::with_name("result-cache")
+                    .long("result-cache")
+                    .takes_value(true))
                 .arg(Arg::with_name("threads")
                     .short("t")
                     .long("threads")
@@ -1030,7 +1555,10 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                 )
                 .arg(Arg::with_name("discard-unmapped")
                     .long("discard-unmapped")
-                    .requires("bam-file-cache-directory"))
+                    .requires("outdir"))
+                .arg(Arg::with_name("cache-cram")
+                    .long("cache-cram")
+                    .requires("outdir"))
 
                 .arg(Arg::with_name("min-read-aligned-length")
                     .long("min-read-aligned-length")
@@ -1106,6 +1634,12 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .long("output-prefix")
                     .short("o")
                     .default_value("output"))
+                .arg(Arg::with_name("output-format")
+                    .long("output-format")
+                    .possible_values(&["tsv", "json"])
+                    .default_value("tsv"))
+                .arg(Arg::with_name("html-report")
+                    .long("html-report"))
                 .arg(Arg::with_name("verbose")
                     .short("v")
                     .long("verbose"))
@@ -1117,6 +1651,12 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                 .help(SUMMARIZE_HELP.as_str())
                 .arg(Arg::with_name("full-help")
                     .long("full-help"))
+                .arg(Arg::with_name("config")
+                    .long("config")
+                    .takes_value(true))
+                .arg(Arg::with_name("sample-sheet")
+                    .long("sample-sheet")
+                    .takes_value(true))
 
                 .arg(Arg::with_name("bam-files")
                     .short("b")
@@ -1124,7 +1664,7 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .multiple(true)
                     .takes_value(true)
                     .required_unless_one(
-                        &["read1","read2","coupled","interleaved","single","full-help"]))
+                        &["read1","read2","coupled","interleaved","single","sample-sheet","full-help"]))
                 .arg(Arg::with_name("sharded")
                     .long("sharded")
                     .required(false))
@@ -1134,7 +1674,7 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .takes_value(true)
                     .requires("read2")
                     .required_unless_one(
-                        &["bam-files","coupled","interleaved","single","full-help"])
+                        &["bam-files","coupled","interleaved","single","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("read2")
                     .short("-2")
@@ -1142,7 +1682,7 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .takes_value(true)
                     .requires("read1")
                     .required_unless_one(
-                        &["bam-files","coupled","interleaved","single","full-help"])
+                        &["bam-files","coupled","interleaved","single","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("coupled")
                     .short("-c")
@@ -1150,21 +1690,21 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .multiple(true)
                     .takes_value(true)
                     .required_unless_one(
-                        &["bam-files","read1","interleaved","single","full-help"])
+                        &["bam-files","read1","interleaved","single","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("interleaved")
                     .long("interleaved")
                     .multiple(true)
                     .takes_value(true)
                     .required_unless_one(
-                        &["bam-files","read1","coupled","single","full-help"])
+                        &["bam-files","read1","coupled","single","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("single")
                     .long("single")
                     .multiple(true)
                     .takes_value(true)
                     .required_unless_one(
-                        &["bam-files","read1","coupled","interleaved","full-help"])
+                        &["bam-files","read1","coupled","interleaved","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("longreads")
                     .long("longreads")
@@ -1178,6 +1718,10 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .takes_value(true)
                     .required(false)
                     .conflicts_with_all(&["longreads", "sharded"]))
+                .arg(Arg::with_name("gff")
+                    .short("g")
+                    .long("gff")
+                    .takes_value(true))
                 .arg(Arg::with_name("reference")
                     .short("-r")
                     .long("reference")
@@ -1188,6 +1732,9 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .long("bam-file-cache-directory")
                     .short("d")
                     .takes_value(true))
+                .arg(Arg::with_name("result-cache")
+                    .long("result-cache")
+                    .takes_value(true))
                 .arg(Arg::with_name("threads")
                     .short("-t")
                     .long("threads")
@@ -1222,7 +1769,10 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                 )
                 .arg(Arg::with_name("discard-unmapped")
                     .long("discard-unmapped")
-                    .requires("bam-file-cache-directory"))
+                    .requires("outdir"))
+                .arg(Arg::with_name("cache-cram")
+                    .long("cache-cram")
+                    .requires("outdir"))
 
                 .arg(Arg::with_name("min-read-aligned-length")
                     .long("min-read-aligned-length")
@@ -1283,6 +1833,45 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                 .arg(Arg::with_name("contig-end-exclusion")
                     .long("contig-end-exclusion")
                     .default_value("75"))
+                .arg(Arg::with_name("min-site-depth")
+                    .long("min-site-depth")
+                    .default_value("5"))
+                .arg(Arg::with_name("min-breadth")
+                    .long("min-breadth")
+                    .default_value("0.5"))
+                .arg(Arg::with_name("window-size")
+                    .long("window-size")
+                    .default_value("1000"))
+                .arg(Arg::with_name("window-step")
+                    .long("window-step")
+                    .takes_value(true))
+                .arg(Arg::with_name("min-linkage-reads")
+                    .long("min-linkage-reads")
+                    .default_value("5"))
+                .arg(Arg::with_name("max-linkage-distance")
+                    .long("max-linkage-distance")
+                    .default_value("5000"))
+                .arg(Arg::with_name("linkage-bin-size")
+                    .long("linkage-bin-size")
+                    .default_value("100"))
+                .arg(Arg::with_name("sample-metadata")
+                    .long("sample-metadata")
+                    .takes_value(true))
+                .arg(Arg::with_name("sweep-frequency-change")
+                    .long("sweep-frequency-change")
+                    .default_value("0.5"))
+                .arg(Arg::with_name("min-gene-sweeps")
+                    .long("min-gene-sweeps")
+                    .default_value("3"))
+                .arg(Arg::with_name("sample-groups")
+                    .long("sample-groups")
+                    .takes_value(true))
+                .arg(Arg::with_name("frequency-bins")
+                    .long("frequency-bins")
+                    .default_value("20"))
+                .arg(Arg::with_name("max-vaf-clusters")
+                    .long("max-vaf-clusters")
+                    .default_value("4"))
                 .arg(Arg::with_name("trim-min")
                     .long("trim-min")
                     .default_value("0.05"))
@@ -1305,6 +1894,12 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .long("output-prefix")
                     .short("o")
                     .default_value("output"))
+                .arg(Arg::with_name("output-format")
+                    .long("output-format")
+                    .possible_values(&["tsv", "json"])
+                    .default_value("tsv"))
+                .arg(Arg::with_name("html-report")
+                    .long("html-report"))
                 .arg(Arg::with_name("verbose")
                     .short("v")
                     .long("verbose"))
@@ -1316,6 +1911,12 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                 .help(GENOTYPE_HELP.as_str())
                 .arg(Arg::with_name("full-help")
                     .long("full-help"))
+                .arg(Arg::with_name("config")
+                    .long("config")
+                    .takes_value(true))
+                .arg(Arg::with_name("sample-sheet")
+                    .long("sample-sheet")
+                    .takes_value(true))
 
                 .arg(Arg::with_name("bam-files")
                     .short("b")
@@ -1323,7 +1924,7 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .multiple(true)
                     .takes_value(true)
                     .required_unless_one(
-                        &["read1","read2","coupled","interleaved","single","full-help"]))
+                        &["read1","read2","coupled","interleaved","single","sample-sheet","full-help"]))
                 .arg(Arg::with_name("sharded")
                     .long("sharded")
                     .required(false))
@@ -1333,7 +1934,7 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .takes_value(true)
                     .requires("read2")
                     .required_unless_one(
-                        &["bam-files","coupled","interleaved","single","full-help"])
+                        &["bam-files","coupled","interleaved","single","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("read2")
                     .short("-2")
@@ -1341,7 +1942,7 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .takes_value(true)
                     .requires("read1")
                     .required_unless_one(
-                        &["bam-files","coupled","interleaved","single","full-help"])
+                        &["bam-files","coupled","interleaved","single","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("coupled")
                     .short("-c")
@@ -1349,21 +1950,21 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .multiple(true)
                     .takes_value(true)
                     .required_unless_one(
-                        &["bam-files","read1","interleaved","single","full-help"])
+                        &["bam-files","read1","interleaved","single","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("interleaved")
                     .long("interleaved")
                     .multiple(true)
                     .takes_value(true)
                     .required_unless_one(
-                        &["bam-files","read1","coupled","single","full-help"])
+                        &["bam-files","read1","coupled","single","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("single")
                     .long("single")
                     .multiple(true)
                     .takes_value(true)
                     .required_unless_one(
-                        &["bam-files","read1","coupled","interleaved","full-help"])
+                        &["bam-files","read1","coupled","interleaved","sample-sheet","full-help"])
                     .conflicts_with("bam-files"))
                 .arg(Arg::with_name("longreads")
                     .long("longreads")
@@ -1377,6 +1978,10 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .takes_value(true)
                     .required(false)
                     .conflicts_with_all(&["longreads", "sharded"]))
+                .arg(Arg::with_name("gff")
+                    .short("g")
+                    .long("gff")
+                    .takes_value(true))
                 .arg(Arg::with_name("reference")
                     .short("-r")
                     .long("reference")
@@ -1387,6 +1992,9 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .long("bam-file-cache-directory")
                     .short("d")
                     .takes_value(true))
+                .arg(Arg::with_name("result-cache")
+                    .long("result-cache")
+                    .takes_value(true))
                 .arg(Arg::with_name("vcfs")
                     .long("vcfs")
                     .multiple(true)
@@ -1425,7 +2033,10 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                 )
                 .arg(Arg::with_name("discard-unmapped")
                     .long("discard-unmapped")
-                    .requires("bam-file-cache-directory"))
+                    .requires("outdir"))
+                .arg(Arg::with_name("cache-cram")
+                    .long("cache-cram")
+                    .requires("outdir"))
 
                 .arg(Arg::with_name("min-read-aligned-length")
                     .long("min-read-aligned-length")
@@ -1498,6 +2109,28 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                 .arg(Arg::with_name("contig-end-exclusion")
                     .long("contig-end-exclusion")
                     .default_value("75"))
+                .arg(Arg::with_name("min-site-depth")
+                    .long("min-site-depth")
+                    .default_value("5"))
+                .arg(Arg::with_name("assign-reads")
+                    .long("assign-reads"))
+                .arg(Arg::with_name("assignment-error-rate")
+                    .long("assignment-error-rate")
+                    .default_value("0.01"))
+                .arg(Arg::with_name("min-assignment-probability")
+                    .long("min-assignment-probability")
+                    .default_value("0.95"))
+                .arg(Arg::with_name("extract-strain-reads")
+                    .long("extract-strain-reads")
+                    .requires("assign-reads"))
+                .arg(Arg::with_name("ambiguous-reads")
+                    .long("ambiguous-reads")
+                    .possible_values(&["best", "all", "discard"])
+                    .default_value("discard"))
+                .arg(Arg::with_name("unassigned-reads")
+                    .long("unassigned-reads")
+                    .possible_values(&["all", "discard"])
+                    .default_value("all"))
                 .arg(Arg::with_name("trim-min")
                     .long("trim-min")
                     .default_value("0.05"))
@@ -1522,6 +2155,12 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .long("output-prefix")
                     .short("o")
                     .default_value("output"))
+                .arg(Arg::with_name("output-format")
+                    .long("output-format")
+                    .possible_values(&["tsv", "json"])
+                    .default_value("tsv"))
+                .arg(Arg::with_name("html-report")
+                    .long("html-report"))
                 .arg(Arg::with_name("verbose")
                     .short("v")
                     .long("verbose"))
@@ -1543,11 +2182,14 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .long("verbose")))
         .subcommand(
             SubCommand::with_name("filter")
-                .about("Remove alignments with insufficient identity")
+                .about("Remove alignments with insufficient identity or quality")
                 .help(FILTER_HELP.as_str())
 
                 .arg(Arg::with_name("full-help")
                     .long("full-help"))
+                .arg(Arg::with_name("config")
+                    .long("config")
+                    .takes_value(true))
 
                 .arg(Arg::with_name("bam-files")
                     .short("b")
@@ -1563,6 +2205,13 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .required_unless_one(&["full-help"]))
                 .arg(Arg::with_name("inverse")
                     .long("inverse"))
+                .arg(Arg::with_name("reference")
+                    .short("r")
+                    .long("reference")
+                    .takes_value(true))
+                .arg(Arg::with_name("output-cram")
+                    .long("output-cram")
+                    .requires("reference"))
 
                 .arg(Arg::with_name("min-read-aligned-length")
                     .long("min-read-aligned-length")
@@ -1589,6 +2238,23 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
 
                 .arg(Arg::with_name("allow-improper-pairs")
                     .long("allow-improper-pairs"))
+                .arg(Arg::with_name("min-mapq")
+                    .long("min-mapq")
+                    .default_value("0"))
+                .arg(Arg::with_name("max-edit-distance")
+                    .long("max-edit-distance")
+                    .takes_value(true))
+                .arg(Arg::with_name("max-soft-clip-fraction")
+                    .long("max-soft-clip-fraction")
+                    .takes_value(true))
+                .arg(Arg::with_name("remove-duplicates")
+                    .long("remove-duplicates"))
+                .arg(Arg::with_name("min-trimmed-length")
+                    .long("min-trimmed-length")
+                    .default_value("0"))
+                .arg(Arg::with_name("trim-quality")
+                    .long("trim-quality")
+                    .default_value("20"))
                 .arg(Arg::with_name("threads")
                     .long("threads")
                     .short("t")
@@ -1604,9 +2270,12 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
         .subcommand(
             SubCommand::with_name("polish")
                 .about("Polish an assembly using highly abundant variant calls")
-                .help(POLYMORPH_HELP.as_str())
+                .help(POLISH_HELP.as_str())
                 .arg(Arg::with_name("full-help")
                     .long("full-help"))
+                .arg(Arg::with_name("config")
+                    .long("config")
+                    .takes_value(true))
                 .arg(Arg::with_name("bam-file")
                     .short("b")
                     .long("bam-file")
@@ -1677,6 +2346,9 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                     .long("bam-file-cache-directory")
                     .short("d")
                     .takes_value(true))
+                .arg(Arg::with_name("result-cache")
+                    .long("result-cache")
+                    .takes_value(true))
                 .arg(Arg::with_name("threads")
                     .short("-t")
                     .long("threads")
@@ -1711,7 +2383,10 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                 )
                 .arg(Arg::with_name("discard-unmapped")
                     .long("discard-unmapped")
-                    .requires("bam-file-cache-directory"))
+                    .requires("outdir"))
+                .arg(Arg::with_name("cache-cram")
+                    .long("cache-cram")
+                    .requires("outdir"))
 
                 .arg(Arg::with_name("min-read-aligned-length")
                     .long("min-read-aligned-length")
@@ -1764,6 +2439,28 @@ Rhys J. P. Newell <r.newell near uq.edu.au>
                 .arg(Arg::with_name("contig-end-exclusion")
                     .long("contig-end-exclusion")
                     .default_value("75"))
+                .arg(Arg::with_name("min-polish-depth")
+                    .long("min-polish-depth")
+                    .default_value("5"))
+                .arg(Arg::with_name("min-polish-frequency")
+                    .long("min-polish-frequency")
+                    .default_value("0.5"))
+                .arg(Arg::with_name("mask-depth")
+                    .long("mask-depth")
+                    .takes_value(true))
+                .arg(Arg::with_name("polish-rounds")
+                    .long("polish-rounds")
+                    .default_value("1"))
+                .arg(Arg::with_name("min-long-polish-depth")
+                    .long("min-long-polish-depth")
+                    .default_value("3"))
+                .arg(Arg::with_name("min-long-indel-length")
+                    .long("min-long-indel-length")
+                    .default_value("1"))
+                .arg(Arg::with_name("polish-conflict")
+                    .long("polish-conflict")
+                    .possible_values(&["trusted", "short", "long", "skip"])
+                    .default_value("trusted"))
                 .arg(Arg::with_name("trim-min")
                     .long("trim-min")
                     .default_value("0.05"))
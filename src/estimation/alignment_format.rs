@@ -0,0 +1,344 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+use coverm::bam_generator::{NamedBamReader, NamedBamReaderGenerator};
+use coverm::filter::ReferenceSortedBamFilter;
+use coverm::FlagFilter;
+use md5;
+use rust_htslib::bam;
+use rust_htslib::bam::Read as BamRead;
+use rust_htslib::errors::Result as HtslibResult;
+
+/// File format of a set of alignments
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlignmentFormat {
+    Bam,
+    /// Reference-compressed, so a reference is needed to read and write it
+    Cram,
+}
+
+impl AlignmentFormat {
+    /// Format of an alignment file, judged by its extension
+    pub fn from_path(path: &str) -> AlignmentFormat {
+        match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("cram") => AlignmentFormat::Cram,
+            _ => AlignmentFormat::Bam,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AlignmentFormat::Bam => "bam",
+            AlignmentFormat::Cram => "cram",
+        }
+    }
+
+    pub fn htslib_format(&self) -> bam::Format {
+        match self {
+            AlignmentFormat::Bam => bam::Format::BAM,
+            AlignmentFormat::Cram => bam::Format::CRAM,
+        }
+    }
+}
+
+/// Opens a BAM or CRAM file. CRAM files are decoded against reference, which must be given
+pub fn open_reader(path: &str, reference: Option<&str>) -> bam::Reader {
+    let mut reader = bam::Reader::from_path(path)
+        .expect(&format!("Unable to find alignment file {}", path));
+    if AlignmentFormat::from_path(path) == AlignmentFormat::Cram {
+        let reference = reference
+            .expect(&format!("A reference is required to read CRAM file {}", path));
+        reader.set_reference(reference)
+            .expect(&format!("Unable to use reference {} for CRAM file {}", reference, path));
+    }
+    reader
+}
+
+/// Creates an alignment file of the given format. CRAM files are encoded against reference,
+/// which must be given
+pub fn create_writer(path: &str, header: &bam::header::Header, format: AlignmentFormat,
+                     reference: Option<&str>) -> bam::Writer {
+    let mut writer = bam::Writer::from_path(path, header, format.htslib_format())
+        .expect(&format!("Failed to write alignment file {}", path));
+    if format == AlignmentFormat::Cram {
+        let reference = reference
+            .expect(&format!("A reference is required to write CRAM file {}", path));
+        writer.set_reference(reference)
+            .expect(&format!("Unable to use reference {} for CRAM file {}", reference, path));
+    }
+    writer
+}
+
+/// Shell command sorting an alignment file into a BAM file, as needed by pilon and sniffles.
+/// CRAM files are decoded against reference
+pub fn sort_to_bam_command(input: &str, output: &str, reference: &str, threads: usize) -> String {
+    let reference_option = match AlignmentFormat::from_path(input) {
+        AlignmentFormat::Cram => format!("--reference '{}' ", reference),
+        AlignmentFormat::Bam => "".to_string(),
+    };
+    format!("samtools sort -O BAM -@ {} {}-o '{}' '{}'",
+            threads, reference_option, output, input)
+}
+
+/// Shell command converting a BAM file into a CRAM file encoded against reference
+pub fn bam_to_cram_command(input: &str, output: &str, reference: &str, threads: usize) -> String {
+    format!("samtools view -C -@ {} -T '{}' -o '{}' '{}'", threads, reference, output, input)
+}
+
+/// Hex encoded MD5 digest, as used by the M5 tag of SAM headers
+pub fn md5_hex(data: &[u8]) -> String {
+    format!("{:x}", md5::compute(data))
+}
+
+/// Hex encoded MD5 digest of a file's contents, read in blocks
+pub fn md5_file(path: &str) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut digest = md5::Context::new();
+    let mut block = vec![0; 1 << 20];
+    loop {
+        let read = file.read(&mut block)?;
+        if read == 0 {
+            break
+        }
+        digest.consume(&block[..read]);
+    }
+    Ok(format!("{:x}", digest.compute()))
+}
+
+lazy_static! {
+    /// Sample names of alignment files standing in for the reads mapped into them, by path,
+    /// so that their samples keep the names of the reads
+    static ref STOIT_NAMES: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+}
+
+/// Names the samples of an alignment file after the reads mapped into it rather than after
+/// the file
+pub fn set_stoit_name(path: &str, name: &str) {
+    STOIT_NAMES.lock().unwrap().insert(path.to_string(), name.to_string());
+}
+
+/// Sample name of an alignment file, which is its file name without the extension as for
+/// coverm's readers, unless named after its reads with set_stoit_name
+pub fn stoit_name(path: &str) -> String {
+    if let Some(name) = STOIT_NAMES.lock().unwrap().get(path) {
+        return name.clone()
+    }
+    Path::new(path).file_stem().unwrap().to_str()
+        .expect("failure to convert alignment file name to stoit name - UTF8 error maybe?")
+        .to_string()
+}
+
+/// Reader of a BAM or CRAM file, in place of coverm's BamFileNamedReader which cannot be
+/// given a reference. CRAM files are decoded against the reference set on their htslib reader
+pub struct AlignmentFileNamedReader {
+    stoit_name: String,
+    reader: bam::Reader,
+    num_detected_primary_alignments: u64,
+}
+
+impl NamedBamReader for AlignmentFileNamedReader {
+    fn name(&self) -> &str {
+        &self.stoit_name
+    }
+
+    fn read(&mut self, record: &mut bam::record::Record) -> HtslibResult<bool> {
+        let result = self.reader.read(record);
+        if let Ok(true) = result {
+            if !record.is_secondary() && !record.is_supplementary() {
+                self.num_detected_primary_alignments += 1;
+            }
+        }
+        result
+    }
+
+    fn header(&self) -> &bam::HeaderView {
+        self.reader.header()
+    }
+
+    fn finish(self) {}
+
+    fn set_threads(&mut self, n_threads: usize) {
+        if n_threads > 1 {
+            self.reader.set_threads(n_threads - 1).unwrap();
+        }
+    }
+
+    fn num_detected_primary_alignments(&self) -> u64 {
+        self.num_detected_primary_alignments
+    }
+}
+
+impl NamedBamReaderGenerator<AlignmentFileNamedReader> for AlignmentFileNamedReader {
+    fn start(self) -> AlignmentFileNamedReader {
+        self
+    }
+}
+
+/// Readers of BAM or CRAM files, named after the files. CRAM files are decoded against
+/// reference, which must be given when any are CRAM files
+pub fn generate_named_alignment_readers(paths: Vec<&str>, reference: Option<&str>)
+                                        -> Vec<AlignmentFileNamedReader> {
+    paths.iter().map(|path| AlignmentFileNamedReader {
+        stoit_name: stoit_name(path),
+        reader: open_reader(path, reference),
+        num_detected_primary_alignments: 0,
+    }).collect()
+}
+
+/// Reader of a BAM or CRAM file passing alignments through coverm's identity and aligned
+/// length thresholds, in place of coverm's FilteredBamReader
+pub struct FilteredAlignmentFileReader {
+    stoit_name: String,
+    filtered_stream: ReferenceSortedBamFilter,
+}
+
+impl NamedBamReader for FilteredAlignmentFileReader {
+    fn name(&self) -> &str {
+        &self.stoit_name
+    }
+
+    fn read(&mut self, record: &mut bam::record::Record) -> HtslibResult<bool> {
+        // The filter reports the end of the file as an error, as in the filter subcommand
+        Ok(self.filtered_stream.read(record).is_ok())
+    }
+
+    fn header(&self) -> &bam::HeaderView {
+        self.filtered_stream.reader.header()
+    }
+
+    fn finish(self) {}
+
+    fn set_threads(&mut self, n_threads: usize) {
+        if n_threads > 1 {
+            self.filtered_stream.reader.set_threads(n_threads - 1).unwrap();
+        }
+    }
+
+    fn num_detected_primary_alignments(&self) -> u64 {
+        self.filtered_stream.num_detected_primary_alignments
+    }
+}
+
+impl NamedBamReaderGenerator<FilteredAlignmentFileReader> for FilteredAlignmentFileReader {
+    fn start(self) -> FilteredAlignmentFileReader {
+        self
+    }
+}
+
+/// Filtered readers of BAM or CRAM files, named after the files. CRAM files are decoded
+/// against reference, which must be given when any are CRAM files
+pub fn generate_filtered_alignment_readers(
+    paths: Vec<&str>,
+    reference: Option<&str>,
+    flag_filters: FlagFilter,
+    min_aligned_length_single: u32,
+    min_percent_identity_single: f32,
+    min_aligned_percent_single: f32,
+    min_aligned_length_pair: u32,
+    min_percent_identity_pair: f32,
+    min_aligned_percent_pair: f32) -> Vec<FilteredAlignmentFileReader> {
+    paths.iter().map(|path| FilteredAlignmentFileReader {
+        stoit_name: stoit_name(path),
+        filtered_stream: ReferenceSortedBamFilter::new(
+            open_reader(path, reference),
+            flag_filters.clone(),
+            min_aligned_length_single,
+            min_percent_identity_single,
+            min_aligned_percent_single,
+            min_aligned_length_pair,
+            min_percent_identity_pair,
+            min_aligned_percent_pair,
+            true),
+    }).collect()
+}
+
+/// BAM files cached for reference in cache_directory, which are named
+/// <reference file name>.<read1 file name>.bam
+pub fn cached_bam_files(cache_directory: &str, reference: &str) -> Vec<PathBuf> {
+    let stem = Path::new(reference).file_name()
+        .expect("Unable to convert reference to file name").to_string_lossy().to_string() + ".";
+    let mut bam_files: Vec<PathBuf> = std::fs::read_dir(cache_directory)
+        .expect("Unable to read cache directory")
+        .map(|entry| entry.expect("Unable to read cache directory").path())
+        .filter(|path| {
+            let file_name = path.file_name().unwrap().to_string_lossy();
+            file_name.starts_with(&stem) && file_name.ends_with(".bam")
+        })
+        .collect();
+    bam_files.sort();
+    bam_files
+}
+
+/// Replaces the BAM files cached for each reference with CRAM files encoded against it.
+/// Requires samtools
+pub fn convert_cached_bams_to_cram(cache_directory: &str, references: &[&str], threads: usize) {
+    for reference in references.iter() {
+        for bam_path in cached_bam_files(cache_directory, reference) {
+            let cram_path = bam_path.with_extension("cram");
+            let cmd_string = format!(
+                "set -e -o pipefail; {}",
+                bam_to_cram_command(bam_path.to_str().unwrap(), cram_path.to_str().unwrap(),
+                                    reference, threads));
+            debug!("Queuing cmd_string: {}", cmd_string);
+            let status = Command::new("bash")
+                .arg("-c")
+                .arg(&cmd_string)
+                .status()
+                .expect("Unable to execute bash");
+            if !status.success() {
+                panic!("Failed to convert cached BAM file {:?} to CRAM", bam_path);
+            }
+            std::fs::remove_file(&bam_path).expect("Unable to remove cached BAM file");
+            info!("Converted cached BAM file {:?} to {:?}", bam_path, cram_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_alignment_format() {
+        assert_eq!(AlignmentFormat::from_path("sample.cram"), AlignmentFormat::Cram);
+        assert_eq!(AlignmentFormat::from_path("dir.cram/sample.CRAM"), AlignmentFormat::Cram);
+        assert_eq!(AlignmentFormat::from_path("sample.bam"), AlignmentFormat::Bam);
+        assert_eq!(AlignmentFormat::from_path("sample"), AlignmentFormat::Bam);
+        assert_eq!(sort_to_bam_command("in.cram", "out.bam", "ref.fna", 2),
+                   "samtools sort -O BAM -@ 2 --reference 'ref.fna' -o 'out.bam' 'in.cram'");
+        assert_eq!(sort_to_bam_command("in.bam", "in.bam", "ref.fna", 2),
+                   "samtools sort -O BAM -@ 2 -o 'in.bam' 'in.bam'");
+    }
+
+    #[test]
+    fn test_md5_hex() {
+        assert_eq!(md5_hex(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(md5_hex(b"abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(md5_hex(b"The quick brown fox jumps over the lazy dog"),
+                   "9e107d9d372bb6826bd81d3542a419d6");
+    }
+
+    #[test]
+    fn test_convert_cached_bams_to_cram() {
+        use tempfile;
+
+        let cache_directory = tempfile::tempdir().unwrap();
+        let cache = cache_directory.path().to_str().unwrap();
+        let cached_bam = cache_directory.path().join("7seqs.fna.reads_for_seq1_and_seq2.1.fq.bam");
+        std::fs::copy("tests/data/7seqs.reads_for_seq1_and_seq2.bam", &cached_bam).unwrap();
+        let other_bam = cache_directory.path().join("genome1.fna.reads.1.fq.bam");
+        std::fs::copy("tests/data/7seqs.reads_for_seq1_and_seq2.bam", &other_bam).unwrap();
+        assert_eq!(cached_bam_files(cache, "tests/data/7seqs.fna"), vec![cached_bam.clone()]);
+
+        convert_cached_bams_to_cram(cache, &["tests/data/7seqs.fna"], 1);
+        assert!(!cached_bam.exists());
+        assert!(other_bam.exists());
+        let cram = cached_bam.with_extension("cram");
+        let mut reader = open_reader(cram.to_str().unwrap(), Some("tests/data/7seqs.fna"));
+        let mut bam_reader = open_reader("tests/data/7seqs.reads_for_seq1_and_seq2.bam", None);
+        assert_eq!(reader.records().count(), bam_reader.records().count());
+    }
+}
@@ -0,0 +1,661 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use bio::io::gff;
+use bio::io::gff::Record;
+use bio_types::strand;
+use estimation::variant_annotation::gene_id;
+
+/// Supported gene annotation file formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnotationFormat {
+    GFF3,
+    GTF,
+    GenBank,
+    EMBL,
+}
+
+impl AnnotationFormat {
+    /// Guess the format of an annotation file from its extension, falling back to
+    /// the first lines of the file when the extension is not recognised
+    pub fn detect(path: &str) -> AnnotationFormat {
+        let lower = path.to_lowercase();
+        if lower.ends_with(".gtf") {
+            return AnnotationFormat::GTF
+        } else if lower.ends_with(".gb") || lower.ends_with(".gbk")
+            || lower.ends_with(".gbff") || lower.ends_with(".genbank") {
+            return AnnotationFormat::GenBank
+        } else if lower.ends_with(".embl") || lower.ends_with(".emb") {
+            return AnnotationFormat::EMBL
+        } else if lower.ends_with(".gff") || lower.ends_with(".gff3") {
+            return AnnotationFormat::GFF3
+        }
+
+        let file = File::open(path)
+            .expect(&format!("Unable to open annotation file {}", path));
+        for line in BufReader::new(file).lines().take(100) {
+            let line = line.expect("Unable to read annotation file");
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue
+            }
+            if line.starts_with("LOCUS") {
+                return AnnotationFormat::GenBank
+            } else if line.starts_with("ID   ") {
+                return AnnotationFormat::EMBL
+            } else if line.contains("gene_id \"") || line.contains("transcript_id \"") {
+                return AnnotationFormat::GTF
+            } else {
+                return AnnotationFormat::GFF3
+            }
+        }
+        AnnotationFormat::GFF3
+    }
+}
+
+/// A coding feature that could not be used and the reason why
+#[derive(Debug, Clone)]
+pub struct SkippedFeature {
+    pub seqname: String,
+    pub id: String,
+    pub reason: String,
+}
+
+/// Coding sequences loaded from an annotation file. Multi-segment CDS and CDS spanning
+/// the origin of a circular contig are represented by one record per segment, sharing
+/// the same ID and each carrying the phase of its first complete codon
+pub struct Annotations {
+    pub genes: HashMap<String, Vec<Record>>,
+    pub skipped: Vec<SkippedFeature>,
+    pub non_coding: usize,
+}
+
+impl Annotations {
+    fn new() -> Annotations {
+        Annotations {
+            genes: HashMap::new(),
+            skipped: Vec::new(),
+            non_coding: 0,
+        }
+    }
+
+    /// Log the number of coding sequences loaded and any that had to be skipped
+    pub fn report(&self) {
+        info!("Loaded {} coding sequence segments across {} contigs, ignored {} non-coding features",
+              self.genes.values().map(|genes| genes.len()).sum::<usize>(),
+              self.genes.len(), self.non_coding);
+        if self.skipped.len() > 0 {
+            warn!("Skipped {} coding features that could not be used", self.skipped.len());
+            for skipped in self.skipped.iter() {
+                warn!("Skipped CDS {} on {}: {}", skipped.id, skipped.seqname, skipped.reason);
+            }
+        }
+    }
+}
+
+/// A segment of a coding feature: 1-based inclusive start and end, and the phase
+/// if the annotation provided one
+type Segment = (u64, u64, Option<usize>);
+
+/// A coding feature with its segments listed in transcription order
+struct CodingFeature {
+    seqname: String,
+    source: String,
+    segments: Vec<Segment>,
+    reverse: bool,
+    attributes: Vec<(String, String)>,
+}
+
+/// Load the coding sequences of an annotation file in any supported format.
+/// contig_lengths is used to validate features and to split CDS spanning the origin of
+/// circular contigs. If it is empty, features are not checked against the reference
+pub fn load_annotations(path: &str, contig_lengths: &HashMap<String, u64>) -> Annotations {
+    let format = AnnotationFormat::detect(path);
+    info!("Reading {:?} annotations from {}", format, path);
+    let file = File::open(path)
+        .expect(&format!("Unable to open annotation file {}", path));
+    parse_annotations(file, format, contig_lengths)
+}
+
+/// Parse coding sequences from a reader containing annotations in the given format
+pub fn parse_annotations<R: Read>(reader: R, format: AnnotationFormat,
+                                  contig_lengths: &HashMap<String, u64>) -> Annotations {
+    let mut annotations = Annotations::new();
+    let mut lengths = contig_lengths.clone();
+    let features = match format {
+        AnnotationFormat::GFF3 | AnnotationFormat::GTF => {
+            read_gff(reader, format, &mut annotations)
+        },
+        AnnotationFormat::GenBank | AnnotationFormat::EMBL => {
+            read_flat_file(reader, format == AnnotationFormat::EMBL,
+                           &mut lengths, &mut annotations)
+        },
+    };
+
+    for feature in features {
+        if contig_lengths.len() > 0 && !contig_lengths.contains_key(&feature.seqname) {
+            annotations.skipped.push(SkippedFeature {
+                seqname: feature.seqname.clone(),
+                id: feature_id(&feature),
+                reason: "contig not found in reference".to_string(),
+            });
+            continue
+        }
+        match feature_records(&feature, lengths.get(&feature.seqname).cloned()) {
+            Ok(records) => {
+                annotations.genes.entry(feature.seqname.clone())
+                    .or_insert(Vec::new())
+                    .extend(records);
+            },
+            Err(reason) => {
+                annotations.skipped.push(SkippedFeature {
+                    seqname: feature.seqname.clone(),
+                    id: feature_id(&feature),
+                    reason,
+                });
+            }
+        }
+    }
+    annotations
+}
+
+/// Read CDS features from GFF3 or GTF, grouping lines that belong to the same
+/// coding sequence by ID, transcript or parent
+fn read_gff<R: Read>(mut reader: R, format: AnnotationFormat,
+                     annotations: &mut Annotations) -> Vec<CodingFeature> {
+    // GFF3 files written by prokka and others may append the sequences after a ##FASTA
+    // directive, which the GFF reader cannot parse
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents).expect("Unable to read annotation file");
+    if let Some(fasta_start) = contents.find("##FASTA") {
+        contents.truncate(fasta_start);
+    }
+
+    let gff_type = match format {
+        AnnotationFormat::GTF => gff::GffType::GTF2,
+        _ => gff::GffType::GFF3,
+    };
+    let group_keys: &[&str] = match format {
+        AnnotationFormat::GTF => &["transcript_id", "gene_id"],
+        _ => &["ID", "Parent", "locus_tag"],
+    };
+
+    let mut gff_reader = gff::Reader::new(contents.as_bytes(), gff_type);
+    let mut features: Vec<CodingFeature> = Vec::new();
+    let mut feature_indices: HashMap<(String, String), usize> = HashMap::new();
+    for (line_idx, record) in gff_reader.records().enumerate() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                annotations.skipped.push(SkippedFeature {
+                    seqname: "-".to_string(),
+                    id: format!("record {}", line_idx + 1),
+                    reason: format!("unable to parse line: {}", e),
+                });
+                continue
+            }
+        };
+        if record.feature_type() != "CDS" {
+            annotations.non_coding += 1;
+            continue
+        }
+
+        let reverse = match record.strand() {
+            Some(strand::Strand::Reverse) => true,
+            _ => false,
+        };
+        let phase = record.frame().parse::<usize>().ok().filter(|phase| *phase < 3);
+        let segment = (*record.start(), *record.end(), phase);
+        let group = group_keys.iter()
+            .filter_map(|key| record.attributes().get(*key))
+            .next()
+            .cloned();
+
+        let existing = group.as_ref()
+            .and_then(|group| feature_indices.get(&(record.seqname().to_string(), group.clone())))
+            .cloned();
+        match existing {
+            Some(idx) => {
+                let feature = &mut features[idx];
+                if feature.reverse != reverse || !strand_is_known(&record) {
+                    feature.segments.clear();
+                } else if feature.segments.len() > 0 {
+                    feature.segments.push(segment);
+                }
+            },
+            None => {
+                if let Some(group) = group {
+                    feature_indices.insert((record.seqname().to_string(), group), features.len());
+                }
+                let segments = if strand_is_known(&record) { vec![segment] } else { vec![] };
+                features.push(CodingFeature {
+                    seqname: record.seqname().to_string(),
+                    source: record.source().to_string(),
+                    segments,
+                    reverse,
+                    attributes: record.attributes().iter()
+                        .map(|(key, value)| (key.clone(), value.clone()))
+                        .collect(),
+                });
+            }
+        }
+    }
+
+    // GFF lines are usually sorted by position, so put segments into transcription order
+    for feature in features.iter_mut() {
+        if feature.reverse {
+            feature.segments.sort_by(|a, b| b.0.cmp(&a.0));
+        } else {
+            feature.segments.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+    }
+    features
+}
+
+fn strand_is_known(record: &Record) -> bool {
+    match record.strand() {
+        Some(strand::Strand::Forward) | Some(strand::Strand::Reverse) => true,
+        _ => false,
+    }
+}
+
+/// A feature from the feature table of a GenBank or EMBL record
+struct FlatFeature {
+    key: String,
+    location: String,
+    qualifiers: Vec<(String, String)>,
+}
+
+/// Read CDS features from GenBank or EMBL flat files. The sequence length in each
+/// record's header is added to lengths if the reference did not provide one
+fn read_flat_file<R: Read>(reader: R, embl: bool, lengths: &mut HashMap<String, u64>,
+                           annotations: &mut Annotations) -> Vec<CodingFeature> {
+    let mut coding_features = Vec::new();
+    let mut names: Vec<String> = Vec::new();
+    let mut length: Option<u64> = None;
+    let mut features: Vec<FlatFeature> = Vec::new();
+    let mut in_features = false;
+
+    for line in BufReader::new(reader).lines() {
+        let line = line.expect("Unable to read annotation file");
+        let line = line.trim_end();
+        if line.starts_with("//") {
+            let seqname = names.iter()
+                .find(|name| lengths.contains_key(*name))
+                .or(names.first())
+                .cloned()
+                .unwrap_or("-".to_string());
+            if let Some(length) = length {
+                lengths.entry(seqname.clone()).or_insert(length);
+            }
+            for feature in features.drain(..) {
+                if feature.key != "CDS" {
+                    annotations.non_coding += 1;
+                    continue
+                }
+                match flat_feature_to_coding(&feature, &seqname) {
+                    Ok(coding_feature) => coding_features.push(coding_feature),
+                    Err(reason) => {
+                        annotations.skipped.push(SkippedFeature {
+                            seqname: seqname.clone(),
+                            id: feature.location.clone(),
+                            reason,
+                        });
+                    }
+                }
+            }
+            names.clear();
+            length = None;
+            in_features = false;
+            continue
+        }
+
+        // EMBL feature tables use the same column layout as GenBank after the FT prefix
+        let feature_line = if embl {
+            if line.starts_with("ID   ") {
+                let fields: Vec<&str> = line[5..].split(';').map(|field| field.trim()).collect();
+                names.push(fields[0].to_string());
+                length = fields.iter()
+                    .find(|field| field.ends_with("BP."))
+                    .and_then(|field| field.trim_end_matches("BP.").trim().parse().ok());
+                continue
+            } else if line.starts_with("AC   ") {
+                names.extend(line[5..].split(';').map(|name| name.trim().to_string())
+                    .filter(|name| name.len() > 0));
+                continue
+            } else if line.starts_with("FT   ") {
+                in_features = true;
+                format!("  {}", &line[2..])
+            } else {
+                in_features = false;
+                continue
+            }
+        } else {
+            if line.starts_with("LOCUS") {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                if fields.len() > 1 {
+                    names.push(fields[1].to_string());
+                }
+                if fields.len() > 2 {
+                    length = fields[2].parse().ok();
+                }
+                continue
+            } else if line.starts_with("ACCESSION") || line.starts_with("VERSION") {
+                if let Some(name) = line.split_whitespace().nth(1) {
+                    names.push(name.to_string());
+                }
+                continue
+            } else if line.starts_with("FEATURES") {
+                in_features = true;
+                continue
+            } else if !line.starts_with(' ') {
+                in_features = false;
+                continue
+            }
+            line.to_string()
+        };
+        if !in_features || feature_line.len() <= 21 {
+            continue
+        }
+
+        let key = feature_line.get(5..21).unwrap_or("").trim();
+        let content = feature_line.get(21..).unwrap_or("").trim();
+        if key.len() > 0 {
+            features.push(FlatFeature {
+                key: key.to_string(),
+                location: content.to_string(),
+                qualifiers: Vec::new(),
+            });
+        } else if let Some(feature) = features.last_mut() {
+            if content.starts_with('/') {
+                let mut qualifier = content[1..].splitn(2, '=');
+                let name = qualifier.next().unwrap_or("").to_string();
+                let value = qualifier.next().unwrap_or("").to_string();
+                feature.qualifiers.push((name, value));
+            } else if let Some(qualifier) = feature.qualifiers.last_mut() {
+                qualifier.1.push(' ');
+                qualifier.1.push_str(content);
+            } else {
+                feature.location.push_str(content);
+            }
+        }
+    }
+    coding_features
+}
+
+fn flat_feature_to_coding(feature: &FlatFeature, seqname: &str) -> Result<CodingFeature, String> {
+    let locations = parse_location(&feature.location)?;
+    let reverse = locations[0].2;
+    if locations.iter().any(|location| location.2 != reverse) {
+        return Err("segments on different strands".to_string())
+    }
+
+    let attributes: Vec<(String, String)> = feature.qualifiers.iter()
+        .filter(|(name, _)| name != "translation")
+        .map(|(name, value)| (name.clone(), value.trim_matches('"').to_string()))
+        .collect();
+    let phase = attributes.iter()
+        .find(|(name, _)| name == "codon_start")
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .filter(|codon_start| *codon_start >= 1 && *codon_start <= 3)
+        .map(|codon_start| codon_start - 1)
+        .unwrap_or(0);
+
+    let segments = locations.iter().enumerate()
+        .map(|(idx, location)| (location.0, location.1, if idx == 0 { Some(phase) } else { None }))
+        .collect();
+    Ok(CodingFeature {
+        seqname: seqname.to_string(),
+        source: "annotation".to_string(),
+        segments,
+        reverse,
+        attributes,
+    })
+}
+
+/// Parse an INSDC feature location into 1-based inclusive segments in transcription
+/// order, flagging those on the reverse strand
+fn parse_location(location: &str) -> Result<Vec<(u64, u64, bool)>, String> {
+    let location: String = location.chars()
+        .filter(|c| !c.is_whitespace() && *c != '<' && *c != '>')
+        .collect();
+
+    if location.starts_with("complement(") && location.ends_with(')') {
+        let mut segments = parse_location(&location[11..location.len() - 1])?;
+        segments.reverse();
+        return Ok(segments.into_iter().map(|(start, end, reverse)| (start, end, !reverse)).collect())
+    } else if (location.starts_with("join(") || location.starts_with("order("))
+        && location.ends_with(')') {
+        let inner = &location[location.find('(').unwrap() + 1..location.len() - 1];
+        let mut segments = Vec::new();
+        let mut depth = 0;
+        let mut part_start = 0;
+        for (idx, c) in inner.char_indices() {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                ',' if depth == 0 => {
+                    segments.extend(parse_location(&inner[part_start..idx])?);
+                    part_start = idx + 1;
+                },
+                _ => {},
+            }
+        }
+        segments.extend(parse_location(&inner[part_start..])?);
+        return Ok(segments)
+    } else if location.contains(':') {
+        return Err(format!("location {} refers to another sequence", location))
+    } else if location.contains('^') {
+        return Err(format!("location {} lies between bases", location))
+    }
+
+    let bounds: Vec<&str> = location.split("..").collect();
+    let parse_bound = |bound: &str| bound.parse::<u64>()
+        .map_err(|_| format!("unable to parse location {}", location));
+    match bounds.len() {
+        1 => {
+            let position = parse_bound(bounds[0])?;
+            Ok(vec![(position, position, false)])
+        },
+        2 => Ok(vec![(parse_bound(bounds[0])?, parse_bound(bounds[1])?, false)]),
+        _ => Err(format!("unable to parse location {}", location)),
+    }
+}
+
+/// Convert a coding feature into one GFF record per segment. Segments running past the
+/// end of a circular contig, or whose start lies after their end, are split at the origin
+fn feature_records(feature: &CodingFeature, contig_length: Option<u64>) -> Result<Vec<Record>, String> {
+    if feature.segments.len() == 0 {
+        return Err("no strand or segments on different strands".to_string())
+    }
+
+    let mut segments: Vec<Segment> = Vec::new();
+    for (start, end, phase) in feature.segments.iter().cloned() {
+        let end = if start > end {
+            match contig_length {
+                Some(length) => end + length,
+                None => return Err(format!("start {} is after end {}", start, end)),
+            }
+        } else {
+            end
+        };
+        match contig_length {
+            Some(length) if start < 1 || start > length => {
+                return Err(format!("segment {}..{} lies outside of contig", start, end))
+            },
+            Some(length) if end > length => {
+                if end - length > length {
+                    return Err(format!("segment {}..{} wraps the contig more than once", start, end))
+                }
+                // Split at the origin, with the provided phase applying to the 5' piece
+                let pieces = if feature.reverse {
+                    vec![(1, end - length, phase), (start, length, None)]
+                } else {
+                    vec![(start, length, phase), (1, end - length, None)]
+                };
+                segments.extend(pieces);
+            },
+            _ => segments.push((start, end, phase)),
+        }
+    }
+
+    // Fill in missing phases from the number of coding bases in the preceding segment
+    let mut phases = Vec::with_capacity(segments.len());
+    for (idx, (start, end, phase)) in segments.iter().enumerate() {
+        let phase = match phase {
+            Some(phase) => *phase,
+            None if idx == 0 => 0,
+            None => {
+                let (previous_start, previous_end, _) = segments[idx - 1];
+                let carried = (previous_end - previous_start + 1)
+                    .saturating_sub(phases[idx - 1] as u64) % 3;
+                ((3 - carried) % 3) as usize
+            },
+        };
+        if phase as u64 > end - start {
+            return Err(format!("segment {}..{} is shorter than its phase", start, end))
+        }
+        phases.push(phase);
+    }
+
+    let mut template = Record::new();
+    *template.seqname_mut() = feature.seqname.clone();
+    *template.source_mut() = feature.source.clone();
+    *template.feature_type_mut() = "CDS".to_string();
+    *template.start_mut() = segments.iter().map(|segment| segment.0).min().unwrap();
+    *template.end_mut() = segments.iter().map(|segment| segment.1).max().unwrap();
+    *template.strand_mut() = if feature.reverse { "-".to_string() } else { "+".to_string() };
+    for (key, value) in feature.attributes.iter() {
+        template.attributes_mut().insert(key.clone(), value.clone());
+    }
+    let id = gene_id(&template);
+    template.attributes_mut().remove("ID");
+    template.attributes_mut().insert("ID".to_string(), id);
+
+    Ok(segments.iter().zip(phases.iter()).map(|((start, end, _), phase)| {
+        let mut record = template.clone();
+        *record.start_mut() = *start;
+        *record.end_mut() = *end;
+        *record.frame_mut() = phase.to_string();
+        record
+    }).collect())
+}
+
+fn feature_id(feature: &CodingFeature) -> String {
+    let mut record = Record::new();
+    *record.seqname_mut() = feature.seqname.clone();
+    if let Some(segment) = feature.segments.first() {
+        *record.start_mut() = segment.0;
+        *record.end_mut() = segment.1;
+    }
+    for (key, value) in feature.attributes.iter() {
+        record.attributes_mut().insert(key.clone(), value.clone());
+    }
+    gene_id(&record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lengths() -> HashMap<String, u64> {
+        let mut lengths = HashMap::new();
+        lengths.insert("contig_1".to_string(), 1000);
+        lengths
+    }
+
+    fn segments(annotations: &Annotations, id: &str) -> Vec<(u64, u64, String, String)> {
+        annotations.genes["contig_1"].iter()
+            .filter(|record| record.attributes()["ID"] == id)
+            .map(|record| (*record.start(), *record.end(),
+                           record.frame().to_string(), record.strand().unwrap().strand_symbol().to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_location_parsing() {
+        assert_eq!(parse_location("<1..>300").unwrap(), vec![(1, 300, false)]);
+        assert_eq!(parse_location("complement(join(10..20, 30..40))").unwrap(),
+                   vec![(30, 40, true), (10, 20, true)]);
+        assert_eq!(parse_location("join(complement(30..40),complement(10..20))").unwrap(),
+                   vec![(30, 40, true), (10, 20, true)]);
+        assert!(parse_location("J00194.1:100..202").is_err());
+        assert!(parse_location("10^11").is_err());
+    }
+
+    #[test]
+    fn test_gff3_loading() {
+        let gff = "##gff-version 3\n\
+            contig_1\tsrc\tgene\t1\t90\t.\t+\t.\tID=gene_a\n\
+            contig_1\tsrc\tCDS\t1\t90\t.\t+\t0\tID=cds_a;Parent=gene_a\n\
+            contig_1\tsrc\tCDS\t200\t250\t.\t-\t0\tParent=rna_b;locus_tag=B_0001\n\
+            contig_1\tsrc\tCDS\t100\t151\t.\t-\t1\tParent=rna_b;locus_tag=B_0001\n\
+            contig_1\tsrc\tCDS\t950\t1100\t.\t+\t0\tName=wrapped\n\
+            contig_2\tsrc\tCDS\t1\t90\t.\t+\t0\tID=elsewhere\n\
+            contig_1\tsrc\tCDS\t400\t500\t.\t.\t0\tID=no_strand\n\
+            ##FASTA\n\
+            >contig_1\n\
+            ACGT\n";
+        let annotations = parse_annotations(gff.as_bytes(), AnnotationFormat::GFF3, &lengths());
+
+        assert_eq!(annotations.non_coding, 1);
+        assert_eq!(segments(&annotations, "cds_a"), vec![(1, 90, "0".to_string(), "+".to_string())]);
+        assert_eq!(segments(&annotations, "B_0001"),
+                   vec![(200, 250, "0".to_string(), "-".to_string()),
+                        (100, 151, "1".to_string(), "-".to_string())]);
+        assert_eq!(segments(&annotations, "wrapped"),
+                   vec![(950, 1000, "0".to_string(), "+".to_string()),
+                        (1, 100, "0".to_string(), "+".to_string())]);
+
+        let mut skipped: Vec<&str> = annotations.skipped.iter().map(|s| s.id.as_str()).collect();
+        skipped.sort();
+        assert_eq!(skipped, vec!["elsewhere", "no_strand"]);
+    }
+
+    #[test]
+    fn test_gtf_loading() {
+        let gtf = "contig_1\tsrc\texon\t1\t100\t.\t+\t.\tgene_id \"g1\"; transcript_id \"t1\";\n\
+            contig_1\tsrc\tCDS\t10\t50\t.\t+\t0\tgene_id \"g1\"; transcript_id \"t1\";\n\
+            contig_1\tsrc\tCDS\t60\t100\t.\t+\t.\tgene_id \"g1\"; transcript_id \"t1\";\n";
+        let annotations = parse_annotations(gtf.as_bytes(), AnnotationFormat::GTF, &lengths());
+        assert_eq!(segments(&annotations, "g1"),
+                   vec![(10, 50, "0".to_string(), "+".to_string()),
+                        (60, 100, "1".to_string(), "+".to_string())]);
+    }
+
+    #[test]
+    fn test_genbank_and_embl_loading() {
+        let genbank = "LOCUS       contig_1    1000 bp    DNA     circular BCT 01-JAN-2020\n\
+            FEATURES             Location/Qualifiers\n\
+            \x20    source          1..1000\n\
+            \x20    CDS             complement(join(900..1000,\n\
+            \x20                    1..101))\n\
+            \x20                    /locus_tag=\"ABC_0001\"\n\
+            \x20                    /product=\"hypothetical\n\
+            \x20                    protein\"\n\
+            \x20                    /translation=\"MKV\"\n\
+            \x20    CDS             J00194.1:100..202\n\
+            \x20                    /locus_tag=\"ABC_0002\"\n\
+            ORIGIN\n\
+            \x20       1 acgt\n\
+            //\n";
+        let annotations = parse_annotations(genbank.as_bytes(), AnnotationFormat::GenBank, &lengths());
+        assert_eq!(annotations.non_coding, 1);
+        assert_eq!(annotations.skipped.len(), 1);
+        assert_eq!(segments(&annotations, "ABC_0001"),
+                   vec![(1, 101, "0".to_string(), "-".to_string()),
+                        (900, 1000, "1".to_string(), "-".to_string())]);
+        let record = &annotations.genes["contig_1"][0];
+        assert_eq!(record.attributes()["product"], "hypothetical protein");
+        assert!(record.attributes().get("translation").is_none());
+
+        let embl = "ID   contig_1; SV 1; circular; genomic DNA; STD; PRO; 1000 BP.\n\
+            FH   Key             Location/Qualifiers\n\
+            FT   CDS             <10..309\n\
+            FT                   /codon_start=2\n\
+            FT                   /gene=\"abcD\"\n\
+            SQ   Sequence 1000 BP;\n\
+            //\n";
+        let annotations = parse_annotations(embl.as_bytes(), AnnotationFormat::EMBL, &HashMap::new());
+        assert_eq!(segments(&annotations, "abcD"), vec![(10, 309, "1".to_string(), "+".to_string())]);
+    }
+}
@@ -2,6 +2,7 @@ use std::collections::{HashMap, HashSet, BTreeMap, BTreeSet};
 use itertools::{izip, Itertools};
 use bio::alphabets::dna;
 use bio_types::strand;
+use estimation::variant_annotation::{coding_positions, is_reverse};
 use model::variants::{Variant, Base};
 
 pub struct GeneInfo {
@@ -65,12 +66,33 @@ impl CodonTable {
             ns_sites: HashMap::new(),
         }
     }
+
+    /// Translate a single codon into its amino acid. Codons containing ambiguous
+    /// bases are returned as 'X'
+    pub fn translate(&self, codon: &[u8]) -> char {
+        let codon = codon.to_ascii_uppercase();
+        match self.aminos.get(&codon) {
+            Some(amino) => *amino,
+            None => 'X',
+        }
+    }
+
+    /// Whether the codon can act as a start codon in the current translation table
+    pub fn is_start(&self, codon: &[u8]) -> bool {
+        let codon = codon.to_ascii_uppercase();
+        match self.starts.get(&codon) {
+            Some(start) => *start == 'M',
+            None => false,
+        }
+    }
 }
 
 pub trait Translations {
     fn get_codon_table(&mut self, table_id: usize);
+    /// Population dN/dS of a gene from the variants found along it. The gene is given as its
+    /// segments in transcription order, which are joined into one coding sequence
     fn find_mutations(&self,
-                      gene: &bio::io::gff::Record,
+                      segments: &[&bio::io::gff::Record],
                       variants: &HashMap<i64, HashMap<Variant, Base>>,
                       ref_sequence: &Vec<u8>,
                       depth: &Vec<i32>) -> f64;
@@ -119,32 +141,33 @@ impl Translations for CodonTable {
     }
 
     fn find_mutations(&self,
-                      gene: &bio::io::gff::Record,
+                      segments: &[&bio::io::gff::Record],
                       variants: &HashMap<i64, HashMap<Variant, Base>>,
                       ref_sequence: &Vec<u8>,
                       _depth: &Vec<i32>) -> f64 {
-        let strand = gene.strand().expect("No strandedness found");
-
-        // bio::gff documentation says start and end positions are 1-based, so we minus 1
-        // Additionally, end position is non-inclusive
-        let start = gene.start().clone() as usize - 1;
-        let end = gene.end().clone() as usize;
-        let frame: usize = gene.frame().parse().unwrap();
-        let gene_sequence = ref_sequence[start..end].to_vec();
+        // Contig positions of the coding sequence from its first complete codon, read on the
+        // coding strand so that codons are contiguous across segments
+        let reverse = is_reverse(segments[0]);
+        let positions = coding_positions(segments, ref_sequence.len());
+        let gene_sequence: Vec<u8> = positions.iter()
+            .map(|pos| if reverse { dna::complement(ref_sequence[*pos]) } else { ref_sequence[*pos] })
+            .collect();
         debug!("Gene Seq {:?}", String::from_utf8_lossy(&gene_sequence));
-        let codon_sequence = get_codons(&gene_sequence, frame, strand);
+        let codon_sequence: Vec<Vec<u8>> = gene_sequence.chunks(3)
+            .map(|chunk| chunk.to_vec()).collect();
         debug!("Codon Sequence {:?}", codon_sequence);
 
         // Calculate N and S
         let mut big_n: f64 = 0.0;
         let mut big_s: f64 = 0.0;
         for codon in codon_sequence.iter() {
-            if String::from_utf8(codon.clone()).expect("Unable to interpret codon").contains("N") {
-                continue
-            } else {
-                let n = self.ns_sites[codon];
-                big_n += n;
-                big_s += 3.0 - n;
+            // Skips ambiguous bases and the partial codons at the edges of CDS segments
+            match self.ns_sites.get(codon) {
+                Some(n) => {
+                    big_n += n;
+                    big_s += 3.0 - n;
+                },
+                None => continue,
             }
         }
 
@@ -162,8 +185,8 @@ impl Translations for CodonTable {
         let mut positionals = 0;
         let mut total_variants = 0;
         let dummy = HashMap::new();
-        for (gene_cursor, cursor) in (start..end).into_iter().enumerate() {
-            let variant_set = match variants.get(&(cursor as i64)){
+        for (gene_cursor, cursor) in positions.iter().enumerate() {
+            let variant_set = match variants.get(&(*cursor as i64)){
                 Some(map) => map,
                 None => &dummy,
             };
@@ -240,6 +263,8 @@ impl Translations for CodonTable {
                 for (variant, base_info) in variant_set.iter() {
                     match variant {
                         Variant::SNV(var) => {
+                            // Variants are called on the forward strand
+                            let var = if reverse { dna::complement(*var) } else { *var };
 
                             if variant_count > 0 {
                                 // Create a copy of codon up to this point
@@ -248,14 +273,14 @@ impl Translations for CodonTable {
 
                                 new_codons.push(codon.clone());
 
-                                new_codons[variant_count][codon_cursor] = *var;
+                                new_codons[variant_count][codon_cursor] = var;
 
                                 debug!("multi variant codon {:?}", new_codons);
                             } else {
 
                                 for var_idx in 0..new_codons.len() {
 
-                                    new_codons[var_idx][codon_cursor] = *var;
+                                    new_codons[var_idx][codon_cursor] = var;
                                 }
                             }
                             variant_count += 1;
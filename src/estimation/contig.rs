@@ -8,7 +8,19 @@ use external_command_checker;
 use estimation::contig_variants::*;
 use estimation::variant_matrix::*;
 use estimation::codon_structs::*;
+use estimation::variant_annotation::*;
+use estimation::gene_prediction;
+use estimation::annotation_loader;
+use estimation::trajectory;
+use estimation::linkage::assign_read_to_alleles;
+use estimation::report::{OutputFormat, LorikeetReport};
+use estimation::lorikeet_config::LorikeetConfig;
+use estimation::sample_sheet::ReadTechnology;
+use estimation::strain_assignment::*;
+use estimation::alignment_format::*;
+use estimation::result_cache::*;
 use coverm::bam_generator::*;
+use coverm::FlagFilter;
 use rayon::prelude::*;
 use estimation::alignment_properties::{InsertSize, AlignmentProperties};
 use model::variants::*;
@@ -16,10 +28,8 @@ use model::variants::*;
 use crate::*;
 use std::str;
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use coverm::mosdepth_genome_coverage_estimators::*;
-use coverm::FlagFilter;
-use bio::io::gff;
 use bio::io::gff::Record;
 use nix::unistd;
 use nix::sys::stat;
@@ -27,32 +37,250 @@ use tempdir::TempDir;
 use tempfile;
 use std::sync::{Arc, Mutex};
 
+/// Variants of every sample piled up against the reference, before the outputs of a mode
+/// are produced
+pub struct Pileup {
+    pub variant_matrix: VariantMatrix,
+    /// Genes of each contig, either loaded from the GFF file or predicted for evolve
+    pub gff_map: HashMap<String, Vec<Record>>,
+    pub codon_table: CodonTable,
+    /// Number of short read samples, which come before the long read samples. Only used by
+    /// polish, as the libraries of a sample sheet sample are otherwise merged
+    pub short_sample_count: usize,
+}
+
+/// Results of summarize, evolve and polymorph
+#[derive(Debug)]
+pub struct SummaryResult {
+    pub report: LorikeetReport,
+    pub variant_matrix: VariantMatrix,
+}
+
+/// Results of genotype
+#[derive(Debug)]
+pub struct GenotypeResult {
+    pub report: LorikeetReport,
+    /// Allele of each strain at the sites separating them
+    pub strain_sites: StrainSites,
+    pub strain_genomes: Vec<StrainGenome>,
+    pub variant_matrix: VariantMatrix,
+}
+
+/// Opens the indexed reference, indexing it with samtools when there is no index yet
+pub fn indexed_reference(reference: &str) -> bio::io::fasta::IndexedReader<File> {
+    if let Ok(reader) = bio::io::fasta::IndexedReader::from_file(&Path::new(reference)) {
+        return reader
+    }
+    external_command_checker::check_for_samtools();
+    info!("Generating reference index");
+    let cmd_string = format!(
+        "set -e -o pipefail; \
+                     samtools faidx {}",
+        reference);
+    debug!("Queuing cmd_string: {}", cmd_string);
+
+    std::process::Command::new("bash")
+        .arg("-c")
+        .arg(&cmd_string)
+        .stdout(std::process::Stdio::piped())
+        .output()
+        .expect("Unable to execute bash");
+
+    bio::io::fasta::IndexedReader::from_file(&Path::new(reference)).expect(
+        "Unable to generate index")
+}
+
+/// Pileup variants across all samples of a summarize run, returning the report and
+/// variant matrix without writing any files
+pub fn run_summarize<R: NamedBamReader + Send,
+    G: NamedBamReaderGenerator<R> + Send,
+    S: NamedBamReader + Send,
+    U: NamedBamReaderGenerator<S> + Send,>(
+    config: &LorikeetConfig,
+    bam_readers: Vec<G>,
+    long_readers: Option<Vec<U>>) -> SummaryResult {
+    run_in_memory(config, bam_readers, long_readers, "summarize")
+}
+
+/// Pileup variants across all samples and calculate the dN/dS of each gene, returning the
+/// report and variant matrix without writing any files
+pub fn run_evolve<R: NamedBamReader + Send,
+    G: NamedBamReaderGenerator<R> + Send,
+    S: NamedBamReader + Send,
+    U: NamedBamReaderGenerator<S> + Send,>(
+    config: &LorikeetConfig,
+    bam_readers: Vec<G>,
+    long_readers: Option<Vec<U>>) -> SummaryResult {
+    run_in_memory(config, bam_readers, long_readers, "evolve")
+}
 
+/// Pileup variants across all samples, returning the variant matrix holding every variant
+/// of every sample without printing them
+pub fn run_polymorph<R: NamedBamReader + Send,
+    G: NamedBamReaderGenerator<R> + Send,
+    S: NamedBamReader + Send,
+    U: NamedBamReaderGenerator<S> + Send,>(
+    config: &LorikeetConfig,
+    bam_readers: Vec<G>,
+    long_readers: Option<Vec<U>>) -> SummaryResult {
+    run_in_memory(config, bam_readers, long_readers, "polymorph")
+}
+
+fn run_in_memory<R: NamedBamReader + Send,
+    G: NamedBamReaderGenerator<R> + Send,
+    S: NamedBamReader + Send,
+    U: NamedBamReaderGenerator<S> + Send,>(
+    config: &LorikeetConfig,
+    bam_readers: Vec<G>,
+    long_readers: Option<Vec<U>>,
+    mode: &str) -> SummaryResult {
+    let mut coverage_estimators = config.coverage_estimators();
+    let pileup = pileup(config, indexed_reference(&config.reference),
+                        bam_readers, long_readers, mode, &mut coverage_estimators);
+    SummaryResult {
+        report: pileup.variant_matrix.report(mode, &config.report_parameters, &pileup.gff_map),
+        variant_matrix: pileup.variant_matrix,
+    }
+}
+
+/// Pileup variants across all samples and cluster them into strains, returning the strain
+/// genomes and the alleles separating the strains without writing any files. Reads are not
+/// assigned to strains
+pub fn run_genotype<R: NamedBamReader + Send,
+    G: NamedBamReaderGenerator<R> + Send,
+    S: NamedBamReader + Send,
+    U: NamedBamReaderGenerator<S> + Send,>(
+    config: &LorikeetConfig,
+    bam_readers: Vec<G>,
+    long_readers: Option<Vec<U>>) -> GenotypeResult {
+    let mut coverage_estimators = config.coverage_estimators();
+    let mut pileup = pileup(config, indexed_reference(&config.reference),
+                            bam_readers, long_readers, "genotype", &mut coverage_estimators);
+    cluster_strains(&mut pileup.variant_matrix, config);
+    GenotypeResult {
+        report: pileup.variant_matrix.report("genotype", &config.report_parameters, &pileup.gff_map),
+        strain_sites: pileup.variant_matrix.strain_sites(),
+        strain_genomes: pileup.variant_matrix.strain_genomes(&pileup.gff_map, &pileup.codon_table),
+        variant_matrix: pileup.variant_matrix,
+    }
+}
+
+/// Fuzzy DBSCAN clustering of the variants into strains
+fn cluster_strains(variant_matrix: &mut VariantMatrix, config: &LorikeetConfig) {
+    variant_matrix.generate_distances(config.threads, &config.output_prefix);
+    variant_matrix.run_fuzzy_scan(config.e_min, config.e_max, config.pts_min, config.pts_max,
+                                  config.phi);
+}
+
+/// Pileup variants across all samples and produce the outputs of the given mode. Returns the
+/// number of edits applied to the reference when polishing, and 0 otherwise
 pub fn pileup_variants<R: NamedBamReader + Send,
     G: NamedBamReaderGenerator<R> + Send,
     S: NamedBamReader + Send,
     U: NamedBamReaderGenerator<S> + Send,>(
-    m: &clap::ArgMatches,
+    config: &LorikeetConfig,
+    reference: bio::io::fasta::IndexedReader<File>,
     bam_readers: Vec<G>,
     long_readers: Option<Vec<U>>,
     mode: &str,
-    coverage_estimators: &mut Vec<CoverageEstimator>,
-    mut reference: bio::io::fasta::IndexedReader<File>,
-    _print_zero_coverage_contigs: bool,
-    flag_filters: FlagFilter,
-    mapq_threshold: u8,
-    min_var_depth: usize,
-    min: f32, max: f32,
-    contig_end_exclusion: u64,
-    output_prefix: &str,
-    n_threads: usize,
-    method: &str,
-    coverage_fold: f32,
-    include_indels: bool,
-    include_soft_clipping: bool,
-    is_long_read: bool) {
+    coverage_estimators: &mut Vec<CoverageEstimator>) -> usize {
+
+    let Pileup {
+        mut variant_matrix,
+        gff_map,
+        codon_table,
+        short_sample_count,
+    } = pileup(config, reference, bam_readers, long_readers, mode, coverage_estimators);
+    let output_prefix = config.output_prefix.as_str();
+
+    let output_format = config.output_format;
+    let mut polish_edits = 0;
+    if mode=="genotype" {
+        cluster_strains(&mut variant_matrix, config);
+        variant_matrix.generate_genotypes(output_prefix, &gff_map, &codon_table);
+        if let Some(assignment_parameters) = &config.assignment {
+            // Each BAM file with its sample name and whether it holds long reads
+            let bam_paths: Vec<(String, &str, bool)> = config.bam_files.iter()
+                .map(|bam_path| (bam_path, false))
+                .chain(config.longread_bam_files.iter().map(|bam_path| (bam_path, true)))
+                .enumerate()
+                .map(|(sample_idx, (bam_path, long))| {
+                    (config.sample_name(sample_idx, &stoit_name(bam_path), long),
+                     bam_path.as_str(), long)
+                }).collect();
+            let strain_sites = variant_matrix.strain_sites();
+            if strain_sites.strains.is_empty() {
+                warn!("No strains were found, so reads were not assigned to strains");
+            } else {
+                let extraction = match config.extraction {
+                    Some(rules) => Some((rules,
+                                         StrainReadWriters::new(output_prefix, &strain_sites.strains))),
+                    None => None,
+                };
+                assign_reads_to_strains(&bam_paths, Some(config.reference.as_str()), &strain_sites,
+                                        output_prefix, assignment_parameters, &extraction);
+            }
+        }
+        if config.gff.is_some() && output_format == OutputFormat::Tsv {
+            variant_matrix.print_strain_variants(output_prefix);
+        }
+        variant_matrix.print_snp_alignment(output_prefix, config.min_site_depth);
+    } else if mode=="summarize" {
+        if output_format == OutputFormat::Tsv {
+            variant_matrix.print_variant_stats(output_prefix);
+        }
+        let diversity_parameters = &config.diversity;
+        variant_matrix.print_diversity(output_prefix, &gff_map, diversity_parameters);
+        variant_matrix.print_pairwise_differentiation(output_prefix, diversity_parameters.min_depth);
+        variant_matrix.print_linkage(output_prefix, &config.linkage);
+        variant_matrix.print_snp_alignment(output_prefix, diversity_parameters.min_depth);
+        variant_matrix.print_frequency_spectra(output_prefix, &config.spectrum);
+        if let Some(sample_metadata) = &config.sample_metadata {
+            let metadata = trajectory::read_sample_metadata(sample_metadata);
+            variant_matrix.print_trajectories(output_prefix, &metadata, &config.trajectory);
+        }
+        if !config.sample_groups.is_empty() {
+            variant_matrix.print_differential_frequencies(
+                output_prefix, &config.sample_groups, diversity_parameters.min_depth);
+        }
+    } else if mode=="polish" {
+        polish_edits = variant_matrix.polish_genomes(
+            output_prefix, &config.polish, short_sample_count);
+    } else if mode=="polymorph" {
+        variant_matrix.print_variants(config.spectrum.max_components);
+    }
+
+    // The JSON report replaces the contig statistics and strain variant tables
+    if output_format == OutputFormat::Json
+        && (mode == "summarize" || mode == "genotype" || mode == "evolve") {
+        variant_matrix.report(mode, &config.report_parameters, &gff_map)
+            .write_json(output_prefix);
+    }
+
+    if config.html_report && (mode == "summarize" || mode == "genotype" || mode == "evolve") {
+        variant_matrix.write_html_report(output_prefix, mode);
+    }
+    polish_edits
+}
 
-    let mut sample_count = bam_readers.len();
+/// Pileup variants across all samples into a variant matrix, annotating them with their
+/// consequences when genes are given
+pub fn pileup<R: NamedBamReader + Send,
+    G: NamedBamReaderGenerator<R> + Send,
+    S: NamedBamReader + Send,
+    U: NamedBamReaderGenerator<S> + Send,>(
+    config: &LorikeetConfig,
+    reference: bio::io::fasta::IndexedReader<File>,
+    bam_readers: Vec<G>,
+    long_readers: Option<Vec<U>>,
+    mode: &str,
+    coverage_estimators: &mut Vec<CoverageEstimator>) -> Pileup {
+
+    let short_sample_count = bam_readers.len();
+    let mut sample_count = short_sample_count;
+    let contig_lengths: HashMap<String, u64> = reference.index.sequences().into_iter()
+        .map(|sequence| (sequence.name, sequence.len))
+        .collect();
     let reference = Arc::new(Mutex::new(reference));
     let coverage_estimators = Arc::new(Mutex::new(coverage_estimators));
     let mut ani = 0.;
@@ -82,62 +310,25 @@ pub fn pileup_variants<R: NamedBamReader + Send,
             codon_table.get_codon_table(11);
             ani = 0.;
 
-            let mut gff_reader;
-            if m.is_present("gff") {
-                let gff_file = m.value_of("gff").unwrap();
-                gff_reader = gff::Reader::from_file(gff_file,
-                                                    bio::io::gff::GffType::GFF3)
-                    .expect("GFF File not found");
+            if let Some(gff) = &config.gff {
+                let annotations = annotation_loader::load_annotations(gff, &contig_lengths);
+                annotations.report();
+                gff_map.lock().unwrap().extend(annotations.genes);
             } else {
-                external_command_checker::check_for_prokka();
-                let tmp_dir = TempDir::new("lorikeet_fifo")
-                    .expect("Unable to create temporary directory");
-                let fifo_path = tmp_dir.path().join("foo.pipe");
-
-                // create new fifo and give read, write and execute rights to the owner.
-                // This is required because we cannot open a Rust stream as a BAM file with
-                // rust-htslib.
-                unistd::mkfifo(&fifo_path, stat::Mode::S_IRWXU)
-                    .expect(&format!("Error creating named pipe {:?}", fifo_path));
-
-                let gff_file = tempfile::Builder::new()
-                    .prefix("lorikeet-prodigal-gff")
-                    .tempfile_in(tmp_dir.path())
-                    .expect(&format!("Failed to create distances tempfile"));
-                let cmd_string = format!(
-                    "set -e -o pipefail; \
-                     prokka -f gff -i {} -o {} {}",
-                    // prodigal
-                    m.value_of("reference").unwrap(),
-                    gff_file.path().to_str()
-                        .expect("Failed to convert tempfile path to str"),
-                    m.value_of("prodigal-params").unwrap_or(""));
-                info!("Queuing cmd_string: {}", cmd_string);
-                std::process::Command::new("bash")
-                    .arg("-c")
-                    .arg(&cmd_string)
-                    .output()
-                    .expect("Unable to execute bash");
-
-                gff_reader = gff::Reader::from_file(gff_file.path().to_str()
-                                                        .expect("Failed to convert tempfile path to str"),
-                                                    bio::io::gff::GffType::GFF3)
-                    .expect("Failed to read prodigal output");
-
-                tmp_dir.close().expect("Failed to close temp directory");
-
-            }
-            gff_reader.records().into_iter().for_each(|record| {
-                let rec = record.unwrap();
+                info!("No GFF file provided, predicting genes on reference");
+                let predicted_genes = gene_prediction::predict_genes(
+                    &config.reference, &codon_table, config.min_gene_length);
                 let mut gff_map = gff_map.lock().unwrap();
-                let contig_genes = gff_map.entry(rec.seqname().to_owned())
-                    .or_insert(Vec::new());
-                contig_genes.push(rec);
-            });
+                gff_map.extend(predicted_genes);
+            }
         },
-        "genotype" | "summarize" => {
-            if m.is_present("strain-ani") {
-                ani = parse_percentage(m, "strain-ani");
+        "genotype" | "summarize" | "polymorph" => {
+            ani = config.strain_ani;
+            if let Some(gff) = &config.gff {
+                codon_table.get_codon_table(11);
+                let annotations = annotation_loader::load_annotations(gff, &contig_lengths);
+                annotations.report();
+                gff_map.lock().unwrap().extend(annotations.genes);
             }
         },
         _ => {
@@ -148,11 +339,12 @@ pub fn pileup_variants<R: NamedBamReader + Send,
     let read_cnt_id: Arc<Mutex<i64>> = Arc::new(Mutex::new(0));
     let read_to_id = Arc::new(Mutex::new(HashMap::new()));
     // Loop through bam generators in parallel
-    let split_threads = std::cmp::max(n_threads / sample_count, 1);
+    let split_threads = std::cmp::max(config.threads / sample_count, 1);
 
     // Process Short Read BAMs
     bam_readers.into_par_iter().enumerate().for_each(|(sample_idx, bam_generator)|{
         process_bam(bam_generator,
+                    sample_idx,
                     sample_idx,
                     sample_count,
                     &reference,
@@ -162,24 +354,18 @@ pub fn pileup_variants<R: NamedBamReader + Send,
                     &read_cnt_id,
                     &read_to_id,
                     split_threads,
-                    m,
-                    output_prefix,
-                    coverage_fold,
+                    config,
                     &codon_table,
-                    min_var_depth,
-                    contig_end_exclusion,
-                    min, max, ani,
+                    ani,
                     mode,
-                    include_soft_clipping,
-                    include_indels,
-                    &flag_filters,
-                    mapq_threshold, method, false)
+                    false)
     });
 
-    // Process Long Read BAMs
-    longreads.into_par_iter().enumerate().for_each(|(sample_idx, bam_generator)|{
+    // Process Long Read BAMs, which follow the short read samples
+    longreads.into_par_iter().enumerate().for_each(|(bam_idx, bam_generator)|{
         process_bam(bam_generator,
-                    sample_idx,
+                    short_sample_count + bam_idx,
+                    bam_idx,
                     sample_count,
                     &reference,
                     &coverage_estimators,
@@ -188,42 +374,174 @@ pub fn pileup_variants<R: NamedBamReader + Send,
                     &read_cnt_id,
                     &read_to_id,
                     split_threads,
-                    m,
-                    output_prefix,
-                    coverage_fold,
+                    config,
                     &codon_table,
-                    min_var_depth,
-                    contig_end_exclusion,
-                    min, max, ani,
+                    ani,
                     mode,
-                    include_soft_clipping,
-                    include_indels,
-                    &flag_filters,
-                    mapq_threshold, method, true)
+                    true)
     });
 
-    if mode=="genotype" {
-        let mut variant_matrix = variant_matrix.lock().unwrap();
-        variant_matrix.generate_distances(n_threads, output_prefix);
-        let e_min: f64 = m.value_of("e-min").unwrap().parse().unwrap();
-        let e_max: f64 = m.value_of("e-max").unwrap().parse().unwrap();
-        let pts_min: f64 = m.value_of("pts-min").unwrap().parse().unwrap();
-        let pts_max: f64 = m.value_of("pts-max").unwrap().parse().unwrap();
-        let phi: f64 = m.value_of("phi").unwrap().parse().unwrap();
-
-        variant_matrix.run_fuzzy_scan(e_min, e_max, pts_min, pts_max, phi);
-        variant_matrix.generate_genotypes(output_prefix);
-    } else if mode=="summarize" {
-        let mut variant_matrix = variant_matrix.lock().unwrap();
-        variant_matrix.print_variant_stats(output_prefix);
+    let mut variant_matrix = Arc::try_unwrap(variant_matrix).ok()
+        .expect("Variant matrix is still shared").into_inner().unwrap();
+    let gff_map = Arc::try_unwrap(gff_map).ok()
+        .expect("Gene map is still shared").into_inner().unwrap();
+    // The short and long read libraries of a sample sheet sample become one sample, except
+    // when polishing, which pools the samples of each technology
+    if config.sample_sheet.is_some() && mode != "polish" {
+        variant_matrix.merge_samples_by_name();
+    }
+    if config.gff.is_some() && mode != "evolve" {
+        let gene_index = GeneIndex::new(&gff_map);
+        variant_matrix.annotate_variants(&gene_index, &codon_table);
+    }
+
+    Pileup {
+        variant_matrix,
+        gff_map,
+        codon_table,
+        short_sample_count,
+    }
+}
+
+/// Scores the reads in each BAM or CRAM file against the alleles of each strain, combining the
+/// alignments of a read and its mate. Writes a copy of each file, in the same format, to
+/// {output_prefix}_{sample}_strains.{bam,cram} with the assigned strain and its posterior
+/// probability as tags, or to {output_prefix}_{sample}_long_strains.{bam,cram} for the long
+/// reads of a sample that also has short reads, along with the number of reads assigned to
+/// each strain in each sample to {output_prefix}_read_assignments.tsv, counting every file of a
+/// sample together. If extraction is given, the primary alignment of
+/// each read is also written to the FASTQ files of the strains chosen by the rules
+fn assign_reads_to_strains(bam_paths: &[(String, &str, bool)],
+                           reference: Option<&str>,
+                           strain_sites: &StrainSites,
+                           output_prefix: &str,
+                           parameters: &AssignmentParameters,
+                           extraction: &Option<(ExtractionRules, StrainReadWriters)>) {
+    use rust_htslib::bam::Read;
+
+    let summaries: Vec<(String, AssignmentSummary)> = bam_paths.par_iter().map(|(sample, bam_path, long)| {
+        let sample = sample.clone();
+
+        // Summed log-likelihoods and informative sites of each read name
+        let mut read_evidence: HashMap<Vec<u8>, (Vec<f64>, usize)> = HashMap::new();
+        let mut reader = open_reader(bam_path, reference);
+        for record in reader.records() {
+            let record = record.expect("Error while reading BAM record");
+            if record.is_unmapped() || record.is_secondary() || record.is_supplementary() {
+                continue
+            }
+            let tid = record.tid();
+            let sites = match strain_sites.sites.get(&tid) {
+                Some(sites) => sites,
+                None => continue,
+            };
+            let observed = observed_alleles(record.pos(), &record.cigar(),
+                                            &record.seq().as_bytes(),
+                                            &strain_sites.references[&tid], sites);
+            if observed.is_empty() {
+                continue
+            }
+            let (log_likelihoods, informative) = strain_sites.log_likelihoods(
+                tid, &observed, parameters.error_rate);
+            let evidence = read_evidence.entry(record.qname().to_vec())
+                .or_insert((vec![0.; strain_sites.strains.len()], 0));
+            for (total, log_likelihood) in evidence.0.iter_mut().zip(log_likelihoods.iter()) {
+                *total += log_likelihood;
+            }
+            evidence.1 += informative;
+        }
+        let assignments: HashMap<Vec<u8>, ReadAssignment> = read_evidence.into_iter()
+            .map(|(qname, (log_likelihoods, informative))| {
+                (qname, ReadAssignment::from_log_likelihoods(
+                    &log_likelihoods, informative, parameters.min_probability))
+            }).collect();
+        let unassigned = ReadAssignment::from_log_likelihoods(
+            &vec![0.; strain_sites.strains.len()], 0, parameters.min_probability);
+
+        let format = AlignmentFormat::from_path(bam_path);
+        // Samples of mapped reads are named <reference>/<read1>, which is kept out of the path
+        let library = match *long && bam_paths.iter()
+            .any(|(other_sample, _, other_long)| *other_sample == sample && !other_long) {
+            true => format!("{}_long", sample),
+            false => sample.clone(),
+        }.replace("/", ".");
+        let output_bam = format!("{}_{}_strains.{}", output_prefix, library, format.extension());
+        let mut reader = open_reader(bam_path, reference);
+        let header = bam::header::Header::from_template(reader.header());
+        let mut writer = create_writer(&output_bam, &header, format, reference);
+        let mut summary = AssignmentSummary::default();
+        // Bases of mates waiting for their pair, along with whether they are the first mate
+        let mut waiting_mates: HashMap<Vec<u8>, (bool, (Vec<u8>, Vec<u8>))> = HashMap::new();
+        for record in reader.records() {
+            let mut record = record.expect("Error while reading BAM record");
+            let assignment = assignments.get(record.qname()).unwrap_or(&unassigned);
+            let label = assignment.label(&strain_sites.strains);
+            let primary = !record.is_secondary() && !record.is_supplementary();
+            // Count each read once, from its primary alignment or that of its first mate
+            if primary && (!record.is_paired() || record.is_first_in_template()) {
+                summary.add(label.clone(), assignment.probability);
+            }
+            if let Some((rules, writers)) = extraction {
+                let destinations = rules.destinations(assignment, strain_sites.strains.len());
+                if primary && !destinations.is_empty() {
+                    let name = String::from_utf8_lossy(record.qname()).to_string();
+                    let bases = fastq_bases(
+                        &record.seq().as_bytes(), record.qual(), record.is_reverse());
+                    if record.is_paired() && !long {
+                        match waiting_mates.remove(record.qname()) {
+                            Some((mate_first, mate)) => {
+                                if mate_first {
+                                    writers.write_pair(&destinations, &name, &mate, &bases);
+                                } else {
+                                    writers.write_pair(&destinations, &name, &bases, &mate);
+                                }
+                            },
+                            None => {
+                                waiting_mates.insert(
+                                    record.qname().to_vec(), (record.is_first_in_template(), bases));
+                            },
+                        }
+                    } else {
+                        writers.write_single(&destinations, &name, &bases, *long);
+                    }
+                }
+            }
+            record.push_aux(STRAIN_TAG, &bam::record::Aux::String(label.as_bytes()));
+            record.push_aux(PROBABILITY_TAG, &bam::record::Aux::Float(assignment.probability));
+            writer.write(&record).expect("Failed to write BAM record");
+        }
+        // Mates missing from the BAM file leave their pair to be written as single reads
+        if let Some((rules, writers)) = extraction {
+            for (qname, (_, bases)) in waiting_mates.into_iter() {
+                let assignment = assignments.get(&qname).unwrap_or(&unassigned);
+                writers.write_single(&rules.destinations(assignment, strain_sites.strains.len()),
+                                     &String::from_utf8_lossy(&qname), &bases, false);
+            }
+        }
+        info!("Assigned reads in {} to {} strains", bam_path, strain_sites.strains.len());
+        (sample, summary)
+    }).collect();
+
+    // The files of a sample with both short and long reads are summarised together
+    let mut merged_summaries: Vec<(String, AssignmentSummary)> = Vec::new();
+    for (sample, summary) in summaries.into_iter() {
+        match merged_summaries.iter_mut().find(|(merged_sample, _)| *merged_sample == sample) {
+            Some((_, merged)) => merged.merge(summary),
+            None => merged_summaries.push((sample, summary)),
+        }
     }
+    let summaries = merged_summaries;
+
+    write_assignment_summary(&format!("{}_read_assignments.tsv", output_prefix), &summaries);
 }
 
-/// Process all reads in a BAM file
+/// Process all reads in a BAM file. sample_idx is the index of the sample in the variant
+/// matrix, while bam_idx is the index of the BAM among those from the same technology
 fn process_bam<R: NamedBamReader + Send,
                 G: NamedBamReaderGenerator<R> + Send>(
     bam_generator: G,
     sample_idx: usize,
+    bam_idx: usize,
     sample_count: usize,
     reference: &Arc<Mutex<bio::io::fasta::IndexedReader<File>>>,
     coverage_estimators: &Arc<Mutex<&mut Vec<CoverageEstimator>>>,
@@ -232,22 +550,20 @@ fn process_bam<R: NamedBamReader + Send,
     read_cnt_id: &Arc<Mutex<i64>>,
     read_to_id: &Arc<Mutex<HashMap<Vec<u8>, i64>>>,
     split_threads: usize,
-    m: &clap::ArgMatches,
-    output_prefix: &str,
-    coverage_fold: f32,
+    config: &LorikeetConfig,
     codon_table: &CodonTable,
-    min_var_depth: usize,
-    contig_end_exclusion: u64,
-    min: f32, max: f32,
     ani: f32,
     mode: &str,
-    include_soft_clipping: bool,
-    include_indels: bool,
-    flag_filters: &FlagFilter,
-    mapq_threshold: u8,
-    method: &str,
     longread: bool) {
 
+    let flag_filters = &config.flag_filters;
+    let mapq_threshold = config.mapq_threshold;
+    let coverage_fold = config.coverage_fold;
+    let min_var_depth = config.min_variant_depth;
+    let contig_end_exclusion = config.contig_end_exclusion;
+    let (min, max) = (config.trim_min, config.trim_max);
+    let method = config.method.as_str();
+
     let mut bam_generated = bam_generator.start();
 
     let mut bam_properties =
@@ -282,13 +598,7 @@ fn process_bam<R: NamedBamReader + Send,
     while bam_generated.read(&mut record)
         .expect("Error while reading BAM record") == true {
 
-        if (!flag_filters.include_supplementary && record.is_supplementary() && !longread) ||
-            (!flag_filters.include_secondary && record.is_secondary() && !longread) ||
-            (!flag_filters.include_improper_pairs && !record.is_proper_pair() && !longread){
-            skipped_reads += 1;
-            continue;
-        } else if (!flag_filters.include_supplementary && record.is_supplementary() && longread) ||
-            (!flag_filters.include_secondary && record.is_secondary() && longread) {
+        if !passes_flag_filters(&record, flag_filters, longread) {
             skipped_reads += 1;
             continue;
         }
@@ -301,18 +611,6 @@ fn process_bam<R: NamedBamReader + Send,
                 skipped_reads += 1;
                 continue;
             }
-            if longread {
-                // Check if new read to id
-                if !read_to_id.lock().unwrap().contains_key(&record.qname().to_vec()) {
-                    let mut read_to_id = read_to_id.lock().unwrap();
-                    let mut read_cnt_id = read_cnt_id.lock().unwrap();
-                    read_to_id.entry(record.qname().to_vec())
-                        .or_insert(*read_cnt_id);
-                    *read_cnt_id += 1;
-                }
-            }
-
-
             // if reference has changed, print the last record
             if tid != last_tid {
                 if last_tid != -2 {
@@ -344,9 +642,7 @@ fn process_bam<R: NamedBamReader + Send,
                         &codon_table,
                         coverage_fold,
                         num_mapped_reads_in_current_contig,
-                        sample_count,
-                        output_prefix,
-                        &stoit_name);
+                        sample_count);
                 }
 
                 ups_and_downs = vec![0; header.target_len(tid as u32).expect("Corrupt BAM file?") as usize];
@@ -378,8 +674,6 @@ fn process_bam<R: NamedBamReader + Send,
 
             num_mapped_reads_in_current_contig += 1;
 
-            // Lock variant matrix to collect variants
-            let mut variant_matrix = variant_matrix.lock().unwrap();
             // for each chunk of the cigar string
             let mut cursor: usize = record.pos() as usize;
             let quals = record.qual();
@@ -390,33 +684,7 @@ fn process_bam<R: NamedBamReader + Send,
                         // if M, X, or = increment start and decrement end index
                         ups_and_downs[cursor] += 1;
                         let final_pos = cursor + cig.len() as usize;
-                        if longread {
-                            for qpos in read_cursor..(read_cursor + cig.len() as usize) {
-                                let mut current_variants = variant_matrix.variants(tid, cursor as i64).unwrap();
-                                let read_char = record.seq()[qpos];
-                                let refr_char = ref_seq[cursor as usize];
-                                current_variants.par_iter_mut().for_each(|(variant, base)|{
-                                    match variant {
-                                        Variant::SNV(alt) => {
-                                            if *alt != refr_char && *alt == read_char {
-                                                let mut read_to_id = read_to_id.lock().unwrap();
-                                                base.assign_read(read_to_id[&record.qname().to_vec()])
-                                            }
-                                        },
-                                        Variant::None => {
-                                            if refr_char == read_char {
-                                                let mut read_to_id = read_to_id.lock().unwrap();
-                                                base.assign_read(read_to_id[&record.qname().to_vec()])
-                                            }
-                                        },
-                                        _ => {}
-                                    }
-                                });
-                                cursor += 1;
-                            }
-                        } else {
-                            cursor += cig.len() as usize;
-                        }
+                        cursor = final_pos;
                         if final_pos < ups_and_downs.len() { // True unless the read hits the contig end.
                             ups_and_downs[final_pos] -= 1;
                         }
@@ -536,9 +804,7 @@ fn process_bam<R: NamedBamReader + Send,
             &codon_table,
             coverage_fold,
             num_mapped_reads_in_current_contig,
-            sample_count,
-            output_prefix,
-            &stoit_name);
+            sample_count);
 
         num_mapped_reads_total += num_mapped_reads_in_current_contig;
     }
@@ -560,8 +826,8 @@ fn process_bam<R: NamedBamReader + Send,
     bam_generated.finish();
 
     let mut vcf_reader = get_vcf(&bam_name,
-                                 &m,
-                                 sample_idx,
+                                 config,
+                                 bam_idx,
                                  split_threads,
                                  longread);
     vcf_reader.set_threads(split_threads);
@@ -589,8 +855,92 @@ fn process_bam<R: NamedBamReader + Send,
             panic!("Bug: VCF record reference ids do not match BAM reference ids. Perhaps BAM is unsorted?")
         }
     });
-    let mut variant_matrix = variant_matrix.lock().unwrap();
-    variant_matrix.add_sample(stoit_name, sample_idx, variant_map);
+    variant_matrix.lock().unwrap().add_sample(
+        config.sample_name(sample_idx, &stoit_name, longread), sample_idx, variant_map);
+
+    // Linkage needs the reads carrying each allele, which can only be found once the
+    // sample's variants have been added
+    if mode == "summarize" {
+        let bam_path = sample_bam_path(&bam_name, config, bam_idx, longread);
+        assign_reads_to_variants(&bam_path, config, variant_matrix, read_cnt_id, read_to_id, longread);
+    }
+}
+
+/// Whether an alignment passes the flag filters. Improper pairs are only filtered from
+/// short reads
+fn passes_flag_filters(record: &bam::Record, flag_filters: &FlagFilter, longread: bool) -> bool {
+    !((!flag_filters.include_supplementary && record.is_supplementary()) ||
+        (!flag_filters.include_secondary && record.is_secondary()) ||
+        (!flag_filters.include_improper_pairs && !record.is_proper_pair() && !longread))
+}
+
+/// Reads the alignments of a sample again once its variants are in the variant matrix, adding
+/// each read to the read sets of the alleles it carries. Reads are identified by name, so both
+/// reads of a pair share an id and alleles seen on either mate are linked
+fn assign_reads_to_variants(bam_path: &str,
+                            config: &LorikeetConfig,
+                            variant_matrix: &Arc<Mutex<VariantMatrix>>,
+                            read_cnt_id: &Arc<Mutex<i64>>,
+                            read_to_id: &Arc<Mutex<HashMap<Vec<u8>, i64>>>,
+                            longread: bool) {
+    use rust_htslib::bam::Read;
+
+    let mut reader = open_reader(bam_path, Some(config.reference.as_str()));
+    let header = reader.header().clone();
+    let mut reference = indexed_reference(&config.reference);
+    let mut ref_seq: Vec<u8> = Vec::new();
+    let mut last_tid = -1;
+    for record in reader.records() {
+        let record = record.expect("Error while reading BAM record");
+        if record.is_unmapped() || record.seq().len() == 0
+            || record.mapq() < config.mapq_threshold
+            || !passes_flag_filters(&record, &config.flag_filters, longread) {
+            continue
+        }
+        let tid = record.tid();
+        if tid != last_tid {
+            let contig_name = str::from_utf8(header.tid2name(tid as u32)).unwrap();
+            reference.fetch_all(contig_name)
+                .expect(&format!("Cannot read sequence {} from reference", contig_name));
+            ref_seq = Vec::new();
+            reference.read(&mut ref_seq)
+                .expect(&format!("Cannot read sequence {} from reference", contig_name));
+            last_tid = tid;
+        }
+
+        let read_id = {
+            let mut read_to_id = read_to_id.lock().unwrap();
+            let mut read_cnt_id = read_cnt_id.lock().unwrap();
+            let next_id = *read_cnt_id;
+            let read_id = *read_to_id.entry(record.qname().to_vec()).or_insert(next_id);
+            if read_id == next_id {
+                *read_cnt_id += 1;
+            }
+            read_id
+        };
+
+        let mut variant_matrix = variant_matrix.lock().unwrap();
+        let mut cursor = record.pos() as usize;
+        let mut read_cursor = 0;
+        for cig in record.cigar().iter() {
+            match cig {
+                Cigar::Match(_) | Cigar::Diff(_) | Cigar::Equal(_) => {
+                    for qpos in read_cursor..(read_cursor + cig.len() as usize) {
+                        // Only positions with known variants have read sets to extend
+                        if let Some(current_variants) = variant_matrix.variants(tid, cursor as i64) {
+                            assign_read_to_alleles(current_variants, record.seq()[qpos],
+                                                   ref_seq[cursor], read_id);
+                        }
+                        cursor += 1;
+                    }
+                    read_cursor += cig.len() as usize;
+                },
+                Cigar::Del(_) | Cigar::RefSkip(_) => cursor += cig.len() as usize,
+                Cigar::Ins(_) | Cigar::SoftClip(_) => read_cursor += cig.len() as usize,
+                Cigar::HardClip(_) | Cigar::Pad(_) => {},
+            }
+        }
+    }
 }
 
 fn process_previous_contigs_var(
@@ -615,9 +965,7 @@ fn process_previous_contigs_var(
     codon_table: &CodonTable,
     coverage_fold: f32,
     num_mapped_reads_in_current_contig: u64,
-    sample_count: usize,
-    output_prefix: &str,
-    stoit_name: &str) {
+    sample_count: usize) {
 
     if last_tid != -2 {
         let mut coverage_estimators = coverage_estimators.lock().unwrap();
@@ -671,17 +1019,7 @@ fn process_previous_contigs_var(
 
 
         match mode {
-            "polymorph" => {
-
-                // calculates minimum number of genotypes possible for each variant location
-//                variant_struct.generate_minimum_genotypes();
-                if variant_struct.len() > 0 {
-
-//                    variant_struct.print_variants(&ref_sequence, stoit_name);
-                }
-
-            },
-            "summarize" | "genotype" => {
+            "polymorph" | "summarize" | "genotype" | "polish" => {
                 let mut variant_matrix = variant_matrix.lock().unwrap();
                 // calculates minimum number of genotypes possible for each variant location
                 variant_matrix.add_contig(variant_struct,
@@ -690,68 +1028,99 @@ fn process_previous_contigs_var(
                                          ref_sequence);
             },
             "evolve" => {
-                let gff_map = gff_map.lock().unwrap();
-                variant_struct.calc_gene_mutations(&*gff_map, &ref_sequence, codon_table);
+                let gene_dnds = {
+                    let gff_map = gff_map.lock().unwrap();
+                    variant_struct.calc_gene_mutations(&*gff_map, &ref_sequence, codon_table)
+                };
+                let mut variant_matrix = variant_matrix.lock().unwrap();
+                variant_matrix.add_gene_dnds(sample_idx, gene_dnds);
             },
-            "polish" => {
-                let stoit_name = stoit_name
-                    .split("..").last().unwrap()
-                    .split("/").last().unwrap();
-                let output_prefix = output_prefix.to_string() + "_" + stoit_name;
-                variant_struct.polish_contig(&ref_sequence,
-                                            &output_prefix);
-            }
             _ => {panic!("unknown mode {}", mode);},
         }
     }
 }
 
 /// Get or generate vcf file
-pub fn get_vcf(stoit_name: &str, m: &clap::ArgMatches,
+pub fn get_vcf(stoit_name: &str, config: &LorikeetConfig,
                sample_idx: usize, threads: usize, longread: bool) -> Reader {
     // if vcfs are already provided find correct one first
-    if m.is_present("vcfs") {
-        let vcf_paths: Vec<&str> = m.values_of("vcfs").unwrap().collect();
+    if !config.vcfs.is_empty() {
         // Filter out bams that don't have stoit name and get their sample idx
-        let vcf_path: Vec<&str> = vcf_paths.iter().cloned()
+        let vcf_path: Vec<&String> = config.vcfs.iter()
             .filter(|x| x.contains(stoit_name)).collect();
 
-        if vcf_path.len() > 1 || vcf_path.len() == 0 {
-            info!("Could not associate VCF file with current BAM file. Re-running variant calling");
-            if longread {
-                let bam_path: &str = m.values_of("longread-bam-files").unwrap().collect::<Vec<&str>>()[sample_idx];
-                return generate_vcf(bam_path, m, threads, longread)
-            } else {
-                let bam_path: &str = m.values_of("bam-files").unwrap().collect::<Vec<&str>>()[sample_idx];
-                return generate_vcf(bam_path, m, threads, longread)
-            }
-        } else {
-            let vcf_path = vcf_path[0];
-            let vcf = Reader::from_path(vcf_path).unwrap();
-            return vcf
+        if vcf_path.len() == 1 {
+            return Reader::from_path(vcf_path[0]).unwrap()
         }
-    } else if longread {
-        let bam_path: &str = m.values_of("longread-bam-files").unwrap().collect::<Vec<&str>>()[sample_idx];
-        return generate_vcf(bam_path, m, threads, longread)
-    } else if m.is_present("bam-files") {
-        let bam_path: &str = m.values_of("bam-files").unwrap().collect::<Vec<&str>>()[sample_idx];
-        return generate_vcf(bam_path, m, threads, longread)
+        info!("Could not associate VCF file with current BAM file. Re-running variant calling");
+    }
+    let bam_path = sample_bam_path(stoit_name, config, sample_idx, longread);
+    generate_vcf(&bam_path, config, threads, longread)
+}
+
+/// Path of the alignments of a sample, given the name of its BAM reader and its index among
+/// the samples of the same technology
+fn sample_bam_path(stoit_name: &str, config: &LorikeetConfig,
+                   sample_idx: usize, longread: bool) -> String {
+    if longread {
+        config.longread_bam_files[sample_idx].clone()
+    } else if !config.bam_files.is_empty() {
+        config.bam_files[sample_idx].clone()
     } else {
         // We are streaming a generated bam file, so we have had to cache the bam for this to work
-        let cache = m.value_of("outdir").unwrap().to_string() + "/";
+        let cache = config.bam_cache_directory.as_ref()
+            .expect("Mapped reads must be cached for variant calling").to_string() + "/";
         let stoit_name: Vec<&str> = stoit_name.split("/").collect();
         let stoit_name = stoit_name.join(".");
         let stoit_name = stoit_name.replace(|c: char| !c.is_ascii(), "");
-        let bam_path = (cache + &(stoit_name + ".bam"));
+        let bam_path = cache + &(stoit_name + ".bam");
         info!("Cached bam path {} ", bam_path);
-
-        return generate_vcf(&bam_path, m, threads, longread)
+        bam_path
     }
-
 }
 
-/// Makes direct call to pilon or sniffles
-pub fn generate_vcf(bam_path: &str, m: &clap::ArgMatches, threads: usize, longread: bool) -> Reader {
+/// Makes direct call to pilon or sniffles. With the result cache, the sorted alignments and
+/// the variant calls are stored there and reused for the same reference and alignments
+pub fn generate_vcf(bam_path: &str, config: &LorikeetConfig, threads: usize, longread: bool) -> Reader {
+    let reference = config.reference.as_str();
+    // Sniffles' own default minimum length applies unless smaller indels are wanted, and its
+    // CCS preset is used for PacBio reads of a sample sheet
+    let (caller, caller_options) = if longread {
+        match config.long_read_technology(bam_path) {
+            Some(ReadTechnology::PacBio) =>
+                ("sniffles", format!("-l {} --ccs_reads", config.min_long_indel_length)),
+            _ => ("sniffles", format!("-l {}", config.min_long_indel_length)),
+        }
+    } else {
+        ("pilon", "--fix none".to_string())
+    };
+
+    // The cache along with the keys of the sorted alignments and of their variant calls.
+    // Alignments mapped into the cache are already sorted and indexed, and are keyed by the
+    // key of their mapping, so they are neither hashed nor sorted again
+    let cache = config.result_cache.as_ref().map(|directory| {
+        let cache = ResultCache::new(directory);
+        let reference_digest = ResultCache::file_digest(reference);
+        let (alignment_digest, sorted_key) = match cache.entry_key(bam_path) {
+            Some(mapping_key) => (mapping_key, None),
+            None => {
+                let alignment_digest = ResultCache::file_digest(bam_path);
+                let sorted_key = ResultCache::key(
+                    &[&reference_digest, &alignment_digest, "sorted"]);
+                (alignment_digest, Some(sorted_key))
+            },
+        };
+        let vcf_key = ResultCache::key(
+            &[&reference_digest, &alignment_digest, caller, &caller_options]);
+        (cache, sorted_key, vcf_key)
+    });
+    if let Some((cache, _, vcf_key)) = &cache {
+        if let Some(entry) = cache.get(vcf_key) {
+            info!("Using cached {} variant calls for {}", caller, bam_path);
+            return Reader::from_path(entry_file(&entry, "variants.vcf"))
+                .expect("Failed to read cached vcf")
+        }
+    }
 
     // setup temp directory
     let tmp_dir = TempDir::new("lorikeet_fifo")
@@ -768,21 +1137,48 @@ pub fn generate_vcf(bam_path: &str, m: &clap::ArgMatches, threads: usize, longre
         .prefix("lorikeet-vcf")
         .tempfile_in(tmp_dir.path())
         .expect(&format!("Failed to create vcf tempfile"));
+
+    // BAM files are sorted in place, while CRAM files are left untouched and sorted into a
+    // temporary BAM file for pilon and sniffles. With the result cache, both are sorted into
+    // a partial result of the cache instead, unless already sorted there
+    let input_path = bam_path;
+    let mut sorted_partial = None;
+    let (sorted_bam, sorted) = match &cache {
+        Some((_, None, _)) => (bam_path.to_string(), true),
+        Some((cache, Some(sorted_key), _)) => match cache.get(sorted_key) {
+            Some(entry) => (entry_file(&entry, "sorted.bam"), true),
+            None => {
+                let partial = cache.partial(sorted_key);
+                let sorted_bam = entry_file(&partial, "sorted.bam");
+                sorted_partial = Some(partial);
+                (sorted_bam, false)
+            },
+        },
+        None => match AlignmentFormat::from_path(input_path) {
+            AlignmentFormat::Cram => (tmp_dir.path().join("sorted.bam").to_str()
+                .expect("Failed to convert tempfile path to str").to_string(), false),
+            AlignmentFormat::Bam => (bam_path.to_string(), false),
+        },
+    };
+    let bam_path = sorted_bam.as_str();
+    let sort_command = match sorted {
+        true => String::new(),
+        false => format!(
+            "{} && samtools index -@ {} {} {} && ",
+            sort_to_bam_command(input_path, bam_path, reference, threads - 1),
+            threads - 1,
+            bam_path,
+            &(bam_path.to_string() + ".bai")),
+    };
     if !longread {
         external_command_checker::check_for_pilon();
         external_command_checker::check_for_samtools();
         let cmd_string = format!(
-            "set -e -o pipefail; samtools sort -O BAM -@ {} -o '{}' {} && \
-                     samtools index -@ {} {} {} && \
-                     pilon --genome {} --bam {} --vcf --fix none --output {} --threads {}",
-            threads - 1,
-            bam_path,
-            bam_path,
-            threads - 1,
-            bam_path,
-            &(bam_path.to_string() + ".bai"),
-            m.value_of("reference").unwrap(),
+            "set -e -o pipefail; {}pilon --genome {} --bam {} --vcf {} --output {} --threads {}",
+            sort_command,
+            reference,
             bam_path,
+            caller_options,
             vcf_file.path().to_str()
                 .expect("Failed to convert tempfile path to str"),
             threads);
@@ -797,8 +1193,7 @@ pub fn generate_vcf(bam_path: &str, m: &clap::ArgMatches, threads: usize, longre
                 .expect("Unable to execute bash"), "pilon");
         let vcf_path = &(vcf_file.path().to_str().unwrap().to_string() + ".vcf");
         debug!("VCF Path {:?}", vcf_path);
-        let vcf_reader = Reader::from_path(vcf_path)
-            .expect("Failed to read pilon vcf output");
+        let vcf_reader = read_called_variants(vcf_path, &cache, &sorted_partial, caller);
 
         tmp_dir.close().expect("Failed to close temp directory");
         return vcf_reader
@@ -810,21 +1205,15 @@ pub fn generate_vcf(bam_path: &str, m: &clap::ArgMatches, threads: usize, longre
             .expect(&format!("Failed to create vcf tempfile"));
 
         let cmd_string = format!(
-            "set -e -o pipefail; samtools sort -O BAM -@ {} -o '{}' {} && \
-                     samtools index -@ {} {} {} && \
-                     sniffles -m {} -v {} --tmp_file {} --threads {}",
-            threads - 1,
-            bam_path,
-            bam_path,
-            threads - 1,
-            bam_path,
-            &(bam_path.to_string() + ".bai"),
+            "set -e -o pipefail; {}sniffles -m {} -v {} --tmp_file {} --threads {} {}",
+            sort_command,
             bam_path,
             vcf_file.path().to_str()
                 .expect("Failed to convert tempfile path to str"),
             sniff_file.path().to_str()
                 .expect("Failed to convert tempfile path to str"),
-            threads);
+            threads,
+            caller_options);
         info!("Queuing cmd_string: {}", cmd_string);
         command::finish_command_safely(
             std::process::Command::new("bash")
@@ -836,14 +1225,34 @@ pub fn generate_vcf(bam_path: &str, m: &clap::ArgMatches, threads: usize, longre
                 .expect("Unable to execute bash"), "sniffles");
         let vcf_path = vcf_file.path();
         debug!("VCF Path {:?}", vcf_path);
-        let vcf_reader = Reader::from_path(vcf_path)
-            .expect("Failed to read sniffles vcf output");
+        let vcf_reader = read_called_variants(
+            vcf_path.to_str().expect("Failed to convert tempfile path to str"), &cache,
+            &sorted_partial, caller);
 
         tmp_dir.close().expect("Failed to close temp directory");
         return vcf_reader
     }
 }
 
+/// Reads the variant calls of pilon or sniffles, first storing them and the alignments they
+/// were called from, when sorted into sorted_partial, in the result cache when it is used
+fn read_called_variants(vcf_path: &str, cache: &Option<(ResultCache, Option<String>, String)>,
+                        sorted_partial: &Option<PathBuf>, caller: &str) -> Reader {
+    let vcf_path = match cache {
+        Some((cache, sorted_key, vcf_key)) => {
+            if let (Some(sorted_key), Some(sorted_partial)) = (sorted_key, sorted_partial) {
+                cache.commit(sorted_key, sorted_partial);
+            }
+            let partial = cache.partial(vcf_key);
+            std::fs::copy(vcf_path, partial.join("variants.vcf"))
+                .expect("Unable to copy variant calls to the result cache");
+            entry_file(&cache.commit(vcf_key, &partial), "variants.vcf")
+        },
+        None => vcf_path.to_string(),
+    };
+    Reader::from_path(&vcf_path).expect(&format!("Failed to read {} vcf output", caller))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,16 +1,12 @@
 use std::collections::{HashMap, HashSet, BTreeMap};
-use std::str;
 use std::sync::{Arc, Mutex};
-use std::io::prelude::*;
 use rayon::prelude::*;
 use estimation::codon_structs::*;
+use estimation::variant_annotation::{gene_id, group_segments};
 use bio_types::strand;
 use linregress::{FormulaRegressionBuilder, RegressionDataBuilder};
 use rust_htslib::bcf::record;
 
-use std::path::Path;
-use std::fs::OpenOptions;
-
 use model::variants::*;
 
 pub enum VariantStats {
@@ -98,16 +94,12 @@ pub trait VariantFunctions {
 //                       min_variant_depth: usize,
 //                       coverage_fold: f64);
 
-    /// Replace reference variants with most dominant variant observed within the reads
-    fn polish_contig(&mut self,
-                     original_contig: &Vec<u8>,
-                     output_prefix: &str);
-
-    /// Perform dN/dS calculations based on read mapping using modified Jukes-Cantor method
+    /// Perform dN/dS calculations based on read mapping using modified Jukes-Cantor method.
+    /// Returns the ID and dN/dS of each gene on the contig
     fn calc_gene_mutations(&mut self,
                            gff_map: &HashMap<String, Vec<bio::io::gff::Record>>,
                            ref_sequence: &Vec<u8>,
-                           codon_table: &CodonTable);
+                           codon_table: &CodonTable) -> Vec<(String, f64)>;
 
 //    /// Prints out variant info for current contig
 //    fn print_variants(&mut self, ref_sequence: &Vec<u8>, stoit_name: &str);
@@ -446,100 +438,10 @@ impl VariantFunctions for VariantStats {
 //    }
 
 
-    fn polish_contig(&mut self,
-                     original_contig: &Vec<u8>,
-                     output_prefix: &str) {
-        match self {
-            VariantStats::VariantContigStats {
-                ref mut variants,
-                target_name,
-                ..
-            } => {
-                let file_name = output_prefix.to_string()
-                    + &"_polished.fna".to_owned();
-
-                let file_path = Path::new(&file_name);
-
-                // Open haplotype file or create one
-
-                let mut file_open = OpenOptions::new().append(true).create(true)
-                    .open(file_path).expect("No Read or Write Permission in current directory");
-                file_open.write(b">").unwrap();
-                file_open.write(target_name).unwrap();
-                file_open.write(b"\n").unwrap();
-
-
-                let mut contig = String::new();
-
-                let mut variations = 0;
-                let mut skip_n = 0;
-                let mut skip_cnt = 0;
-                // Generate the consensus genome by checking each variant
-                // Variant has to be in more than 0.5 of population
-                for (pos, base) in original_contig.iter().enumerate() {
-                    if skip_cnt < skip_n {
-                        skip_cnt += 1;
-                    } else {
-                        let mut max_var = Variant::None;
-                        let mut max_abund = 0.0;
-                        skip_n = 0;
-                        skip_cnt = 0;
-                        if variants.contains_key(&(pos as i64)){
-                            let alleles = &variants[&(pos as i64)];
-                            for (var, base) in alleles.iter() {
-                                if base.freq[0] > max_abund {
-                                    max_var = var.clone();
-                                    max_abund = base.freq[0];
-                                }
-                            }
-                            if max_abund >= 0.5 {
-                                match max_var {
-                                    Variant::Deletion(size) => {
-                                        // Skip the next n bases but rescue the reference prefix
-                                        skip_n = size - 1;
-                                        skip_cnt = 0;
-                                        variations += 1;
-                                    },
-                                    Variant::Insertion(alt) | Variant::Insertion(alt) => {
-                                        // Insertions have a reference prefix that needs to be removed
-                                        let removed_first_base = str::from_utf8(
-                                            &alt[1..]).unwrap();
-                                        contig = contig + removed_first_base;
-                                        variations += 1;
-                                    },
-                                    Variant::None => {
-                                        contig = contig + str::from_utf8(&[*base]).unwrap();
-                                    },
-                                    Variant::SNV(alt) => {
-                                        contig = contig + str::from_utf8(&[alt]).unwrap();
-                                        variations += 1;
-                                    },
-                                    _ => {
-                                        contig = contig + str::from_utf8(&[*base]).unwrap();
-                                    }
-                                }
-                            } else {
-                                contig = contig + str::from_utf8(&[*base]).unwrap();
-                            }
-                        } else {
-                            contig = contig + str::from_utf8(&[*base]).unwrap();
-                        }
-                    }
-                };
-//                contig = contig + "\n";
-                for line in contig.as_bytes().to_vec()[..].chunks(60).into_iter(){
-                    file_open.write(line).unwrap();
-                    file_open.write(b"\n").unwrap();
-                };
-//                writeln!(file_open, "{:?}", contig);
-            }
-        }
-    }
-
     fn calc_gene_mutations(&mut self,
                            gff_map: &HashMap<String, Vec<bio::io::gff::Record>>,
                            ref_sequence: &Vec<u8>,
-                           codon_table: &CodonTable) {
+                           codon_table: &CodonTable) -> Vec<(String, f64)> {
         match self {
             VariantStats::VariantContigStats {
                 variants,
@@ -555,10 +457,13 @@ impl VariantFunctions for VariantStats {
                     Some(records) => records,
                     None => &placeholder,
                 };
-                debug!("Calculating population dN/dS from reads for {} genes", gff_records.len());
+                // Segments of spliced and origin spanning genes are joined into one gene
+                let genes = group_segments(gff_records);
+                debug!("Calculating population dN/dS from reads for {} genes", genes.len());
                 let print_stream = Arc::new(Mutex::new(std::io::stdout()));
-                gff_records.par_iter().enumerate().for_each(|(_id, gene)| {
-                    let dnds = codon_table.find_mutations(gene, variants, ref_sequence, depth);
+                genes.par_iter().enumerate().map(|(_id, segments)| {
+                    let gene = segments[0];
+                    let dnds = codon_table.find_mutations(segments, variants, ref_sequence, depth);
                     let strand = gene.strand().expect("No strandedness found");
                     let frame: usize = gene.frame().parse().unwrap();
                     let start = gene.start().clone() as usize - 1;
@@ -573,7 +478,7 @@ impl VariantFunctions for VariantStats {
                         }
 
                     };
-                    let gene_id = &gene.attributes()["ID"].split("_").collect::<Vec<&str>>()[1];
+                    let gene_id = gene_id(gene);
                     let mut contig = gene.seqname().to_owned();
                     contig = contig + "_";
 
@@ -618,11 +523,10 @@ impl VariantFunctions for VariantStats {
 //                            }
 //                        }
 //                    }
-                });
-
+                    (gene_id, dnds)
+                }).collect()
             }
         }
-
     }
 
 //    fn print_variants(&mut self, ref_sequence: &Vec<u8>, stoit_name: &str){
@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use statrs::function::erf::erfc;
+
+/// Stratum given to samples when the sample groups file has no stratum column
+pub const DEFAULT_STRATUM: &str = "all";
+
+/// Header matching the columns produced by [CmhTest::to_tsv]
+pub const CMH_HEADER: &str = "oddsRatio\tstatistic\tpValue";
+
+/// Group and stratum a sample belongs to for differential allele frequency testing
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleGroup {
+    pub group: String,
+    pub stratum: String,
+}
+
+pub fn read_sample_groups(path: &str) -> HashMap<String, SampleGroup> {
+    let file = File::open(path)
+        .expect(&format!("Unable to open sample groups file {}", path));
+    parse_sample_groups(file)
+}
+
+/// Parse a tab separated table of sample name, group and an optional stratum. Blank lines,
+/// lines starting with # and a header line starting with "sample" are skipped
+pub fn parse_sample_groups<R: Read>(reader: R) -> HashMap<String, SampleGroup> {
+    let mut groups = HashMap::new();
+    for (line_idx, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line.expect("Unable to read sample groups");
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue
+        }
+        let fields: Vec<&str> = line.split('\t').map(|field| field.trim()).collect();
+        if line_idx == 0 && fields[0].eq_ignore_ascii_case("sample") {
+            continue
+        }
+        if fields.len() < 2 {
+            panic!("Sample groups line {} should have sample and group columns", line_idx + 1);
+        }
+        groups.insert(fields[0].to_string(), SampleGroup {
+            group: fields[1].to_string(),
+            stratum: fields.get(2).unwrap_or(&DEFAULT_STRATUM).to_string(),
+        });
+    }
+    groups
+}
+
+/// Samples of groups A and B within each stratum, in order of the strata's first samples.
+/// The reads of each group's samples in a stratum are pooled into one table of the CMH
+/// test, so every sample is tested even when the groups have different numbers of samples.
+/// Strata with samples of only one group add nothing to the test, but are kept as their
+/// samples still count towards the group's mean frequency
+pub fn stratify_samples<'a>(sample_groups: &[&'a SampleGroup], group_a: &str, group_b: &str)
+                            -> Vec<(&'a str, Vec<usize>, Vec<usize>)> {
+    let mut strata: Vec<&str> = Vec::new();
+    for group in sample_groups.iter() {
+        if !strata.contains(&group.stratum.as_str()) {
+            strata.push(&group.stratum);
+        }
+    }
+    strata.into_iter().map(|stratum| {
+        let members = |name: &str| -> Vec<usize> {
+            sample_groups.iter().enumerate()
+                .filter(|(_, group)| group.stratum == stratum && group.group == name)
+                .map(|(sample_idx, _)| sample_idx)
+                .collect()
+        };
+        (stratum, members(group_a), members(group_b))
+    }).filter(|(_, samples_a, samples_b)| !samples_a.is_empty() || !samples_b.is_empty())
+        .collect()
+}
+
+/// Reads carrying the alternate allele and any other allele in a sample of each of the
+/// two groups being compared
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StratumTable {
+    pub alt_a: f64,
+    pub other_a: f64,
+    pub alt_b: f64,
+    pub other_b: f64,
+}
+
+impl StratumTable {
+    /// Add a sample's reads as group A or B, splitting its depth by the allele frequency
+    pub fn add_sample(&mut self, in_group_a: bool, depth: i32, frequency: f64) {
+        let alt = (frequency * depth as f64).round();
+        let other = depth as f64 - alt;
+        if in_group_a {
+            self.alt_a += alt;
+            self.other_a += other;
+        } else {
+            self.alt_b += alt;
+            self.other_b += other;
+        }
+    }
+
+    fn total(&self) -> f64 {
+        self.alt_a + self.other_a + self.alt_b + self.other_b
+    }
+}
+
+/// Cochran-Mantel-Haenszel test of association between group and allele across strata
+#[derive(Debug, Clone, PartialEq)]
+pub struct CmhTest {
+    /// Mantel-Haenszel common odds ratio of carrying the alternate allele in group A
+    pub odds_ratio: Option<f64>,
+    pub statistic: f64,
+    pub p_value: f64,
+}
+
+impl CmhTest {
+    /// Columns matching [CMH_HEADER]
+    pub fn to_tsv(&self) -> String {
+        let odds_ratio = match self.odds_ratio {
+            Some(odds_ratio) => format!("{:.4}", odds_ratio),
+            None => "NA".to_string(),
+        };
+        format!("{}\t{:.4}\t{:.4e}", odds_ratio, self.statistic, self.p_value)
+    }
+}
+
+/// Continuity corrected CMH test over the tables of each stratum. Returns None when no
+/// table has reads in both groups and both alleles, as there is then nothing to test
+pub fn cmh_test(tables: &[StratumTable]) -> Option<CmhTest> {
+    let mut deviation = 0.;
+    let mut variance = 0.;
+    let mut odds_numerator = 0.;
+    let mut odds_denominator = 0.;
+    for table in tables.iter() {
+        let n = table.total();
+        if n < 2. {
+            continue
+        }
+        let group_a = table.alt_a + table.other_a;
+        let group_b = table.alt_b + table.other_b;
+        let alt = table.alt_a + table.alt_b;
+        let other = table.other_a + table.other_b;
+        deviation += table.alt_a - group_a * alt / n;
+        variance += group_a * group_b * alt * other / (n * n * (n - 1.));
+        odds_numerator += table.alt_a * table.other_b / n;
+        odds_denominator += table.other_a * table.alt_b / n;
+    }
+    if variance <= 0. {
+        return None
+    }
+    let corrected = (deviation.abs() - 0.5).max(0.);
+    let statistic = corrected * corrected / variance;
+    Some(CmhTest {
+        odds_ratio: if odds_denominator > 0. {
+            Some(odds_numerator / odds_denominator)
+        } else {
+            None
+        },
+        statistic,
+        // Upper tail of the chi-squared distribution with one degree of freedom
+        p_value: erfc((statistic / 2.).sqrt()),
+    })
+}
+
+/// Benjamini-Hochberg adjusted p-values, returned in the order of the input
+pub fn benjamini_hochberg(p_values: &[f64]) -> Vec<f64> {
+    let n = p_values.len();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|a, b| p_values[*b].partial_cmp(&p_values[*a]).unwrap());
+    let mut q_values = vec![0.; n];
+    let mut minimum: f64 = 1.;
+    for (rank_from_top, idx) in order.into_iter().enumerate() {
+        let rank = (n - rank_from_top) as f64;
+        minimum = minimum.min(p_values[idx] * n as f64 / rank);
+        q_values[idx] = minimum;
+    }
+    q_values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sample_groups() {
+        let table = "sample\tgroup\tstratum\n\
+                     S1\tcase\tbatch1\n\
+                     S2\tcontrol\n";
+        let groups = parse_sample_groups(table.as_bytes());
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups["S1"], SampleGroup { group: "case".to_string(), stratum: "batch1".to_string() });
+        assert_eq!(groups["S2"].stratum, DEFAULT_STRATUM);
+    }
+
+    #[test]
+    fn test_stratify_samples() {
+        let group = |group: &str, stratum: &str| SampleGroup {
+            group: group.to_string(), stratum: stratum.to_string() };
+        let groups = [
+            group("case", "batch1"), group("control", "batch1"), group("case", "batch1"),
+            group("control", "batch2"), group("case", "batch2"), group("control", "batch1"),
+            group("other", "batch3"), group("case", "batch2"), group("case", "batch4"),
+        ];
+        let groups: Vec<&SampleGroup> = groups.iter().collect();
+        let strata = stratify_samples(&groups, "case", "control");
+        assert_eq!(strata, vec![("batch1", vec![0, 2], vec![1, 5]),
+                                ("batch2", vec![4, 7], vec![3]),
+                                ("batch4", vec![8], vec![])]);
+    }
+
+    #[test]
+    fn test_cmh_test() {
+        let mut table = StratumTable::default();
+        table.add_sample(true, 100, 0.8);
+        table.add_sample(false, 100, 0.2);
+        assert_eq!(table, StratumTable { alt_a: 80., other_a: 20., alt_b: 20., other_b: 80. });
+
+        let test = cmh_test(&[table]).unwrap();
+        let variance = 100. * 100. * 100. * 100. / (200. * 200. * 199.);
+        let statistic = (30f64 - 0.5).powi(2) / variance;
+        assert!((test.statistic - statistic).abs() < 1e-9);
+        assert!((test.odds_ratio.unwrap() - 16.).abs() < 1e-9);
+        assert!(test.p_value < 1e-10);
+
+        // A chi-squared statistic of 3.84 is significant at 0.05
+        let p_value = erfc((3.841459f64 / 2.).sqrt());
+        assert!((p_value - 0.05).abs() < 1e-6);
+
+        // Identical groups are not different and monomorphic sites cannot be tested
+        let mut identical = StratumTable::default();
+        identical.add_sample(true, 50, 0.5);
+        identical.add_sample(false, 50, 0.5);
+        assert!(cmh_test(&[identical]).unwrap().p_value > 0.99);
+        let mut monomorphic = StratumTable::default();
+        monomorphic.add_sample(true, 50, 0.);
+        monomorphic.add_sample(false, 50, 0.);
+        assert_eq!(cmh_test(&[monomorphic]), None);
+    }
+
+    #[test]
+    fn test_benjamini_hochberg() {
+        let q_values = benjamini_hochberg(&[0.01, 0.04, 0.03, 0.005]);
+        let expected = [0.02, 0.04, 0.04, 0.02];
+        for (q, e) in q_values.iter().zip(expected.iter()) {
+            assert!((q - e).abs() < 1e-9, "{:?}", q_values);
+        }
+    }
+}
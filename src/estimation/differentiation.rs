@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use model::variants::*;
+use estimation::diversity::site_heterozygosity;
+
+/// Minimum frequency for an allele to count as present in a sample when calculating popANI
+pub const MIN_ALLELE_FREQUENCY: f64 = 0.05;
+
+/// Header matching the columns produced by [PairwiseComparison::to_tsv]
+pub const PAIRWISE_HEADER: &str = "comparedSites\tconSubstitutions\tpopSubstitutions\
+                                   \tconANI\tpopANI\tfst";
+
+/// Frequencies of the alleles observed at a site in one sample. Alternate SNVs are taken
+/// from the variants at the site and the reference allele makes up the remainder
+pub fn site_alleles(reference: u8, variants: Option<&HashMap<Variant, Base>>,
+                    sample_idx: usize) -> Vec<(u8, f64)> {
+    let mut alleles: Vec<(u8, f64)> = match variants {
+        Some(variants) => variants.iter()
+            .filter_map(|(variant, base)| match variant {
+                Variant::SNV(alt) => Some((*alt, base.allele_frequency(sample_idx))),
+                _ => None,
+            })
+            .filter(|(_, frequency)| *frequency > 0.)
+            .collect(),
+        None => Vec::new(),
+    };
+    let alt_total: f64 = alleles.iter().map(|(_, frequency)| frequency).sum();
+    if alt_total < 1. {
+        alleles.push((reference.to_ascii_uppercase(), 1. - alt_total));
+    }
+    alleles.sort_by(|a, b| a.0.cmp(&b.0));
+    alleles
+}
+
+/// Most frequent allele, breaking ties by base so that results are deterministic
+pub fn consensus(alleles: &[(u8, f64)]) -> Option<u8> {
+    alleles.iter()
+        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap().then(b.0.cmp(&a.0)))
+        .map(|(base, _)| *base)
+}
+
+fn frequency(alleles: &[(u8, f64)], base: u8) -> f64 {
+    alleles.iter().find(|(allele, _)| *allele == base).map(|(_, frequency)| *frequency).unwrap_or(0.)
+}
+
+/// Accumulated comparison of two samples over the sites covered in both
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PairwiseComparison {
+    pub compared_sites: usize,
+    /// Sites where the consensus alleles of the samples differ
+    pub con_substitutions: usize,
+    /// Sites where the samples share no allele above MIN_ALLELE_FREQUENCY
+    pub pop_substitutions: usize,
+    fst_numerator: f64,
+    fst_denominator: f64,
+}
+
+impl PairwiseComparison {
+    pub fn new() -> PairwiseComparison {
+        PairwiseComparison::default()
+    }
+
+    /// Add a site covered in both samples at the given depths
+    pub fn add_site(&mut self, alleles_a: &[(u8, f64)], depth_a: i32,
+                    alleles_b: &[(u8, f64)], depth_b: i32) {
+        self.compared_sites += 1;
+        if alleles_a.len() == 1 && alleles_b.len() == 1 && alleles_a[0].0 == alleles_b[0].0 {
+            // Both samples are fixed for the same allele
+            return
+        }
+
+        if consensus(alleles_a) != consensus(alleles_b) {
+            self.con_substitutions += 1;
+        }
+        let shared = alleles_a.iter().any(|(base, frequency_a)| {
+            *frequency_a >= MIN_ALLELE_FREQUENCY
+                && frequency(alleles_b, *base) >= MIN_ALLELE_FREQUENCY
+        });
+        if !shared {
+            self.pop_substitutions += 1;
+        }
+
+        // Hudson's FST following Bhatia et al. (2013), generalised to multiple alleles.
+        // The between population diversity is the probability two alleles drawn from
+        // different samples differ, and within diversity is corrected for read depth
+        let identity: f64 = alleles_a.iter()
+            .map(|(base, frequency_a)| frequency_a * frequency(alleles_b, *base))
+            .sum();
+        let between = 1. - identity;
+        let frequencies_a: Vec<f64> = alleles_a.iter().map(|(_, f)| *f).collect();
+        let frequencies_b: Vec<f64> = alleles_b.iter().map(|(_, f)| *f).collect();
+        let within = (site_heterozygosity(depth_a, &frequencies_a)
+            + site_heterozygosity(depth_b, &frequencies_b)) / 2.;
+        self.fst_numerator += between - within;
+        self.fst_denominator += between;
+    }
+
+    /// Merge the comparison of another region of the same pair of samples
+    pub fn combine(&mut self, other: &PairwiseComparison) {
+        self.compared_sites += other.compared_sites;
+        self.con_substitutions += other.con_substitutions;
+        self.pop_substitutions += other.pop_substitutions;
+        self.fst_numerator += other.fst_numerator;
+        self.fst_denominator += other.fst_denominator;
+    }
+
+    pub fn con_ani(&self) -> Option<f64> {
+        if self.compared_sites == 0 {
+            None
+        } else {
+            Some(1. - self.con_substitutions as f64 / self.compared_sites as f64)
+        }
+    }
+
+    pub fn pop_ani(&self) -> Option<f64> {
+        if self.compared_sites == 0 {
+            None
+        } else {
+            Some(1. - self.pop_substitutions as f64 / self.compared_sites as f64)
+        }
+    }
+
+    /// Ratio of averages estimate of FST across all compared sites
+    pub fn fst(&self) -> Option<f64> {
+        if self.fst_denominator <= 0. {
+            None
+        } else {
+            Some(self.fst_numerator / self.fst_denominator)
+        }
+    }
+
+    /// Columns matching [PAIRWISE_HEADER]
+    pub fn to_tsv(&self) -> String {
+        format!("{}\t{}\t{}\t{}\t{}\t{}",
+                self.compared_sites, self.con_substitutions, self.pop_substitutions,
+                format_statistic(self.con_ani()), format_statistic(self.pop_ani()),
+                format_statistic(self.fst()))
+    }
+}
+
+/// Formats an optional statistic, using NA when it could not be calculated
+pub fn format_statistic(statistic: Option<f64>) -> String {
+    match statistic {
+        Some(value) => format!("{:.6}", value),
+        None => "NA".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_identical_samples() {
+        let mut comparison = PairwiseComparison::new();
+        let alleles = vec![(b'A', 0.5), (b'C', 0.5)];
+        comparison.add_site(&alleles, 100, &alleles, 100);
+        comparison.add_site(&[(b'G', 1.)], 100, &[(b'G', 1.)], 100);
+        assert_eq!(comparison.compared_sites, 2);
+        assert_eq!(comparison.con_ani(), Some(1.));
+        assert_eq!(comparison.pop_ani(), Some(1.));
+        assert!(comparison.fst().unwrap() < 0.01);
+    }
+
+    #[test]
+    fn test_fixed_differences() {
+        let mut comparison = PairwiseComparison::new();
+        comparison.add_site(&[(b'A', 1.)], 50, &[(b'T', 1.)], 50);
+        comparison.add_site(&[(b'C', 1.)], 50, &[(b'C', 1.)], 50);
+        assert_eq!(comparison.con_ani(), Some(0.5));
+        assert_eq!(comparison.pop_ani(), Some(0.5));
+        assert_eq!(comparison.fst(), Some(1.));
+    }
+
+    #[test]
+    fn test_shared_minor_allele() {
+        // Consensus differs but the samples still share an allele, so only conANI drops
+        let mut comparison = PairwiseComparison::new();
+        comparison.add_site(&[(b'A', 0.8), (b'T', 0.2)], 50, &[(b'A', 0.1), (b'T', 0.9)], 50);
+        assert_eq!(comparison.con_substitutions, 1);
+        assert_eq!(comparison.pop_substitutions, 0);
+
+        // Biallelic Hudson FST matches the closed form of Bhatia et al. (2013)
+        let (p1, p2, n) = (0.8, 0.1, 50.);
+        let numerator = (p1 - p2) * (p1 - p2) - p1 * (1. - p1) / (n - 1.) - p2 * (1. - p2) / (n - 1.);
+        let denominator = p1 * (1. - p2) + p2 * (1. - p1);
+        assert!((comparison.fst().unwrap() - numerator / denominator).abs() < 1e-9);
+
+        let mut combined = PairwiseComparison::new();
+        combined.combine(&comparison);
+        combined.combine(&comparison);
+        assert_eq!(combined.compared_sites, 2);
+        assert_eq!(combined.fst(), comparison.fst());
+        assert_eq!(PairwiseComparison::new().to_tsv(), "0\t0\t0\tNA\tNA\tNA");
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use model::variants::*;
+
+/// Header matching the statistic columns produced by [DiversityStatistics::to_tsv]
+pub const DIVERSITY_HEADER: &str = "length\tcallableSites\tbreadth\tmeanDepth\
+                                    \tsegregatingSites\tpi\tthetaW\ttajimasD";
+
+/// Thresholds and window settings used when calculating diversity statistics
+#[derive(Debug, Clone)]
+pub struct DiversityParameters {
+    /// Minimum read depth for a site to be included
+    pub min_depth: i32,
+    /// Minimum fraction of a region's sites that must pass min_depth for it to be reported
+    pub min_breadth: f64,
+    pub window_size: usize,
+    pub window_step: usize,
+}
+
+impl DiversityParameters {
+    pub fn generate_from_clap(m: &clap::ArgMatches) -> DiversityParameters {
+        let window_size: usize = m.value_of("window-size").unwrap().parse()
+            .expect("Unable to parse --window-size");
+        let window_step = match m.value_of("window-step") {
+            Some(step) => step.parse().expect("Unable to parse --window-step"),
+            None => window_size,
+        };
+        if window_size == 0 || window_step == 0 {
+            panic!("--window-size and --window-step must be greater than 0");
+        }
+        DiversityParameters {
+            min_depth: m.value_of("min-site-depth").unwrap().parse()
+                .expect("Unable to parse --min-site-depth"),
+            min_breadth: m.value_of("min-breadth").unwrap().parse()
+                .expect("Unable to parse --min-breadth"),
+            window_size,
+            window_step,
+        }
+    }
+}
+
+impl Default for DiversityParameters {
+    fn default() -> DiversityParameters {
+        DiversityParameters {
+            min_depth: 5,
+            min_breadth: 0.5,
+            window_size: 1000,
+            window_step: 1000,
+        }
+    }
+}
+
+/// Read depth and allele frequencies at a single site in a single sample
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SiteDiversity {
+    pub depth: i32,
+    pub heterozygosity: f64,
+    pub segregating: bool,
+}
+
+impl SiteDiversity {
+    /// Summarise a site from its depth and the frequencies of the alternate SNVs
+    /// observed at it. The reference allele makes up the remaining frequency
+    pub fn new(depth: i32, alt_frequencies: &[f64]) -> SiteDiversity {
+        let alt_total: f64 = alt_frequencies.iter().sum();
+        let mut frequencies: Vec<f64> = alt_frequencies.iter().cloned()
+            .filter(|frequency| *frequency > 0.)
+            .collect();
+        if alt_total < 1. {
+            frequencies.push(1. - alt_total);
+        }
+        let segregating = frequencies.iter().filter(|frequency| **frequency > 1e-6).count() > 1;
+        SiteDiversity {
+            depth,
+            heterozygosity: site_heterozygosity(depth, &frequencies),
+            segregating,
+        }
+    }
+}
+
+/// Expected heterozygosity of a site, corrected for sampling depth
+pub fn site_heterozygosity(depth: i32, frequencies: &[f64]) -> f64 {
+    if depth < 2 {
+        return 0.
+    }
+    let n = depth as f64;
+    let homozygosity: f64 = frequencies.iter().map(|frequency| frequency * frequency).sum();
+    (n / (n - 1.) * (1. - homozygosity)).max(0.)
+}
+
+/// Harmonic numbers used by Watterson's estimator and Tajima's D
+fn harmonic(n: usize) -> (f64, f64) {
+    (1..n).fold((0., 0.), |(a1, a2), i| {
+        let i = i as f64;
+        (a1 + 1. / i, a2 + 1. / (i * i))
+    })
+}
+
+/// Tajima's D from the summed pairwise diversity, number of segregating sites and
+/// sample size. Returns None when there are no segregating sites
+pub fn tajimas_d(pi: f64, segregating_sites: usize, n: usize) -> Option<f64> {
+    if segregating_sites == 0 || n < 2 {
+        return None
+    }
+    let (a1, a2) = harmonic(n);
+    let n = n as f64;
+    let s = segregating_sites as f64;
+    let b1 = (n + 1.) / (3. * (n - 1.));
+    let b2 = 2. * (n * n + n + 3.) / (9. * n * (n - 1.));
+    let c1 = b1 - 1. / a1;
+    let c2 = b2 - (n + 2.) / (a1 * n) + a2 / (a1 * a1);
+    let e1 = c1 / a1;
+    let e2 = c2 / (a1 * a1 + a2);
+    let variance = e1 * s + e2 * s * (s - 1.);
+    if variance <= 0. {
+        return None
+    }
+    Some((pi - s / a1) / variance.sqrt())
+}
+
+/// π, θ_W and Tajima's D over a region for a single sample
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiversityStatistics {
+    pub length: usize,
+    pub callable_sites: usize,
+    pub mean_depth: f64,
+    pub segregating_sites: usize,
+    /// Nucleotide diversity per callable site
+    pub pi: f64,
+    /// Watterson's theta per callable site
+    pub theta_w: f64,
+    pub tajimas_d: Option<f64>,
+}
+
+impl DiversityStatistics {
+    /// Calculate statistics over the sites of a region. Sites below min_depth are ignored.
+    /// Reads from pooled samples are treated as the sampled sequences, so the sample size
+    /// used by θ_W and Tajima's D is the mean depth of the callable sites
+    pub fn from_sites<'a, I: Iterator<Item=&'a SiteDiversity>>(sites: I, min_depth: i32) -> DiversityStatistics {
+        let mut length = 0;
+        let mut callable_sites = 0;
+        let mut total_depth = 0.;
+        let mut segregating_sites = 0;
+        let mut pi = 0.;
+        for site in sites {
+            length += 1;
+            if site.depth < min_depth || site.depth < 2 {
+                continue
+            }
+            callable_sites += 1;
+            total_depth += site.depth as f64;
+            pi += site.heterozygosity;
+            if site.segregating {
+                segregating_sites += 1;
+            }
+        }
+
+        if callable_sites == 0 {
+            return DiversityStatistics {
+                length,
+                callable_sites,
+                mean_depth: 0.,
+                segregating_sites,
+                pi: 0.,
+                theta_w: 0.,
+                tajimas_d: None,
+            }
+        }
+        let mean_depth = total_depth / callable_sites as f64;
+        let n = std::cmp::max(mean_depth.floor() as usize, 2);
+        let (a1, _) = harmonic(n);
+        DiversityStatistics {
+            length,
+            callable_sites,
+            mean_depth,
+            segregating_sites,
+            pi: pi / callable_sites as f64,
+            theta_w: segregating_sites as f64 / a1 / callable_sites as f64,
+            tajimas_d: tajimas_d(pi, segregating_sites, n),
+        }
+    }
+
+    pub fn breadth(&self) -> f64 {
+        if self.length == 0 {
+            0.
+        } else {
+            self.callable_sites as f64 / self.length as f64
+        }
+    }
+
+    /// Columns matching [DIVERSITY_HEADER]. Statistics are reported as NA for regions
+    /// with breadth below min_breadth
+    pub fn to_tsv(&self, min_breadth: f64) -> String {
+        let counts = format!("{}\t{}\t{:.3}\t{:.2}\t{}",
+                             self.length, self.callable_sites, self.breadth(),
+                             self.mean_depth, self.segregating_sites);
+        if self.callable_sites == 0 || self.breadth() < min_breadth {
+            return format!("{}\tNA\tNA\tNA", counts)
+        }
+        let tajimas_d = match self.tajimas_d {
+            Some(d) => format!("{:.4}", d),
+            None => "NA".to_string(),
+        };
+        format!("{}\t{:.6}\t{:.6}\t{}", counts, self.pi, self.theta_w, tajimas_d)
+    }
+}
+
+/// 0-based half open windows of size window_size moved along a contig by window_step.
+/// The final window is truncated at the end of the contig
+pub fn windows(contig_len: usize, window_size: usize, window_step: usize) -> Vec<(usize, usize)> {
+    let mut windows = Vec::new();
+    let mut start = 0;
+    while start < contig_len {
+        let end = std::cmp::min(start + window_size, contig_len);
+        windows.push((start, end));
+        if end == contig_len {
+            break
+        }
+        start += window_step;
+    }
+    windows
+}
+
+/// Summarise every site of a contig for one sample from its per-position depths and
+/// the SNVs observed on the contig
+pub fn contig_sites(depths: &[i32], variants: Option<&HashMap<i64, HashMap<Variant, Base>>>,
+                    sample_idx: usize) -> Vec<SiteDiversity> {
+    depths.iter().enumerate().map(|(pos, depth)| {
+        let alt_frequencies: Vec<f64> = match variants.and_then(|variants| variants.get(&(pos as i64))) {
+            Some(position_variants) => position_variants.iter()
+                .filter_map(|(variant, base)| match variant {
+                    Variant::SNV(_) => Some(base.allele_frequency(sample_idx)),
+                    _ => None,
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        SiteDiversity::new(*depth, &alt_frequencies)
+    }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_site_heterozygosity() {
+        let site = SiteDiversity::new(10, &[0.5]);
+        assert!((site.heterozygosity - 10. / 9. * 0.5).abs() < 1e-9);
+        assert!(site.segregating);
+
+        let fixed = SiteDiversity::new(10, &[1.]);
+        assert_eq!(fixed.heterozygosity, 0.);
+        assert!(!fixed.segregating);
+
+        let invariant = SiteDiversity::new(10, &[]);
+        assert_eq!(invariant.heterozygosity, 0.);
+        assert!(!invariant.segregating);
+    }
+
+    #[test]
+    fn test_tajimas_d() {
+        // Sample of 10 sequences with 16 segregating sites and mean pairwise difference of 3.89
+        let d = tajimas_d(3.888889, 16, 10).unwrap();
+        assert!((d - -1.446172).abs() < 1e-3, "D was {}", d);
+        assert_eq!(tajimas_d(0., 0, 10), None);
+    }
+
+    #[test]
+    fn test_region_statistics() {
+        let mut sites = vec![SiteDiversity::new(20, &[]); 100];
+        sites[10] = SiteDiversity::new(20, &[0.25]);
+        sites[50] = SiteDiversity::new(20, &[0.5]);
+        for site in sites[90..].iter_mut() {
+            site.depth = 1;
+        }
+        let statistics = DiversityStatistics::from_sites(sites.iter(), 5);
+        assert_eq!(statistics.length, 100);
+        assert_eq!(statistics.callable_sites, 90);
+        assert_eq!(statistics.segregating_sites, 2);
+        let pi = 20. / 19. * ((1. - 0.25 * 0.25 - 0.75 * 0.75) + 0.5) / 90.;
+        assert!((statistics.pi - pi).abs() < 1e-9);
+        let (a1, _) = harmonic(20);
+        assert!((statistics.theta_w - 2. / a1 / 90.).abs() < 1e-9);
+        assert!(statistics.tajimas_d.is_some());
+        assert!(statistics.to_tsv(0.95).ends_with("NA\tNA\tNA"));
+        assert!(!statistics.to_tsv(0.5).contains("NA"));
+    }
+
+    #[test]
+    fn test_windows() {
+        assert_eq!(windows(10, 4, 2), vec![(0, 4), (2, 6), (4, 8), (6, 10)]);
+        assert_eq!(windows(10, 4, 4), vec![(0, 4), (4, 8), (8, 10)]);
+        assert_eq!(windows(3, 4, 4), vec![(0, 3)]);
+    }
+}
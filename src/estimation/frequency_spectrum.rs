@@ -0,0 +1,316 @@
+use estimation::trajectory::FIXED_FREQUENCY;
+
+/// Header matching the rows produced by [FrequencySpectra::rows]
+pub const SPECTRUM_HEADER: &str = "spectrum\tbinStart\tbinEnd\tcount";
+
+/// Header matching the columns produced by [GaussianMixture::to_tsv]
+pub const MIXTURE_HEADER: &str = "variants\tcomponents\testimatedStrains\tmeans\tstdDevs\tweights";
+
+// Standard deviations of mixture components are kept above this to stop a component
+// collapsing onto identical frequencies
+const MIN_STD_DEV: f64 = 0.01;
+const MAX_ITERATIONS: usize = 200;
+const CONVERGENCE: f64 = 1e-6;
+
+/// Settings used when writing frequency spectra and fitting frequency mixtures
+#[derive(Debug, Clone)]
+pub struct SpectrumParameters {
+    /// Number of equal width bins used for each spectrum
+    pub bins: usize,
+    /// Maximum number of modes fitted to each sample's allele frequencies
+    pub max_components: usize,
+}
+
+impl SpectrumParameters {
+    pub fn generate_from_clap(m: &clap::ArgMatches) -> SpectrumParameters {
+        let bins: usize = m.value_of("frequency-bins").unwrap().parse()
+            .expect("Unable to parse --frequency-bins");
+        if bins == 0 {
+            panic!("--frequency-bins must be greater than 0");
+        }
+        SpectrumParameters {
+            bins,
+            max_components: max_components_from_clap(m),
+        }
+    }
+}
+
+impl Default for SpectrumParameters {
+    fn default() -> SpectrumParameters {
+        SpectrumParameters {
+            bins: 20,
+            max_components: 4,
+        }
+    }
+}
+
+pub fn max_components_from_clap(m: &clap::ArgMatches) -> usize {
+    let max_components: usize = m.value_of("max-vaf-clusters").unwrap().parse()
+        .expect("Unable to parse --max-vaf-clusters");
+    if max_components == 0 {
+        panic!("--max-vaf-clusters must be greater than 0");
+    }
+    max_components
+}
+
+/// Bin of a value between 0 and max, with values of max falling in the last bin
+fn bin_index(value: f64, bins: usize, max: f64) -> usize {
+    let bin = (value / max * bins as f64).floor().max(0.) as usize;
+    bin.min(bins - 1)
+}
+
+/// Allele frequency histogram and unfolded and folded site frequency spectra of one sample.
+/// The reference allele is treated as ancestral, so the unfolded spectrum counts sites by
+/// their total non-reference frequency and the folded spectrum by their minor allele frequency
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrequencySpectra {
+    bins: usize,
+    pub allele_frequencies: Vec<usize>,
+    pub unfolded: Vec<usize>,
+    pub folded: Vec<usize>,
+}
+
+impl FrequencySpectra {
+    pub fn new(bins: usize) -> FrequencySpectra {
+        FrequencySpectra {
+            bins,
+            allele_frequencies: vec![0; bins],
+            unfolded: vec![0; bins],
+            folded: vec![0; bins],
+        }
+    }
+
+    /// Add a site given the frequencies of each non-reference allele observed at it
+    pub fn add_site(&mut self, frequencies: &[f64]) {
+        if frequencies.is_empty() {
+            return
+        }
+        for frequency in frequencies.iter() {
+            self.allele_frequencies[bin_index(*frequency, self.bins, 1.)] += 1;
+        }
+        let site_frequency = frequencies.iter().sum::<f64>().min(1.);
+        self.unfolded[bin_index(site_frequency, self.bins, 1.)] += 1;
+        self.folded[bin_index(site_frequency.min(1. - site_frequency), self.bins, 0.5)] += 1;
+    }
+
+    pub fn combine(&mut self, other: &FrequencySpectra) {
+        for (bin, count) in other.allele_frequencies.iter().enumerate() {
+            self.allele_frequencies[bin] += count;
+            self.unfolded[bin] += other.unfolded[bin];
+            self.folded[bin] += other.folded[bin];
+        }
+    }
+
+    /// Rows matching [SPECTRUM_HEADER] for each spectrum and bin
+    pub fn rows(&self) -> Vec<String> {
+        let mut rows = Vec::new();
+        for (name, counts, max) in [("alleleFrequency", &self.allele_frequencies, 1.),
+                                    ("unfoldedSFS", &self.unfolded, 1.),
+                                    ("foldedSFS", &self.folded, 0.5)].iter() {
+            let width = max / self.bins as f64;
+            for (bin, count) in counts.iter().enumerate() {
+                rows.push(format!("{}\t{:.4}\t{:.4}\t{}",
+                                  name, bin as f64 * width, (bin + 1) as f64 * width, count));
+            }
+        }
+        rows
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MixtureComponent {
+    pub mean: f64,
+    pub std_dev: f64,
+    pub weight: f64,
+}
+
+impl MixtureComponent {
+    fn density(&self, value: f64) -> f64 {
+        let z = (value - self.mean) / self.std_dev;
+        (-0.5 * z * z).exp() / (self.std_dev * (2. * std::f64::consts::PI).sqrt())
+    }
+}
+
+/// Gaussian mixture fitted to the allele frequencies of a sample, with components
+/// ordered by increasing mean frequency
+#[derive(Debug, Clone, PartialEq)]
+pub struct GaussianMixture {
+    pub components: Vec<MixtureComponent>,
+    pub variants: usize,
+}
+
+impl GaussianMixture {
+    /// Index of the component most likely to have produced a frequency
+    pub fn assign(&self, value: f64) -> usize {
+        let mut best = 0;
+        let mut best_likelihood = -1.;
+        for (idx, component) in self.components.iter().enumerate() {
+            let likelihood = component.weight * component.density(value);
+            if likelihood > best_likelihood {
+                best = idx;
+                best_likelihood = likelihood;
+            }
+        }
+        best
+    }
+
+    /// Modes below fixation each indicate a strain segregating at that frequency. Variants
+    /// fixed in every strain add a mode at fixation, so at least one strain is always present
+    pub fn estimated_strains(&self) -> usize {
+        std::cmp::max(self.components.iter()
+                          .filter(|component| component.mean < FIXED_FREQUENCY).count(), 1)
+    }
+
+    /// Columns matching [MIXTURE_HEADER]
+    pub fn to_tsv(&self) -> String {
+        let join = |values: Vec<String>| values.join(",");
+        format!("{}\t{}\t{}\t{}\t{}\t{}",
+                self.variants, self.components.len(), self.estimated_strains(),
+                join(self.components.iter().map(|c| format!("{:.4}", c.mean)).collect()),
+                join(self.components.iter().map(|c| format!("{:.4}", c.std_dev)).collect()),
+                join(self.components.iter().map(|c| format!("{:.4}", c.weight)).collect()))
+    }
+}
+
+/// Fit a mixture of k Gaussians by expectation maximisation, starting from components
+/// spread across the quantiles of the values. Returns the mixture and its log likelihood
+pub fn fit_mixture(values: &[f64], k: usize) -> (GaussianMixture, f64) {
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let std_dev = (values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / n)
+        .sqrt().max(MIN_STD_DEV);
+    let mut components: Vec<MixtureComponent> = (0..k).map(|idx| MixtureComponent {
+        mean: sorted[((2 * idx + 1) * values.len()) / (2 * k)],
+        std_dev,
+        weight: 1. / k as f64,
+    }).collect();
+
+    let mut log_likelihood = std::f64::NEG_INFINITY;
+    let mut responsibilities = vec![vec![0.; k]; values.len()];
+    for _ in 0..MAX_ITERATIONS {
+        // Expectation
+        let mut new_log_likelihood = 0.;
+        for (value, responsibility) in values.iter().zip(responsibilities.iter_mut()) {
+            let mut total = 0.;
+            for (idx, component) in components.iter().enumerate() {
+                responsibility[idx] = component.weight * component.density(*value);
+                total += responsibility[idx];
+            }
+            if total > 0. {
+                for r in responsibility.iter_mut() {
+                    *r /= total;
+                }
+                new_log_likelihood += total.ln();
+            } else {
+                // Far from every component, so share it equally
+                for r in responsibility.iter_mut() {
+                    *r = 1. / k as f64;
+                }
+                new_log_likelihood += std::f64::MIN_POSITIVE.ln();
+            }
+        }
+
+        // Maximisation
+        for (idx, component) in components.iter_mut().enumerate() {
+            let weight: f64 = responsibilities.iter().map(|r| r[idx]).sum();
+            if weight <= 0. {
+                continue
+            }
+            let mean = values.iter().zip(responsibilities.iter())
+                .map(|(value, r)| r[idx] * value).sum::<f64>() / weight;
+            let variance = values.iter().zip(responsibilities.iter())
+                .map(|(value, r)| r[idx] * (value - mean).powi(2)).sum::<f64>() / weight;
+            component.mean = mean;
+            component.std_dev = variance.sqrt().max(MIN_STD_DEV);
+            component.weight = weight / n;
+        }
+
+        let converged = (new_log_likelihood - log_likelihood).abs() < CONVERGENCE;
+        log_likelihood = new_log_likelihood;
+        if converged {
+            break
+        }
+    }
+
+    components.sort_by(|a, b| a.mean.partial_cmp(&b.mean).unwrap());
+    (GaussianMixture { components, variants: values.len() }, log_likelihood)
+}
+
+/// Test for multimodality by fitting mixtures of 1 to max_components Gaussians and keeping
+/// the one with the lowest Bayesian information criterion. Returns None without any values
+pub fn select_mixture(values: &[f64], max_components: usize) -> Option<GaussianMixture> {
+    if values.is_empty() {
+        return None
+    }
+    let n = values.len() as f64;
+    let mut best: Option<(GaussianMixture, f64)> = None;
+    for k in 1..=std::cmp::min(max_components, values.len()) {
+        let (mixture, log_likelihood) = fit_mixture(values, k);
+        // Each component has a mean, standard deviation and weight, less one as weights sum to 1
+        let bic = -2. * log_likelihood + (3 * k - 1) as f64 * n.ln();
+        let better = match &best {
+            Some((_, best_bic)) => bic < *best_bic,
+            None => true,
+        };
+        if better {
+            best = Some((mixture, bic));
+        }
+    }
+    best.map(|(mixture, _)| mixture)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frequency_spectra() {
+        let mut spectra = FrequencySpectra::new(4);
+        spectra.add_site(&[0.1]);
+        spectra.add_site(&[0.3, 0.5]);
+        spectra.add_site(&[1.]);
+        spectra.add_site(&[]);
+        assert_eq!(spectra.allele_frequencies, vec![1, 1, 1, 1]);
+        assert_eq!(spectra.unfolded, vec![1, 0, 0, 2]);
+        assert_eq!(spectra.folded, vec![2, 1, 0, 0]);
+
+        let mut combined = FrequencySpectra::new(4);
+        combined.combine(&spectra);
+        combined.combine(&spectra);
+        assert_eq!(combined.folded, vec![4, 2, 0, 0]);
+        let rows = spectra.rows();
+        assert_eq!(rows.len(), 12);
+        assert_eq!(rows[8], "foldedSFS\t0.0000\t0.1250\t2");
+    }
+
+    #[test]
+    fn test_select_mixture() {
+        // Two strains at 30% and 70%, along with variants fixed in both
+        let mut values = Vec::new();
+        for i in 0..30 {
+            let offset = (i % 5) as f64 * 0.01 - 0.02;
+            values.push(0.3 + offset);
+            values.push(0.7 + offset);
+            values.push(1. - (i % 3) as f64 * 0.005);
+        }
+        let mixture = select_mixture(&values, 4).unwrap();
+        assert_eq!(mixture.components.len(), 3);
+        assert!((mixture.components[0].mean - 0.3).abs() < 0.01);
+        assert!((mixture.components[1].mean - 0.7).abs() < 0.01);
+        assert_eq!(mixture.estimated_strains(), 2);
+        assert_eq!(mixture.assign(0.32), 0);
+        assert_eq!(mixture.assign(0.99), 2);
+    }
+
+    #[test]
+    fn test_single_mode() {
+        let values: Vec<f64> = (0..50).map(|i| 0.98 + (i % 3) as f64 * 0.01).collect();
+        let mixture = select_mixture(&values, 4).unwrap();
+        assert_eq!(mixture.components.len(), 1);
+        assert_eq!(mixture.estimated_strains(), 1);
+        assert!(mixture.to_tsv().starts_with("50\t1\t1\t"));
+        assert_eq!(select_mixture(&[], 4), None);
+    }
+}
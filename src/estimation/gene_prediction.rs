@@ -0,0 +1,528 @@
+use std::collections::HashMap;
+use bio::alphabets::dna;
+use bio::io::fasta;
+use bio::io::gff::Record;
+use rayon::prelude::*;
+use estimation::codon_structs::*;
+use estimation::variant_annotation::PREDICTED_GENE_SOURCE;
+
+/// Default minimum length in bp of a predicted gene, including the stop codon
+pub const MIN_GENE_LENGTH: usize = 90;
+
+/// Minimum length of the open reading frames used to train the coding model
+const TRAINING_LENGTH: usize = 300;
+
+/// Largest overlap in bp allowed between two predicted genes
+const MAX_OVERLAP: usize = 60;
+
+/// Start codons considered by the predictor and their prior log-odds weights,
+/// following the relative usage of ATG, GTG and TTG in prokaryotic genomes
+const START_CODONS: [(&[u8], f64); 3] = [(b"ATG", 0.0), (b"GTG", -1.5), (b"TTG", -2.0)];
+
+/// Shine-Dalgarno consensus searched for upstream of each start codon
+const SD_MOTIF: &[u8] = b"AGGAGG";
+
+/// An open reading frame running between two in-frame stop codons on one orientation
+/// of a contig. Start positions are 0-based and end is exclusive, including the stop codon.
+/// ORFs running off the contig are partial: an ORF without an upstream stop codon can start
+/// at the first in-frame codon of the contig, and one without a stop codon ends at the
+/// last complete codon
+#[derive(Debug, Clone)]
+struct Orf {
+    starts: Vec<usize>,
+    end: usize,
+    open_start: bool,
+    open_end: bool,
+}
+
+impl Orf {
+    fn is_complete(&self) -> bool {
+        !self.open_start && !self.open_end
+    }
+
+    /// End of the coding codons, excluding the stop codon if present
+    fn coding_end(&self) -> usize {
+        if self.open_end { self.end } else { self.end - 3 }
+    }
+
+    /// Whether a start position is the contig edge rather than a start codon
+    fn is_edge_start(&self, start: usize) -> bool {
+        self.open_start && start < 3
+    }
+}
+
+/// A scored gene prediction in forward strand coordinates
+#[derive(Debug, Clone)]
+struct Candidate {
+    start: usize,
+    end: usize,
+    reverse: bool,
+    partial_left: bool,
+    partial_right: bool,
+    start_type: String,
+    rbs_motif: Option<String>,
+    rbs_spacer: usize,
+    coding_score: f64,
+    start_score: f64,
+}
+
+impl Candidate {
+    fn score(&self) -> f64 {
+        self.coding_score + self.start_score
+    }
+
+    fn overlap(&self, other: &Candidate) -> usize {
+        let start = std::cmp::max(self.start, other.start);
+        let end = std::cmp::min(self.end, other.end);
+        if end > start { end - start } else { 0 }
+    }
+}
+
+/// Ab initio prokaryotic gene predictor in the spirit of Prodigal. A codon usage model is
+/// trained on long non-overlapping ORFs found in the input sequences, and each candidate
+/// start codon is scored on its type and the strength of the upstream ribosome binding site
+pub struct GenePredictor {
+    coding: Vec<f64>,
+    stops: Vec<bool>,
+    min_gene_length: usize,
+}
+
+impl GenePredictor {
+    /// Train the coding model on a set of sequences using the stop codons of the
+    /// provided codon table
+    pub fn train(sequences: &[Vec<u8>], codon_table: &CodonTable, min_gene_length: usize) -> GenePredictor {
+        let mut stops = vec![false; 64];
+        for (idx, stop) in stops.iter_mut().enumerate() {
+            *stop = codon_table.translate(&index_codon(idx)) == '*';
+        }
+        let mut predictor = GenePredictor {
+            coding: vec![0.; 64],
+            stops,
+            min_gene_length,
+        };
+
+        let mut background = vec![0.; 64];
+        let mut coding = vec![0.; 64];
+        let mut training_orfs = 0;
+        for threshold in [TRAINING_LENGTH, min_gene_length].iter() {
+            for sequence in sequences.iter() {
+                let sequence = sequence.to_ascii_uppercase();
+                let reverse = dna::revcomp(&sequence);
+                if *threshold == TRAINING_LENGTH {
+                    for oriented in [&sequence, &reverse].iter() {
+                        for frame in 0..3 {
+                            count_codons(&oriented, frame, oriented.len(), &mut background);
+                        }
+                    }
+                }
+                for (orf, oriented) in predictor.training_orfs(&sequence, &reverse, *threshold) {
+                    count_codons(oriented, orf.starts[0], orf.end - 3, &mut coding);
+                    training_orfs += 1;
+                }
+            }
+            if training_orfs > 0 {
+                break
+            }
+        }
+
+        if training_orfs == 0 {
+            warn!("No open reading frames long enough to train gene prediction, \
+                   genes will be called on start codon and RBS scores only");
+            return predictor
+        }
+        debug!("Trained gene prediction on {} open reading frames", training_orfs);
+
+        let coding_total: f64 = coding.iter().sum::<f64>() + 64.;
+        let background_total: f64 = background.iter().sum::<f64>() + 64.;
+        for idx in 0..64 {
+            if predictor.stops[idx] {
+                continue
+            }
+            predictor.coding[idx] = ((coding[idx] + 1.) / coding_total).ln()
+                - ((background[idx] + 1.) / background_total).ln();
+        }
+        predictor
+    }
+
+    /// Longest ORF for each pair of stop codons that does not overlap a longer ORF on
+    /// either strand, paired with the orientation of the contig it was found on
+    fn training_orfs<'a>(&self, sequence: &'a Vec<u8>, reverse: &'a Vec<u8>,
+                         min_length: usize) -> Vec<(Orf, &'a Vec<u8>)> {
+        let contig_len = sequence.len();
+        let mut orfs: Vec<(Orf, &Vec<u8>, usize, usize)> = Vec::new();
+        for orf in self.find_orfs(sequence, min_length).into_iter().filter(Orf::is_complete) {
+            let (start, end) = (orf.starts[0], orf.end);
+            orfs.push((orf, sequence, start, end));
+        }
+        for orf in self.find_orfs(reverse, min_length).into_iter().filter(Orf::is_complete) {
+            let (start, end) = (contig_len - orf.end, contig_len - orf.starts[0]);
+            orfs.push((orf, reverse, start, end));
+        }
+        orfs.sort_by(|a, b| (b.3 - b.2).cmp(&(a.3 - a.2)));
+
+        let mut accepted: Vec<(Orf, &Vec<u8>, usize, usize)> = Vec::new();
+        for orf in orfs {
+            if accepted.iter().all(|other| orf.3 <= other.2 || orf.2 >= other.3) {
+                accepted.push(orf);
+            }
+        }
+        accepted.into_iter().map(|(orf, oriented, _, _)| (orf, oriented)).collect()
+    }
+
+    /// Find all stop to stop open reading frames in the three forward frames of a sequence
+    /// that contain at least one start codon giving a gene of at least min_length bp,
+    /// along with the partial ORFs running off either end of the sequence
+    fn find_orfs(&self, sequence: &[u8], min_length: usize) -> Vec<Orf> {
+        let mut orfs = Vec::new();
+        for frame in 0..3 {
+            let mut region_start = frame;
+            let mut cursor = frame;
+            loop {
+                let at_end = cursor + 3 > sequence.len();
+                let is_stop = !at_end && match codon_index(&sequence[cursor..cursor + 3]) {
+                    Some(idx) => self.stops[idx],
+                    None => false,
+                };
+                if is_stop || at_end {
+                    let open_start = region_start == frame;
+                    let end = if is_stop { cursor + 3 } else { cursor };
+                    let starts: Vec<usize> = (region_start..cursor).step_by(3)
+                        .filter(|start| end - start >= min_length
+                            && ((open_start && *start == region_start)
+                                || start_weight(&sequence[*start..*start + 3]).is_some()))
+                        .collect();
+                    if starts.len() > 0 {
+                        orfs.push(Orf { starts, end, open_start, open_end: !is_stop });
+                    }
+                    if at_end {
+                        break
+                    }
+                    region_start = end;
+                }
+                cursor += 3;
+            }
+        }
+        orfs
+    }
+
+    /// Choose the best scoring start codon for each ORF in one orientation of a contig
+    fn score_orfs(&self, oriented: &[u8], reverse: bool) -> Vec<Candidate> {
+        let contig_len = oriented.len();
+        let mut candidates = Vec::new();
+        for orf in self.find_orfs(oriented, self.min_gene_length) {
+            let mut cursor = orf.coding_end();
+            let mut coding_score = 0.;
+            let mut best: Option<Candidate> = None;
+            for start in orf.starts.iter().rev() {
+                while cursor > *start {
+                    cursor -= 3;
+                    if let Some(idx) = codon_index(&oriented[cursor..cursor + 3]) {
+                        coding_score += self.coding[idx];
+                    }
+                }
+                let codon = &oriented[*start..*start + 3];
+                let edge_start = orf.is_edge_start(*start);
+                let (start_type, rbs_motif, rbs_spacer, start_score) = if edge_start {
+                    // No start codon to score for genes running off the contig
+                    ("Edge".to_string(), None, 0, 0.)
+                } else {
+                    let (rbs_motif, rbs_spacer, rbs_score) = score_rbs(oriented, *start);
+                    (String::from_utf8_lossy(codon).to_string(), rbs_motif, rbs_spacer,
+                     start_weight(codon).unwrap() + rbs_score)
+                };
+                let (gene_start, gene_end) = if reverse {
+                    (contig_len - orf.end, contig_len - start)
+                } else {
+                    (*start, orf.end)
+                };
+                let (partial_left, partial_right) = if reverse {
+                    (orf.open_end, edge_start)
+                } else {
+                    (edge_start, orf.open_end)
+                };
+                let candidate = Candidate {
+                    start: gene_start,
+                    end: gene_end,
+                    reverse,
+                    partial_left,
+                    partial_right,
+                    start_type,
+                    rbs_motif,
+                    rbs_spacer,
+                    coding_score,
+                    start_score,
+                };
+                let replace = match best {
+                    Some(ref current) => candidate.score() > current.score(),
+                    None => true,
+                };
+                if replace {
+                    best = Some(candidate);
+                }
+            }
+            if let Some(candidate) = best {
+                if candidate.coding_score > 0. && candidate.score() > 0. {
+                    candidates.push(candidate);
+                }
+            }
+        }
+        candidates
+    }
+
+    /// Predict genes on a single contig, returning CDS records named in the same
+    /// manner as Prodigal, i.e. ID={contig_idx}_{gene_idx}. Genes running off the left or
+    /// right end of the contig are flagged as in Prodigal, e.g. partial=10 or partial=01
+    pub fn predict(&self, contig_name: &str, sequence: &[u8], contig_idx: usize) -> Vec<Record> {
+        let sequence = sequence.to_ascii_uppercase();
+        let reverse = dna::revcomp(&sequence);
+        let mut candidates = self.score_orfs(&sequence, false);
+        candidates.extend(self.score_orfs(&reverse, true));
+        candidates.sort_by(|a, b| b.score().partial_cmp(&a.score())
+            .expect("Unable to compare gene scores"));
+
+        let mut genes: Vec<Candidate> = Vec::new();
+        for candidate in candidates {
+            if genes.iter().all(|gene| gene.overlap(&candidate) <= MAX_OVERLAP) {
+                genes.push(candidate);
+            }
+        }
+        genes.sort_by_key(|gene| gene.start);
+
+        genes.into_iter().enumerate().map(|(gene_idx, gene)| {
+            let mut record = Record::new();
+            *record.seqname_mut() = contig_name.to_string();
+            *record.source_mut() = PREDICTED_GENE_SOURCE.to_string();
+            *record.feature_type_mut() = "CDS".to_string();
+            *record.start_mut() = gene.start as u64 + 1;
+            *record.end_mut() = gene.end as u64;
+            *record.score_mut() = format!("{:.1}", gene.score());
+            *record.strand_mut() = if gene.reverse { "-".to_string() } else { "+".to_string() };
+            *record.frame_mut() = "0".to_string();
+            let attributes = record.attributes_mut();
+            attributes.insert("ID".to_string(), format!("{}_{}", contig_idx, gene_idx + 1));
+            attributes.insert("partial".to_string(),
+                              format!("{}{}", gene.partial_left as u8, gene.partial_right as u8));
+            attributes.insert("start_type".to_string(), gene.start_type.clone());
+            match gene.rbs_motif {
+                Some(ref motif) => {
+                    attributes.insert("rbs_motif".to_string(), motif.clone());
+                    attributes.insert("rbs_spacer".to_string(), format!("{}bp", gene.rbs_spacer));
+                },
+                None => {
+                    attributes.insert("rbs_motif".to_string(), "None".to_string());
+                    attributes.insert("rbs_spacer".to_string(), "None".to_string());
+                }
+            }
+            attributes.insert("cscore".to_string(), format!("{:.2}", gene.coding_score));
+            attributes.insert("sscore".to_string(), format!("{:.2}", gene.start_score));
+            record
+        }).collect()
+    }
+}
+
+/// Predict genes on every contig of a FASTA file, returning the records keyed by contig name
+pub fn predict_genes(fasta_path: &str, codon_table: &CodonTable,
+                     min_gene_length: usize) -> HashMap<String, Vec<Record>> {
+    let reader = fasta::Reader::from_file(fasta_path)
+        .expect(&format!("Unable to read reference FASTA {}", fasta_path));
+    let mut names = Vec::new();
+    let mut sequences = Vec::new();
+    for record in reader.records() {
+        let record = record.expect("Unable to parse reference FASTA record");
+        names.push(record.id().to_string());
+        sequences.push(record.seq().to_vec());
+    }
+
+    let predictor = GenePredictor::train(&sequences, codon_table, min_gene_length);
+    let gff_map: HashMap<String, Vec<Record>> = names.par_iter().zip(sequences.par_iter())
+        .enumerate()
+        .map(|(contig_idx, (name, sequence))| {
+            (name.clone(), predictor.predict(name, sequence, contig_idx + 1))
+        }).collect();
+
+    info!("Predicted {} genes across {} contigs",
+          gff_map.values().map(|genes| genes.len()).sum::<usize>(), gff_map.len());
+    gff_map
+}
+
+/// Prior weight of a start codon, or None if the codon is not a considered start
+fn start_weight(codon: &[u8]) -> Option<f64> {
+    START_CODONS.iter().find(|(start, _)| *start == codon).map(|(_, weight)| *weight)
+}
+
+/// Find the strongest Shine-Dalgarno match upstream of a start codon. Longer matches to the
+/// consensus score higher, and spacers outside of 5-10bp are penalised.
+/// Returns the motif, spacer length and score
+fn score_rbs(sequence: &[u8], start: usize) -> (Option<String>, usize, f64) {
+    let mut best = (None, 0, 0.);
+    for motif_len in 3..SD_MOTIF.len() + 1 {
+        let motif_score = match motif_len {
+            3 => 1.0,
+            4 => 2.0,
+            5 => 2.5,
+            _ => 3.0,
+        };
+        for offset in 0..SD_MOTIF.len() - motif_len + 1 {
+            let motif = &SD_MOTIF[offset..offset + motif_len];
+            for spacer in 3..16 {
+                if start < spacer + motif_len {
+                    break
+                }
+                let motif_start = start - spacer - motif_len;
+                if &sequence[motif_start..motif_start + motif_len] == motif {
+                    let score = if spacer >= 5 && spacer <= 10 { motif_score } else { motif_score / 2. };
+                    if score > best.2 {
+                        best = (Some(String::from_utf8_lossy(motif).to_string()), spacer, score);
+                    }
+                }
+            }
+        }
+    }
+    best
+}
+
+/// Add counts of codons between start and end in frame to the provided counts
+fn count_codons(sequence: &[u8], start: usize, end: usize, counts: &mut Vec<f64>) {
+    let mut cursor = start;
+    while cursor + 3 <= end {
+        if let Some(idx) = codon_index(&sequence[cursor..cursor + 3]) {
+            counts[idx] += 1.;
+        }
+        cursor += 3;
+    }
+}
+
+fn codon_index(codon: &[u8]) -> Option<usize> {
+    let mut idx = 0;
+    for base in codon.iter() {
+        idx = idx * 4 + match base {
+            b'A' => 0,
+            b'C' => 1,
+            b'G' => 2,
+            b'T' => 3,
+            _ => return None,
+        };
+    }
+    Some(idx)
+}
+
+fn index_codon(idx: usize) -> Vec<u8> {
+    let bases = [b'A', b'C', b'G', b'T'];
+    vec![bases[(idx / 16) % 4], bases[(idx / 4) % 4], bases[idx % 4]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bio_types::strand;
+
+    fn codon_table() -> CodonTable {
+        let mut codon_table = CodonTable::setup();
+        codon_table.get_codon_table(11);
+        codon_table
+    }
+
+    // Build a gene with a strong RBS upstream from pseudo-randomly chosen codons.
+    // TTA and TCA are included so that antisense frames are broken up by stop codons
+    fn synthetic_gene(codons: usize, seed: u64) -> Vec<u8> {
+        let mut gene = b"AGGAGGTAACAT".to_vec();
+        gene.extend_from_slice(b"ATG");
+        let usage: [&[u8]; 10] = [b"GCT", b"GAA", b"AAA", b"CTG", b"GCG",
+            b"ATT", b"TTA", b"CGC", b"GAT", b"TCA"];
+        let mut state = seed;
+        for _ in 0..codons {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            gene.extend_from_slice(usage[(state >> 33) as usize % usage.len()]);
+        }
+        gene.extend_from_slice(b"TAA");
+        gene
+    }
+
+    #[test]
+    fn test_codon_indices() {
+        for idx in 0..64 {
+            assert_eq!(codon_index(&index_codon(idx)), Some(idx));
+        }
+        assert_eq!(codon_index(b"ANG"), None);
+        assert_eq!(start_weight(b"ATG"), Some(0.0));
+        assert_eq!(start_weight(b"CTG"), None);
+    }
+
+    #[test]
+    fn test_rbs_scoring() {
+        let sequence = b"CCCAGGAGGTAACATATG";
+        let (motif, spacer, score) = score_rbs(sequence, 15);
+        assert_eq!(motif, Some("AGGAGG".to_string()));
+        assert_eq!(spacer, 6);
+        assert_eq!(score, 3.0);
+
+        let (motif, _, score) = score_rbs(b"CCCCCCCCCCCCCCCATG", 15);
+        assert_eq!(motif, None);
+        assert_eq!(score, 0.);
+    }
+
+    #[test]
+    fn test_predict_both_strands() {
+        let spacer = b"TTTTTTTTTTTTTTTTTTTT".to_vec();
+        let forward = synthetic_gene(120, 1);
+        let reverse = dna::revcomp(&synthetic_gene(100, 2));
+        let mut contig = spacer.clone();
+        contig.extend(&forward);
+        contig.extend(&spacer);
+        contig.extend(&reverse);
+        contig.extend(&spacer);
+
+        let codon_table = codon_table();
+        let predictor = GenePredictor::train(&vec![contig.clone()], &codon_table, MIN_GENE_LENGTH);
+        let genes = predictor.predict("contig", &contig, 1);
+        assert_eq!(genes.len(), 2);
+
+        let forward_start = spacer.len() + 12;
+        assert_eq!(genes[0].start(), &(forward_start as u64 + 1));
+        assert_eq!(genes[0].end(), &((spacer.len() + forward.len()) as u64));
+        assert_eq!(genes[0].strand(), Some(strand::Strand::Forward));
+        assert_eq!(genes[0].attributes()["ID"], "1_1");
+        assert_eq!(genes[0].attributes()["rbs_motif"], "AGGAGG");
+
+        let reverse_start = 2 * spacer.len() + forward.len();
+        assert_eq!(genes[1].start(), &(reverse_start as u64 + 1));
+        assert_eq!(genes[1].end(), &((reverse_start + reverse.len() - 12) as u64));
+        assert_eq!(genes[1].strand(), Some(strand::Strand::Reverse));
+        assert_eq!(genes[1].attributes()["ID"], "1_2");
+    }
+
+    #[test]
+    fn test_predict_partial_genes() {
+        let spacer = b"TTTTTTTTTTTTTTTTTTTT".to_vec();
+        let complete = synthetic_gene(120, 1);
+        // Drop the upstream region and start codon of the first gene, and the stop
+        // codon of the last, so that both run off the contig
+        let left = synthetic_gene(100, 3)[15..].to_vec();
+        let right = synthetic_gene(100, 4);
+        let right = right[..right.len() - 3].to_vec();
+        let mut contig = left.clone();
+        contig.extend(&spacer);
+        contig.extend(&complete);
+        contig.extend(&spacer);
+        contig.extend(&right);
+
+        let codon_table = codon_table();
+        let predictor = GenePredictor::train(&vec![contig.clone()], &codon_table, MIN_GENE_LENGTH);
+        let genes = predictor.predict("contig", &contig, 1);
+        assert_eq!(genes.len(), 3);
+
+        assert_eq!(genes[0].start(), &1);
+        assert_eq!(genes[0].end(), &(left.len() as u64));
+        assert_eq!(genes[0].attributes()["partial"], "10");
+        assert_eq!(genes[0].attributes()["start_type"], "Edge");
+        assert_eq!(genes[0].attributes()["rbs_motif"], "None");
+
+        assert_eq!(genes[1].attributes()["partial"], "00");
+        assert_eq!(genes[1].attributes()["start_type"], "ATG");
+
+        let right_start = contig.len() - right.len() + 12;
+        assert_eq!(genes[2].start(), &(right_start as u64 + 1));
+        assert_eq!(genes[2].end(), &(contig.len() as u64));
+        assert_eq!(genes[2].attributes()["partial"], "01");
+    }
+}
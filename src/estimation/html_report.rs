@@ -0,0 +1,222 @@
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use clap::crate_version;
+
+const CHART_WIDTH: f64 = 640.;
+const CHART_HEIGHT: f64 = 260.;
+// Space left for the axes and their labels
+const MARGIN_LEFT: f64 = 60.;
+const MARGIN_BOTTOM: f64 = 50.;
+const MARGIN_TOP: f64 = 10.;
+// Bars are only individually labelled when there is room for the labels
+const MAX_LABELLED_BARS: usize = 30;
+
+const STYLE: &str = "body{font-family:sans-serif;margin:2em;color:#222}\
+h1{font-size:1.6em}h2{border-bottom:1px solid #ccc;padding-bottom:.2em;margin-top:2em}\
+h3{font-size:1em}table{border-collapse:collapse;margin:1em 0}\
+th,td{border:1px solid #ccc;padding:.3em .6em;text-align:right}\
+th{background:#f0f0f0}td:first-child,th:first-child{text-align:left}\
+nav a{margin-right:1em}.chart rect.bar{fill:#4477aa}.chart rect.bar:hover{fill:#cc6677}\
+.chart text{font-size:11px}.note{color:#666}";
+
+/// Escapes text so it can be placed inside HTML or SVG elements and attributes
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Counts of the values falling in each of `bins` equal width bins between min and max.
+/// Values outside of the range are counted in the first or last bin
+pub fn histogram(values: &[f64], bins: usize, min: f64, max: f64) -> Vec<usize> {
+    let mut counts = vec![0; bins];
+    if bins == 0 || max <= min {
+        return counts
+    }
+    let width = (max - min) / bins as f64;
+    for value in values.iter().filter(|value| value.is_finite()) {
+        let bin = ((value - min) / width).floor().max(0.) as usize;
+        counts[bin.min(bins - 1)] += 1;
+    }
+    counts
+}
+
+/// Inline SVG bar chart with one bar per label. Each bar shows its label and value
+/// when hovered over
+pub fn bar_chart(labels: &[String], values: &[f64], x_label: &str, y_label: &str) -> String {
+    let plot_width = CHART_WIDTH - MARGIN_LEFT;
+    let plot_height = CHART_HEIGHT - MARGIN_BOTTOM - MARGIN_TOP;
+    let max = values.iter().cloned().filter(|value| value.is_finite()).fold(0., f64::max);
+    let bar_width = plot_width / std::cmp::max(values.len(), 1) as f64;
+
+    let mut svg = format!("<svg class=\"chart\" xmlns=\"http://www.w3.org/2000/svg\" \
+                           width=\"{}\" height=\"{}\">\n", CHART_WIDTH, CHART_HEIGHT);
+    for (idx, (label, value)) in labels.iter().zip(values.iter()).enumerate() {
+        let height = if max > 0. && value.is_finite() { value / max * plot_height } else { 0. };
+        let x = MARGIN_LEFT + idx as f64 * bar_width;
+        svg.push_str(&format!("<rect class=\"bar\" x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" \
+                               height=\"{:.1}\"><title>{}: {}</title></rect>\n",
+                              x, MARGIN_TOP + plot_height - height, (bar_width - 1.).max(0.5),
+                              height, escape_html(label), format_value(*value)));
+        if values.len() <= MAX_LABELLED_BARS
+            || idx == 0 || idx == values.len() - 1 {
+            svg.push_str(&format!("<text x=\"{:.1}\" y=\"{:.1}\" text-anchor=\"middle\">{}</text>\n",
+                                  x + bar_width / 2., MARGIN_TOP + plot_height + 14.,
+                                  escape_html(label)));
+        }
+    }
+    // Axes, the maximum of the y axis and the axis labels
+    svg.push_str(&format!("<line x1=\"{0}\" y1=\"{1}\" x2=\"{0}\" y2=\"{2}\" stroke=\"#444\"/>\n\
+                           <line x1=\"{0}\" y1=\"{2}\" x2=\"{3}\" y2=\"{2}\" stroke=\"#444\"/>\n",
+                          MARGIN_LEFT, MARGIN_TOP, MARGIN_TOP + plot_height, CHART_WIDTH));
+    svg.push_str(&format!("<text x=\"{}\" y=\"{}\" text-anchor=\"end\">{}</text>\n",
+                          MARGIN_LEFT - 4., MARGIN_TOP + 10., format_value(max)));
+    svg.push_str(&format!("<text x=\"{}\" y=\"{}\" text-anchor=\"end\">0</text>\n",
+                          MARGIN_LEFT - 4., MARGIN_TOP + plot_height));
+    svg.push_str(&format!("<text x=\"{}\" y=\"{}\" text-anchor=\"middle\">{}</text>\n",
+                          MARGIN_LEFT + plot_width / 2., CHART_HEIGHT - 8., escape_html(x_label)));
+    svg.push_str(&format!("<text transform=\"translate(14,{}) rotate(-90)\" \
+                           text-anchor=\"middle\">{}</text>\n",
+                          MARGIN_TOP + plot_height / 2., escape_html(y_label)));
+    svg.push_str("</svg>\n");
+    svg
+}
+
+/// Inline SVG histogram of values between min and max
+pub fn histogram_chart(values: &[f64], bins: usize, min: f64, max: f64, x_label: &str) -> String {
+    let counts = histogram(values, bins, min, max);
+    let width = (max - min) / bins as f64;
+    let labels: Vec<String> = (0..bins)
+        .map(|bin| format_value(min + bin as f64 * width))
+        .collect();
+    let counts: Vec<f64> = counts.into_iter().map(|count| count as f64).collect();
+    bar_chart(&labels, &counts, x_label, "Count")
+}
+
+/// HTML table with the given header. Cells are escaped
+pub fn table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut html = "<table>\n<tr>".to_string();
+    for column in header.iter() {
+        html.push_str(&format!("<th>{}</th>", escape_html(column)));
+    }
+    html.push_str("</tr>\n");
+    for row in rows.iter() {
+        html.push_str("<tr>");
+        for cell in row.iter() {
+            html.push_str(&format!("<td>{}</td>", escape_html(cell)));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</table>\n");
+    html
+}
+
+/// Paragraph of explanatory text
+pub fn note(text: &str) -> String {
+    format!("<p class=\"note\">{}</p>\n", escape_html(text))
+}
+
+/// Integers are shown as is and other values to three decimal places
+pub fn format_value(value: f64) -> String {
+    if value.fract() == 0. && value.abs() < 1e15 {
+        format!("{}", value as i64)
+    } else {
+        format!("{:.3}", value)
+    }
+}
+
+/// Single self-contained HTML document. All styling and charts are inline so the report
+/// can be opened without network access
+#[derive(Debug, Clone)]
+pub struct HtmlReport {
+    title: String,
+    sections: Vec<(String, String)>,
+}
+
+impl HtmlReport {
+    pub fn new(title: &str) -> HtmlReport {
+        HtmlReport {
+            title: title.to_string(),
+            sections: Vec::new(),
+        }
+    }
+
+    /// Adds a section with the given heading. The body is inserted as is
+    pub fn add_section(&mut self, heading: &str, body: String) {
+        self.sections.push((heading.to_string(), body));
+    }
+
+    pub fn to_html(&self) -> String {
+        let mut html = format!("<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n\
+                                <title>{0}</title>\n<style>{1}</style>\n</head>\n<body>\n\
+                                <h1>{0}</h1>\n<p class=\"note\">Lorikeet v{2}</p>\n<nav>",
+                               escape_html(&self.title), STYLE, crate_version!());
+        for (idx, (heading, _)) in self.sections.iter().enumerate() {
+            html.push_str(&format!("<a href=\"#section-{}\">{}</a>", idx, escape_html(heading)));
+        }
+        html.push_str("</nav>\n");
+        for (idx, (heading, body)) in self.sections.iter().enumerate() {
+            html.push_str(&format!("<section id=\"section-{}\">\n<h2>{}</h2>\n{}</section>\n",
+                                   idx, escape_html(heading), body));
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+
+    /// Writes the report to {output_prefix}_report.html
+    pub fn write(&self, output_prefix: &str) {
+        let file_name = format!("{}_report.html", output_prefix);
+        let mut file_open = File::create(Path::new(&file_name))
+            .expect("No Read or Write Permission in current directory");
+        file_open.write_all(self.to_html().as_bytes())
+            .expect("Unable to write HTML report");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram() {
+        let values = [0., 0.05, 0.5, 0.99, 1., 1.5, -0.2, std::f64::NAN];
+        assert_eq!(histogram(&values, 4, 0., 1.), vec![3, 0, 1, 3]);
+        assert_eq!(histogram(&values, 0, 0., 1.), Vec::<usize>::new());
+        assert_eq!(format_value(2.), "2");
+        assert_eq!(format_value(0.25), "0.250");
+    }
+
+    #[test]
+    fn test_escape_html() {
+        assert_eq!(escape_html("<a href=\"x\">S&P's</a>"),
+                   "&lt;a href=&quot;x&quot;&gt;S&amp;P&#39;s&lt;/a&gt;");
+        let html = table(&["sample", "depth"], &[vec!["<S1>".to_string(), "10".to_string()]]);
+        assert!(html.contains("<td>&lt;S1&gt;</td><td>10</td>"));
+    }
+
+    #[test]
+    fn test_report_is_self_contained() {
+        let mut report = HtmlReport::new("Lorikeet summarize report");
+        report.add_section("Allele frequency spectra",
+                           histogram_chart(&[0.1, 0.2, 0.9], 10, 0., 1., "Allele frequency"));
+        report.add_section("Strains", note("No strains"));
+        let html = report.to_html();
+        assert!(html.starts_with("<!DOCTYPE html>"));
+        assert_eq!(html.matches("<section").count(), 2);
+        assert_eq!(html.matches("<rect class=\"bar\"").count(), 10);
+        assert!(html.contains("href=\"#section-1\""));
+        // Only the SVG namespace may refer to a URL, nothing is loaded from the network
+        assert!(!html.contains("<script src") && !html.contains("<link"));
+        assert_eq!(html.matches("http").count(), 1);
+    }
+}
@@ -0,0 +1,387 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use bio::alphabets::dna;
+use bio::io::gff;
+use bio::io::gff::Record;
+use bio_types::strand;
+use estimation::codon_structs::CodonTable;
+use estimation::polish::variant_replacement;
+use estimation::variant_annotation::{gene_id, variant_span};
+use model::variants::Variant;
+
+/// Run of bases carried over unchanged in length from a source sequence to a target sequence.
+/// Substitutions fall inside blocks, while insertions and deletions separate them
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlignedBlock {
+    pub source_start: usize,
+    pub target_start: usize,
+    pub length: usize,
+}
+
+impl AlignedBlock {
+    fn source_end(&self) -> usize {
+        self.source_start + self.length
+    }
+
+    fn target_end(&self) -> usize {
+        self.target_start + self.length
+    }
+}
+
+/// Replacement of the source bases start..end by other bases
+#[derive(Debug, Clone, PartialEq)]
+pub struct SequenceEdit {
+    pub start: usize,
+    pub end: usize,
+    pub replacement: Vec<u8>,
+}
+
+/// Coordinate mapping from a contig to a copy of it with variants applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct Liftover {
+    pub source_name: String,
+    pub source_length: usize,
+    pub target_name: String,
+    pub target_length: usize,
+    pub blocks: Vec<AlignedBlock>,
+}
+
+impl Liftover {
+    /// Build the mapping from the edits made to the source. Edits must be sorted and must not
+    /// overlap. The first bases of an edit are aligned to the first bases replacing them, so
+    /// only the difference in length opens a gap
+    pub fn from_edits(source_name: &str, source_length: usize, target_name: &str,
+                      edits: &[SequenceEdit]) -> Liftover {
+        let mut blocks = Vec::new();
+        let mut block_source = 0;
+        let mut block_target = 0;
+        let mut source_pos = 0;
+        let mut target_pos = 0;
+        for edit in edits.iter() {
+            let (start, end, replacement) = (edit.start, edit.end, edit.replacement.len());
+            let aligned = std::cmp::min(end - start, replacement);
+            target_pos += start - source_pos + aligned;
+            source_pos = start + aligned;
+            if end - start != replacement {
+                if source_pos > block_source {
+                    blocks.push(AlignedBlock {
+                        source_start: block_source,
+                        target_start: block_target,
+                        length: source_pos - block_source,
+                    });
+                }
+                source_pos = end;
+                target_pos += replacement - aligned;
+                block_source = source_pos;
+                block_target = target_pos;
+            }
+        }
+        target_pos += source_length - source_pos;
+        if source_length > block_source {
+            blocks.push(AlignedBlock {
+                source_start: block_source,
+                target_start: block_target,
+                length: source_length - block_source,
+            });
+        }
+
+        Liftover {
+            source_name: source_name.to_string(),
+            source_length,
+            target_name: target_name.to_string(),
+            target_length: target_pos,
+            blocks,
+        }
+    }
+
+    /// Position in the target of a source position, or None if it was deleted
+    pub fn lift(&self, pos: usize) -> Option<usize> {
+        self.blocks.iter()
+            .find(|block| block.source_start <= pos && pos < block.source_end())
+            .map(|block| block.target_start + pos - block.source_start)
+    }
+
+    /// Target positions of the first and last bases of the source interval start..=end that
+    /// were not deleted, or None if the whole interval was deleted
+    pub fn lift_interval(&self, start: usize, end: usize) -> Option<(usize, usize)> {
+        let first = self.blocks.iter()
+            .find(|block| block.source_end() > start && block.source_start <= end)?;
+        let last = self.blocks.iter().rev()
+            .find(|block| block.source_start <= end && block.source_end() > start)?;
+        let lifted_start = first.target_start + start.saturating_sub(first.source_start);
+        let lifted_end = last.target_start + std::cmp::min(end, last.source_end() - 1) - last.source_start;
+        Some((lifted_start, lifted_end))
+    }
+
+    /// UCSC chain describing the mapping, with the source as the reference sequence.
+    /// Returns None if every base was deleted
+    pub fn to_chain(&self, id: usize) -> Option<String> {
+        let first = self.blocks.first()?;
+        let last = self.blocks.last()?;
+        let score: usize = self.blocks.iter().map(|block| block.length).sum();
+        let mut chain = format!("chain {} {} {} + {} {} {} {} + {} {} {}\n",
+                                score, self.source_name, self.source_length,
+                                first.source_start, last.source_end(),
+                                self.target_name, self.target_length,
+                                first.target_start, last.target_end(), id);
+        for (block, next) in self.blocks.iter().zip(self.blocks.iter().skip(1)) {
+            chain.push_str(&format!("{}\t{}\t{}\n", block.length,
+                                    next.source_start - block.source_end(),
+                                    next.target_start - block.target_end()));
+        }
+        chain.push_str(&format!("{}\n\n", last.length));
+        Some(chain)
+    }
+}
+
+/// Apply variants to a contig in order of position, skipping variants that overlap bases
+/// replaced by an earlier variant and those that do not change the sequence. Returns the new
+/// sequence along with the edit made by each applied variant
+pub fn apply_variants(reference: &[u8], mut variants: Vec<(usize, Variant)>)
+    -> (Vec<u8>, Vec<SequenceEdit>) {
+    variants.sort_by_key(|(pos, _)| *pos);
+    let mut sequence = Vec::with_capacity(reference.len());
+    let mut edits = Vec::new();
+    let mut cursor = 0;
+    for (pos, variant) in variants.iter() {
+        match variant {
+            Variant::SNV(_) | Variant::MNV(_) | Variant::Insertion(_) | Variant::Deletion(_) => {},
+            _ => continue,
+        }
+        if *pos < cursor || *pos >= reference.len() {
+            continue
+        }
+        let (start, end) = variant_span(*pos, variant, reference.len());
+        let replacement = variant_replacement(reference, *pos, variant);
+        sequence.extend_from_slice(&reference[cursor..start]);
+        sequence.extend_from_slice(&replacement);
+        edits.push(SequenceEdit { start, end, replacement });
+        cursor = end;
+    }
+    sequence.extend_from_slice(&reference[cursor..]);
+    (sequence, edits)
+}
+
+/// Writes the chain of each liftover to a single file
+pub fn write_chain_file(file_name: &str, liftovers: &[Liftover]) {
+    let mut file_open = File::create(Path::new(file_name))
+        .expect("No Read or Write Permission in current directory");
+    for (id, liftover) in liftovers.iter().enumerate() {
+        if let Some(chain) = liftover.to_chain(id + 1) {
+            file_open.write_all(chain.as_bytes()).expect("Unable to write to file");
+        }
+    }
+}
+
+/// Whether a gene still encodes a complete protein after liftover
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GeneStatus {
+    Intact,
+    /// The coding length changed by a number of bases that is not a multiple of three
+    Frameshift,
+    /// A stop codon appears before the end of the coding sequence
+    PrematureStop,
+}
+
+impl GeneStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            GeneStatus::Intact => "intact",
+            GeneStatus::Frameshift => "frameshift",
+            GeneStatus::PrematureStop => "premature_stop",
+        }
+    }
+}
+
+/// Status of a lifted gene from its coding sequence, in coding orientation and starting at
+/// its first complete codon, and the length of the original coding sequence
+pub fn gene_status(coding: &[u8], original_length: usize, codon_table: &CodonTable) -> GeneStatus {
+    if (coding.len() as i64 - original_length as i64) % 3 != 0 {
+        return GeneStatus::Frameshift
+    }
+    let codons = coding.len() / 3;
+    if coding.chunks(3).take(codons.saturating_sub(1))
+        .any(|codon| codon_table.translate(codon) == '*') {
+        GeneStatus::PrematureStop
+    } else {
+        GeneStatus::Intact
+    }
+}
+
+/// Coding sequence of the segments start..=end of a gene, in coding orientation and skipping
+/// the bases before the first complete codon
+fn coding_sequence(sequence: &[u8], segments: &[(usize, usize)], reverse: bool, frame: usize) -> Vec<u8> {
+    let mut coding = Vec::new();
+    for (start, end) in segments.iter() {
+        if *start <= *end && *end < sequence.len() {
+            coding.extend_from_slice(&sequence[*start..=*end]);
+        }
+    }
+    if reverse {
+        coding = dna::revcomp(&coding);
+    }
+    coding.split_off(std::cmp::min(frame, coding.len()))
+}
+
+/// Lift the gene records of a contig onto the target contig. Segments that were entirely
+/// deleted are dropped, and genes disrupted by the applied variants are marked as pseudogenes
+/// with the reason given by the disruption attribute
+pub fn lift_genes(genes: &[Record], liftover: &Liftover, source: &[u8], target: &[u8],
+                  codon_table: &CodonTable) -> Vec<Record> {
+    let mut by_gene: BTreeMap<String, Vec<&Record>> = BTreeMap::new();
+    for gene in genes.iter() {
+        by_gene.entry(gene_id(gene)).or_default().push(gene);
+    }
+
+    let mut lifted_records = Vec::new();
+    for (id, mut segments) in by_gene.into_iter() {
+        segments.sort_by_key(|segment| *segment.start());
+        let reverse = match segments[0].strand() {
+            Some(strand::Strand::Reverse) => true,
+            _ => false,
+        };
+        // The phase of the segment containing the start codon
+        let first = if reverse { segments[segments.len() - 1] } else { segments[0] };
+        let frame: usize = first.frame().parse().unwrap_or(0);
+
+        let original: Vec<(usize, usize)> = segments.iter()
+            .map(|segment| (segment.start().saturating_sub(1) as usize, *segment.end() as usize - 1))
+            .collect();
+        let lifted: Vec<Option<(usize, usize)>> = original.iter()
+            .map(|(start, end)| liftover.lift_interval(*start, *end))
+            .collect();
+        if lifted.iter().all(|segment| segment.is_none()) {
+            debug!("Gene {} was deleted from {}", id, liftover.target_name);
+            continue
+        }
+
+        let lifted_segments: Vec<(usize, usize)> = lifted.iter().filter_map(|segment| *segment).collect();
+        let original_length = coding_sequence(source, &original, reverse, frame).len();
+        let coding = coding_sequence(target, &lifted_segments, reverse, frame);
+        let status = gene_status(&coding, original_length, codon_table);
+
+        for (segment, lifted) in segments.iter().zip(lifted.iter()) {
+            if let Some((start, end)) = lifted {
+                let mut record = (*segment).clone();
+                *record.seqname_mut() = liftover.target_name.clone();
+                *record.start_mut() = *start as u64 + 1;
+                *record.end_mut() = *end as u64 + 1;
+                if status != GeneStatus::Intact {
+                    record.attributes_mut().insert("pseudo".to_string(), "true".to_string());
+                    record.attributes_mut().insert("disruption".to_string(), status.name().to_string());
+                }
+                lifted_records.push(record);
+            }
+        }
+    }
+    lifted_records
+}
+
+/// Writes gene records to a GFF3 file
+pub fn write_gff(file_name: &str, records: &[Record]) {
+    let mut writer = gff::Writer::to_file(file_name, gff::GffType::GFF3)
+        .expect("No Read or Write Permission in current directory");
+    for record in records.iter() {
+        writer.write(record).expect("Unable to write to file");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use estimation::codon_structs::Translations;
+
+    #[test]
+    fn test_strain_indels_keep_anchor_base() {
+        // Indels keep the reference base at their position and then insert the inserted
+        // bases or skip the deleted ones, as in the VCF records they were parsed from
+        let reference = b"ACGTACGTACGT";
+        let variants = vec![
+            (9, Variant::SNV(b'T')),
+            (1, Variant::Deletion(2)),
+            (5, Variant::Insertion(b"GG".to_vec())),
+            // Overlaps the deletion so is skipped
+            (2, Variant::SNV(b'A')),
+        ];
+        let (sequence, edits) = apply_variants(reference, variants);
+        assert_eq!(String::from_utf8(sequence).unwrap(), "ACACGGGTATGT");
+        assert_eq!(edits, vec![
+            SequenceEdit { start: 1, end: 4, replacement: b"C".to_vec() },
+            SequenceEdit { start: 5, end: 6, replacement: b"CGG".to_vec() },
+            SequenceEdit { start: 9, end: 10, replacement: b"T".to_vec() },
+        ]);
+
+        let (sequence, _) = apply_variants(b"ACGT", vec![(1, Variant::MNV(b"TT".to_vec()))]);
+        assert_eq!(String::from_utf8(sequence).unwrap(), "ATTT");
+    }
+
+    #[test]
+    fn test_liftover() {
+        let reference = b"ACGTACGTACGT";
+        let variants = vec![
+            (9, Variant::SNV(b'T')),
+            (1, Variant::Deletion(2)),
+            (5, Variant::Insertion(b"GG".to_vec())),
+            // Overlaps the deletion so is skipped
+            (2, Variant::SNV(b'A')),
+        ];
+        let (sequence, edits) = apply_variants(reference, variants);
+        assert_eq!(String::from_utf8(sequence.clone()).unwrap(), "ACACGGGTATGT");
+        let liftover = Liftover::from_edits("contig_1", reference.len(), "contig_1_strain_0", &edits);
+        assert_eq!(liftover.target_length, sequence.len());
+        assert_eq!(liftover.lift(0), Some(0));
+        assert_eq!(liftover.lift(2), None);
+        assert_eq!(liftover.lift(4), Some(2));
+        assert_eq!(liftover.lift(9), Some(9));
+        for (pos, base) in reference.iter().enumerate() {
+            if let Some(lifted) = liftover.lift(pos) {
+                if pos != 9 {
+                    assert_eq!(*base, sequence[lifted]);
+                }
+            }
+        }
+        assert_eq!(liftover.lift_interval(2, 3), None);
+        assert_eq!(liftover.lift_interval(2, 6), Some((2, 6)));
+        assert_eq!(liftover.to_chain(1).unwrap(),
+                   "chain 10 contig_1 12 + 0 12 contig_1_strain_0 12 + 0 12 1\n\
+                    2\t2\t0\n2\t0\t2\n6\n\n");
+    }
+
+    #[test]
+    fn test_lift_genes() {
+        let mut codon_table = CodonTable::setup();
+        codon_table.get_codon_table(11);
+        let source = b"CCATGAAACCCGGGTAACC";
+        let gene = |start, end| {
+            let mut record = Record::new();
+            *record.seqname_mut() = "contig_1".to_string();
+            *record.feature_type_mut() = "CDS".to_string();
+            *record.start_mut() = start;
+            *record.end_mut() = end;
+            *record.strand_mut() = "+".to_string();
+            *record.frame_mut() = "0".to_string();
+            record.attributes_mut().insert("ID".to_string(), "gene_1".to_string());
+            record
+        };
+        let lift = |variants: Vec<(usize, Variant)>| {
+            let (target, edits) = apply_variants(source, variants);
+            let liftover = Liftover::from_edits("contig_1", source.len(), "strain", &edits);
+            lift_genes(&[gene(3, 17)], &liftover, source, &target, &codon_table)
+        };
+
+        // A codon deleted upstream of the gene shifts it without disrupting it
+        let lifted = lift(vec![(0, Variant::Deletion(1))]);
+        assert_eq!((*lifted[0].start(), *lifted[0].end()), (2, 16));
+        assert_eq!(lifted[0].seqname(), "strain");
+        assert!(lifted[0].attributes().get("pseudo").is_none());
+
+        let lifted = lift(vec![(6, Variant::Insertion(b"A".to_vec()))]);
+        assert_eq!(*lifted[0].end(), 18);
+        assert_eq!(lifted[0].attributes()["disruption"], "frameshift");
+
+        let lifted = lift(vec![(5, Variant::SNV(b'T')), (7, Variant::SNV(b'A'))]);
+        assert_eq!(lifted[0].attributes()["disruption"], "premature_stop");
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use model::variants::{Base, Variant};
+
+/// Header matching the columns produced by [HaplotypeCounts::to_tsv]
+pub const LINKAGE_HEADER: &str = "distance\treads\trefRef\trefAlt\taltRef\taltAlt\
+                                  \tD\tDPrime\trSquared";
+
+/// Header matching the columns produced by [LinkageDecay::rows]
+pub const DECAY_HEADER: &str = "binStart\tbinEnd\tpairs\tmeanRSquared\tmeanDPrime";
+
+/// Thresholds used when calculating linkage between pairs of variants
+#[derive(Debug, Clone)]
+pub struct LinkageParameters {
+    /// Minimum number of reads covering both sites for a pair to be reported
+    pub min_reads: usize,
+    /// Maximum distance between a pair of sites
+    pub max_distance: i64,
+    /// Width of the distance bins used for LD decay
+    pub bin_size: i64,
+}
+
+impl LinkageParameters {
+    pub fn generate_from_clap(m: &clap::ArgMatches) -> LinkageParameters {
+        let bin_size: i64 = m.value_of("linkage-bin-size").unwrap().parse()
+            .expect("Unable to parse --linkage-bin-size");
+        if bin_size <= 0 {
+            panic!("--linkage-bin-size must be greater than 0");
+        }
+        LinkageParameters {
+            min_reads: m.value_of("min-linkage-reads").unwrap().parse()
+                .expect("Unable to parse --min-linkage-reads"),
+            max_distance: m.value_of("max-linkage-distance").unwrap().parse()
+                .expect("Unable to parse --max-linkage-distance"),
+            bin_size,
+        }
+    }
+}
+
+impl Default for LinkageParameters {
+    fn default() -> LinkageParameters {
+        LinkageParameters {
+            min_reads: 5,
+            max_distance: 5000,
+            bin_size: 100,
+        }
+    }
+}
+
+/// Adds a read to the read set of each allele at a site that the read carries: an SNV when
+/// the read has its alternate base and the reference allele when the read matches the
+/// reference. Both reads of a pair share an id, so alleles seen on either mate are linked
+pub fn assign_read_to_alleles(alleles: &mut HashMap<Variant, Base>, read_base: u8, ref_base: u8,
+                              read_id: i64) {
+    for (variant, base) in alleles.iter_mut() {
+        match variant {
+            Variant::SNV(alt) if *alt != ref_base && *alt == read_base => {
+                base.assign_read(read_id)
+            },
+            Variant::None if ref_base == read_base => base.assign_read(read_id),
+            _ => {}
+        }
+    }
+}
+
+/// Counts of the two-site haplotypes observed on reads covering a pair of sites. At each
+/// site the alternate allele is compared against every other allele seen at that site
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct HaplotypeCounts {
+    pub ref_ref: usize,
+    pub ref_alt: usize,
+    pub alt_ref: usize,
+    pub alt_alt: usize,
+}
+
+impl HaplotypeCounts {
+    /// Count haplotypes from the read ids carrying the alternate and other alleles at each
+    /// site. Reads assigned to both alleles of a site are ambiguous and ignored
+    pub fn from_reads(alt_a: &HashSet<i64>, other_a: &HashSet<i64>,
+                      alt_b: &HashSet<i64>, other_b: &HashSet<i64>) -> HaplotypeCounts {
+        let mut counts = HaplotypeCounts::default();
+        for read in alt_a.iter().chain(other_a.iter()) {
+            let a_alt = alt_a.contains(read);
+            let b_alt = alt_b.contains(read);
+            if a_alt && other_a.contains(read) {
+                continue
+            }
+            if b_alt == other_b.contains(read) {
+                // Either the read does not cover the second site or it is ambiguous there
+                continue
+            }
+            match (a_alt, b_alt) {
+                (false, false) => counts.ref_ref += 1,
+                (false, true) => counts.ref_alt += 1,
+                (true, false) => counts.alt_ref += 1,
+                (true, true) => counts.alt_alt += 1,
+            }
+        }
+        counts
+    }
+
+    pub fn total(&self) -> usize {
+        self.ref_ref + self.ref_alt + self.alt_ref + self.alt_alt
+    }
+
+    /// Alternate allele frequencies at each site and the frequency of the alt-alt haplotype
+    fn frequencies(&self) -> Option<(f64, f64, f64)> {
+        let total = self.total();
+        if total == 0 {
+            return None
+        }
+        let total = total as f64;
+        let p_a = (self.alt_ref + self.alt_alt) as f64 / total;
+        let p_b = (self.ref_alt + self.alt_alt) as f64 / total;
+        if p_a == 0. || p_a == 1. || p_b == 0. || p_b == 1. {
+            // One of the sites is not polymorphic on these reads
+            return None
+        }
+        Some((p_a, p_b, self.alt_alt as f64 / total))
+    }
+
+    /// Coefficient of linkage disequilibrium D
+    pub fn d(&self) -> Option<f64> {
+        self.frequencies().map(|(p_a, p_b, p_ab)| p_ab - p_a * p_b)
+    }
+
+    /// Lewontin's D′, D normalised by its maximum given the allele frequencies
+    pub fn d_prime(&self) -> Option<f64> {
+        self.frequencies().map(|(p_a, p_b, p_ab)| {
+            let d = p_ab - p_a * p_b;
+            let d_max = if d < 0. {
+                (p_a * p_b).min((1. - p_a) * (1. - p_b))
+            } else {
+                (p_a * (1. - p_b)).min((1. - p_a) * p_b)
+            };
+            d / d_max
+        })
+    }
+
+    pub fn r_squared(&self) -> Option<f64> {
+        self.frequencies().map(|(p_a, p_b, p_ab)| {
+            let d = p_ab - p_a * p_b;
+            d * d / (p_a * (1. - p_a) * p_b * (1. - p_b))
+        })
+    }
+
+    /// Columns matching [LINKAGE_HEADER]
+    pub fn to_tsv(&self, distance: i64) -> String {
+        let format = |statistic: Option<f64>| match statistic {
+            Some(value) => format!("{:.4}", value),
+            None => "NA".to_string(),
+        };
+        format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                distance, self.total(), self.ref_ref, self.ref_alt, self.alt_ref, self.alt_alt,
+                format(self.d()), format(self.d_prime()), format(self.r_squared()))
+    }
+}
+
+/// Mean r² and |D′| of variant pairs binned by the distance between them
+#[derive(Debug, Clone)]
+pub struct LinkageDecay {
+    bin_size: i64,
+    // Bin index, pairs, summed r², summed |D′|
+    bins: BTreeMap<i64, (usize, f64, f64)>,
+}
+
+impl LinkageDecay {
+    pub fn new(bin_size: i64) -> LinkageDecay {
+        LinkageDecay {
+            bin_size,
+            bins: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, distance: i64, counts: &HaplotypeCounts) {
+        if let (Some(r_squared), Some(d_prime)) = (counts.r_squared(), counts.d_prime()) {
+            let bin = self.bins.entry(distance / self.bin_size).or_insert((0, 0., 0.));
+            bin.0 += 1;
+            bin.1 += r_squared;
+            bin.2 += d_prime.abs();
+        }
+    }
+
+    /// Columns matching [DECAY_HEADER] for each bin containing at least one pair
+    pub fn rows(&self) -> Vec<String> {
+        self.bins.iter().map(|(bin, (pairs, r_squared, d_prime))| {
+            format!("{}\t{}\t{}\t{:.4}\t{:.4}",
+                    bin * self.bin_size, (bin + 1) * self.bin_size - 1, pairs,
+                    r_squared / *pairs as f64, d_prime / *pairs as f64)
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reads(ids: &[i64]) -> HashSet<i64> {
+        ids.iter().cloned().collect()
+    }
+
+    #[test]
+    fn test_complete_linkage() {
+        let counts = HaplotypeCounts::from_reads(
+            &reads(&[1, 2, 3]), &reads(&[4, 5, 6, 7]),
+            &reads(&[1, 2, 3]), &reads(&[4, 5, 6, 7, 8]));
+        assert_eq!(counts, HaplotypeCounts { ref_ref: 4, ref_alt: 0, alt_ref: 0, alt_alt: 3 });
+        assert!((counts.r_squared().unwrap() - 1.).abs() < 1e-9);
+        assert!((counts.d_prime().unwrap() - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_linkage_statistics() {
+        let counts = HaplotypeCounts { ref_ref: 40, ref_alt: 10, alt_ref: 20, alt_alt: 30 };
+        let (p_a, p_b, p_ab) = (0.5, 0.4, 0.3);
+        let d = p_ab - p_a * p_b;
+        assert!((counts.d().unwrap() - d).abs() < 1e-9);
+        assert!((counts.d_prime().unwrap() - d / (0.5f64 * 0.6).min(0.5 * 0.4)).abs() < 1e-9);
+        assert!((counts.r_squared().unwrap() - d * d / (0.25 * 0.24)).abs() < 1e-9);
+
+        let monomorphic = HaplotypeCounts { ref_ref: 10, ref_alt: 5, alt_ref: 0, alt_alt: 0 };
+        assert_eq!(monomorphic.r_squared(), None);
+        assert!(monomorphic.to_tsv(10).ends_with("NA\tNA\tNA"));
+    }
+
+    #[test]
+    fn test_linkage_across_mates() {
+        // Two SNV sites further apart than a read, so each mate of a pair covers only one.
+        // Pairs 1 and 2 carry both alternate alleles and pairs 3 and 4 neither
+        let site = |alt: u8, ref_base: u8| {
+            let mut alleles = HashMap::new();
+            alleles.insert(Variant::SNV(alt), Base::new(100, vec![ref_base], 1));
+            alleles.insert(Variant::None, Base::new(100, vec![ref_base], 1));
+            alleles
+        };
+        let mut site_a = site(b'T', b'A');
+        let mut site_b = site(b'G', b'C');
+        let mates: Vec<(&[u8], u8, u8)> = vec![
+            (b"pair_1", b'T', b'G'), (b"pair_2", b'T', b'G'),
+            (b"pair_3", b'A', b'C'), (b"pair_4", b'A', b'C'),
+        ];
+
+        // Reads are given ids by name as they are seen, so both mates share one
+        let mut read_to_id: HashMap<Vec<u8>, i64> = HashMap::new();
+        for (name, base_a, base_b) in mates.iter() {
+            let next_id = read_to_id.len() as i64;
+            let first_mate = *read_to_id.entry(name.to_vec()).or_insert(next_id);
+            assign_read_to_alleles(&mut site_a, *base_a, b'A', first_mate);
+            let next_id = read_to_id.len() as i64;
+            let second_mate = *read_to_id.entry(name.to_vec()).or_insert(next_id);
+            assign_read_to_alleles(&mut site_b, *base_b, b'C', second_mate);
+        }
+
+        let counts = HaplotypeCounts::from_reads(
+            &site_a[&Variant::SNV(b'T')].reads, &site_a[&Variant::None].reads,
+            &site_b[&Variant::SNV(b'G')].reads, &site_b[&Variant::None].reads);
+        assert_eq!(counts, HaplotypeCounts { ref_ref: 2, ref_alt: 0, alt_ref: 0, alt_alt: 2 });
+        assert!((counts.r_squared().unwrap() - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_linkage_decay() {
+        let linked = HaplotypeCounts { ref_ref: 10, ref_alt: 0, alt_ref: 0, alt_alt: 10 };
+        let unlinked = HaplotypeCounts { ref_ref: 5, ref_alt: 5, alt_ref: 5, alt_alt: 5 };
+        let mut decay = LinkageDecay::new(100);
+        decay.add(10, &linked);
+        decay.add(50, &unlinked);
+        decay.add(250, &unlinked);
+        assert_eq!(decay.rows(), vec![
+            "0\t99\t2\t0.5000\t0.5000".to_string(),
+            "200\t299\t1\t0.0000\t0.0000".to_string(),
+        ]);
+    }
+}
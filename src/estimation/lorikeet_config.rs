@@ -0,0 +1,553 @@
+use std::collections::{BTreeMap, HashMap};
+use coverm::FlagFilter;
+use coverm::mosdepth_genome_coverage_estimators::CoverageEstimator;
+use serde_json::Value;
+use estimation::differential_frequency::{self, SampleGroup};
+use estimation::diversity::DiversityParameters;
+use estimation::linkage::LinkageParameters;
+use estimation::frequency_spectrum::{self, SpectrumParameters};
+use estimation::trajectory::TrajectoryParameters;
+use estimation::polish::PolishParameters;
+use estimation::report::OutputFormat;
+use estimation::run_config::resolved_parameters;
+use estimation::strain_assignment::{AssignmentParameters, ExtractionRules};
+use estimation::sample_sheet::{ReadTechnology, SampleSheet};
+use parse_percentage;
+
+/// Options of a pileup run. Created from the command line with generate_from_clap, or
+/// from code with LorikeetConfigBuilder. Options a mode does not use are ignored
+#[derive(Debug, Clone)]
+pub struct LorikeetConfig {
+    pub reference: String,
+    pub bam_files: Vec<String>,
+    pub longread_bam_files: Vec<String>,
+    /// Name of each sample, short read samples first. Samples are named after their files
+    /// when empty
+    pub sample_names: Vec<String>,
+    /// Sample sheet naming the samples after the libraries their reads come from, used in
+    /// place of sample_names when given
+    pub sample_sheet: Option<SampleSheet>,
+    /// Previously called variants, matched to BAM files by sample name
+    pub vcfs: Vec<String>,
+    /// Directory holding the BAM files of mapped reads, used for variant calling when reads
+    /// are mapped rather than given as BAM files
+    pub bam_cache_directory: Option<String>,
+    /// Directory where sorted BAM files and variant calls are stored and reused across runs
+    pub result_cache: Option<String>,
+    pub gff: Option<String>,
+    pub output_prefix: String,
+    pub output_format: OutputFormat,
+    /// Write a self-contained HTML report of the run when set
+    pub html_report: bool,
+    pub threads: usize,
+    pub flag_filters: FlagFilter,
+    pub mapq_threshold: u8,
+    pub min_variant_depth: usize,
+    /// Coverage estimation method, either mean or trimmed_mean
+    pub method: String,
+    pub min_covered_fraction: f32,
+    pub trim_min: f32,
+    pub trim_max: f32,
+    pub contig_end_exclusion: u64,
+    pub coverage_fold: f32,
+    pub include_indels: bool,
+    pub include_soft_clipping: bool,
+    /// Fraction of identity above which strains are merged, or 0 to keep all strains
+    pub strain_ani: f32,
+    /// Minimum length of genes predicted when no GFF file is given
+    pub min_gene_length: usize,
+    pub e_min: f64,
+    pub e_max: f64,
+    pub pts_min: f64,
+    pub pts_max: f64,
+    pub phi: f64,
+    /// Minimum read depth for a sample's allele to be included in the SNP alignment
+    pub min_site_depth: i32,
+    /// Minimum length of indels called by sniffles in long reads
+    pub min_long_indel_length: usize,
+    pub diversity: DiversityParameters,
+    pub linkage: LinkageParameters,
+    pub spectrum: SpectrumParameters,
+    pub trajectory: TrajectoryParameters,
+    pub polish: PolishParameters,
+    pub sample_metadata: Option<String>,
+    /// Groups compared by the differential frequency tests, which are run when there are any
+    pub sample_groups: HashMap<String, SampleGroup>,
+    /// Reads are assigned to strains after genotyping when set
+    pub assignment: Option<AssignmentParameters>,
+    /// The reads of each strain are extracted when reads are assigned and this is set
+    pub extraction: Option<ExtractionRules>,
+    /// Parameters recorded in the JSON report
+    pub report_parameters: BTreeMap<String, Value>,
+}
+
+impl LorikeetConfig {
+    /// Defaults of the command line, with the given reference and no samples
+    pub fn new(reference: &str) -> LorikeetConfig {
+        LorikeetConfig {
+            reference: reference.to_string(),
+            bam_files: Vec::new(),
+            longread_bam_files: Vec::new(),
+            sample_names: Vec::new(),
+            sample_sheet: None,
+            vcfs: Vec::new(),
+            bam_cache_directory: None,
+            result_cache: None,
+            gff: None,
+            output_prefix: "output".to_string(),
+            output_format: OutputFormat::Tsv,
+            html_report: false,
+            threads: 1,
+            flag_filters: FlagFilter {
+                include_improper_pairs: false,
+                include_secondary: false,
+                include_supplementary: false,
+            },
+            mapq_threshold: 0,
+            min_variant_depth: 10,
+            method: "trimmed_mean".to_string(),
+            min_covered_fraction: 0.,
+            trim_min: 0.05,
+            trim_max: 0.95,
+            contig_end_exclusion: 75,
+            coverage_fold: 0.5,
+            include_indels: false,
+            include_soft_clipping: false,
+            strain_ani: 0.,
+            min_gene_length: 90,
+            e_min: 0.05,
+            e_max: 0.15,
+            pts_min: 10.,
+            pts_max: 25.,
+            phi: 0.,
+            min_site_depth: 5,
+            min_long_indel_length: 30,
+            diversity: DiversityParameters::default(),
+            linkage: LinkageParameters::default(),
+            spectrum: SpectrumParameters::default(),
+            trajectory: TrajectoryParameters::default(),
+            polish: PolishParameters::default(),
+            sample_metadata: None,
+            sample_groups: HashMap::new(),
+            assignment: None,
+            extraction: None,
+            report_parameters: BTreeMap::new(),
+        }
+    }
+
+    /// Options of a pileup subcommand. Options the subcommand does not have keep their
+    /// defaults
+    pub fn generate_from_clap(m: &clap::ArgMatches, subcommand: &str) -> LorikeetConfig {
+        let values = |name: &str| -> Vec<String> {
+            match m.values_of(name) {
+                Some(values) => values.map(|value| value.to_string()).collect(),
+                None => Vec::new(),
+            }
+        };
+        let mut config = LorikeetConfig::new(m.value_of("reference").unwrap());
+        config.bam_files = values("bam-files");
+        config.longread_bam_files = values("longread-bam-files");
+        if let Some(sample_sheet) = m.value_of("sample-sheet") {
+            let sheet = SampleSheet::from_file(sample_sheet);
+            config.sample_groups = sheet.groups.clone();
+            config.sample_sheet = Some(sheet);
+        }
+        config.vcfs = values("vcfs");
+        config.bam_cache_directory = m.value_of("outdir").map(|dir| dir.to_string());
+        config.result_cache = m.value_of("result-cache").map(|dir| dir.to_string());
+        config.gff = m.value_of("gff").map(|gff| gff.to_string());
+        if let Some(output_prefix) = m.value_of("output-prefix") {
+            config.output_prefix = output_prefix.to_string();
+        }
+        config.output_format = OutputFormat::from_clap(m);
+        config.html_report = m.is_present("html-report");
+        config.threads = m.value_of("threads").unwrap().parse()
+            .expect("Unable to parse --threads");
+        config.flag_filters = FlagFilter {
+            include_improper_pairs: m.is_present("allow-improper-pairs"),
+            include_secondary: m.is_present("include-secondary"),
+            include_supplementary: m.is_present("include-supplementary"),
+        };
+        config.mapq_threshold = m.value_of("mapq-threshold").unwrap().parse()
+            .expect("Unable to parse --mapq-threshold");
+        config.min_variant_depth = m.value_of("min-variant-depth").unwrap().parse()
+            .expect("Unable to parse --min-variant-depth");
+        config.method = m.value_of("method").unwrap().to_string();
+        config.min_covered_fraction = parse_percentage(m, "min-covered-fraction");
+        config.trim_min = m.value_of("trim-min").unwrap().parse()
+            .expect("Unable to parse --trim-min");
+        config.trim_max = m.value_of("trim-max").unwrap().parse()
+            .expect("Unable to parse --trim-max");
+        config.contig_end_exclusion = m.value_of("contig-end-exclusion").unwrap().parse()
+            .expect("Unable to parse --contig-end-exclusion");
+        config.coverage_fold = m.value_of("coverage-fold").unwrap().parse()
+            .expect("Unable to parse --coverage-fold");
+        config.include_indels = m.is_present("include-indels");
+        config.include_soft_clipping = m.is_present("include-soft-clipping");
+        config.strain_ani = parse_percentage(m, "strain-ani");
+        if let Some(min_gene_length) = m.value_of("min-gene-length") {
+            config.min_gene_length = min_gene_length.parse()
+                .expect("Unable to parse --min-gene-length");
+        }
+        if m.is_present("e-min") {
+            config.e_min = m.value_of("e-min").unwrap().parse().unwrap();
+            config.e_max = m.value_of("e-max").unwrap().parse().unwrap();
+            config.pts_min = m.value_of("pts-min").unwrap().parse().unwrap();
+            config.pts_max = m.value_of("pts-max").unwrap().parse().unwrap();
+            config.phi = m.value_of("phi").unwrap().parse().unwrap();
+        }
+        if let Some(min_site_depth) = m.value_of("min-site-depth") {
+            config.min_site_depth = min_site_depth.parse()
+                .expect("Unable to parse --min-site-depth");
+        }
+        if let Some(min_indel_length) = m.value_of("min-long-indel-length") {
+            config.min_long_indel_length = min_indel_length.parse()
+                .expect("Unable to parse --min-long-indel-length");
+        }
+        if m.is_present("window-size") {
+            config.diversity = DiversityParameters::generate_from_clap(m);
+        }
+        if m.is_present("linkage-bin-size") {
+            config.linkage = LinkageParameters::generate_from_clap(m);
+        }
+        if m.is_present("frequency-bins") {
+            config.spectrum = SpectrumParameters::generate_from_clap(m);
+        } else if m.is_present("max-vaf-clusters") {
+            config.spectrum.max_components = frequency_spectrum::max_components_from_clap(m);
+        }
+        if m.is_present("sweep-frequency-change") {
+            config.trajectory = TrajectoryParameters::generate_from_clap(m);
+        }
+        if m.is_present("min-polish-frequency") {
+            config.polish = PolishParameters::generate_from_clap(m);
+        }
+        config.sample_metadata = m.value_of("sample-metadata").map(|path| path.to_string());
+        if let Some(sample_groups) = m.value_of("sample-groups") {
+            config.sample_groups.extend(differential_frequency::read_sample_groups(sample_groups));
+        }
+        if m.is_present("assign-reads") {
+            config.assignment = Some(AssignmentParameters::generate_from_clap(m));
+            if m.is_present("extract-strain-reads") {
+                config.extraction = Some(ExtractionRules::generate_from_clap(m));
+            }
+        }
+        config.report_parameters = resolved_parameters(m, subcommand);
+        config.validate();
+        config
+    }
+
+    /// Name of the sample at sample_idx read by the named reader. With a sample sheet this is
+    /// the sample of the library the reader's reads come from, otherwise the sample's entry in
+    /// sample_names or the reader name when samples are named after their files
+    pub fn sample_name(&self, sample_idx: usize, reader_name: &str, longread: bool) -> String {
+        if let Some(sheet) = &self.sample_sheet {
+            return match sheet.reader_sample_name(reader_name, longread) {
+                Some(name) => name.to_string(),
+                None => {
+                    warn!("Unable to find the sample sheet library of {}, naming it after its \
+                           file", reader_name);
+                    reader_name.to_string()
+                },
+            }
+        }
+        match self.sample_names.get(sample_idx) {
+            Some(name) => name.clone(),
+            None => reader_name.to_string(),
+        }
+    }
+
+    /// Technology of the long reads in a BAM or CRAM file, when given by the sample sheet
+    pub fn long_read_technology(&self, bam_path: &str) -> Option<ReadTechnology> {
+        match &self.sample_sheet {
+            Some(sheet) => sheet.long_read_technology(bam_path),
+            None => None,
+        }
+    }
+
+    /// Coverage estimators used for the contig statistics, following method
+    pub fn coverage_estimators(&self) -> Vec<CoverageEstimator> {
+        let mut estimators = vec![CoverageEstimator::new_estimator_length()];
+        match self.method.as_str() {
+            "mean" => estimators.push(CoverageEstimator::new_estimator_mean(
+                self.min_covered_fraction,
+                self.contig_end_exclusion,
+                false,
+            )),
+            "trimmed_mean" => estimators.push(CoverageEstimator::new_estimator_trimmed_mean(
+                self.trim_min,
+                self.trim_max,
+                self.min_covered_fraction,
+                self.contig_end_exclusion,
+            )),
+            _ => panic!("Unknown coverage method {}, expected mean or trimmed_mean", self.method),
+        }
+        estimators.push(CoverageEstimator::new_estimator_variance(
+            self.min_covered_fraction,
+            self.contig_end_exclusion,
+        ));
+        estimators
+    }
+
+    fn validate(&self) {
+        if self.trim_min < 0.0 || self.trim_min > 1.0 || self.trim_max <= self.trim_min
+            || self.trim_max > 1.0 {
+            panic!("error: Trim bounds must be between 0 and 1, and \
+                    min must be less than max, found {} and {}", self.trim_min, self.trim_max);
+        }
+        if self.threads == 0 {
+            panic!("threads must be greater than 0");
+        }
+    }
+}
+
+/// Builds a LorikeetConfig from the command line defaults
+#[derive(Debug, Clone)]
+pub struct LorikeetConfigBuilder {
+    config: LorikeetConfig,
+}
+
+impl LorikeetConfigBuilder {
+    pub fn new(reference: &str) -> LorikeetConfigBuilder {
+        LorikeetConfigBuilder {
+            config: LorikeetConfig::new(reference),
+        }
+    }
+
+    pub fn bam_files(mut self, bam_files: &[&str]) -> LorikeetConfigBuilder {
+        self.config.bam_files = bam_files.iter().map(|path| path.to_string()).collect();
+        self
+    }
+
+    pub fn longread_bam_files(mut self, bam_files: &[&str]) -> LorikeetConfigBuilder {
+        self.config.longread_bam_files = bam_files.iter().map(|path| path.to_string()).collect();
+        self
+    }
+
+    /// Names of the samples, short read samples first
+    pub fn sample_names(mut self, names: &[&str]) -> LorikeetConfigBuilder {
+        self.config.sample_names = names.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
+    pub fn vcfs(mut self, vcfs: &[&str]) -> LorikeetConfigBuilder {
+        self.config.vcfs = vcfs.iter().map(|path| path.to_string()).collect();
+        self
+    }
+
+    pub fn bam_cache_directory(mut self, directory: &str) -> LorikeetConfigBuilder {
+        self.config.bam_cache_directory = Some(directory.to_string());
+        self
+    }
+
+    pub fn result_cache(mut self, directory: &str) -> LorikeetConfigBuilder {
+        self.config.result_cache = Some(directory.to_string());
+        self
+    }
+
+    pub fn gff(mut self, gff: &str) -> LorikeetConfigBuilder {
+        self.config.gff = Some(gff.to_string());
+        self
+    }
+
+    pub fn output_prefix(mut self, output_prefix: &str) -> LorikeetConfigBuilder {
+        self.config.output_prefix = output_prefix.to_string();
+        self
+    }
+
+    pub fn output_format(mut self, output_format: OutputFormat) -> LorikeetConfigBuilder {
+        self.config.output_format = output_format;
+        self
+    }
+
+    pub fn html_report(mut self, html_report: bool) -> LorikeetConfigBuilder {
+        self.config.html_report = html_report;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> LorikeetConfigBuilder {
+        self.config.threads = threads;
+        self
+    }
+
+    pub fn flag_filters(mut self, flag_filters: FlagFilter) -> LorikeetConfigBuilder {
+        self.config.flag_filters = flag_filters;
+        self
+    }
+
+    pub fn mapq_threshold(mut self, mapq_threshold: u8) -> LorikeetConfigBuilder {
+        self.config.mapq_threshold = mapq_threshold;
+        self
+    }
+
+    pub fn min_variant_depth(mut self, min_variant_depth: usize) -> LorikeetConfigBuilder {
+        self.config.min_variant_depth = min_variant_depth;
+        self
+    }
+
+    /// Coverage estimation method, either mean or trimmed_mean
+    pub fn method(mut self, method: &str) -> LorikeetConfigBuilder {
+        self.config.method = method.to_string();
+        self
+    }
+
+    pub fn trim(mut self, min: f32, max: f32) -> LorikeetConfigBuilder {
+        self.config.trim_min = min;
+        self.config.trim_max = max;
+        self
+    }
+
+    pub fn contig_end_exclusion(mut self, contig_end_exclusion: u64) -> LorikeetConfigBuilder {
+        self.config.contig_end_exclusion = contig_end_exclusion;
+        self
+    }
+
+    pub fn coverage_fold(mut self, coverage_fold: f32) -> LorikeetConfigBuilder {
+        self.config.coverage_fold = coverage_fold;
+        self
+    }
+
+    pub fn include_indels(mut self, include_indels: bool) -> LorikeetConfigBuilder {
+        self.config.include_indels = include_indels;
+        self
+    }
+
+    pub fn include_soft_clipping(mut self, include_soft_clipping: bool) -> LorikeetConfigBuilder {
+        self.config.include_soft_clipping = include_soft_clipping;
+        self
+    }
+
+    pub fn strain_ani(mut self, strain_ani: f32) -> LorikeetConfigBuilder {
+        self.config.strain_ani = strain_ani;
+        self
+    }
+
+    pub fn min_gene_length(mut self, min_gene_length: usize) -> LorikeetConfigBuilder {
+        self.config.min_gene_length = min_gene_length;
+        self
+    }
+
+    /// Parameters of the fuzzy DBSCAN clustering used by genotype
+    pub fn clustering(mut self, e_min: f64, e_max: f64, pts_min: f64, pts_max: f64, phi: f64)
+        -> LorikeetConfigBuilder {
+        self.config.e_min = e_min;
+        self.config.e_max = e_max;
+        self.config.pts_min = pts_min;
+        self.config.pts_max = pts_max;
+        self.config.phi = phi;
+        self
+    }
+
+    pub fn min_site_depth(mut self, min_site_depth: i32) -> LorikeetConfigBuilder {
+        self.config.min_site_depth = min_site_depth;
+        self
+    }
+
+    pub fn min_long_indel_length(mut self, min_long_indel_length: usize) -> LorikeetConfigBuilder {
+        self.config.min_long_indel_length = min_long_indel_length;
+        self
+    }
+
+    pub fn diversity(mut self, parameters: DiversityParameters) -> LorikeetConfigBuilder {
+        self.config.diversity = parameters;
+        self
+    }
+
+    pub fn linkage(mut self, parameters: LinkageParameters) -> LorikeetConfigBuilder {
+        self.config.linkage = parameters;
+        self
+    }
+
+    pub fn spectrum(mut self, parameters: SpectrumParameters) -> LorikeetConfigBuilder {
+        self.config.spectrum = parameters;
+        self
+    }
+
+    pub fn trajectory(mut self, parameters: TrajectoryParameters) -> LorikeetConfigBuilder {
+        self.config.trajectory = parameters;
+        self
+    }
+
+    pub fn polish(mut self, parameters: PolishParameters) -> LorikeetConfigBuilder {
+        self.config.polish = parameters;
+        self
+    }
+
+    pub fn sample_metadata(mut self, path: &str) -> LorikeetConfigBuilder {
+        self.config.sample_metadata = Some(path.to_string());
+        self
+    }
+
+    pub fn sample_groups(mut self, groups: HashMap<String, SampleGroup>) -> LorikeetConfigBuilder {
+        self.config.sample_groups = groups;
+        self
+    }
+
+    pub fn assignment(mut self, parameters: AssignmentParameters) -> LorikeetConfigBuilder {
+        self.config.assignment = Some(parameters);
+        self
+    }
+
+    pub fn extraction(mut self, rules: ExtractionRules) -> LorikeetConfigBuilder {
+        self.config.extraction = Some(rules);
+        self
+    }
+
+    pub fn report_parameters(mut self, parameters: BTreeMap<String, Value>)
+        -> LorikeetConfigBuilder {
+        self.config.report_parameters = parameters;
+        self
+    }
+
+    /// Panics when the trim bounds or thread count are invalid
+    pub fn build(self) -> LorikeetConfig {
+        self.config.validate();
+        self.config
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cli::build_cli;
+
+    #[test]
+    fn test_config_from_clap() {
+        let matches = build_cli().get_matches_from(vec![
+            "lorikeet", "summarize", "-r", "reference.fna", "-b", "sample_1.bam", "sample_2.bam",
+            "--threads", "4", "--include-indels", "--window-size", "500"]);
+        let config = LorikeetConfig::generate_from_clap(
+            matches.subcommand_matches("summarize").unwrap(), "summarize");
+        let built = LorikeetConfigBuilder::new("reference.fna")
+            .bam_files(&["sample_1.bam", "sample_2.bam"])
+            .threads(4)
+            .include_indels(true)
+            .build();
+
+        assert_eq!(config.reference, built.reference);
+        assert_eq!(config.bam_files, built.bam_files);
+        assert_eq!(config.threads, built.threads);
+        assert_eq!(config.include_indels, built.include_indels);
+        assert_eq!(config.min_variant_depth, built.min_variant_depth);
+        assert_eq!(config.method, built.method);
+        assert_eq!(config.trim_max, built.trim_max);
+        assert_eq!(config.contig_end_exclusion, built.contig_end_exclusion);
+        assert_eq!(config.coverage_fold, built.coverage_fold);
+        assert_eq!(config.output_prefix, built.output_prefix);
+        assert_eq!(config.min_site_depth, built.min_site_depth);
+        assert_eq!(config.diversity.window_size, 500);
+        assert_eq!(config.diversity.window_step, 500);
+        assert_eq!(config.linkage.max_distance, built.linkage.max_distance);
+        assert_eq!(config.spectrum.bins, built.spectrum.bins);
+        assert_eq!(config.trajectory.min_change, built.trajectory.min_change);
+        assert!(config.assignment.is_none());
+        assert_eq!(config.report_parameters["threads"], Value::String("4".to_string()));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_invalid_trim_bounds() {
+        LorikeetConfigBuilder::new("reference.fna")
+            .trim(0.9, 0.1)
+            .build();
+    }
+}
@@ -1,6 +1,27 @@
-
+pub mod alignment_format;
 pub mod alignment_properties;
+pub mod annotation_loader;
 pub mod codon_structs;
 pub mod contig;
 pub mod contig_variants;
-pub mod variant_matrix;
\ No newline at end of file
+pub mod differential_frequency;
+pub mod differentiation;
+pub mod diversity;
+pub mod frequency_spectrum;
+pub mod gene_prediction;
+pub mod html_report;
+pub mod liftover;
+pub mod linkage;
+pub mod lorikeet_config;
+pub mod phylogeny;
+pub mod polish;
+pub mod read_filter;
+pub mod report;
+pub mod result_cache;
+pub mod run_config;
+pub mod sample_sheet;
+pub mod strain_assignment;
+pub mod trajectory;
+pub mod variant_annotation;
+pub mod variant_matrix;
+pub mod variation_graph;
\ No newline at end of file
@@ -0,0 +1,132 @@
+/// Character used for positions masked in the SNP alignment
+pub const MASKED: u8 = b'N';
+
+/// Whether an alignment column has at least two different unmasked alleles
+pub fn is_variable(column: &[u8]) -> bool {
+    let mut alleles = column.iter().filter(|allele| **allele != MASKED);
+    match alleles.next() {
+        Some(first) => alleles.any(|allele| allele != first),
+        None => false,
+    }
+}
+
+/// Pairwise SNP distances between aligned sequences. Positions masked in either
+/// sequence of a pair are ignored
+pub fn snp_distances(sequences: &[Vec<u8>]) -> Vec<Vec<usize>> {
+    let mut distances = vec![vec![0; sequences.len()]; sequences.len()];
+    for i in 0..sequences.len() {
+        for j in (i + 1)..sequences.len() {
+            let distance = sequences[i].iter().zip(sequences[j].iter())
+                .filter(|(a, b)| **a != MASKED && **b != MASKED && a != b)
+                .count();
+            distances[i][j] = distance;
+            distances[j][i] = distance;
+        }
+    }
+    distances
+}
+
+/// Replace characters that have meaning in Newick so names can be used as leaf labels
+fn newick_label(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '(' | ')' | ',' | ':' | ';' | '[' | ']' | '\'' => '_',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+/// Builds an unrooted neighbour-joining tree (Saitou and Nei, 1987) from a symmetric
+/// distance matrix and returns it in Newick format. Negative branch lengths are set to 0
+pub fn neighbour_joining(names: &[String], distances: &[Vec<f64>]) -> String {
+    let mut nodes: Vec<String> = names.iter().map(|name| newick_label(name)).collect();
+    let mut distances: Vec<Vec<f64>> = distances.to_vec();
+
+    match nodes.len() {
+        0 => return ";".to_string(),
+        1 => return format!("{};", nodes[0]),
+        2 => return format!("({}:{:.4},{}:{:.4});",
+                            nodes[0], distances[0][1] / 2., nodes[1], distances[0][1] / 2.),
+        _ => {},
+    }
+
+    while nodes.len() > 3 {
+        let n = nodes.len();
+        let totals: Vec<f64> = distances.iter().map(|row| row.iter().sum()).collect();
+
+        // Pair minimising the Q criterion, taking the first pair found on ties
+        let mut best = (0, 1);
+        let mut best_q = std::f64::INFINITY;
+        for i in 0..n {
+            for j in (i + 1)..n {
+                let q = (n - 2) as f64 * distances[i][j] - totals[i] - totals[j];
+                if q < best_q {
+                    best_q = q;
+                    best = (i, j);
+                }
+            }
+        }
+        let (i, j) = best;
+        let branch_i = distances[i][j] / 2. + (totals[i] - totals[j]) / (2. * (n - 2) as f64);
+        let branch_j = distances[i][j] - branch_i;
+        let joined = format!("({}:{:.4},{}:{:.4})",
+                             nodes[i], branch_i.max(0.), nodes[j], branch_j.max(0.));
+
+        // The joined node replaces i and j is removed
+        for k in 0..n {
+            if k != i && k != j {
+                let distance = (distances[i][k] + distances[j][k] - distances[i][j]) / 2.;
+                distances[i][k] = distance;
+                distances[k][i] = distance;
+            }
+        }
+        nodes[i] = joined;
+        nodes.remove(j);
+        distances.remove(j);
+        for row in distances.iter_mut() {
+            row.remove(j);
+        }
+    }
+
+    let branch = |a: usize, b: usize, c: usize| {
+        ((distances[a][b] + distances[a][c] - distances[b][c]) / 2.).max(0.)
+    };
+    format!("({}:{:.4},{}:{:.4},{}:{:.4});",
+            nodes[0], branch(0, 1, 2), nodes[1], branch(1, 0, 2), nodes[2], branch(2, 0, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snp_distances() {
+        let sequences = vec![b"ACGT".to_vec(), b"ACGA".to_vec(), b"TNGA".to_vec()];
+        let distances = snp_distances(&sequences);
+        assert_eq!(distances, vec![vec![0, 1, 2], vec![1, 0, 1], vec![2, 1, 0]]);
+        assert!(is_variable(b"ANCA"));
+        assert!(!is_variable(b"ANAA"));
+        assert!(!is_variable(b"NN"));
+    }
+
+    #[test]
+    fn test_neighbour_joining() {
+        // Additive distances from the example of Saitou and Nei's algorithm on Wikipedia
+        let names: Vec<String> = ["a", "b", "c", "d", "e"].iter().map(|s| s.to_string()).collect();
+        let distances = vec![
+            vec![0., 5., 9., 9., 8.],
+            vec![5., 0., 10., 10., 9.],
+            vec![9., 10., 0., 8., 7.],
+            vec![9., 10., 8., 0., 3.],
+            vec![8., 9., 7., 3., 0.],
+        ];
+        assert_eq!(neighbour_joining(&names, &distances),
+                   "(((a:2.0000,b:3.0000):3.0000,c:4.0000):2.0000,d:2.0000,e:1.0000);");
+        assert_eq!(neighbour_joining(&names[..2], &[vec![0., 4.], vec![4., 0.]]),
+                   "(a:2.0000,b:2.0000);");
+        assert_eq!(newick_label("sample (1): x"), "sample__1___x");
+        assert_eq!(neighbour_joining(&[], &[]), ";");
+        assert!(snp_distances(&[]).is_empty());
+    }
+}
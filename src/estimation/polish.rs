@@ -0,0 +1,399 @@
+use model::variants::Variant;
+use estimation::variant_annotation::variant_span;
+
+/// Character written in place of bases without enough coverage to polish
+pub const MASKED: u8 = b'N';
+
+/// Sequencing technology providing the evidence for an edit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Technology {
+    Short,
+    Long,
+    /// Short and long reads support the same allele
+    Hybrid,
+}
+
+impl Technology {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Technology::Short => "short",
+            Technology::Long => "long",
+            Technology::Hybrid => "hybrid",
+        }
+    }
+}
+
+/// Short reads are more accurate for substitutions, while long reads better resolve
+/// insertions and deletions, particularly in homopolymers
+pub fn trusted_technology(variant: &Variant) -> Technology {
+    match variant {
+        Variant::Insertion(_) | Variant::Deletion(_) => Technology::Long,
+        _ => Technology::Short,
+    }
+}
+
+/// How sites are resolved when short and long reads both cover a site but support
+/// different alleles
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictRule {
+    /// Keep the allele called by the technology trusted for that kind of edit
+    Trusted,
+    Short,
+    Long,
+    /// Leave the reference unchanged
+    Skip,
+}
+
+impl ConflictRule {
+    pub fn from_clap(m: &clap::ArgMatches) -> ConflictRule {
+        let rule = m.value_of("polish-conflict").unwrap();
+        match rule {
+            "trusted" => ConflictRule::Trusted,
+            "short" => ConflictRule::Short,
+            "long" => ConflictRule::Long,
+            "skip" => ConflictRule::Skip,
+            _ => panic!("Unknown polish conflict rule {}", rule),
+        }
+    }
+}
+
+/// Thresholds used when deciding which alleles are applied to the reference
+#[derive(Debug, Clone)]
+pub struct PolishParameters {
+    /// Minimum short reads supporting an allele, pooled across samples
+    pub min_depth: i32,
+    /// Minimum long reads supporting an allele, pooled across samples
+    pub min_long_depth: i32,
+    /// Minimum fraction of the pooled reads at a site supporting an allele
+    pub min_frequency: f64,
+    /// Bases with fewer pooled reads than this are masked, if set
+    pub mask_depth: Option<i32>,
+    pub conflict_rule: ConflictRule,
+}
+
+impl PolishParameters {
+    pub fn generate_from_clap(m: &clap::ArgMatches) -> PolishParameters {
+        let min_frequency: f64 = m.value_of("min-polish-frequency").unwrap().parse()
+            .expect("Unable to parse --min-polish-frequency");
+        if min_frequency <= 0. || min_frequency > 1. {
+            panic!("--min-polish-frequency must be greater than 0 and at most 1");
+        }
+        PolishParameters {
+            min_depth: m.value_of("min-polish-depth").unwrap().parse()
+                .expect("Unable to parse --min-polish-depth"),
+            min_long_depth: m.value_of("min-long-polish-depth").unwrap().parse()
+                .expect("Unable to parse --min-long-polish-depth"),
+            min_frequency,
+            mask_depth: m.value_of("mask-depth").map(|depth| depth.parse()
+                .expect("Unable to parse --mask-depth")),
+            conflict_rule: ConflictRule::from_clap(m),
+        }
+    }
+}
+
+impl Default for PolishParameters {
+    fn default() -> PolishParameters {
+        PolishParameters {
+            min_depth: 5,
+            min_long_depth: 3,
+            min_frequency: 0.5,
+            mask_depth: None,
+            conflict_rule: ConflictRule::Trusted,
+        }
+    }
+}
+
+/// Reads supporting an allele at a site, pooled across samples
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlleleSupport {
+    pub variant: Variant,
+    pub depth: i32,
+    /// Number of samples with at least one read supporting the allele
+    pub samples: usize,
+}
+
+/// Choose the allele to apply at a site from the alternate alleles observed there. The best
+/// supported allele is applied when it passes both thresholds, so at multi-allelic sites only
+/// the dominant allele can replace the reference. Ties are broken by the allele itself so
+/// results do not depend on the order alleles were seen
+pub fn choose_allele(alleles: &[AlleleSupport], total_depth: i32,
+                     parameters: &PolishParameters) -> Option<AlleleSupport> {
+    if total_depth <= 0 {
+        return None
+    }
+    let best = alleles.iter()
+        .filter(|allele| allele.variant != Variant::None)
+        .max_by(|a, b| a.depth.cmp(&b.depth)
+            .then_with(|| b.variant.to_string().cmp(&a.variant.to_string())))?;
+    if best.depth >= parameters.min_depth
+        && best.depth as f64 / total_depth as f64 >= parameters.min_frequency {
+        Some(best.clone())
+    } else {
+        None
+    }
+}
+
+/// Alleles observed at a site by one technology and the reads covering it, pooled across samples
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiteEvidence {
+    pub alleles: Vec<AlleleSupport>,
+    pub depth: i32,
+}
+
+/// Choose the edit at a site from short and long read evidence. Each technology calls an
+/// allele using its own depth threshold, and a site covered by only one technology uses that
+/// technology's call. When both cover the site but call different alleles, or only one calls
+/// an allele, the conflict rule decides which call is kept. Under the trusted rule, calls are
+/// only kept from the technology trusted for that kind of edit, preferring the call with the
+/// higher frequency if both are
+pub fn choose_hybrid_edit(pos: usize, short: &SiteEvidence, long: &SiteEvidence,
+                          parameters: &PolishParameters) -> Option<PolishEdit> {
+    let long_parameters = PolishParameters { min_depth: parameters.min_long_depth, ..parameters.clone() };
+    let short_call = choose_allele(&short.alleles, short.depth, parameters)
+        .map(|allele| PolishEdit::new(pos, allele, short.depth, Technology::Short));
+    let long_call = choose_allele(&long.alleles, long.depth, &long_parameters)
+        .map(|allele| PolishEdit::new(pos, allele, long.depth, Technology::Long));
+
+    if long.depth <= 0 {
+        return short_call
+    } else if short.depth <= 0 {
+        return long_call
+    }
+    match (short_call, long_call) {
+        (None, None) => None,
+        (Some(short_call), Some(long_call)) if short_call.variant == long_call.variant => {
+            Some(PolishEdit {
+                pos,
+                variant: short_call.variant,
+                depth: short_call.depth + long_call.depth,
+                total_depth: short.depth + long.depth,
+                samples: short_call.samples + long_call.samples,
+                technology: Technology::Hybrid,
+            })
+        },
+        (short_call, long_call) => match parameters.conflict_rule {
+            ConflictRule::Skip => None,
+            ConflictRule::Short => short_call,
+            ConflictRule::Long => long_call,
+            ConflictRule::Trusted => {
+                let short_call = short_call
+                    .filter(|edit| trusted_technology(&edit.variant) == Technology::Short);
+                let long_call = long_call
+                    .filter(|edit| trusted_technology(&edit.variant) == Technology::Long);
+                match (short_call, long_call) {
+                    (Some(short_call), Some(long_call)) => {
+                        if long_call.frequency() > short_call.frequency() {
+                            Some(long_call)
+                        } else {
+                            Some(short_call)
+                        }
+                    },
+                    (short_call, long_call) => short_call.or(long_call),
+                }
+            },
+        },
+    }
+}
+
+/// Bases written in place of the span of a variant at pos
+pub fn variant_replacement(reference: &[u8], pos: usize, variant: &Variant) -> Vec<u8> {
+    match variant {
+        Variant::SNV(alt) => vec![*alt],
+        Variant::MNV(alt) => alt.clone(),
+        Variant::Insertion(inserted) => {
+            let mut bases = vec![reference[pos]];
+            bases.extend(inserted.iter());
+            bases
+        },
+        _ => vec![reference[pos]],
+    }
+}
+
+/// An allele applied to the reference. Positions and alleles follow lorikeet's VCF parsing:
+/// an SNV or MNV replaces the bases starting at pos, while insertions and deletions keep the
+/// anchor base at pos and then insert bases or delete the bases following it
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolishEdit {
+    pub pos: usize,
+    pub variant: Variant,
+    pub depth: i32,
+    pub total_depth: i32,
+    pub samples: usize,
+    pub technology: Technology,
+}
+
+impl PolishEdit {
+    pub fn new(pos: usize, allele: AlleleSupport, total_depth: i32,
+               technology: Technology) -> PolishEdit {
+        PolishEdit {
+            pos,
+            variant: allele.variant,
+            depth: allele.depth,
+            total_depth,
+            samples: allele.samples,
+            technology,
+        }
+    }
+
+    pub fn frequency(&self) -> f64 {
+        self.depth as f64 / self.total_depth as f64
+    }
+
+    /// Reference bases replaced by the edit, including the anchor base of indels
+    pub fn span(&self, reference_len: usize) -> (usize, usize) {
+        variant_span(self.pos, &self.variant, reference_len)
+    }
+
+    /// Bases written in place of the span
+    pub fn replacement(&self, reference: &[u8]) -> Vec<u8> {
+        variant_replacement(reference, self.pos, &self.variant)
+    }
+
+    /// VCF record of the edit on the unpolished reference
+    pub fn to_vcf(&self, contig_name: &str, reference: &[u8]) -> String {
+        let (start, end) = self.span(reference.len());
+        format!("{}\t{}\t.\t{}\t{}\t.\tPASS\tDP={};AD={};AF={:.4};NS={};TECH={}",
+                contig_name, start + 1,
+                String::from_utf8_lossy(&reference[start..end]),
+                String::from_utf8_lossy(&self.replacement(reference)),
+                self.total_depth, self.depth, self.frequency(), self.samples,
+                self.technology.name())
+    }
+}
+
+/// VCF header declaring each polished contig and its length
+pub fn vcf_header(contigs: &[(String, usize)]) -> String {
+    let mut header = "##fileformat=VCFv4.2\n##source=lorikeet polish\n".to_string();
+    for (name, length) in contigs.iter() {
+        header.push_str(&format!("##contig=<ID={},length={}>\n", name, length));
+    }
+    header.push_str("##INFO=<ID=DP,Number=1,Type=Integer,Description=\"Reads covering the site pooled across samples\">\n\
+                     ##INFO=<ID=AD,Number=1,Type=Integer,Description=\"Reads supporting the applied allele pooled across samples\">\n\
+                     ##INFO=<ID=AF,Number=1,Type=Float,Description=\"Fraction of reads supporting the applied allele\">\n\
+                     ##INFO=<ID=NS,Number=1,Type=Integer,Description=\"Samples with reads supporting the applied allele\">\n\
+                     ##INFO=<ID=TECH,Number=1,Type=String,Description=\"Reads the applied allele was called from: short, long or hybrid\">\n\
+                     #CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO\n");
+    header
+}
+
+/// Apply edits to a reference, masking bases flagged in mask that are not replaced by an
+/// edit. Edits are applied in order of position, and an edit overlapping bases already
+/// replaced by an earlier edit is skipped. Returns the polished sequence and applied edits
+pub fn apply_edits(reference: &[u8], mut edits: Vec<PolishEdit>,
+                   mask: &[bool]) -> (Vec<u8>, Vec<PolishEdit>) {
+    edits.sort_by_key(|edit| edit.pos);
+    let masked = |pos: usize| if mask.get(pos).cloned().unwrap_or(false) {
+        MASKED
+    } else {
+        reference[pos]
+    };
+
+    let mut polished = Vec::with_capacity(reference.len());
+    let mut applied = Vec::new();
+    let mut cursor = 0;
+    for edit in edits.into_iter() {
+        if edit.pos < cursor || edit.pos >= reference.len() {
+            debug!("Skipping edit at {} overlapping an earlier edit", edit.pos);
+            continue
+        }
+        polished.extend((cursor..edit.pos).map(&masked));
+        polished.extend(edit.replacement(reference));
+        cursor = edit.span(reference.len()).1;
+        applied.push(edit);
+    }
+    polished.extend((cursor..reference.len()).map(masked));
+    (polished, applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameters() -> PolishParameters {
+        PolishParameters {
+            min_depth: 5,
+            min_long_depth: 3,
+            min_frequency: 0.5,
+            mask_depth: None,
+            conflict_rule: ConflictRule::Trusted,
+        }
+    }
+
+    fn support(variant: Variant, depth: i32) -> AlleleSupport {
+        AlleleSupport { variant, depth, samples: 1 }
+    }
+
+    #[test]
+    fn test_choose_allele() {
+        let alleles = vec![support(Variant::SNV(b'T'), 6), support(Variant::SNV(b'G'), 3),
+                           support(Variant::None, 1)];
+        assert_eq!(choose_allele(&alleles, 10, &parameters()).unwrap().variant, Variant::SNV(b'T'));
+        // Below the frequency and depth thresholds
+        assert_eq!(choose_allele(&alleles, 20, &parameters()), None);
+        assert_eq!(choose_allele(&[support(Variant::SNV(b'T'), 4)], 4, &parameters()), None);
+        // Ties are broken by the allele
+        let tied = vec![support(Variant::SNV(b'T'), 5), support(Variant::SNV(b'C'), 5)];
+        let relaxed = PolishParameters { min_frequency: 0.4, ..parameters() };
+        assert_eq!(choose_allele(&tied, 10, &relaxed).unwrap().variant, Variant::SNV(b'C'));
+    }
+
+    #[test]
+    fn test_apply_edits() {
+        let reference = b"ACGTACGTAC";
+        let edit = |pos, variant| PolishEdit {
+            pos, variant, depth: 8, total_depth: 10, samples: 2, technology: Technology::Short
+        };
+        let edits = vec![
+            edit(6, Variant::Insertion(b"TT".to_vec())),
+            edit(1, Variant::MNV(b"TT".to_vec())),
+            // Overlaps the MNV so is skipped
+            edit(2, Variant::SNV(b'A')),
+            edit(3, Variant::Deletion(2)),
+        ];
+        let mut mask = vec![false; reference.len()];
+        mask[9] = true;
+        mask[4] = true;
+        let (polished, applied) = apply_edits(reference, edits, &mask);
+        assert_eq!(String::from_utf8(polished).unwrap(), "ATTTGTTTAN");
+        assert_eq!(applied.len(), 3);
+        assert_eq!(applied[1].to_vcf("contig_1", reference),
+                   "contig_1\t4\t.\tTAC\tT\t.\tPASS\tDP=10;AD=8;AF=0.8000;NS=2;TECH=short");
+        assert_eq!(applied[2].to_vcf("contig_1", reference),
+                   "contig_1\t7\t.\tG\tGTT\t.\tPASS\tDP=10;AD=8;AF=0.8000;NS=2;TECH=short");
+        assert!(vcf_header(&[("contig_1".to_string(), 10)]).contains("##contig=<ID=contig_1,length=10>"));
+    }
+
+    #[test]
+    fn test_choose_hybrid_edit() {
+        let evidence = |alleles: Vec<AlleleSupport>, depth| SiteEvidence { alleles, depth };
+        let snv = evidence(vec![support(Variant::SNV(b'T'), 8)], 10);
+        let deletion = evidence(vec![support(Variant::Deletion(1), 4)], 6);
+        let no_reads = evidence(vec![], 0);
+        let reference = evidence(vec![], 10);
+
+        // Either technology is used alone where the other has no reads
+        let edit = choose_hybrid_edit(3, &snv, &no_reads, &parameters()).unwrap();
+        assert_eq!(edit.technology, Technology::Short);
+        let edit = choose_hybrid_edit(3, &no_reads, &deletion, &parameters()).unwrap();
+        assert_eq!((edit.technology, edit.variant), (Technology::Long, Variant::Deletion(1)));
+
+        // Agreeing calls are combined
+        let long_snv = evidence(vec![support(Variant::SNV(b'T'), 3)], 4);
+        let edit = choose_hybrid_edit(3, &snv, &long_snv, &parameters()).unwrap();
+        assert_eq!((edit.technology, edit.depth, edit.total_depth), (Technology::Hybrid, 11, 14));
+
+        // Substitutions follow short reads and indels long reads
+        assert_eq!(choose_hybrid_edit(3, &snv, &deletion, &parameters()).unwrap().variant,
+                   Variant::SNV(b'T'));
+        assert_eq!(choose_hybrid_edit(3, &reference, &deletion, &parameters()).unwrap().variant,
+                   Variant::Deletion(1));
+        assert_eq!(choose_hybrid_edit(3, &reference, &long_snv, &parameters()), None);
+
+        // Other conflict rules
+        let long = PolishParameters { conflict_rule: ConflictRule::Long, ..parameters() };
+        assert_eq!(choose_hybrid_edit(3, &snv, &deletion, &long).unwrap().variant, Variant::Deletion(1));
+        let skip = PolishParameters { conflict_rule: ConflictRule::Skip, ..parameters() };
+        assert_eq!(choose_hybrid_edit(3, &snv, &deletion, &skip), None);
+        assert_eq!(choose_hybrid_edit(3, &snv, &reference, &skip), None);
+    }
+}
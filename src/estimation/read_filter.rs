@@ -0,0 +1,418 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use rust_htslib::bam::record::Cigar;
+use serde_json;
+
+/// Thresholds applied by the filter subcommand in addition to coverm's identity and aligned
+/// length thresholds
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReadFilterParameters {
+    pub min_mapq: u8,
+    /// Maximum NM tag value. Alignments without an NM tag are kept
+    pub max_edit_distance: Option<i64>,
+    /// Maximum fraction of the read's bases that are soft clipped
+    pub max_soft_clip_fraction: Option<f64>,
+    pub remove_duplicates: bool,
+    /// Minimum read length after trimming bases below trim_quality from both ends
+    pub min_trimmed_length: usize,
+    pub trim_quality: u8,
+}
+
+impl ReadFilterParameters {
+    pub fn generate_from_clap(m: &clap::ArgMatches) -> ReadFilterParameters {
+        let max_soft_clip_fraction = m.value_of("max-soft-clip-fraction").map(|fraction| {
+            let fraction: f64 = fraction.parse()
+                .expect("Unable to parse --max-soft-clip-fraction");
+            if fraction < 0. || fraction > 1. {
+                panic!("--max-soft-clip-fraction must be between 0 and 1");
+            }
+            fraction
+        });
+        ReadFilterParameters {
+            min_mapq: m.value_of("min-mapq").unwrap().parse()
+                .expect("Unable to parse --min-mapq"),
+            max_edit_distance: m.value_of("max-edit-distance").map(|distance| {
+                distance.parse().expect("Unable to parse --max-edit-distance")
+            }),
+            max_soft_clip_fraction,
+            remove_duplicates: m.is_present("remove-duplicates"),
+            min_trimmed_length: m.value_of("min-trimmed-length").unwrap().parse()
+                .expect("Unable to parse --min-trimmed-length"),
+            trim_quality: m.value_of("trim-quality").unwrap().parse()
+                .expect("Unable to parse --trim-quality"),
+        }
+    }
+}
+
+/// Reason an alignment was removed. Alignments are checked against each criterion in order
+/// and attributed to the first one they fail
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterCriterion {
+    Mapq,
+    EditDistance,
+    SoftClipping,
+    TrimmedLength,
+    Duplicate,
+}
+
+/// Fraction of the bases of a read that are soft clipped
+pub fn soft_clip_fraction(cigar: &[Cigar]) -> f64 {
+    let mut clipped = 0;
+    let mut read_length = 0;
+    for cig in cigar.iter() {
+        match cig {
+            Cigar::SoftClip(_) => {
+                clipped += cig.len();
+                read_length += cig.len();
+            },
+            Cigar::Match(_) | Cigar::Ins(_) | Cigar::Equal(_) | Cigar::Diff(_) => {
+                read_length += cig.len();
+            },
+            _ => {},
+        }
+    }
+    if read_length == 0 {
+        0.
+    } else {
+        clipped as f64 / read_length as f64
+    }
+}
+
+/// Length of a read after removing bases below min_quality from both of its ends
+pub fn trimmed_length(qual: &[u8], min_quality: u8) -> usize {
+    // BAM records without qualities store 255 for every base
+    if qual.iter().all(|q| *q == 255) {
+        return qual.len()
+    }
+    match qual.iter().position(|q| *q >= min_quality) {
+        Some(start) => {
+            let end = qual.iter().rposition(|q| *q >= min_quality).unwrap();
+            end + 1 - start
+        },
+        None => 0,
+    }
+}
+
+/// Reference position of the 5' end of a read as if none of its bases were clipped, so
+/// duplicates clipped by different amounts share it. This is the last base of reverse
+/// strand reads
+pub fn unclipped_five_prime(pos: i64, cigar: &[Cigar], reverse: bool) -> i64 {
+    let is_clip = |cig: &&Cigar| match cig {
+        Cigar::SoftClip(_) | Cigar::HardClip(_) => true,
+        _ => false,
+    };
+    if reverse {
+        let reference_length: i64 = cigar.iter()
+            .map(|cig| match cig {
+                Cigar::Match(_) | Cigar::Del(_) | Cigar::RefSkip(_) | Cigar::Equal(_)
+                | Cigar::Diff(_) => cig.len() as i64,
+                _ => 0,
+            }).sum();
+        let clipped: i64 = cigar.iter().rev().take_while(is_clip).map(|cig| cig.len() as i64).sum();
+        pos + reference_length + clipped - 1
+    } else {
+        let clipped: i64 = cigar.iter().take_while(is_clip).map(|cig| cig.len() as i64).sum();
+        pos - clipped
+    }
+}
+
+/// Finds alignments with the same unclipped 5' position, strand and mate position as an
+/// earlier alignment on the same reference. Reverse strand reads are keyed on their end, so
+/// positions are remembered until the reference changes. Alignments must be reference sorted
+#[derive(Debug, Default)]
+pub struct DuplicateTracker {
+    tid: i32,
+    seen: HashSet<(i64, bool, i32, i64)>,
+}
+
+impl DuplicateTracker {
+    pub fn is_duplicate(&mut self, tid: i32, five_prime: i64, reverse: bool,
+                        mate_tid: i32, mate_pos: i64) -> bool {
+        if self.tid != tid {
+            self.tid = tid;
+            self.seen.clear();
+        }
+        !self.seen.insert((five_prime, reverse, mate_tid, mate_pos))
+    }
+}
+
+/// Keeps or removes both mates of a pair together while returning alignments in the order
+/// they were read. Alignments are held from the first mate of a pair until its mate arrives
+/// and the pair is decided. A pair is first checked for the first criterion either mate
+/// fails, and the decide function given the pair's alignments then makes the final call
+#[derive(Debug)]
+pub struct PairFilter<T> {
+    /// Alignments not yet returned in the order they were read, with their criterion and
+    /// whether it has been decided
+    queue: VecDeque<(T, Option<FilterCriterion>, bool)>,
+    /// Index in the input of each first mate whose mate is still to come
+    waiting: HashMap<Vec<u8>, usize>,
+    /// Index in the input of the alignment at the front of the queue
+    front_index: usize,
+}
+
+impl<T> Default for PairFilter<T> {
+    fn default() -> PairFilter<T> {
+        PairFilter {
+            queue: VecDeque::new(),
+            waiting: HashMap::new(),
+            front_index: 0,
+        }
+    }
+}
+
+impl<T> PairFilter<T> {
+    /// Adds an alignment with the criterion it failed, if any. Alignments without a mapped
+    /// mate are decided alone, and the second mate of a pair decides both mates. Returns the
+    /// alignments at the front of the input that have been decided, in input order
+    pub fn add<F>(&mut self, name: &[u8], has_mapped_mate: bool, record: T,
+                  criterion: Option<FilterCriterion>, decide: &mut F)
+                  -> Vec<(T, Option<FilterCriterion>)>
+        where F: FnMut(&[&T], Option<FilterCriterion>) -> Option<FilterCriterion> {
+        if !has_mapped_mate {
+            let criterion = decide(&[&record], criterion);
+            self.queue.push_back((record, criterion, true));
+        } else {
+            match self.waiting.remove(name) {
+                Some(mate_index) => {
+                    let queue_index = mate_index - self.front_index;
+                    let criterion = {
+                        let (ref mate, mate_criterion, _) = self.queue[queue_index];
+                        decide(&[mate, &record], mate_criterion.or(criterion))
+                    };
+                    self.queue[queue_index].1 = criterion;
+                    self.queue[queue_index].2 = true;
+                    self.queue.push_back((record, criterion, true));
+                },
+                None => {
+                    self.waiting.insert(name.to_vec(), self.front_index + self.queue.len());
+                    self.queue.push_back((record, criterion, false));
+                },
+            }
+        }
+        self.decided()
+    }
+
+    /// Decides the alignments whose mate was never read on their own, returning every
+    /// remaining alignment in input order
+    pub fn finish<F>(mut self, decide: &mut F) -> Vec<(T, Option<FilterCriterion>)>
+        where F: FnMut(&[&T], Option<FilterCriterion>) -> Option<FilterCriterion> {
+        for pending in self.queue.iter_mut() {
+            if !pending.2 {
+                pending.1 = decide(&[&pending.0], pending.1);
+                pending.2 = true;
+            }
+        }
+        self.decided()
+    }
+
+    fn decided(&mut self) -> Vec<(T, Option<FilterCriterion>)> {
+        let mut decided = Vec::new();
+        while self.queue.front().map(|pending| pending.2) == Some(true) {
+            let (record, criterion, _) = self.queue.pop_front().unwrap();
+            decided.push((record, criterion));
+            self.front_index += 1;
+        }
+        decided
+    }
+}
+
+/// Numbers of alignments read, removed by each criterion and written for one input file
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct FilterStats {
+    pub input_file: String,
+    pub output_file: String,
+    /// Primary alignments read from the input file
+    pub input_alignments: u64,
+    /// Primary alignments removed by the identity and aligned length thresholds
+    pub removed_identity_and_length: u64,
+    pub removed_mapq: u64,
+    pub removed_edit_distance: u64,
+    pub removed_soft_clipping: u64,
+    pub removed_trimmed_length: u64,
+    pub removed_duplicates: u64,
+    pub output_alignments: u64,
+}
+
+impl FilterStats {
+    pub fn new(input_file: &str, output_file: &str) -> FilterStats {
+        FilterStats {
+            input_file: input_file.to_string(),
+            output_file: output_file.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn remove(&mut self, criterion: FilterCriterion) {
+        match criterion {
+            FilterCriterion::Mapq => self.removed_mapq += 1,
+            FilterCriterion::EditDistance => self.removed_edit_distance += 1,
+            FilterCriterion::SoftClipping => self.removed_soft_clipping += 1,
+            FilterCriterion::TrimmedLength => self.removed_trimmed_length += 1,
+            FilterCriterion::Duplicate => self.removed_duplicates += 1,
+        }
+    }
+
+    fn rows(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            ("input", self.input_alignments),
+            ("identity_and_length", self.removed_identity_and_length),
+            ("mapq", self.removed_mapq),
+            ("edit_distance", self.removed_edit_distance),
+            ("soft_clipping", self.removed_soft_clipping),
+            ("trimmed_length", self.removed_trimmed_length),
+            ("duplicate", self.removed_duplicates),
+            ("output", self.output_alignments),
+        ]
+    }
+
+    /// Writes the number of alignments removed by each criterion to {stats_prefix}.tsv and
+    /// the same statistics to {stats_prefix}.json
+    pub fn write(&self, stats_prefix: &str) {
+        let file_name = format!("{}.tsv", stats_prefix);
+        let mut file_open = File::create(Path::new(&file_name))
+            .expect("No Read or Write Permission in current directory");
+        writeln!(file_open, "criterion\talignments\tfraction_of_input")
+            .expect("Unable to write to file");
+        for (criterion, count) in self.rows() {
+            let fraction = if self.input_alignments == 0 {
+                0.
+            } else {
+                count as f64 / self.input_alignments as f64
+            };
+            writeln!(file_open, "{}\t{}\t{:.4}", criterion, count, fraction)
+                .expect("Unable to write to file");
+        }
+
+        let file_name = format!("{}.json", stats_prefix);
+        let file_open = File::create(Path::new(&file_name))
+            .expect("No Read or Write Permission in current directory");
+        serde_json::to_writer_pretty(file_open, self)
+            .expect("Unable to write JSON filter statistics");
+    }
+}
+
+/// First criterion other than duplication an alignment fails, if any. Duplicates are found
+/// once both mates of a pair pass, so pairs removed for other reasons do not make later
+/// pairs at the same position duplicates
+pub fn failed_criterion(parameters: &ReadFilterParameters, mapq: u8, edit_distance: Option<i64>,
+                        cigar: &[Cigar], qual: &[u8]) -> Option<FilterCriterion> {
+    if mapq < parameters.min_mapq {
+        return Some(FilterCriterion::Mapq)
+    }
+    if let (Some(max), Some(distance)) = (parameters.max_edit_distance, edit_distance) {
+        if distance > max {
+            return Some(FilterCriterion::EditDistance)
+        }
+    }
+    if let Some(max) = parameters.max_soft_clip_fraction {
+        if soft_clip_fraction(cigar) > max {
+            return Some(FilterCriterion::SoftClipping)
+        }
+    }
+    if trimmed_length(qual, parameters.trim_quality) < parameters.min_trimmed_length {
+        return Some(FilterCriterion::TrimmedLength)
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parameters() -> ReadFilterParameters {
+        ReadFilterParameters {
+            min_mapq: 20,
+            max_edit_distance: Some(3),
+            max_soft_clip_fraction: Some(0.2),
+            remove_duplicates: true,
+            min_trimmed_length: 4,
+            trim_quality: 20,
+        }
+    }
+
+    #[test]
+    fn test_read_measures() {
+        let cigar = [Cigar::SoftClip(2), Cigar::Match(5), Cigar::Del(3), Cigar::Ins(1), Cigar::Match(2)];
+        assert!((soft_clip_fraction(&cigar) - 0.2).abs() < 1e-9);
+        assert_eq!(soft_clip_fraction(&[Cigar::Match(10)]), 0.);
+        assert_eq!(trimmed_length(&[2, 30, 10, 30, 30, 2, 2], 20), 4);
+        assert_eq!(trimmed_length(&[2, 2], 20), 0);
+        assert_eq!(trimmed_length(&[255, 255, 255], 20), 3);
+
+    }
+
+    #[test]
+    fn test_duplicates() {
+        // Clipping does not move the unclipped 5' end, and reverse reads are keyed on their end
+        assert_eq!(unclipped_five_prime(12, &[Cigar::SoftClip(2), Cigar::Match(8)], false), 10);
+        assert_eq!(unclipped_five_prime(10, &[Cigar::Match(10)], false), 10);
+        assert_eq!(unclipped_five_prime(10, &[Cigar::Match(6), Cigar::Del(2), Cigar::SoftClip(3)], true),
+                   20);
+        assert_eq!(unclipped_five_prime(10, &[Cigar::HardClip(4), Cigar::Match(11)], true), 20);
+
+        let mut tracker = DuplicateTracker::default();
+        assert!(!tracker.is_duplicate(0, 10, false, 0, 200));
+        assert!(tracker.is_duplicate(0, 10, false, 0, 200));
+        assert!(!tracker.is_duplicate(0, 10, true, 0, 200));
+        assert!(!tracker.is_duplicate(0, 11, false, 0, 200));
+        assert!(!tracker.is_duplicate(0, 10, false, 0, 300));
+        // Reverse reads ending at the same position are found after reads starting between them
+        assert!(!tracker.is_duplicate(0, 50, true, 0, 5));
+        assert!(!tracker.is_duplicate(0, 30, false, 0, 80));
+        assert!(tracker.is_duplicate(0, 50, true, 0, 5));
+        assert!(!tracker.is_duplicate(1, 10, false, 0, 200));
+        assert!(!tracker.is_duplicate(0, 10, false, 0, 200));
+    }
+
+    #[test]
+    fn test_pair_filter() {
+        // Pairs whose first alignment is 10 or above are duplicates
+        let mut decided = Vec::new();
+        let mut decide = |alignments: &[&i32], criterion: Option<FilterCriterion>| {
+            decided.push(alignments.iter().map(|alignment| **alignment).collect::<Vec<i32>>());
+            criterion.or(if *alignments[0] >= 10 { Some(FilterCriterion::Duplicate) } else { None })
+        };
+        let mut pairs = PairFilter::default();
+        assert_eq!(pairs.add(b"single", false, 1, None, &mut decide), vec![(1, None)]);
+        assert_eq!(pairs.add(b"pair_1", true, 2, None, &mut decide), vec![]);
+        assert_eq!(pairs.add(b"pair_2", true, 3, Some(FilterCriterion::Mapq), &mut decide), vec![]);
+        // Alignments after an undecided pair are held so that the input order is kept
+        assert_eq!(pairs.add(b"single", false, 4, None, &mut decide), vec![]);
+        // A mate failing a criterion removes the pair, whichever mate it is
+        assert_eq!(pairs.add(b"pair_1", true, 5, Some(FilterCriterion::SoftClipping), &mut decide),
+                   vec![(2, Some(FilterCriterion::SoftClipping))]);
+        assert_eq!(pairs.add(b"pair_2", true, 6, None, &mut decide),
+                   vec![(3, Some(FilterCriterion::Mapq)), (4, None),
+                        (5, Some(FilterCriterion::SoftClipping)), (6, Some(FilterCriterion::Mapq))]);
+        assert_eq!(pairs.add(b"pair_3", true, 10, None, &mut decide), vec![]);
+        assert_eq!(pairs.add(b"orphan", true, 7, None, &mut decide), vec![]);
+        assert_eq!(pairs.add(b"pair_3", true, 8, None, &mut decide),
+                   vec![(10, Some(FilterCriterion::Duplicate))]);
+        assert_eq!(pairs.finish(&mut decide), vec![(7, None), (8, Some(FilterCriterion::Duplicate))]);
+        assert_eq!(decided, vec![vec![1], vec![4], vec![2, 5], vec![3, 6], vec![10, 8], vec![7]]);
+    }
+
+    #[test]
+    fn test_failed_criterion() {
+        let parameters = parameters();
+        let cigar = [Cigar::Match(10)];
+        let qual = [30; 10];
+        assert_eq!(failed_criterion(&parameters, 30, Some(1), &cigar, &qual), None);
+        assert_eq!(failed_criterion(&parameters, 10, Some(5), &cigar, &qual),
+                   Some(FilterCriterion::Mapq));
+        assert_eq!(failed_criterion(&parameters, 30, Some(5), &cigar, &qual),
+                   Some(FilterCriterion::EditDistance));
+        assert_eq!(failed_criterion(&parameters, 30, None, &[Cigar::SoftClip(5), Cigar::Match(5)], &qual),
+                   Some(FilterCriterion::SoftClipping));
+        assert_eq!(failed_criterion(&parameters, 30, None, &cigar, &[2, 2, 2, 30, 30, 30, 2]),
+                   Some(FilterCriterion::TrimmedLength));
+
+        let mut stats = FilterStats::new("in.bam", "out.bam");
+        stats.remove(FilterCriterion::Duplicate);
+        stats.remove(FilterCriterion::Duplicate);
+        assert_eq!(stats.removed_duplicates, 2);
+    }
+}
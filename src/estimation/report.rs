@@ -0,0 +1,155 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::Path;
+use serde_json::{self, Value};
+use clap::crate_version;
+
+/// Version of the JSON document layout. Bumped whenever fields are renamed or removed
+pub const SCHEMA_VERSION: &str = "2.0";
+
+/// Output formats available for summarize, evolve and genotype
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Tsv,
+    Json,
+}
+
+impl OutputFormat {
+    pub fn from_clap(m: &clap::ArgMatches) -> OutputFormat {
+        match m.value_of("output-format") {
+            Some("json") => OutputFormat::Json,
+            Some("tsv") | None => OutputFormat::Tsv,
+            Some(format) => panic!("Unknown output format {}, expected tsv or json", format),
+        }
+    }
+}
+
+/// Variant statistics of one sample on one contig
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SampleContigStats {
+    pub sample: String,
+    pub subs_per_10kb: f64,
+    pub variants: usize,
+    pub mean_ref_abundance: f64,
+    pub ref_std_dev: f64,
+    pub mean_var_abundance: f64,
+    pub var_std_dev: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ContigReport {
+    pub name: String,
+    pub length: f64,
+    pub samples: Vec<SampleContigStats>,
+    /// Variant counts of each consequence, only present when genes were provided
+    pub consequences: Option<BTreeMap<String, usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct GeneReport {
+    pub id: String,
+    pub contig: String,
+    pub start: u64,
+    pub end: u64,
+    pub strand: String,
+    pub variants: usize,
+    pub consequences: BTreeMap<String, usize>,
+    /// Population dN/dS of the gene in each sample, calculated by evolve
+    pub dnds: Option<BTreeMap<String, f64>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StrainVariant {
+    pub contig: String,
+    pub position: i64,
+    pub variant: String,
+    pub reference: String,
+    pub category: String,
+    pub gene_id: Option<String>,
+    pub consequence: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StrainReport {
+    pub id: usize,
+    pub variants: Vec<StrainVariant>,
+}
+
+/// Machine readable document of the results of a run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LorikeetReport {
+    pub schema_version: String,
+    pub lorikeet_version: String,
+    pub mode: String,
+    /// Value of every argument of the mode, as recorded in the run config
+    pub parameters: BTreeMap<String, Value>,
+    pub samples: Vec<String>,
+    pub contigs: Vec<ContigReport>,
+    pub genes: Vec<GeneReport>,
+    pub strains: Vec<StrainReport>,
+}
+
+impl LorikeetReport {
+    pub fn new(mode: &str, parameters: BTreeMap<String, Value>) -> LorikeetReport {
+        LorikeetReport {
+            schema_version: SCHEMA_VERSION.to_string(),
+            lorikeet_version: crate_version!().to_string(),
+            mode: mode.to_string(),
+            parameters,
+            samples: Vec::new(),
+            contigs: Vec::new(),
+            genes: Vec::new(),
+            strains: Vec::new(),
+        }
+    }
+
+    /// Writes the report to {output_prefix}.json
+    pub fn write_json(&self, output_prefix: &str) {
+        let file_name = format!("{}.json", output_prefix);
+        let file_open = File::create(Path::new(&file_name))
+            .expect("No Read or Write Permission in current directory");
+        serde_json::to_writer_pretty(file_open, self)
+            .expect("Unable to write JSON report");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cli::build_cli;
+    use estimation::run_config::resolved_parameters;
+
+    #[test]
+    fn test_report_json() {
+        let matches = build_cli().get_matches_from(vec![
+            "lorikeet", "summarize", "-r", "reference.fna", "-b", "sample_1.bam",
+            "--output-format", "json", "--include-indels"]);
+        let m = matches.subcommand_matches("summarize").unwrap();
+        assert_eq!(OutputFormat::from_clap(m), OutputFormat::Json);
+
+        let mut report = LorikeetReport::new("summarize", resolved_parameters(m, "summarize"));
+        assert_eq!(report.parameters["include-indels"], Value::Bool(true));
+        assert_eq!(report.parameters["gff"], Value::Null);
+        report.samples.push("sample_1".to_string());
+        report.contigs.push(ContigReport {
+            name: "contig_1".to_string(),
+            length: 1000.,
+            samples: vec![SampleContigStats {
+                sample: "sample_1".to_string(),
+                subs_per_10kb: 20.,
+                variants: 2,
+                mean_ref_abundance: 0.9,
+                ref_std_dev: 0.,
+                mean_var_abundance: 0.1,
+                var_std_dev: 0.,
+            }],
+            consequences: None,
+        });
+
+        let json: serde_json::Value = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["schema_version"], SCHEMA_VERSION);
+        assert_eq!(json["parameters"]["output-format"], "json");
+        assert_eq!(json["contigs"][0]["samples"][0]["subs_per_10kb"], 20.);
+        assert!(json["contigs"][0]["consequences"].is_null());
+    }
+}
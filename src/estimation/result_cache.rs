@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+use estimation::alignment_format::{md5_file, md5_hex};
+
+/// Changed whenever the format of cached results changes, so that older entries are ignored
+const CACHE_VERSION: &str = "lorikeet-result-cache-2";
+
+lazy_static! {
+    /// Digests of the files hashed so far, by path, size and modification time, so that each
+    /// input is read once per run however many results it keys
+    static ref FILE_DIGESTS: Mutex<HashMap<(PathBuf, u64, SystemTime), String>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Number of partial results created so far, which makes the partial directories of a run
+/// distinct
+static PARTIAL_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Directory of results that are expensive to derive, such as sorted BAM files of mapped
+/// reads and variant calls. Each result is stored in a directory named after a key hashed
+/// from the contents of its inputs and the parameters used, so a result is reused exactly
+/// when the same inputs are processed the same way
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultCache {
+    pub directory: PathBuf,
+}
+
+impl ResultCache {
+    pub fn new(directory: &str) -> ResultCache {
+        fs::create_dir_all(directory)
+            .expect(&format!("Unable to create result cache directory {}", directory));
+        ResultCache {
+            directory: PathBuf::from(directory),
+        }
+    }
+
+    /// Key of a result, from the digests of its inputs and its parameters in a fixed order
+    pub fn key(parts: &[&str]) -> String {
+        let mut joined = CACHE_VERSION.to_string();
+        for part in parts.iter() {
+            joined.push('\n');
+            joined.push_str(part);
+        }
+        md5_hex(joined.as_bytes())
+    }
+
+    /// Digest of an input, which is that of its contents for files and its path otherwise, as
+    /// for the stem of a BWA index
+    pub fn input_digest(path: &str) -> String {
+        if Path::new(path).is_file() {
+            ResultCache::file_digest(path)
+        } else {
+            md5_hex(path.as_bytes())
+        }
+    }
+
+    /// MD5 digest of a file's contents
+    pub fn file_digest(path: &str) -> String {
+        let metadata = fs::metadata(path)
+            .expect(&format!("Unable to read metadata of {}", path));
+        let canonical = fs::canonicalize(path)
+            .expect(&format!("Unable to canonicalize {}", path));
+        let file_id = (canonical, metadata.len(),
+                       metadata.modified().expect("Unable to read file modification time"));
+        if let Some(digest) = FILE_DIGESTS.lock().unwrap().get(&file_id) {
+            return digest.clone()
+        }
+        let digest = md5_file(path).expect(&format!("Unable to read {}", path));
+        FILE_DIGESTS.lock().unwrap().insert(file_id, digest.clone());
+        digest
+    }
+
+    /// Directory of the result with this key, if it has been stored
+    pub fn get(&self, key: &str) -> Option<PathBuf> {
+        let entry = self.directory.join(key);
+        if entry.is_dir() {
+            Some(entry)
+        } else {
+            None
+        }
+    }
+
+    /// Key of the entry holding a file, for files stored in this cache
+    pub fn entry_key(&self, path: &str) -> Option<String> {
+        let entry = Path::new(path).parent()?;
+        if entry.parent()? != self.directory.as_path() {
+            return None
+        }
+        let key = entry.file_name()?.to_str()?;
+        if key.len() == 32 && key.chars().all(|c| c.is_ascii_hexdigit()) {
+            Some(key.to_string())
+        } else {
+            None
+        }
+    }
+
+    /// Empty directory of this run to write a result into before it is stored with commit,
+    /// so that runs producing the same result at once do not write over each other
+    pub fn partial(&self, key: &str) -> PathBuf {
+        let partial = self.directory.join(format!(
+            "{}.partial.{}.{}", key, process::id(), PARTIAL_COUNT.fetch_add(1, Ordering::SeqCst)));
+        if partial.exists() {
+            fs::remove_dir_all(&partial)
+                .expect(&format!("Unable to remove unfinished result {:?}", partial));
+        }
+        fs::create_dir_all(&partial)
+            .expect(&format!("Unable to create result directory {:?}", partial));
+        partial
+    }
+
+    /// Stores the result written to a partial directory of this key, returning its
+    /// directory. The result appears at once, so it is never read while being written
+    pub fn commit(&self, key: &str, partial: &Path) -> PathBuf {
+        let entry = self.directory.join(key);
+        if let Err(error) = fs::rename(partial, &entry) {
+            if !entry.is_dir() {
+                panic!("Unable to store result {:?}: {}", entry, error)
+            }
+            // Stored by a concurrent run, whose copy is kept
+            fs::remove_dir_all(partial).expect("Unable to remove duplicate result");
+        }
+        debug!("Stored result {:?}", entry);
+        entry
+    }
+}
+
+/// Path of a file within a result directory
+pub fn entry_file(entry: &Path, file_name: &str) -> String {
+    entry.join(file_name).to_str()
+        .expect("Unable to convert result cache path to str").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Write;
+
+    #[test]
+    fn test_key() {
+        let reference = md5_hex(b">contig\nACGT\n");
+        let key = ResultCache::key(&[&reference, "pilon", "--fix none"]);
+        assert_eq!(key.len(), 32);
+        assert_eq!(key, ResultCache::key(&[&reference, "pilon", "--fix none"]));
+        assert_ne!(key, ResultCache::key(&[&reference, "sniffles", "-l 30"]));
+        // Parts are separated, so moving text between them changes the key
+        assert_ne!(ResultCache::key(&["ab", "c"]), ResultCache::key(&["a", "bc"]));
+    }
+
+    #[test]
+    fn test_store_result() {
+        let directory = env::temp_dir().join(
+            format!("lorikeet_result_cache_test_{}", std::process::id()));
+        let cache = ResultCache::new(directory.to_str().unwrap());
+        let input = directory.join("reads.fq");
+        write!(fs::File::create(&input).unwrap(), "@read\nACGT\n+\nIIII\n").unwrap();
+        let digest = ResultCache::file_digest(input.to_str().unwrap());
+        assert_eq!(digest, md5_file(input.to_str().unwrap()).unwrap());
+
+        let key = ResultCache::key(&[&digest, "minimap2-sr"]);
+        assert_eq!(cache.get(&key), None);
+        let partial = cache.partial(&key);
+        let concurrent = cache.partial(&key);
+        assert_ne!(partial, concurrent);
+        fs::write(partial.join("sorted.bam"), b"BAM").unwrap();
+        fs::write(concurrent.join("sorted.bam"), b"Other BAM").unwrap();
+        assert_eq!(cache.get(&key), None);
+        let entry = cache.commit(&key, &partial);
+        assert_eq!(cache.get(&key), Some(entry.clone()));
+        assert_eq!(cache.entry_key(&entry_file(&entry, "sorted.bam")), Some(key.clone()));
+        assert_eq!(cache.entry_key(input.to_str().unwrap()), None);
+
+        // The first copy stored is kept
+        assert_eq!(cache.commit(&key, &concurrent), entry);
+        assert!(!concurrent.exists());
+        assert_eq!(fs::read(entry_file(&entry, "sorted.bam")).unwrap(), b"BAM");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
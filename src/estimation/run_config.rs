@@ -0,0 +1,609 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+use clap::{App, AppSettings, ArgMatches};
+use rayon::prelude::*;
+use serde_json::{self, Map, Value};
+use serde_yaml;
+use toml;
+use estimation::alignment_format::set_stoit_name;
+use estimation::result_cache::ResultCache;
+
+/// Arguments giving reads to be mapped
+pub const READ_ARGUMENTS: &[&str] = &["read1", "read2", "coupled", "interleaved", "single"];
+
+/// Arguments whose values are input files, which are described in the run record
+const INPUT_FILE_ARGUMENTS: &[&str] = &[
+    "config", "bam-files", "bam-file", "longread-bam-files", "reference", "read1", "read2",
+    "coupled", "interleaved", "single", "gff", "vcfs", "sample-metadata", "sample-groups",
+    "sample-sheet"];
+
+/// Subcommands taking a config file
+const ALL: &[&str] = &["polymorph", "evolve", "summarize", "genotype", "filter", "polish"];
+/// Subcommands mapping reads and piling them up against the reference
+const MAPPING: &[&str] = &["polymorph", "evolve", "summarize", "genotype", "polish"];
+/// Subcommands calling variants in multiple samples
+const CALLING: &[&str] = &["polymorph", "evolve", "summarize", "genotype"];
+/// Subcommands writing per sample reports
+const REPORTS: &[&str] = &["evolve", "summarize", "genotype"];
+/// Subcommands taking long reads
+const LONG_READS: &[&str] = &["summarize", "genotype", "polish"];
+/// Subcommands clustering variants into strains
+const CLUSTERING: &[&str] = &["polymorph", "summarize"];
+
+/// Arguments that can be set from a config file, as their name, long flag (or short flag for
+/// those without one), whether they take a value and the subcommands accepting them. Must be
+/// kept in line with cli::build_cli, which test_configurable_arguments checks
+const CONFIGURABLE_ARGUMENTS: &[(&str, &str, bool, &[&str])] = &[
+    ("sample-sheet", "--sample-sheet", true, CALLING),
+    ("bam-files", "--bam-files", true, &["polymorph", "evolve", "summarize", "genotype", "filter"]),
+    ("bam-file", "--bam-file", true, &["polish"]),
+    ("sharded", "--sharded", false, MAPPING),
+    ("read1", "-1", true, MAPPING),
+    ("read2", "-2", true, MAPPING),
+    ("coupled", "--coupled", true, MAPPING),
+    ("interleaved", "--interleaved", true, MAPPING),
+    ("single", "--single", true, MAPPING),
+    ("longreads", "--longreads", true, LONG_READS),
+    ("longread-bam-files", "-l", true, LONG_READS),
+    ("gff", "--gff", true, CALLING),
+    ("reference", "--reference", true, ALL),
+    ("outdir", "--bam-file-cache-directory", true, MAPPING),
+    ("result-cache", "--result-cache", true, MAPPING),
+    ("threads", "--threads", true, ALL),
+    ("mapper", "--mapper", true, MAPPING),
+    ("minimap2-params", "--minimap2-parameters", true, MAPPING),
+    ("minimap2-reference-is-index", "--minimap2-reference-is-index", false, MAPPING),
+    ("bwa-params", "--bwa-parameters", true, MAPPING),
+    ("discard-unmapped", "--discard-unmapped", false, MAPPING),
+    ("cache-cram", "--cache-cram", false, MAPPING),
+    ("min-read-aligned-length", "--min-read-aligned-length", true, ALL),
+    ("min-read-percent-identity", "--min-read-percent-identity", true, ALL),
+    ("min-read-aligned-percent", "--min-read-aligned-percent", true, ALL),
+    ("min-read-aligned-length-pair", "--min-read-aligned-length-pair", true, ALL),
+    ("min-read-percent-identity-pair", "--min-read-percent-identity-pair", true, ALL),
+    ("min-read-aligned-percent-pair", "--min-read-aligned-percent-pair", true, ALL),
+    ("allow-improper-pairs", "--allow-improper-pairs", false, ALL),
+    ("output-prefix", "--output-prefix", true, CALLING),
+    ("method", "--method", true, MAPPING),
+    ("min-covered-fraction", "--min-covered-fraction", true, MAPPING),
+    ("coverage-fold", "--coverage-fold", true, MAPPING),
+    ("min-variant-depth", "--min-variant-depth", true, MAPPING),
+    ("mapq-threshold", "--mapq-threshold", true, MAPPING),
+    ("contig-end-exclusion", "--contig-end-exclusion", true, MAPPING),
+    ("trim-min", "--trim-min", true, MAPPING),
+    ("trim-max", "--trim-max", true, MAPPING),
+    ("no-zeros", "--no-zeros", false, MAPPING),
+    ("include-indels", "--include-indels", false, MAPPING),
+    ("nanopore", "--nanopore", false, CALLING),
+    ("include-secondary", "--include-secondary", false, CALLING),
+    ("include-supplementary", "--include-supplementary", false, CALLING),
+    ("verbose", "--verbose", false, ALL),
+    ("quiet", "--quiet", false, ALL),
+    ("max-vaf-clusters", "--max-vaf-clusters", true, CLUSTERING),
+    ("epsilon", "--epsilon", true, CLUSTERING),
+    ("min-cluster-size", "--min-cluster-size", true, CLUSTERING),
+    ("min-gene-length", "--min-gene-length", true, &["evolve"]),
+    ("kmer-size", "--kmer-size", true, &["evolve", "genotype"]),
+    ("output-format", "--output-format", true, REPORTS),
+    ("html-report", "--html-report", false, REPORTS),
+    ("min-site-depth", "--min-site-depth", true, &["summarize", "genotype"]),
+    ("strain-ani", "--strain-ani", false, &["summarize"]),
+    ("strain-ani", "--strain-ani", true, &["genotype"]),
+    ("min-breadth", "--min-breadth", true, &["summarize"]),
+    ("window-size", "--window-size", true, &["summarize"]),
+    ("window-step", "--window-step", true, &["summarize"]),
+    ("min-linkage-reads", "--min-linkage-reads", true, &["summarize"]),
+    ("max-linkage-distance", "--max-linkage-distance", true, &["summarize"]),
+    ("linkage-bin-size", "--linkage-bin-size", true, &["summarize"]),
+    ("sample-metadata", "--sample-metadata", true, &["summarize"]),
+    ("sweep-frequency-change", "--sweep-frequency-change", true, &["summarize"]),
+    ("min-gene-sweeps", "--min-gene-sweeps", true, &["summarize"]),
+    ("sample-groups", "--sample-groups", true, &["summarize"]),
+    ("frequency-bins", "--frequency-bins", true, &["summarize"]),
+    ("vcfs", "--vcfs", false, &["genotype"]),
+    ("e-min", "--e-min", true, &["genotype"]),
+    ("e-max", "--e-max", true, &["genotype"]),
+    ("pts-min", "--pts-min", true, &["genotype"]),
+    ("pts-max", "--pts-max", true, &["genotype"]),
+    ("phi", "--phi", true, &["genotype"]),
+    ("assign-reads", "--assign-reads", false, &["genotype"]),
+    ("assignment-error-rate", "--assignment-error-rate", true, &["genotype"]),
+    ("min-assignment-probability", "--min-assignment-probability", true, &["genotype"]),
+    ("extract-strain-reads", "--extract-strain-reads", false, &["genotype"]),
+    ("ambiguous-reads", "--ambiguous-reads", true, &["genotype"]),
+    ("unassigned-reads", "--unassigned-reads", true, &["genotype"]),
+    ("include-soft-clipping", "--include-soft-clipping", false, &["genotype"]),
+    ("output-bam-files", "--output-bam-files", true, &["filter"]),
+    ("inverse", "--inverse", false, &["filter"]),
+    ("output-cram", "--output-cram", false, &["filter"]),
+    ("min-mapq", "--min-mapq", true, &["filter"]),
+    ("max-edit-distance", "--max-edit-distance", true, &["filter"]),
+    ("max-soft-clip-fraction", "--max-soft-clip-fraction", true, &["filter"]),
+    ("remove-duplicates", "--remove-duplicates", false, &["filter"]),
+    ("min-trimmed-length", "--min-trimmed-length", true, &["filter"]),
+    ("trim-quality", "--trim-quality", true, &["filter"]),
+    ("min-polish-depth", "--min-polish-depth", true, &["polish"]),
+    ("min-polish-frequency", "--min-polish-frequency", true, &["polish"]),
+    ("mask-depth", "--mask-depth", true, &["polish"]),
+    ("polish-rounds", "--polish-rounds", true, &["polish"]),
+    ("min-long-polish-depth", "--min-long-polish-depth", true, &["polish"]),
+    ("min-long-indel-length", "--min-long-indel-length", true, &["polish"]),
+    ("polish-conflict", "--polish-conflict", true, &["polish"]),
+];
+
+/// Argument of a subcommand, as needed to set it from a config file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigurableArgument {
+    pub name: String,
+    pub long: Option<String>,
+    pub short: Option<char>,
+    pub takes_value: bool,
+}
+
+impl ConfigurableArgument {
+    /// Name of the argument in config files and run records, which is its long flag where it
+    /// has one
+    pub fn key(&self) -> &str {
+        self.long.as_ref().unwrap_or(&self.name)
+    }
+
+    /// Whether a config file key refers to this argument. Keys may use either the long flag
+    /// or the name of the argument, with underscores in place of hyphens
+    fn matches_key(&self, key: &str) -> bool {
+        let key = key.replace('_', "-");
+        key == self.name || self.long.as_ref() == Some(&key)
+    }
+
+    /// Command line arguments giving this argument the value
+    pub fn tokens(&self, value: &str) -> Vec<String> {
+        match (&self.long, self.takes_value) {
+            // Joined to the flag so that values starting with a hyphen are not taken as flags
+            (Some(long), true) => vec![format!("--{}={}", long, value)],
+            (Some(long), false) => vec![format!("--{}", long)],
+            (None, true) => vec![format!("-{}", self.short.unwrap()), value.to_string()],
+            (None, false) => vec![format!("-{}", self.short.unwrap())],
+        }
+    }
+}
+
+/// Whether a subcommand takes a config file
+fn takes_config(subcommand: &str) -> bool {
+    ALL.contains(&subcommand)
+}
+
+/// Arguments of a subcommand that can be set from a config file
+pub fn subcommand_arguments(subcommand: &str) -> Vec<ConfigurableArgument> {
+    if !takes_config(subcommand) {
+        panic!("lorikeet {} does not take a config file", subcommand)
+    }
+    CONFIGURABLE_ARGUMENTS.iter()
+        .filter(|(_, _, _, subcommands)| subcommands.contains(&subcommand))
+        .map(|(name, flag, takes_value, _)| {
+            let (long, short) = if flag.starts_with("--") {
+                (Some(flag.trim_start_matches('-').to_string()), None)
+            } else {
+                (None, flag.trim_start_matches('-').chars().next())
+            };
+            ConfigurableArgument {
+                name: name.to_string(),
+                long,
+                short,
+                takes_value: *takes_value,
+            }
+        })
+        .collect()
+}
+
+/// Reads a YAML config file, or a TOML one if its name ends in .toml, into a map of option
+/// names to values
+pub fn load_config(path: &str) -> Map<String, Value> {
+    let mut contents = String::new();
+    File::open(path)
+        .expect(&format!("Unable to open config file {}", path))
+        .read_to_string(&mut contents)
+        .expect(&format!("Unable to read config file {}", path));
+    let config: Value = match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+        Some("toml") => toml::from_str(&contents)
+            .expect(&format!("Unable to parse TOML config file {}", path)),
+        _ => serde_yaml::from_str(&contents)
+            .expect(&format!("Unable to parse YAML config file {}", path)),
+    };
+    match config {
+        Value::Object(options) => options,
+        Value::Null => Map::new(),
+        _ => panic!("Config file {} must map option names to values", path),
+    }
+}
+
+fn scalar_to_string(key: &str, value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        Value::Number(value) => value.to_string(),
+        Value::Bool(value) => value.to_string(),
+        _ => panic!("Option {} in config file must be a single value or a list of values", key),
+    }
+}
+
+/// Command line arguments setting the options of config for a subcommand, keyed by argument
+/// name. Top level options apply to every subcommand and are ignored by subcommands without
+/// them, while those in a section named after the subcommand override them and must exist
+pub fn config_arguments(arguments: &[ConfigurableArgument], config: &Map<String, Value>,
+                        subcommand: &str) -> Vec<(String, Vec<String>)> {
+    let top_level = config.iter()
+        .filter(|(_, value)| !value.is_object())
+        .map(|(key, value)| (key, value, false));
+    let section = config.get(subcommand)
+        .and_then(|section| section.as_object())
+        .into_iter()
+        .flat_map(|section| section.iter().map(|(key, value)| (key, value, true)));
+
+    let mut options = BTreeMap::new();
+    for (key, value, in_section) in top_level.chain(section) {
+        let argument = arguments.iter().find(|argument| argument.matches_key(key));
+        match argument {
+            Some(argument) => {
+                options.insert(argument.name.clone(), (argument, key, value));
+            },
+            None if in_section => {
+                panic!("Unknown option {} for lorikeet {} in config file", key, subcommand)
+            },
+            None => {
+                debug!("Ignoring config option {} not used by lorikeet {}", key, subcommand);
+            },
+        }
+    }
+
+    options.into_iter().map(|(name, (argument, key, value))| {
+        let tokens = if argument.takes_value {
+            match value {
+                Value::Null => Vec::new(),
+                Value::Array(values) => values.iter()
+                    .flat_map(|value| argument.tokens(&scalar_to_string(key, value)))
+                    .collect(),
+                _ => argument.tokens(&scalar_to_string(key, value)),
+            }
+        } else {
+            match value {
+                Value::Bool(true) => argument.tokens(""),
+                Value::Bool(false) | Value::Null => Vec::new(),
+                _ => panic!("Option {} in config file is a flag, so must be true or false", key),
+            }
+        };
+        (name, tokens)
+    }).collect()
+}
+
+/// Subcommand, config file and remaining arguments of a command line using --config
+fn split_config_argument(args: &[String]) -> Option<(String, String, Vec<String>)> {
+    let subcommand = args.get(1)?;
+    if !takes_config(subcommand) {
+        return None
+    }
+    let mut config = None;
+    let mut rest = Vec::new();
+    let mut args_iter = args[2..].iter();
+    while let Some(arg) = args_iter.next() {
+        let mut flag_and_value = arg.splitn(2, '=');
+        match (flag_and_value.next(), flag_and_value.next()) {
+            (Some("--config"), None) => config = args_iter.next().cloned(),
+            (Some("--config"), Some(path)) => config = Some(path.to_string()),
+            _ => rest.push(arg.clone()),
+        }
+    }
+    config.map(|config| (subcommand.clone(), config, rest))
+}
+
+/// Parses the command line, filling in options from the file given to the subcommand's
+/// --config argument. Options given on the command line take precedence over the file
+pub fn get_matches_with_config(app: &App<'static, 'static>, args: Vec<String>) -> ArgMatches<'static> {
+    let (subcommand, config_path, rest) = match split_config_argument(&args) {
+        Some(split) => split,
+        None => return app.clone().get_matches_from(args),
+    };
+    let config = load_config(&config_path);
+    let mut from_config = config_arguments(
+        &subcommand_arguments(&subcommand), &config, &subcommand);
+
+    // Options from the file go before --config and the command line after it
+    let compose = |from_config: &[(String, Vec<String>)]| {
+        let mut composed = vec![args[0].clone(), subcommand.clone()];
+        for (_, tokens) in from_config.iter() {
+            composed.extend(tokens.iter().cloned());
+        }
+        composed.push(format!("--config={}", config_path));
+        composed.extend(rest.iter().cloned());
+        composed
+    };
+
+    // Letting every argument override itself, the options also given on the command line
+    // are those with an occurrence after --config. They are then dropped from the file's
+    // options, so that clap applies its usual rules to the command line
+    let on_command_line: Vec<String> = {
+        let matches = app.clone()
+            .global_setting(AppSettings::AllArgsOverrideSelf)
+            .get_matches_from(compose(&from_config));
+        let m = matches.subcommand_matches(&subcommand).unwrap();
+        let config_index = m.index_of("config").unwrap();
+        from_config.iter()
+            .filter(|(name, _)| {
+                m.indices_of(name).into_iter().flatten().any(|idx| idx > config_index)
+            })
+            .map(|(name, _)| name.clone())
+            .collect()
+    };
+    from_config.retain(|(name, _)| !on_command_line.contains(name));
+    info!("Using {} options from config file {}", from_config.len(), config_path);
+    app.clone().get_matches_from(compose(&from_config))
+}
+
+/// Size, modification time in seconds since the epoch and MD5 checksum of an input file.
+/// Inputs that are not files, such as index stems, have none
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct InputFile {
+    pub argument: String,
+    pub path: String,
+    pub size: Option<u64>,
+    pub modified: Option<u64>,
+    pub md5: Option<String>,
+}
+
+/// Everything needed to repeat a run. The parameters are valid config file options
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunRecord {
+    pub lorikeet_version: String,
+    pub subcommand: String,
+    pub command_line: Vec<String>,
+    pub config_file: Option<String>,
+    /// Value of every argument of the subcommand after applying the config file and defaults
+    pub parameters: BTreeMap<String, Value>,
+    pub input_files: Vec<InputFile>,
+}
+
+/// Value of every argument of a subcommand as config file options, after applying the config
+/// file and defaults
+pub fn resolved_parameters(m: &ArgMatches, subcommand: &str) -> BTreeMap<String, Value> {
+    let mut parameters = BTreeMap::new();
+    for argument in subcommand_arguments(subcommand) {
+        let value = if argument.takes_value {
+            match m.values_of(&argument.name) {
+                Some(values) => {
+                    let mut values: Vec<Value> = values
+                        .map(|value| Value::String(value.to_string()))
+                        .collect();
+                    if values.len() == 1 {
+                        values.pop().unwrap()
+                    } else {
+                        Value::Array(values)
+                    }
+                },
+                None => Value::Null,
+            }
+        } else {
+            Value::Bool(m.is_present(&argument.name))
+        };
+        parameters.insert(argument.key().to_string(), value);
+    }
+    parameters
+}
+
+/// Matches of a subcommand with the reads of read_arguments replaced by the BAM files they
+/// were mapped to, keeping every other option. bam_argument is the subcommand's argument for
+/// those BAM files, whose samples keep the names of their reads given in stoit_names
+pub fn matches_with_bam_files(app: &App<'static, 'static>, m: &ArgMatches, subcommand: &str,
+                              read_arguments: &[&str], bam_argument: &str, bam_files: &[String],
+                              stoit_names: &[String]) -> ArgMatches<'static> {
+    for (bam_file, stoit_name) in bam_files.iter().zip(stoit_names.iter()) {
+        set_stoit_name(bam_file, stoit_name);
+    }
+    let mut parameters = resolved_parameters(m, subcommand);
+    for name in read_arguments.iter() {
+        parameters.remove(*name);
+    }
+    parameters.insert(bam_argument.to_string(), Value::Array(
+        bam_files.iter().map(|bam_file| Value::String(bam_file.clone())).collect()));
+    let parameters: Map<String, Value> = parameters.into_iter().collect();
+
+    let mut args = vec!["lorikeet".to_string(), subcommand.to_string()];
+    let arguments = subcommand_arguments(subcommand);
+    for (_, tokens) in config_arguments(&arguments, &parameters, subcommand) {
+        args.extend(tokens);
+    }
+    app.clone().get_matches_from(args).subcommand_matches(subcommand).unwrap().clone()
+}
+
+impl RunRecord {
+    pub fn new(m: &ArgMatches, subcommand: &str) -> RunRecord {
+        let parameters = resolved_parameters(m, subcommand);
+
+        let mut input_files: Vec<InputFile> = INPUT_FILE_ARGUMENTS.iter()
+            .filter_map(|argument| m.values_of(argument).map(|paths| (argument, paths)))
+            .flat_map(|(argument, paths)| paths.map(move |path| InputFile {
+                argument: argument.to_string(),
+                path: path.to_string(),
+                size: None,
+                modified: None,
+                md5: None,
+            }))
+            .collect();
+        input_files.par_iter_mut().for_each(|input| {
+            let metadata = match fs::metadata(&input.path) {
+                Ok(metadata) => metadata,
+                Err(_) => return,
+            };
+            if !metadata.is_file() {
+                return
+            }
+            input.size = Some(metadata.len());
+            input.modified = metadata.modified().ok()
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|modified| modified.as_secs());
+            // Shares the digests of the inputs hashed for the result cache
+            input.md5 = Some(ResultCache::file_digest(&input.path));
+        });
+
+        RunRecord {
+            lorikeet_version: env!("CARGO_PKG_VERSION").to_string(),
+            subcommand: subcommand.to_string(),
+            command_line: env::args().collect(),
+            config_file: m.value_of("config").map(|config| config.to_string()),
+            parameters,
+            input_files,
+        }
+    }
+
+    pub fn write(&self, file_name: &str) {
+        let file_open = File::create(Path::new(file_name))
+            .expect("No Read or Write Permission in current directory");
+        serde_json::to_writer_pretty(file_open, self)
+            .expect("Unable to write run record");
+        info!("Wrote run configuration to {}", file_name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ErrorKind;
+    use cli::build_cli;
+    use estimation::alignment_format::{md5_file, stoit_name};
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_configurable_arguments() {
+        let app = build_cli();
+        for subcommand in ALL.iter() {
+            for argument in subcommand_arguments(subcommand) {
+                // --full-help lifts the required arguments
+                let mut args = to_args(&["lorikeet", subcommand]);
+                args.extend(argument.tokens("1"));
+                args.push("--full-help".to_string());
+                match app.clone().get_matches_from_safe(args) {
+                    Ok(matches) => {
+                        let m = matches.subcommand_matches(subcommand).unwrap();
+                        assert!(m.is_present(&argument.name));
+                        assert_eq!(m.value_of(&argument.name).is_some(), argument.takes_value,
+                                   "{} of lorikeet {}", argument.name, subcommand);
+                    },
+                    // Values rejected by the argument, or arguments it needs
+                    Err(error) => assert!(
+                        [ErrorKind::InvalidValue, ErrorKind::ValueValidation,
+                         ErrorKind::MissingRequiredArgument].contains(&error.kind),
+                        "{} of lorikeet {}: {}", argument.name, subcommand, error.message),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_config_arguments() {
+        let config: Map<String, Value> = serde_json::from_str(r#"{
+            "threads": 4,
+            "output-bam-files": "unused.bam",
+            "bam_file_cache_directory": "cache",
+            "read1": ["a_1.fq", "b_1.fq"],
+            "nanopore": false,
+            "genotype": {"threads": 8, "include-indels": true, "minimap2-params": "-x sr"},
+            "filter": {"threads": 2}
+        }"#).unwrap();
+        let arguments = subcommand_arguments("genotype");
+        assert_eq!(config_arguments(&arguments, &config, "genotype"), vec![
+            ("include-indels".to_string(), to_args(&["--include-indels"])),
+            ("minimap2-params".to_string(), to_args(&["--minimap2-parameters=-x sr"])),
+            ("nanopore".to_string(), vec![]),
+            ("outdir".to_string(), to_args(&["--bam-file-cache-directory=cache"])),
+            ("read1".to_string(), to_args(&["-1", "a_1.fq", "-1", "b_1.fq"])),
+            ("threads".to_string(), to_args(&["--threads=8"])),
+        ]);
+    }
+
+    #[test]
+    fn test_get_matches_with_config() {
+        let config_path = env::temp_dir().join(
+            format!("lorikeet_config_test_{}.yaml", std::process::id()));
+        let config_path = config_path.to_str().unwrap();
+        let mut config_file = File::create(config_path).unwrap();
+        // JSON is valid YAML
+        writeln!(config_file, r#"{{"bam-files": ["a.bam", "b.bam"], "threads": 8,
+                                  "include-indels": true, "minimap2-params": "-x sr"}}"#).unwrap();
+
+        let app = build_cli();
+        let matches = get_matches_with_config(&app, to_args(
+            &["lorikeet", "genotype", "-r", "assembly.fna", "-t", "2", "--config", config_path]));
+        let m = matches.subcommand_matches("genotype").unwrap();
+        assert_eq!(m.values_of("bam-files").unwrap().collect::<Vec<_>>(), vec!["a.bam", "b.bam"]);
+        assert_eq!(m.value_of("threads"), Some("2"));
+        assert_eq!(m.value_of("minimap2-params"), Some("-x sr"));
+        assert!(m.is_present("include-indels"));
+
+        let matches = get_matches_with_config(&app, to_args(
+            &["lorikeet", "genotype", "-r", "assembly.fna", "--config", config_path, "-b", "c.bam"]));
+        let m = matches.subcommand_matches("genotype").unwrap();
+        assert_eq!(m.values_of("bam-files").unwrap().collect::<Vec<_>>(), vec!["c.bam"]);
+        assert_eq!(m.value_of("threads"), Some("8"));
+
+        let record = RunRecord::new(m, "genotype");
+        assert_eq!(record.parameters["bam-files"], Value::String("c.bam".to_string()));
+        assert_eq!(record.parameters["bam-file-cache-directory"], Value::Null);
+        assert_eq!(record.parameters["nanopore"], Value::Bool(false));
+        assert!(!record.parameters.contains_key("config"));
+        assert_eq!(record.input_files.len(), 3);
+        assert_eq!(record.input_files[0].argument, "config");
+        assert!(record.input_files[0].size.is_some() && record.input_files[0].modified.is_some());
+        assert_eq!(record.input_files[0].md5, Some(md5_file(config_path).unwrap()));
+        assert_eq!(record.input_files[1].size, None);
+        assert_eq!(record.input_files[1].md5, None);
+        std::fs::remove_file(config_path).unwrap();
+    }
+
+    #[test]
+    fn test_matches_with_bam_files() {
+        let app = build_cli();
+        let matches = app.clone().get_matches_from(vec![
+            "lorikeet", "summarize", "-r", "assembly.fna", "-b", "short.bam",
+            "--longreads", "a.fq", "b.fq", "-t", "4", "--include-indels"]);
+        let m = matches.subcommand_matches("summarize").unwrap();
+        let bam_files = vec!["cache/a.bam".to_string(), "cache/b.bam".to_string()];
+        let stoit_names = vec!["assembly.fna/a.fq".to_string(), "assembly.fna/b.fq".to_string()];
+        let m = matches_with_bam_files(
+            &app, m, "summarize", &["longreads"], "longread-bam-files", &bam_files, &stoit_names);
+
+        assert_eq!(m.values_of("longread-bam-files").unwrap().collect::<Vec<_>>(),
+                   vec!["cache/a.bam", "cache/b.bam"]);
+        assert!(!m.is_present("longreads"));
+        assert_eq!(m.value_of("bam-files"), Some("short.bam"));
+        assert_eq!(m.value_of("reference"), Some("assembly.fna"));
+        assert_eq!(m.value_of("threads"), Some("4"));
+        assert!(m.is_present("include-indels"));
+    }
+
+    #[test]
+    fn test_matches_with_mapped_reads() {
+        let app = build_cli();
+        let matches = app.clone().get_matches_from(vec![
+            "lorikeet", "summarize", "-r", "assembly.fna", "-1", "a_1.fq", "-2", "a_2.fq",
+            "--single", "b.fq", "--result-cache", "cache", "-t", "4", "--include-indels"]);
+        let m = matches.subcommand_matches("summarize").unwrap();
+        let bam_files = vec!["cache/a.bam".to_string(), "cache/b.bam".to_string()];
+        let stoit_names = vec!["assembly.fna/a_1.fq".to_string(), "assembly.fna/b.fq".to_string()];
+        let m = matches_with_bam_files(
+            &app, m, "summarize", READ_ARGUMENTS, "bam-files", &bam_files, &stoit_names);
+
+        assert_eq!(m.values_of("bam-files").unwrap().collect::<Vec<_>>(),
+                   vec!["cache/a.bam", "cache/b.bam"]);
+        assert!(!m.is_present("read1") && !m.is_present("single"));
+        assert_eq!(m.value_of("reference"), Some("assembly.fna"));
+        assert_eq!(m.value_of("threads"), Some("4"));
+        assert_eq!(m.value_of("result-cache"), Some("cache"));
+        assert!(m.is_present("include-indels"));
+        // The samples keep the names they have when the reads are mapped during the run
+        assert_eq!(stoit_name("cache/b.bam"), "assembly.fna/b.fq");
+        assert_eq!(stoit_name("cache/c.bam"), "c");
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::path::Path;
+use clap::{App, ArgMatches};
+use estimation::differential_frequency::{SampleGroup, DEFAULT_STRATUM};
+use estimation::run_config::*;
+
+/// Arguments giving the inputs of a run, which are set by the sample sheet instead
+const SHEET_INPUT_ARGUMENTS: &[&str] = &[
+    "bam-files", "longread-bam-files", "read1", "read2", "coupled", "interleaved", "single",
+    "longreads"];
+
+/// Sequencing technology of a library
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadTechnology {
+    Short,
+    Nanopore,
+    PacBio,
+}
+
+impl ReadTechnology {
+    pub fn from_name(name: &str) -> ReadTechnology {
+        match name.to_ascii_lowercase().as_str() {
+            "short" | "illumina" => ReadTechnology::Short,
+            "ont" | "nanopore" => ReadTechnology::Nanopore,
+            "pacbio" | "pb" => ReadTechnology::PacBio,
+            _ => panic!("Unknown read technology {}, expected short, ont or pacbio", name),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReadTechnology::Short => "short",
+            ReadTechnology::Nanopore => "ont",
+            ReadTechnology::PacBio => "pacbio",
+        }
+    }
+
+    pub fn is_long(&self) -> bool {
+        *self != ReadTechnology::Short
+    }
+}
+
+/// Reads of one sample from one technology, either as a BAM or CRAM file or as one or two
+/// FASTA/Q files to be mapped
+#[derive(Debug, Clone, PartialEq)]
+pub struct Library {
+    pub sample: String,
+    pub technology: ReadTechnology,
+    pub files: Vec<String>,
+}
+
+impl Library {
+    pub fn is_alignment(&self) -> bool {
+        self.files.len() == 1 && {
+            let file = self.files[0].to_ascii_lowercase();
+            file.ends_with(".bam") || file.ends_with(".cram")
+        }
+    }
+
+    /// Whether a reader's reads come from this library. Readers of BAM and CRAM files are
+    /// named after the file stem, and those of mapped reads after the reference and the first
+    /// read file joined by /, or by . in the stem of the BAM file they are mapped into
+    fn is_read_by(&self, reader_name: &str) -> bool {
+        let path = Path::new(&self.files[0]);
+        if self.is_alignment() {
+            path.file_stem().map_or(false, |stem| stem.to_string_lossy() == reader_name)
+        } else {
+            let file_name = path.file_name().map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default();
+            reader_name.ends_with(&format!("/{}", file_name))
+                || reader_name.ends_with(&format!(".{}", file_name))
+        }
+    }
+}
+
+/// Libraries and groups of the samples of a run
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleSheet {
+    pub libraries: Vec<Library>,
+    pub groups: HashMap<String, SampleGroup>,
+}
+
+impl SampleSheet {
+    pub fn from_file(path: &str) -> SampleSheet {
+        let file = File::open(path)
+            .expect(&format!("Unable to open sample sheet {}", path));
+        SampleSheet::parse(file)
+    }
+
+    /// Parse a tab separated table of sample name, technology, comma separated files and
+    /// optional group and stratum. Blank lines, lines starting with # and a header line
+    /// starting with "sample" are skipped. A sample may have one short read and one long
+    /// read library
+    pub fn parse<R: Read>(reader: R) -> SampleSheet {
+        let mut libraries: Vec<Library> = Vec::new();
+        let mut groups = HashMap::new();
+        for (line_idx, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line.expect("Unable to read sample sheet");
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue
+            }
+            let fields: Vec<&str> = line.split('\t').map(|field| field.trim()).collect();
+            if line_idx == 0 && fields[0].eq_ignore_ascii_case("sample") {
+                continue
+            }
+            if fields.len() < 3 || fields[0].is_empty() || fields[2].is_empty() {
+                panic!("Sample sheet line {} should have sample, technology and files columns",
+                       line_idx + 1);
+            }
+            let library = Library {
+                sample: fields[0].to_string(),
+                technology: ReadTechnology::from_name(fields[1]),
+                files: fields[2].split(',').map(|file| file.trim().to_string()).collect(),
+            };
+            if !library.is_alignment() && library.files.len() > 2 {
+                panic!("Sample sheet line {} should give one BAM or CRAM file, or one or two \
+                        read files", line_idx + 1);
+            }
+            if library.technology.is_long() && library.files.len() > 1 {
+                panic!("Sample sheet line {} should give one BAM, CRAM or read file of long \
+                        reads", line_idx + 1);
+            }
+            if libraries.iter().any(|other| other.sample == library.sample
+                && other.technology.is_long() == library.technology.is_long()) {
+                panic!("Sample {} has more than one {} read library in the sample sheet",
+                       library.sample, if library.technology.is_long() { "long" } else { "short" });
+            }
+            if let Some(group) = fields.get(3).filter(|group| !group.is_empty()) {
+                groups.insert(library.sample.clone(), SampleGroup {
+                    group: group.to_string(),
+                    stratum: fields.get(4).filter(|stratum| !stratum.is_empty())
+                        .unwrap_or(&DEFAULT_STRATUM).to_string(),
+                });
+            }
+            libraries.push(library);
+        }
+        if libraries.is_empty() {
+            panic!("The sample sheet does not list any samples");
+        }
+        SampleSheet {
+            libraries,
+            groups,
+        }
+    }
+
+    pub fn short_libraries(&self) -> Vec<&Library> {
+        self.libraries.iter().filter(|library| !library.technology.is_long()).collect()
+    }
+
+    pub fn long_libraries(&self) -> Vec<&Library> {
+        self.libraries.iter().filter(|library| library.technology.is_long()).collect()
+    }
+
+    /// Technology of the long read library of the given read, BAM or CRAM file, or of the
+    /// BAM file its reads were mapped to
+    pub fn long_read_technology(&self, path: &str) -> Option<ReadTechnology> {
+        let file_stem = Path::new(path).file_stem()
+            .map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        self.long_libraries().into_iter()
+            .find(|library| library.files[0] == path || library.is_read_by(&file_stem))
+            .map(|library| library.technology)
+    }
+
+    /// Sample of the short or long read library read by the named reader. Both libraries of
+    /// a sample with short and long reads are reported under its name
+    pub fn reader_sample_name(&self, reader_name: &str, longread: bool) -> Option<&str> {
+        let libraries: Vec<&Library> = self.libraries.iter()
+            .filter(|library| library.technology.is_long() == longread)
+            .filter(|library| library.is_read_by(reader_name))
+            .collect();
+        if libraries.len() > 1 {
+            panic!("Reads {} match the files of samples {} and {} in the sample sheet, whose \
+                    files must have distinct names", reader_name, libraries[0].sample,
+                   libraries[1].sample);
+        }
+        libraries.first().map(|library| library.sample.as_str())
+    }
+
+    /// Arguments and values giving the files of each library on the command line
+    pub fn input_arguments(&self) -> Vec<(&'static str, String)> {
+        let short = self.short_libraries();
+        if short.iter().any(|library| library.is_alignment())
+            && short.iter().any(|library| !library.is_alignment()) {
+            panic!("The short read libraries of a sample sheet must either all be BAM or CRAM \
+                    files or all be read files");
+        }
+        let mut arguments = Vec::new();
+        for library in short.into_iter() {
+            if library.is_alignment() {
+                arguments.push(("bam-files", library.files[0].clone()));
+            } else if library.files.len() == 2 {
+                arguments.push(("read1", library.files[0].clone()));
+                arguments.push(("read2", library.files[1].clone()));
+            } else {
+                arguments.push(("single", library.files[0].clone()));
+            }
+        }
+        let long = self.long_libraries();
+        if long.iter().any(|library| library.is_alignment())
+            && long.iter().any(|library| !library.is_alignment()) {
+            panic!("The long read libraries of a sample sheet must either all be BAM or CRAM \
+                    files or all be read files");
+        }
+        for library in long.into_iter() {
+            if library.is_alignment() {
+                arguments.push(("longread-bam-files", library.files[0].clone()));
+            } else {
+                arguments.push(("longreads", library.files[0].clone()));
+            }
+        }
+        arguments
+    }
+}
+
+/// Parses the command line as get_matches_with_config does, adding the files of the sample
+/// sheet given to the subcommand's --sample-sheet argument as if they were given on the
+/// command line
+pub fn get_matches_with_sample_sheet(app: &App<'static, 'static>, args: Vec<String>)
+    -> ArgMatches<'static> {
+    let matches = get_matches_with_config(app, args.clone());
+    let (subcommand, sheet_path) = match matches.subcommand() {
+        (subcommand, Some(m)) if m.is_present("sample-sheet") => {
+            for name in SHEET_INPUT_ARGUMENTS.iter() {
+                if m.is_present(name) {
+                    panic!("--sample-sheet cannot be combined with --{}", name);
+                }
+            }
+            (subcommand.to_string(), m.value_of("sample-sheet").unwrap().to_string())
+        },
+        _ => return matches,
+    };
+    let sheet = SampleSheet::from_file(&sheet_path);
+    let arguments = subcommand_arguments(&subcommand);
+    let mut tokens = Vec::new();
+    for (name, value) in sheet.input_arguments().into_iter() {
+        let argument = arguments.iter().find(|argument| argument.name == name)
+            .expect(&format!("lorikeet {} does not accept the libraries of this sample sheet",
+                             subcommand));
+        tokens.extend(argument.tokens(&value));
+    }
+    let mut expanded = args[..2].to_vec();
+    expanded.extend(tokens);
+    expanded.extend(args[2..].iter().cloned());
+    get_matches_with_config(app, expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use cli::build_cli;
+    use estimation::lorikeet_config::LorikeetConfig;
+
+    #[test]
+    fn test_parse_sample_sheet() {
+        let sheet = SampleSheet::parse("sample\ttechnology\tfiles\tgroup\n\
+            # Comment\n\
+            gut_a\tshort\ta_1.fq.gz, a_2.fq.gz\tcontrol\n\
+            gut_b\tshort\tb.fq.gz\ttreated\tsite_2\n\
+            gut_a\tONT\ta_ont.fq.gz\n\
+            gut_c\tshort\tc_1.fq.gz,c_2.fq.gz\n\
+            gut_c\tpacbio\tc_pb.fq.gz\n".as_bytes());
+
+        assert_eq!(sheet.libraries.len(), 5);
+        assert_eq!(sheet.libraries[0].files, vec!["a_1.fq.gz".to_string(), "a_2.fq.gz".to_string()]);
+        assert_eq!(sheet.libraries[2].technology, ReadTechnology::Nanopore);
+        assert!(!sheet.libraries[2].is_alignment());
+        assert_eq!(sheet.groups["gut_b"].stratum, "site_2");
+        assert_eq!(sheet.groups["gut_a"].stratum, DEFAULT_STRATUM);
+
+        assert!(!sheet.groups.contains_key("gut_c"));
+
+        // Streamed and cached mappings, with both libraries of gut_a under its name
+        assert_eq!(sheet.reader_sample_name("assembly.fna/c_1.fq.gz", false), Some("gut_c"));
+        assert_eq!(sheet.reader_sample_name("assembly.fna.b.fq.gz", false), Some("gut_b"));
+        assert_eq!(sheet.reader_sample_name("assembly.fna/a_1.fq.gz", false), Some("gut_a"));
+        assert_eq!(sheet.reader_sample_name("assembly.fna.a_ont.fq.gz", true), Some("gut_a"));
+        assert_eq!(sheet.reader_sample_name("assembly.fna.a_ont.fq.gz", false), None);
+        assert_eq!(sheet.reader_sample_name("assembly.fna/a_2.fq.gz", false), None);
+
+        assert_eq!(sheet.input_arguments(), vec![
+            ("read1", "a_1.fq.gz".to_string()), ("read2", "a_2.fq.gz".to_string()),
+            ("single", "b.fq.gz".to_string()),
+            ("read1", "c_1.fq.gz".to_string()), ("read2", "c_2.fq.gz".to_string()),
+            ("longreads", "a_ont.fq.gz".to_string()), ("longreads", "c_pb.fq.gz".to_string())]);
+
+        // Long reads are mapped with the preset of their technology and called from the BAM
+        // files they are mapped to
+        assert_eq!(sheet.long_read_technology("c_pb.fq.gz"), Some(ReadTechnology::PacBio));
+        assert_eq!(sheet.long_read_technology("cache/assembly.fna.a_ont.fq.gz.bam"),
+                   Some(ReadTechnology::Nanopore));
+        assert_eq!(sheet.long_read_technology("c_1.fq.gz"), None);
+    }
+
+    #[test]
+    fn test_get_matches_with_sample_sheet() {
+        let sheet_path = env::temp_dir().join(
+            format!("lorikeet_sample_sheet_test_{}.tsv", std::process::id()));
+        let sheet_path = sheet_path.to_str().unwrap();
+        let mut sheet_file = File::create(sheet_path).unwrap();
+        write!(sheet_file, "gut_a\tshort\tsample_1.bam\tcontrol\n\
+                            gut_b\tshort\tsample_2.bam\ttreated\n\
+                            gut_b\tpacbio\tsample_2_pb.bam\ttreated\n").unwrap();
+
+        let app = build_cli();
+        let args: Vec<String> = vec!["lorikeet", "summarize", "--sample-sheet", sheet_path,
+                                     "-r", "assembly.fna"].iter().map(|arg| arg.to_string()).collect();
+        let matches = get_matches_with_sample_sheet(&app, args);
+        let m = matches.subcommand_matches("summarize").unwrap();
+        assert_eq!(m.values_of("bam-files").unwrap().collect::<Vec<_>>(),
+                   vec!["sample_1.bam", "sample_2.bam"]);
+        assert_eq!(m.values_of("longread-bam-files").unwrap().collect::<Vec<_>>(),
+                   vec!["sample_2_pb.bam"]);
+
+        let config = LorikeetConfig::generate_from_clap(m, "summarize");
+        assert_eq!(config.sample_name(1, "sample_2", false), "gut_b");
+        assert_eq!(config.sample_name(2, "sample_2_pb", true), "gut_b");
+        assert_eq!(config.sample_groups["gut_b"].group, "treated");
+        std::fs::remove_file(sheet_path).unwrap();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_duplicate_library() {
+        SampleSheet::parse("gut_a\tont\ta.bam\ngut_a\tpacbio\ta_pb.bam\n".as_bytes());
+    }
+}
@@ -0,0 +1,492 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use std::sync::Mutex;
+use bio::alphabets::dna;
+use bio::io::fastq;
+use rust_htslib::bam::record::Cigar;
+use model::variants::Variant;
+
+/// BAM tag holding the strain a read is assigned to
+pub const STRAIN_TAG: &[u8] = b"YS";
+/// BAM tag holding the posterior probability of the strain in STRAIN_TAG
+pub const PROBABILITY_TAG: &[u8] = b"YP";
+
+/// Settings used when scoring reads against the alleles of each strain
+#[derive(Debug, Clone)]
+pub struct AssignmentParameters {
+    /// Probability that a read shows a different allele to the strain it came from
+    pub error_rate: f64,
+    /// Reads whose best strain has a lower posterior probability are ambiguous
+    pub min_probability: f64,
+}
+
+impl AssignmentParameters {
+    pub fn generate_from_clap(m: &clap::ArgMatches) -> AssignmentParameters {
+        let error_rate: f64 = m.value_of("assignment-error-rate").unwrap().parse()
+            .expect("Unable to parse --assignment-error-rate");
+        if error_rate <= 0. || error_rate >= 0.5 {
+            panic!("--assignment-error-rate must be greater than 0 and less than 0.5");
+        }
+        let min_probability: f64 = m.value_of("min-assignment-probability").unwrap().parse()
+            .expect("Unable to parse --min-assignment-probability");
+        if min_probability <= 0. || min_probability > 1. {
+            panic!("--min-assignment-probability must be greater than 0 and at most 1");
+        }
+        AssignmentParameters {
+            error_rate,
+            min_probability,
+        }
+    }
+}
+
+impl Default for AssignmentParameters {
+    fn default() -> AssignmentParameters {
+        AssignmentParameters {
+            error_rate: 0.01,
+            min_probability: 0.95,
+        }
+    }
+}
+
+/// Alleles carried by each strain at every site where at least one strain differs from the
+/// reference. Strains without a variant at a site carry Variant::None
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrainSites {
+    /// Strain index of each entry in the allele vectors
+    pub strains: Vec<usize>,
+    pub sites: HashMap<i32, BTreeMap<i64, Vec<Variant>>>,
+    pub references: HashMap<i32, Vec<u8>>,
+}
+
+impl StrainSites {
+    /// Log-likelihood of each strain having produced the observed alleles on contig tid,
+    /// along with the number of observed sites at which the strains do not all agree
+    pub fn log_likelihoods(&self, tid: i32, observed: &[(i64, Variant)], error_rate: f64)
+        -> (Vec<f64>, usize) {
+        let mut log_likelihoods = vec![0.; self.strains.len()];
+        let mut informative = 0;
+        let contig_sites = match self.sites.get(&tid) {
+            Some(contig_sites) => contig_sites,
+            None => return (log_likelihoods, informative),
+        };
+        for (pos, allele) in observed.iter() {
+            if let Some(alleles) = contig_sites.get(pos) {
+                if alleles.iter().any(|strain_allele| strain_allele != &alleles[0]) {
+                    informative += 1;
+                }
+                for (log_likelihood, strain_allele) in log_likelihoods.iter_mut().zip(alleles.iter()) {
+                    *log_likelihood += if strain_allele == allele {
+                        (1. - error_rate).ln()
+                    } else {
+                        error_rate.ln()
+                    };
+                }
+            }
+        }
+        (log_likelihoods, informative)
+    }
+}
+
+/// Allele shown by an aligned read at each site it covers. Alignment starts at pos on the
+/// reference, and seq holds the read bases. Insertions and deletions are reported at the
+/// anchor base preceding them, and an MNV is reported when a strain carries it and the
+/// read matches it across its whole length
+pub fn observed_alleles(pos: i64, cigar: &[Cigar], seq: &[u8], reference: &[u8],
+                        sites: &BTreeMap<i64, Vec<Variant>>) -> Vec<(i64, Variant)> {
+    let mut aligned = BTreeMap::new();
+    let mut indels = HashMap::new();
+    let mut ref_cursor = pos;
+    let mut read_cursor = 0;
+    for cig in cigar.iter() {
+        match cig {
+            Cigar::Match(_) | Cigar::Equal(_) | Cigar::Diff(_) => {
+                for offset in 0..cig.len() as usize {
+                    aligned.insert(ref_cursor + offset as i64, seq[read_cursor + offset]);
+                }
+                ref_cursor += cig.len() as i64;
+                read_cursor += cig.len() as usize;
+            },
+            Cigar::Del(length) => {
+                indels.insert(ref_cursor - 1, Variant::Deletion(*length));
+                ref_cursor += cig.len() as i64;
+            },
+            Cigar::RefSkip(_) => {
+                ref_cursor += cig.len() as i64;
+            },
+            Cigar::Ins(_) => {
+                let inserted = seq[read_cursor..read_cursor + cig.len() as usize].to_vec();
+                indels.insert(ref_cursor - 1, Variant::Insertion(inserted));
+                read_cursor += cig.len() as usize;
+            },
+            Cigar::SoftClip(_) => {
+                read_cursor += cig.len() as usize;
+            },
+            Cigar::HardClip(_) | Cigar::Pad(_) => {},
+        }
+    }
+
+    let mut observed = Vec::new();
+    for (site, alleles) in sites.range(pos..ref_cursor) {
+        let base = match aligned.get(site) {
+            Some(base) => *base,
+            // Deleted or skipped by this read
+            None => continue,
+        };
+        if let Some(indel) = indels.remove(site) {
+            observed.push((*site, indel));
+            continue
+        }
+        let mnv = alleles.iter().find(|allele| {
+            match allele {
+                Variant::MNV(alt) => {
+                    alt.iter().enumerate().all(|(offset, alt_base)| {
+                        aligned.get(&(site + offset as i64)) == Some(alt_base)
+                    })
+                },
+                _ => false,
+            }
+        });
+        let allele = match mnv {
+            Some(mnv) => mnv.clone(),
+            None => {
+                if reference.get(*site as usize) == Some(&base) {
+                    Variant::None
+                } else {
+                    Variant::SNV(base)
+                }
+            }
+        };
+        observed.push((*site, allele));
+    }
+    observed
+}
+
+/// Outcome of scoring a read against every strain
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Assignment {
+    /// Index into StrainSites::strains
+    Strain(usize),
+    /// The best strain did not reach the minimum posterior probability
+    Ambiguous,
+    /// The read covers no site at which the strains differ
+    Unassigned,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReadAssignment {
+    pub assignment: Assignment,
+    /// Index into StrainSites::strains of the most likely strain
+    pub best: usize,
+    /// Posterior probability of the most likely strain
+    pub probability: f64,
+}
+
+impl ReadAssignment {
+    /// Assigns a read from the summed log-likelihoods of its alignments, using a uniform
+    /// prior across strains
+    pub fn from_log_likelihoods(log_likelihoods: &[f64], informative: usize, min_probability: f64)
+        -> ReadAssignment {
+        let max = log_likelihoods.iter().cloned().fold(std::f64::NEG_INFINITY, f64::max);
+        let total: f64 = log_likelihoods.iter().map(|ll| (ll - max).exp()).sum();
+        let (best, probability) = log_likelihoods.iter().enumerate()
+            .map(|(idx, ll)| (idx, (ll - max).exp() / total))
+            .fold((0, 0.), |best, current| if current.1 > best.1 { current } else { best });
+        let assignment = if informative == 0 {
+            Assignment::Unassigned
+        } else if probability < min_probability {
+            Assignment::Ambiguous
+        } else {
+            Assignment::Strain(best)
+        };
+        ReadAssignment {
+            assignment,
+            best,
+            probability,
+        }
+    }
+
+    /// Value of STRAIN_TAG for this read
+    pub fn label(&self, strains: &[usize]) -> String {
+        match self.assignment {
+            Assignment::Strain(idx) => strains[idx].to_string(),
+            Assignment::Ambiguous => "ambiguous".to_string(),
+            Assignment::Unassigned => "unassigned".to_string(),
+        }
+    }
+}
+
+/// Which strains a read that was not assigned to a single strain is extracted for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadRule {
+    /// The most likely strain
+    Best,
+    /// Every strain
+    All,
+    Discard,
+}
+
+impl ReadRule {
+    fn from_clap(m: &clap::ArgMatches, name: &str) -> ReadRule {
+        let rule = m.value_of(name).unwrap();
+        match rule {
+            "best" => ReadRule::Best,
+            "all" => ReadRule::All,
+            "discard" => ReadRule::Discard,
+            _ => panic!("Unknown --{} rule {}", name, rule),
+        }
+    }
+}
+
+/// How ambiguous and unassigned reads are handled when extracting the reads of each strain.
+/// Unassigned reads cover no site separating the strains, so are compatible with the
+/// reference and every strain
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExtractionRules {
+    pub ambiguous: ReadRule,
+    pub unassigned: ReadRule,
+}
+
+impl ExtractionRules {
+    pub fn generate_from_clap(m: &clap::ArgMatches) -> ExtractionRules {
+        ExtractionRules {
+            ambiguous: ReadRule::from_clap(m, "ambiguous-reads"),
+            unassigned: ReadRule::from_clap(m, "unassigned-reads"),
+        }
+    }
+
+    /// Indices into StrainSites::strains of the strains a read is extracted for
+    pub fn destinations(&self, assignment: &ReadAssignment, strain_count: usize) -> Vec<usize> {
+        let rule = match assignment.assignment {
+            Assignment::Strain(idx) => return vec![idx],
+            Assignment::Ambiguous => self.ambiguous,
+            Assignment::Unassigned => self.unassigned,
+        };
+        match rule {
+            ReadRule::Best => vec![assignment.best],
+            ReadRule::All => (0..strain_count).collect(),
+            ReadRule::Discard => Vec::new(),
+        }
+    }
+}
+
+impl Default for ExtractionRules {
+    fn default() -> ExtractionRules {
+        ExtractionRules {
+            ambiguous: ReadRule::Discard,
+            unassigned: ReadRule::All,
+        }
+    }
+}
+
+/// Read bases and Phred+33 qualities in sequencing orientation, from the bases and raw
+/// qualities stored in a BAM record. Missing qualities are written as 0
+pub fn fastq_bases(seq: &[u8], qual: &[u8], reverse: bool) -> (Vec<u8>, Vec<u8>) {
+    let mut qual: Vec<u8> = qual.iter()
+        .map(|q| if *q == 255 { b'!' } else { q.saturating_add(33) })
+        .collect();
+    if reverse {
+        qual.reverse();
+        (dna::revcomp(seq), qual)
+    } else {
+        (seq.to_vec(), qual)
+    }
+}
+
+#[derive(Default)]
+struct StrainFastq {
+    first: Option<fastq::Writer<File>>,
+    second: Option<fastq::Writer<File>>,
+    single: Option<fastq::Writer<File>>,
+    long: Option<fastq::Writer<File>>,
+}
+
+fn write_fastq(writer: &mut Option<fastq::Writer<File>>, file_name: String,
+               name: &str, seq: &[u8], qual: &[u8]) {
+    if writer.is_none() {
+        *writer = Some(fastq::Writer::to_file(&file_name)
+            .expect("No Read or Write Permission in current directory"));
+    }
+    writer.as_mut().unwrap().write(name, None, seq, qual)
+        .expect("Unable to write to file");
+}
+
+/// FASTQ files holding the reads extracted for each strain, pooled across samples. Files
+/// are only created once a read is written to them
+pub struct StrainReadWriters {
+    output_prefix: String,
+    strains: Vec<usize>,
+    writers: Vec<Mutex<StrainFastq>>,
+}
+
+impl StrainReadWriters {
+    pub fn new(output_prefix: &str, strains: &[usize]) -> StrainReadWriters {
+        StrainReadWriters {
+            output_prefix: output_prefix.to_string(),
+            strains: strains.to_vec(),
+            writers: strains.iter().map(|_| Mutex::new(StrainFastq::default())).collect(),
+        }
+    }
+
+    fn file_name(&self, idx: usize, suffix: &str) -> String {
+        format!("{}_strain_{}_{}.fastq", self.output_prefix, self.strains[idx], suffix)
+    }
+
+    /// Writes both mates of a pair together so the R1 and R2 files stay in the same order
+    pub fn write_pair(&self, destinations: &[usize], name: &str,
+                      first: &(Vec<u8>, Vec<u8>), second: &(Vec<u8>, Vec<u8>)) {
+        for idx in destinations.iter() {
+            let mut writer = self.writers[*idx].lock().unwrap();
+            write_fastq(&mut writer.first, self.file_name(*idx, "R1"), name, &first.0, &first.1);
+            write_fastq(&mut writer.second, self.file_name(*idx, "R2"), name, &second.0, &second.1);
+        }
+    }
+
+    pub fn write_single(&self, destinations: &[usize], name: &str, read: &(Vec<u8>, Vec<u8>),
+                        long: bool) {
+        for idx in destinations.iter() {
+            let mut writer = self.writers[*idx].lock().unwrap();
+            if long {
+                write_fastq(&mut writer.long, self.file_name(*idx, "long"), name, &read.0, &read.1);
+            } else {
+                write_fastq(&mut writer.single, self.file_name(*idx, "single"), name, &read.0, &read.1);
+            }
+        }
+    }
+}
+
+/// Number of reads given each label in a sample, along with their summed probabilities
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AssignmentSummary {
+    pub counts: BTreeMap<String, (usize, f64)>,
+}
+
+impl AssignmentSummary {
+    pub fn add(&mut self, label: String, probability: f64) {
+        let entry = self.counts.entry(label).or_insert((0, 0.));
+        entry.0 += 1;
+        entry.1 += probability;
+    }
+
+    pub fn merge(&mut self, other: AssignmentSummary) {
+        for (label, (count, probability)) in other.counts.into_iter() {
+            let entry = self.counts.entry(label).or_insert((0, 0.));
+            entry.0 += count;
+            entry.1 += probability;
+        }
+    }
+}
+
+/// Writes the number of reads assigned to each strain in each sample, along with their mean
+/// posterior probability
+pub fn write_assignment_summary(file_name: &str, summaries: &[(String, AssignmentSummary)]) {
+    let mut file_open = File::create(Path::new(file_name))
+        .expect("No Read or Write Permission in current directory");
+    writeln!(file_open, "sample\tstrain\treads\tfraction\tmean_probability")
+        .expect("Unable to write to file");
+    for (sample, summary) in summaries.iter() {
+        let total: usize = summary.counts.values().map(|(count, _)| count).sum();
+        for (label, (count, probability)) in summary.counts.iter() {
+            writeln!(file_open, "{}\t{}\t{}\t{:.4}\t{:.4}",
+                     sample, label, count,
+                     *count as f64 / total as f64,
+                     probability / *count as f64).expect("Unable to write to file");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strain_sites() -> StrainSites {
+        let mut contig_sites = BTreeMap::new();
+        contig_sites.insert(2, vec![Variant::SNV(b'T'), Variant::None]);
+        contig_sites.insert(5, vec![Variant::Deletion(2), Variant::Deletion(2)]);
+        contig_sites.insert(10, vec![Variant::None, Variant::Insertion(b"CC".to_vec())]);
+        let mut sites = HashMap::new();
+        sites.insert(0, contig_sites);
+        let mut references = HashMap::new();
+        references.insert(0, b"ACGTACGTACGTACGT".to_vec());
+        StrainSites {
+            strains: vec![0, 3],
+            sites,
+            references,
+        }
+    }
+
+    #[test]
+    fn test_observed_alleles() {
+        let strain_sites = strain_sites();
+        let reference = &strain_sites.references[&0];
+        let sites = &strain_sites.sites[&0];
+        // Read carries the SNV and the deletion, then the reference at the insertion site
+        let seq = b"ACTTACACGTA";
+        let cigar = [Cigar::SoftClip(1), Cigar::Match(5), Cigar::Del(2), Cigar::Match(5)];
+        let observed = observed_alleles(1, &cigar, seq, reference, sites);
+        assert_eq!(observed, vec![
+            (2, Variant::SNV(b'T')),
+            (5, Variant::Deletion(2)),
+            (10, Variant::None),
+        ]);
+
+        let seq = b"TACGCCTA";
+        let cigar = [Cigar::Match(4), Cigar::Ins(2), Cigar::Match(2)];
+        let observed = observed_alleles(7, &cigar, seq, reference, sites);
+        assert_eq!(observed, vec![(10, Variant::Insertion(b"CC".to_vec()))]);
+    }
+
+    #[test]
+    fn test_read_assignment() {
+        let strain_sites = strain_sites();
+        let observed = vec![(2, Variant::SNV(b'T')), (5, Variant::Deletion(2)), (10, Variant::None)];
+        let (log_likelihoods, informative) = strain_sites.log_likelihoods(0, &observed, 0.01);
+        assert_eq!(informative, 2);
+        let assignment = ReadAssignment::from_log_likelihoods(&log_likelihoods, informative, 0.95);
+        assert_eq!(assignment.assignment, Assignment::Strain(0));
+        assert!(assignment.probability > 0.99);
+        assert_eq!(assignment.label(&strain_sites.strains), "0");
+
+        // One site supports each strain
+        let observed = vec![(2, Variant::SNV(b'T')), (10, Variant::Insertion(b"CC".to_vec()))];
+        let (log_likelihoods, informative) = strain_sites.log_likelihoods(0, &observed, 0.01);
+        let assignment = ReadAssignment::from_log_likelihoods(&log_likelihoods, informative, 0.95);
+        assert_eq!(assignment.assignment, Assignment::Ambiguous);
+        assert!((assignment.probability - 0.5).abs() < 1e-9);
+
+        // Shared alleles do not separate the strains
+        let observed = vec![(5, Variant::Deletion(2))];
+        let (log_likelihoods, informative) = strain_sites.log_likelihoods(0, &observed, 0.01);
+        let assignment = ReadAssignment::from_log_likelihoods(&log_likelihoods, informative, 0.95);
+        assert_eq!(assignment.assignment, Assignment::Unassigned);
+        assert_eq!(assignment.label(&strain_sites.strains), "unassigned");
+    }
+
+    #[test]
+    fn test_read_extraction() {
+        let rules = ExtractionRules {
+            ambiguous: ReadRule::Best,
+            unassigned: ReadRule::Discard,
+        };
+        let assigned = ReadAssignment::from_log_likelihoods(&[-10., -1., -10.], 2, 0.95);
+        assert_eq!(rules.destinations(&assigned, 3), vec![1]);
+        let ambiguous = ReadAssignment::from_log_likelihoods(&[-1.5, -1., -10.], 2, 0.95);
+        assert_eq!(ambiguous.assignment, Assignment::Ambiguous);
+        assert_eq!(rules.destinations(&ambiguous, 3), vec![1]);
+        let unassigned = ReadAssignment::from_log_likelihoods(&[0., 0., 0.], 0, 0.95);
+        assert!(rules.destinations(&unassigned, 3).is_empty());
+        let rules = ExtractionRules {
+            ambiguous: ReadRule::Discard,
+            unassigned: ReadRule::All,
+        };
+        assert!(rules.destinations(&ambiguous, 3).is_empty());
+        assert_eq!(rules.destinations(&unassigned, 3), vec![0, 1, 2]);
+
+        let (seq, qual) = fastq_bases(b"AACG", &[30, 30, 20, 10], true);
+        assert_eq!(seq, b"CGTT".to_vec());
+        assert_eq!(qual, b"+5??".to_vec());
+        let (_, qual) = fastq_bases(b"AC", &[255, 255], false);
+        assert_eq!(qual, b"!!".to_vec());
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+
+/// Frequency at or above which an allele is considered fixed
+pub const FIXED_FREQUENCY: f64 = 0.95;
+/// Frequency at or below which an allele is considered lost
+pub const LOST_FREQUENCY: f64 = 0.05;
+
+/// Header matching the columns produced by [TrajectoryCounts::to_tsv]
+pub const TRAJECTORY_COUNTS_HEADER: &str = "variants\tsweeping\tfixed\tlost\tstable";
+
+/// Subject and time point of a sample in a longitudinal series
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampleMetadata {
+    pub subject: String,
+    pub time: f64,
+}
+
+/// Settings used when classifying allele frequency trajectories
+#[derive(Debug, Clone)]
+pub struct TrajectoryParameters {
+    /// Minimum read depth for a time point to be included in a trajectory
+    pub min_depth: i32,
+    /// Minimum change in frequency across a trajectory for a variant to be sweeping
+    pub min_change: f64,
+    /// Minimum number of sweeping or fixed variants for a gene to be flagged
+    pub min_gene_sweeps: usize,
+}
+
+impl TrajectoryParameters {
+    pub fn generate_from_clap(m: &clap::ArgMatches) -> TrajectoryParameters {
+        TrajectoryParameters {
+            min_depth: m.value_of("min-site-depth").unwrap().parse()
+                .expect("Unable to parse --min-site-depth"),
+            min_change: m.value_of("sweep-frequency-change").unwrap().parse()
+                .expect("Unable to parse --sweep-frequency-change"),
+            min_gene_sweeps: m.value_of("min-gene-sweeps").unwrap().parse()
+                .expect("Unable to parse --min-gene-sweeps"),
+        }
+    }
+}
+
+impl Default for TrajectoryParameters {
+    fn default() -> TrajectoryParameters {
+        TrajectoryParameters {
+            min_depth: 5,
+            min_change: 0.5,
+            min_gene_sweeps: 3,
+        }
+    }
+}
+
+pub fn read_sample_metadata(path: &str) -> HashMap<String, SampleMetadata> {
+    let file = File::open(path)
+        .expect(&format!("Unable to open sample metadata file {}", path));
+    parse_sample_metadata(file)
+}
+
+/// Parse a tab separated table of sample name, subject and time point. Blank lines,
+/// lines starting with # and a header line whose time point is not numeric are skipped
+pub fn parse_sample_metadata<R: Read>(reader: R) -> HashMap<String, SampleMetadata> {
+    let mut metadata = HashMap::new();
+    for (line_idx, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line.expect("Unable to read sample metadata");
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue
+        }
+        let fields: Vec<&str> = line.split('\t').map(|field| field.trim()).collect();
+        if fields.len() < 3 {
+            panic!("Sample metadata line {} should have sample, subject and time point columns",
+                   line_idx + 1);
+        }
+        let time = match fields[2].parse::<f64>() {
+            Ok(time) if !time.is_finite() => panic!("Time point '{}' on sample metadata line {} \
+                                                     must be a finite number",
+                                                    fields[2], line_idx + 1),
+            Ok(time) => time,
+            Err(_) if line_idx == 0 => continue,
+            Err(_) => panic!("Unable to parse time point '{}' on sample metadata line {}",
+                             fields[2], line_idx + 1),
+        };
+        metadata.insert(fields[0].to_string(), SampleMetadata {
+            subject: fields[1].to_string(),
+            time,
+        });
+    }
+    metadata
+}
+
+/// File extensions removed from sample names and metadata names before they are compared
+const SAMPLE_EXTENSIONS: &[&str] = &[
+    "gz", "bz2", "xz", "bam", "cram", "sam", "fastq", "fq", "fasta", "fa", "fna",
+];
+
+/// Sample name without its directories and known read or alignment file extensions,
+/// e.g. reference.fna/S1.bam becomes S1
+fn strip_sample_name(name: &str) -> &str {
+    let mut name = name.rsplit('/').next().unwrap_or(name);
+    while let Some(dot) = name.rfind('.') {
+        if !SAMPLE_EXTENSIONS.contains(&&name[dot + 1..]) {
+            break
+        }
+        name = &name[..dot];
+    }
+    name
+}
+
+/// Find the metadata of a sample by its exact name, or failing that the metadata names
+/// equal to it once directories and file extensions are stripped from both. Errors if no
+/// metadata name or more than one matches
+pub fn match_sample<'a, T>(sample_name: &str, metadata: &'a HashMap<String, T>)
+                           -> Result<&'a T, String> {
+    if let Some(sample) = metadata.get(sample_name) {
+        return Ok(sample)
+    }
+    let stripped = strip_sample_name(sample_name);
+    let mut matches: Vec<(&String, &T)> = metadata.iter()
+        .filter(|(name, _)| strip_sample_name(name) == stripped)
+        .collect();
+    matches.sort_by(|a, b| a.0.cmp(b.0));
+    match matches.len() {
+        0 => Err(format!("No metadata found for sample {}", sample_name)),
+        1 => Ok(matches[0].1),
+        _ => Err(format!("Sample {} matches more than one metadata name: {}", sample_name,
+                         matches.iter().map(|(name, _)| name.as_str())
+                             .collect::<Vec<&str>>().join(", "))),
+    }
+}
+
+/// How the frequency of a variant changed over a subject's time series
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TrajectoryClass {
+    Sweeping,
+    Fixed,
+    Lost,
+    Stable,
+}
+
+impl fmt::Display for TrajectoryClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TrajectoryClass::Sweeping => write!(f, "sweeping"),
+            TrajectoryClass::Fixed => write!(f, "fixed"),
+            TrajectoryClass::Lost => write!(f, "lost"),
+            TrajectoryClass::Stable => write!(f, "stable"),
+        }
+    }
+}
+
+impl TrajectoryClass {
+    /// Variants that rose to fixation or changed substantially in frequency
+    pub fn is_sweep(&self) -> bool {
+        match self {
+            TrajectoryClass::Sweeping | TrajectoryClass::Fixed => true,
+            _ => false,
+        }
+    }
+}
+
+/// Classify a trajectory of (time, frequency) points sorted by time. Variants that reach
+/// FIXED_FREQUENCY or fall to LOST_FREQUENCY by the final time point are fixed or lost,
+/// otherwise a range of frequencies of at least min_change is a sweep
+pub fn classify(trajectory: &[(f64, f64)], min_change: f64) -> TrajectoryClass {
+    if trajectory.len() < 2 {
+        return TrajectoryClass::Stable
+    }
+    let first = trajectory[0].1;
+    let last = trajectory[trajectory.len() - 1].1;
+    if first < FIXED_FREQUENCY && last >= FIXED_FREQUENCY {
+        return TrajectoryClass::Fixed
+    } else if first > LOST_FREQUENCY && last <= LOST_FREQUENCY {
+        return TrajectoryClass::Lost
+    }
+    let max = trajectory.iter().map(|(_, frequency)| *frequency).fold(0., f64::max);
+    let min = trajectory.iter().map(|(_, frequency)| *frequency).fold(1., f64::min);
+    if max - min >= min_change {
+        TrajectoryClass::Sweeping
+    } else {
+        TrajectoryClass::Stable
+    }
+}
+
+/// Number of variants in each trajectory class
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrajectoryCounts {
+    pub sweeping: usize,
+    pub fixed: usize,
+    pub lost: usize,
+    pub stable: usize,
+}
+
+impl TrajectoryCounts {
+    pub fn add(&mut self, class: TrajectoryClass) {
+        match class {
+            TrajectoryClass::Sweeping => self.sweeping += 1,
+            TrajectoryClass::Fixed => self.fixed += 1,
+            TrajectoryClass::Lost => self.lost += 1,
+            TrajectoryClass::Stable => self.stable += 1,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.sweeping + self.fixed + self.lost + self.stable
+    }
+
+    pub fn sweeps(&self) -> usize {
+        self.sweeping + self.fixed
+    }
+
+    /// Columns matching [TRAJECTORY_COUNTS_HEADER]
+    pub fn to_tsv(&self) -> String {
+        format!("{}\t{}\t{}\t{}\t{}",
+                self.total(), self.sweeping, self.fixed, self.lost, self.stable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sample_metadata() {
+        let table = "sample\tsubject\ttime\n\
+                     S1\tpatient_a\t0\n\
+                     # comment\n\
+                     S2\tpatient_a\t7.5\n\
+                     \n\
+                     S10\tpatient_b\t0\n";
+        let metadata = parse_sample_metadata(table.as_bytes());
+        assert_eq!(metadata.len(), 3);
+        assert_eq!(metadata["S2"], SampleMetadata { subject: "patient_a".to_string(), time: 7.5 });
+        assert_eq!(match_sample("S1", &metadata).unwrap().time, 0.);
+        assert_eq!(match_sample("reference.fna/S10.bam", &metadata).unwrap().subject, "patient_b");
+        assert_eq!(match_sample("reference.fna/S10.fq.gz", &metadata).unwrap().subject, "patient_b");
+        assert!(match_sample("S3", &metadata).is_err());
+        // Names that only contain a metadata name are not matched
+        assert!(match_sample("S100", &metadata).is_err());
+        assert!(match_sample("reference.fna/S1_trimmed.bam", &metadata).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "must be a finite number")]
+    fn test_parse_sample_metadata_nan() {
+        let table = "sample\tsubject\ttime\n\
+                     S1\tpatient_a\tNaN\n";
+        parse_sample_metadata(table.as_bytes());
+    }
+
+    #[test]
+    fn test_match_sample_ambiguous() {
+        let table = "sample\tsubject\ttime\n\
+                     S1.bam\tpatient_a\t0\n\
+                     S1.cram\tpatient_b\t0\n";
+        let metadata = parse_sample_metadata(table.as_bytes());
+        assert_eq!(match_sample("S1.bam", &metadata).unwrap().subject, "patient_a");
+        assert_eq!(match_sample("S1", &metadata),
+                   Err("Sample S1 matches more than one metadata name: S1.bam, S1.cram".to_string()));
+    }
+
+    #[test]
+    fn test_classify() {
+        assert_eq!(classify(&[(0., 0.1), (1., 0.5), (2., 0.97)], 0.5), TrajectoryClass::Fixed);
+        assert_eq!(classify(&[(0., 0.6), (1., 0.02)], 0.5), TrajectoryClass::Lost);
+        assert_eq!(classify(&[(0., 0.2), (1., 0.8), (2., 0.3)], 0.5), TrajectoryClass::Sweeping);
+        assert_eq!(classify(&[(0., 0.4), (1., 0.5)], 0.5), TrajectoryClass::Stable);
+        assert_eq!(classify(&[(0., 1.), (1., 1.)], 0.5), TrajectoryClass::Stable);
+        assert_eq!(classify(&[(0., 0.1)], 0.5), TrajectoryClass::Stable);
+
+        let mut counts = TrajectoryCounts::default();
+        counts.add(TrajectoryClass::Fixed);
+        counts.add(TrajectoryClass::Sweeping);
+        counts.add(TrajectoryClass::Stable);
+        assert_eq!(counts.sweeps(), 2);
+        assert_eq!(counts.to_tsv(), "3\t1\t1\t0\t1");
+    }
+}
@@ -0,0 +1,643 @@
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use bio::alphabets::dna;
+use bio::data_structures::interval_tree::IntervalTree;
+use bio::io::gff::Record;
+use bio_types::strand;
+use estimation::codon_structs::*;
+use model::variants::*;
+
+/// Distance before a gene's start codon in which variants are reported as upstream
+pub const UPSTREAM_DISTANCE: u64 = 100;
+
+/// The predicted effect of a variant on the gene it falls within
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Consequence {
+    Synonymous,
+    Missense,
+    Nonsense,
+    StopLost,
+    StartLost,
+    Frameshift,
+    InframeIndel,
+    Upstream,
+    Intergenic,
+}
+
+impl Consequence {
+    /// All consequence classes in the order they are reported
+    pub fn all() -> Vec<Consequence> {
+        vec![Consequence::Synonymous, Consequence::Missense, Consequence::Nonsense,
+             Consequence::StopLost, Consequence::StartLost, Consequence::Frameshift,
+             Consequence::InframeIndel, Consequence::Upstream, Consequence::Intergenic]
+    }
+}
+
+impl fmt::Display for Consequence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Consequence::Synonymous => "synonymous",
+            Consequence::Missense => "missense",
+            Consequence::Nonsense => "nonsense",
+            Consequence::StopLost => "stop_lost",
+            Consequence::StartLost => "start_lost",
+            Consequence::Frameshift => "frameshift",
+            Consequence::InframeIndel => "inframe_indel",
+            Consequence::Upstream => "upstream",
+            Consequence::Intergenic => "intergenic",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Effect of a single variant on the overlapping gene, if any
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VariantAnnotation {
+    pub gene_id: Option<String>,
+    // 1-based index of the first codon affected by the variant
+    pub codon_position: Option<usize>,
+    pub ref_codon: Option<String>,
+    pub alt_codon: Option<String>,
+    pub ref_aa: Option<String>,
+    pub alt_aa: Option<String>,
+    pub consequence: Consequence,
+}
+
+impl VariantAnnotation {
+    pub fn intergenic() -> VariantAnnotation {
+        VariantAnnotation {
+            gene_id: None,
+            codon_position: None,
+            ref_codon: None,
+            alt_codon: None,
+            ref_aa: None,
+            alt_aa: None,
+            consequence: Consequence::Intergenic,
+        }
+    }
+
+    /// Tab separated annotation columns, using "-" for missing values
+    pub fn to_tsv(&self) -> String {
+        let or_dash = |value: &Option<String>| -> String {
+            match value {
+                Some(value) => value.clone(),
+                None => "-".to_string(),
+            }
+        };
+        format!("{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                or_dash(&self.gene_id),
+                match self.codon_position {
+                    Some(codon) => codon.to_string(),
+                    None => "-".to_string(),
+                },
+                or_dash(&self.ref_codon),
+                or_dash(&self.alt_codon),
+                or_dash(&self.ref_aa),
+                or_dash(&self.alt_aa),
+                self.consequence)
+    }
+}
+
+/// Header matching the columns produced by [VariantAnnotation::to_tsv]
+pub const ANNOTATION_HEADER: &str = "gene\tcodon\tref_codon\talt_codon\tref_aa\talt_aa\tconsequence";
+
+/// Attributes checked in order for an identifier to report a gene with
+pub const GENE_ID_ATTRIBUTES: [&str; 8] = ["ID", "locus_tag", "Name", "gene", "gene_name",
+    "gene_id", "transcript_id", "protein_id"];
+
+/// Source given to the genes lorikeet predicts itself
+pub const PREDICTED_GENE_SOURCE: &str = "lorikeet";
+
+/// Returns the identifier used to report a gene, i.e. its first identifying attribute,
+/// falling back to its coordinates if it has none. Genes predicted by lorikeet keep their
+/// full {contig_idx}_{gene_idx} ID so that genes on different contigs are told apart
+pub fn gene_id(gene: &Record) -> String {
+    GENE_ID_ATTRIBUTES.iter()
+        .filter_map(|key| gene.attributes().get(*key))
+        .find(|id| id.len() > 0)
+        .map(|id| id.to_string())
+        .unwrap_or(format!("{}:{}-{}", gene.seqname(), gene.start(), gene.end()))
+}
+
+/// Segments of each coding sequence in a list of gene records, grouped by gene ID in the
+/// order the genes are first seen. Segments keep their order, which the annotation loader
+/// gives in transcription order
+pub fn group_segments(records: &[Record]) -> Vec<Vec<&Record>> {
+    let mut gene_indices: HashMap<String, usize> = HashMap::new();
+    let mut genes: Vec<Vec<&Record>> = Vec::new();
+    for record in records.iter() {
+        let id = gene_id(record);
+        match gene_indices.get(&id) {
+            Some(idx) => genes[*idx].push(record),
+            None => {
+                gene_indices.insert(id, genes.len());
+                genes.push(vec![record]);
+            }
+        }
+    }
+    genes
+}
+
+/// Whether a gene is coded on the reverse strand
+pub fn is_reverse(gene: &Record) -> bool {
+    match gene.strand() {
+        Some(strand::Strand::Reverse) => true,
+        _ => false,
+    }
+}
+
+/// 0-based contig positions of the bases of a spliced coding sequence in coding order,
+/// starting at its first complete codon. Segments must be in transcription order. The phase
+/// of the first segment sets the reading frame, which the following segments continue
+pub fn coding_positions<R: Borrow<Record>>(segments: &[R], contig_length: usize) -> Vec<usize> {
+    let mut positions = Vec::new();
+    for segment in segments.iter() {
+        let segment = segment.borrow();
+        let start = segment.start().saturating_sub(1) as usize;
+        let end = std::cmp::min(*segment.end() as usize, contig_length);
+        if start >= end {
+            continue
+        }
+        if is_reverse(segment) {
+            positions.extend((start..end).rev());
+        } else {
+            positions.extend(start..end);
+        }
+    }
+    let frame: usize = match segments.first() {
+        Some(segment) => segment.borrow().frame().parse().unwrap_or(0),
+        None => 0,
+    };
+    positions.split_off(std::cmp::min(frame, positions.len()))
+}
+
+/// Contig positions of the bases of a coding sequence, along with the index in the coding
+/// sequence of each position
+struct CodingSequence {
+    positions: Vec<usize>,
+    indices: HashMap<usize, usize>,
+}
+
+impl CodingSequence {
+    fn new<R: Borrow<Record>>(segments: &[R], contig_length: usize) -> CodingSequence {
+        let positions = coding_positions(segments, contig_length);
+        let indices = positions.iter().enumerate()
+            .map(|(coding_idx, pos)| (*pos, coding_idx))
+            .collect();
+        CodingSequence {
+            positions,
+            indices,
+        }
+    }
+}
+
+/// Reference bases replaced by a variant at pos, including the anchor base of indels
+pub fn variant_span(pos: usize, variant: &Variant, reference_len: usize) -> (usize, usize) {
+    let end = match variant {
+        Variant::MNV(alt) => pos + alt.len(),
+        Variant::Deletion(length) => pos + 1 + *length as usize,
+        _ => pos + 1,
+    };
+    (pos, end.min(reference_len))
+}
+
+/// Contig positions changed by a variant at pos: the bases replaced by SNVs and MNVs, the
+/// bases deleted after the anchor base of deletions and the anchor base of insertions
+fn changed_bases(pos: usize, variant: &Variant, reference_len: usize) -> Range<usize> {
+    let (start, end) = variant_span(pos, variant, reference_len);
+    match variant {
+        Variant::Deletion(_) => std::cmp::min(start + 1, end)..end,
+        _ => start..end,
+    }
+}
+
+/// The base at a contig position read on the coding strand of a gene
+fn coding_base(ref_sequence: &[u8], pos: usize, reverse: bool) -> u8 {
+    if reverse {
+        dna::complement(ref_sequence[pos])
+    } else {
+        ref_sequence[pos]
+    }
+}
+
+/// Interval index over the genes of each contig. Each gene is kept as its segments in
+/// transcription order, so spliced and origin spanning genes are annotated as one sequence
+pub struct GeneIndex {
+    genes: HashMap<String, Vec<Vec<Record>>>,
+    /// Coding sequence of each gene, ignoring the length of its contig
+    coding: HashMap<String, Vec<CodingSequence>>,
+    trees: HashMap<String, IntervalTree<u64, usize>>,
+}
+
+impl GeneIndex {
+    pub fn new(gff_map: &HashMap<String, Vec<Record>>) -> GeneIndex {
+        let mut genes = HashMap::new();
+        let mut coding = HashMap::new();
+        let mut trees = HashMap::new();
+        for (contig, records) in gff_map.iter() {
+            let mut tree = IntervalTree::new();
+            let mut contig_genes = Vec::new();
+            let mut contig_coding = Vec::new();
+            for segments in group_segments(records) {
+                for segment in segments.iter() {
+                    // GFF coordinates are 1-based inclusive, store as 0-based half open
+                    let start = segment.start().saturating_sub(1);
+                    let end = *segment.end();
+                    if end <= start {
+                        continue
+                    }
+                    tree.insert(start..end, contig_genes.len());
+                }
+                let gene_end = segments.iter().map(|segment| *segment.end() as usize).max();
+                contig_coding.push(CodingSequence::new(&segments, gene_end.unwrap_or(0)));
+                contig_genes.push(segments.into_iter().cloned().collect());
+            }
+            genes.insert(contig.clone(), contig_genes);
+            coding.insert(contig.clone(), contig_coding);
+            trees.insert(contig.clone(), tree);
+        }
+        GeneIndex {
+            genes,
+            coding,
+            trees,
+        }
+    }
+
+    /// Genes with a segment overlapping the 0-based half open interval start..end, each given
+    /// as its segments in transcription order and sorted by start
+    pub fn overlapping(&self, contig: &str, start: u64, end: u64) -> Vec<&[Record]> {
+        let genes = &self.genes[contig];
+        self.overlapping_indices(contig, start, end).into_iter()
+            .map(|idx| genes[idx].as_slice())
+            .collect()
+    }
+
+    /// Indices of the genes of a contig overlapping start..end, sorted by start
+    fn overlapping_indices(&self, contig: &str, start: u64, end: u64) -> Vec<usize> {
+        let end = std::cmp::max(end, start + 1);
+        match (self.trees.get(contig), self.genes.get(contig)) {
+            (Some(tree), Some(genes)) => {
+                let mut indices: Vec<usize> = tree.find(start..end)
+                    .map(|entry| *entry.data())
+                    .collect();
+                indices.sort();
+                indices.dedup();
+                indices.sort_by_key(|idx| {
+                    genes[*idx].iter().map(|segment| *segment.start()).min()
+                });
+                indices
+            },
+            _ => Vec::new(),
+        }
+    }
+
+    /// The closest gene whose start codon lies within UPSTREAM_DISTANCE downstream of pos,
+    /// given by the segment containing its start codon
+    pub fn upstream_of(&self, contig: &str, pos: u64) -> Option<&Record> {
+        let window_start = pos.saturating_sub(UPSTREAM_DISTANCE);
+        let window_end = pos + UPSTREAM_DISTANCE + 1;
+        let mut closest: Option<(u64, &Record)> = None;
+        for segments in self.overlapping(contig, window_start, window_end) {
+            let gene = &segments[0];
+            let start = gene.start().saturating_sub(1);
+            let end = *gene.end();
+            let distance = match gene.strand() {
+                Some(strand::Strand::Reverse) => {
+                    if pos >= end { Some(pos - end + 1) } else { None }
+                },
+                _ => {
+                    if pos < start { Some(start - pos) } else { None }
+                },
+            };
+            match distance {
+                Some(distance) if distance <= UPSTREAM_DISTANCE => {
+                    closest = match closest {
+                        Some((best, record)) if best <= distance => Some((best, record)),
+                        _ => Some((distance, gene)),
+                    };
+                },
+                _ => {},
+            }
+        }
+        closest.map(|(_, gene)| gene)
+    }
+
+    /// Annotate a variant at 0-based position pos against each gene it overlaps. Variants
+    /// outside genes have a single upstream or intergenic annotation
+    pub fn annotate(&self,
+                    contig: &str,
+                    pos: i64,
+                    variant: &Variant,
+                    ref_sequence: &[u8],
+                    codon_table: &CodonTable) -> Vec<VariantAnnotation> {
+        let pos = pos as usize;
+        let changed = changed_bases(pos, variant, ref_sequence.len());
+        let indices = self.overlapping_indices(contig, changed.start as u64, changed.end as u64);
+        if indices.len() > 0 {
+            return indices.into_iter().map(|idx| {
+                let segments = &self.genes[contig][idx];
+                let coding = &self.coding[contig][idx];
+                if coding.positions.iter().any(|coding_pos| *coding_pos >= ref_sequence.len()) {
+                    // Gene runs past the end of the contig
+                    annotate_coding_variant(segments, pos, variant, ref_sequence, codon_table)
+                } else {
+                    annotate_coding_sequence(
+                        &segments[0], coding, pos, variant, ref_sequence, codon_table)
+                }
+            }).collect()
+        }
+        match self.upstream_of(contig, pos as u64) {
+            Some(gene) => {
+                let mut annotation = VariantAnnotation::intergenic();
+                annotation.gene_id = Some(gene_id(gene));
+                annotation.consequence = Consequence::Upstream;
+                vec![annotation]
+            },
+            None => vec![VariantAnnotation::intergenic()],
+        }
+    }
+}
+
+/// Determine the codon change caused by a variant falling inside a gene, given as its segments
+/// in transcription order
+pub fn annotate_coding_variant<R: Borrow<Record>>(segments: &[R],
+                                                  pos: usize,
+                                                  variant: &Variant,
+                                                  ref_sequence: &[u8],
+                                                  codon_table: &CodonTable) -> VariantAnnotation {
+    let coding = CodingSequence::new(segments, ref_sequence.len());
+    annotate_coding_sequence(segments[0].borrow(), &coding, pos, variant, ref_sequence, codon_table)
+}
+
+/// Determine the codon change caused by a variant falling inside the coding sequence of a
+/// gene, given by the segment holding its start codon
+fn annotate_coding_sequence(gene: &Record,
+                            coding: &CodingSequence,
+                            pos: usize,
+                            variant: &Variant,
+                            ref_sequence: &[u8],
+                            codon_table: &CodonTable) -> VariantAnnotation {
+    let reverse = is_reverse(gene);
+    let positions = &coding.positions;
+
+    let mut annotation = VariantAnnotation::intergenic();
+    annotation.gene_id = Some(gene_id(gene));
+
+    // Bases replaced by the variant, in genome orientation
+    let alt: Vec<u8> = match variant {
+        Variant::SNV(alt) => vec![*alt],
+        Variant::MNV(alt) => alt.clone(),
+        Variant::Insertion(_) | Variant::Deletion(_) => vec![],
+        _ => vec![ref_sequence[pos]],
+    };
+
+    // Position in the coding sequence of each changed base that is coding, along with the
+    // base replacing it on the coding strand
+    let mut changes: Vec<(usize, u8)> = Vec::new();
+    for changed_pos in changed_bases(pos, variant, ref_sequence.len()) {
+        if let Some(coding_idx) = coding.indices.get(&changed_pos) {
+            let base = match alt.get(changed_pos - pos) {
+                Some(base) => *base,
+                None => ref_sequence[changed_pos],
+            };
+            let base = if reverse { dna::complement(base) } else { base };
+            changes.push((*coding_idx, base));
+        }
+    }
+    if changes.len() == 0 {
+        // Variant sits before the gene, in an intron or in the partial codon before the
+        // reading frame starts
+        annotation.consequence = Consequence::Upstream;
+        return annotation
+    }
+
+    let first_codon = changes.iter().map(|(coding_idx, _)| *coding_idx).min().unwrap() / 3;
+    let last_codon = changes.iter().map(|(coding_idx, _)| *coding_idx).max().unwrap() / 3;
+    let codon_start = first_codon * 3;
+    let codon_end = std::cmp::min((last_codon + 1) * 3, positions.len());
+    annotation.codon_position = Some(first_codon + 1);
+    if codon_end <= codon_start || codon_end - codon_start < 3 {
+        // Gene is truncated at the contig edge
+        annotation.consequence = match variant {
+            Variant::Insertion(_) | Variant::Deletion(_) => Consequence::Frameshift,
+            _ => Consequence::Synonymous,
+        };
+        return annotation
+    }
+    let ref_codons: Vec<u8> = positions[codon_start..codon_end].iter()
+        .map(|coding_pos| coding_base(ref_sequence, *coding_pos, reverse))
+        .collect();
+    annotation.ref_codon = Some(String::from_utf8_lossy(&ref_codons).to_string());
+    let ref_aa = translate_codons(&ref_codons, codon_table);
+    annotation.ref_aa = Some(ref_aa.clone());
+
+    match variant {
+        Variant::Insertion(_) | Variant::Deletion(_) => {
+            annotation.consequence = if variant.len() % 3 == 0 {
+                Consequence::InframeIndel
+            } else {
+                Consequence::Frameshift
+            };
+        },
+        _ => {
+            let mut alt_codons = ref_codons.clone();
+            for (coding_idx, base) in changes.iter() {
+                if *coding_idx - codon_start < alt_codons.len() {
+                    alt_codons[*coding_idx - codon_start] = *base;
+                }
+            }
+            let alt_aa = translate_codons(&alt_codons, codon_table);
+            annotation.alt_codon = Some(String::from_utf8_lossy(&alt_codons).to_string());
+            annotation.alt_aa = Some(alt_aa.clone());
+
+            annotation.consequence = if first_codon == 0
+                && codon_table.is_start(&ref_codons[..3])
+                && !codon_table.is_start(&alt_codons[..3]) {
+                Consequence::StartLost
+            } else if ref_aa.contains('*') && !alt_aa.contains('*') {
+                Consequence::StopLost
+            } else if alt_aa.contains('*') && !ref_aa.contains('*') {
+                Consequence::Nonsense
+            } else if ref_aa == alt_aa {
+                Consequence::Synonymous
+            } else {
+                Consequence::Missense
+            };
+        }
+    }
+    annotation
+}
+
+/// Translate a run of complete codons into a peptide string
+fn translate_codons(codons: &[u8], codon_table: &CodonTable) -> String {
+    codons.chunks(3)
+        .filter(|codon| codon.len() == 3)
+        .map(|codon| codon_table.translate(codon))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bio::io::gff;
+    use bio_types::strand::Strand;
+
+    fn gene(start: u64, end: u64, strand: &str) -> Record {
+        let mut record = gff::Record::new();
+        *record.seqname_mut() = "contig".to_string();
+        *record.feature_type_mut() = "CDS".to_string();
+        *record.start_mut() = start;
+        *record.end_mut() = end;
+        *record.frame_mut() = "0".to_string();
+        *record.strand_mut() = strand.to_string();
+        record.attributes_mut().insert("ID".to_string(), "gene_1".to_string());
+        record
+    }
+
+    fn index(records: Vec<Record>) -> GeneIndex {
+        let mut gff_map = HashMap::new();
+        gff_map.insert("contig".to_string(), records);
+        GeneIndex::new(&gff_map)
+    }
+
+    #[test]
+    fn test_forward_consequences() {
+        let mut codon_table = CodonTable::setup();
+        codon_table.get_codon_table(11);
+        // ATG AAA CCC GGG TTT TAA
+        let reference = b"ATGAAACCCGGGTTTTAA".to_vec();
+        let genes = index(vec![gene(1, 18, "+")]);
+
+        let annotation = &genes.annotate("contig", 5, &Variant::SNV(b'G'), &reference, &codon_table)[0];
+        assert_eq!(annotation.consequence, Consequence::Synonymous);
+        assert_eq!(annotation.codon_position, Some(2));
+
+        let annotation = &genes.annotate("contig", 3, &Variant::SNV(b'T'), &reference, &codon_table)[0];
+        assert_eq!(annotation.consequence, Consequence::Nonsense);
+        assert_eq!(annotation.alt_codon, Some("TAA".to_string()));
+
+        let annotation = &genes.annotate("contig", 6, &Variant::SNV(b'G'), &reference, &codon_table)[0];
+        assert_eq!(annotation.consequence, Consequence::Missense);
+        assert_eq!(annotation.alt_aa, Some("A".to_string()));
+
+        let annotation = &genes.annotate("contig", 1, &Variant::SNV(b'C'), &reference, &codon_table)[0];
+        assert_eq!(annotation.consequence, Consequence::StartLost);
+
+        let annotation = &genes.annotate("contig", 16, &Variant::SNV(b'T'), &reference, &codon_table)[0];
+        assert_eq!(annotation.consequence, Consequence::StopLost);
+
+        // The deleted bases follow the anchor base, so this deletes the last base of the third
+        // codon and the first of the fourth
+        let annotation = &genes.annotate("contig", 7, &Variant::Deletion(2), &reference, &codon_table)[0];
+        assert_eq!(annotation.consequence, Consequence::Frameshift);
+        assert_eq!(annotation.codon_position, Some(3));
+
+        let annotation = &genes.annotate("contig", 7, &Variant::Insertion(b"GGG".to_vec()),
+                                        &reference, &codon_table)[0];
+        assert_eq!(annotation.consequence, Consequence::InframeIndel);
+    }
+
+    #[test]
+    fn test_reverse_strand_and_intergenic() {
+        let mut codon_table = CodonTable::setup();
+        codon_table.get_codon_table(11);
+        // Reverse complement of ATG AAA CCC GGG TTT TAA, preceded by 20 intergenic bases
+        let mut reference = b"CCCCCCCCCCCCCCCCCCCC".to_vec();
+        reference.extend(dna::revcomp(b"ATGAAACCCGGGTTTTAA".to_vec()));
+        let genes = index(vec![gene(21, 38, "-")]);
+        assert_eq!(genes.overlapping("contig", 20, 21)[0][0].strand(), Some(Strand::Reverse));
+
+        // Middle base of the start codon on the reverse strand, ATG -> ACG
+        let annotation = &genes.annotate("contig", 36, &Variant::SNV(b'G'), &reference, &codon_table)[0];
+        assert_eq!(annotation.consequence, Consequence::StartLost);
+        assert_eq!(annotation.codon_position, Some(1));
+
+        let annotation = &genes.annotate("contig", 2, &Variant::SNV(b'T'), &reference, &codon_table)[0];
+        assert_eq!(annotation.consequence, Consequence::Intergenic);
+        assert_eq!(annotation.gene_id, None);
+    }
+
+    #[test]
+    fn test_upstream() {
+        let mut codon_table = CodonTable::setup();
+        codon_table.get_codon_table(11);
+        let mut reference = vec![b'C'; 50];
+        reference.extend(b"ATGAAACCCGGGTTTTAA".iter());
+        let genes = index(vec![gene(51, 68, "+")]);
+
+        let annotation = &genes.annotate("contig", 10, &Variant::SNV(b'T'), &reference, &codon_table)[0];
+        assert_eq!(annotation.consequence, Consequence::Upstream);
+        assert_eq!(annotation.gene_id, Some("gene_1".to_string()));
+
+        // Anchored on the last intergenic base, deleting the start codon
+        let annotation = &genes.annotate("contig", 49, &Variant::Deletion(3), &reference, &codon_table)[0];
+        assert_eq!(annotation.consequence, Consequence::InframeIndel);
+        assert_eq!(annotation.codon_position, Some(1));
+    }
+
+    #[test]
+    fn test_overlapping_genes() {
+        let mut codon_table = CodonTable::setup();
+        codon_table.get_codon_table(11);
+        // ATG AAA CCC GGG TTT TAA, with a second gene starting at its second codon
+        let reference = b"ATGAAACCCGGGTTTTAA".to_vec();
+        let mut second = gene(4, 18, "+");
+        second.attributes_mut().remove("ID");
+        second.attributes_mut().insert("ID".to_string(), "gene_2".to_string());
+        let genes = index(vec![gene(1, 18, "+"), second]);
+
+        let annotations = genes.annotate("contig", 6, &Variant::SNV(b'G'), &reference, &codon_table);
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0].gene_id, Some("gene_1".to_string()));
+        assert_eq!(annotations[0].codon_position, Some(3));
+        assert_eq!(annotations[1].gene_id, Some("gene_2".to_string()));
+        assert_eq!(annotations[1].codon_position, Some(2));
+
+        let annotations = genes.annotate("contig", 1, &Variant::SNV(b'C'), &reference, &codon_table);
+        assert_eq!(annotations.len(), 1);
+        assert_eq!(annotations[0].consequence, Consequence::StartLost);
+    }
+
+    #[test]
+    fn test_spliced_genes() {
+        let mut codon_table = CodonTable::setup();
+        codon_table.get_codon_table(11);
+        // ATG AAA C|CC GGG TTT TAA split by a five base intron inside the third codon
+        let reference = b"ATGAAACTTTTTCCGGGTTTTAA".to_vec();
+        let genes = index(vec![gene(1, 7, "+"), gene(13, 23, "+")]);
+        assert_eq!(genes.overlapping("contig", 0, 23).len(), 1);
+
+        let annotation = &genes.annotate("contig", 12, &Variant::SNV(b'T'), &reference, &codon_table)[0];
+        assert_eq!(annotation.codon_position, Some(3));
+        assert_eq!(annotation.ref_codon, Some("CCC".to_string()));
+        assert_eq!(annotation.alt_codon, Some("CTC".to_string()));
+        assert_eq!(annotation.consequence, Consequence::Missense);
+
+        let annotation = &genes.annotate("contig", 9, &Variant::SNV(b'A'), &reference, &codon_table)[0];
+        assert_eq!(annotation.consequence, Consequence::Intergenic);
+
+        // The same gene on the reverse strand, with its segments in transcription order
+        let reverse = dna::revcomp(&reference);
+        let genes = index(vec![gene(17, 23, "-"), gene(1, 11, "-")]);
+        let annotation = &genes.annotate("contig", 10, &Variant::SNV(b'A'), &reverse, &codon_table)[0];
+        assert_eq!(annotation.codon_position, Some(3));
+        assert_eq!(annotation.alt_codon, Some("CTC".to_string()));
+        assert_eq!(coding_positions(&[gene(17, 23, "-"), gene(1, 11, "-")], reverse.len())[..8],
+                   [22, 21, 20, 19, 18, 17, 16, 10]);
+    }
+
+    #[test]
+    fn test_gene_ids() {
+        let mut predicted = gene(1, 9, "+");
+        *predicted.source_mut() = PREDICTED_GENE_SOURCE.to_string();
+        predicted.attributes_mut().clear();
+        predicted.attributes_mut().insert("ID".to_string(), "2_1".to_string());
+        assert_eq!(gene_id(&predicted), "2_1");
+
+        let mut unnamed = gene(1, 9, "+");
+        unnamed.attributes_mut().clear();
+        assert_eq!(gene_id(&unnamed), "contig:1-9");
+    }
+}
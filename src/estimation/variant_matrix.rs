@@ -1,5 +1,20 @@
 use std::collections::{HashMap, HashSet, BTreeMap, BTreeSet};
 use estimation::contig_variants::*;
+use estimation::codon_structs::*;
+use estimation::variant_annotation::*;
+use estimation::diversity::*;
+use estimation::differentiation::*;
+use estimation::linkage::*;
+use estimation::phylogeny;
+use estimation::trajectory::*;
+use estimation::differential_frequency::*;
+use estimation::frequency_spectrum::*;
+use estimation::report::*;
+use estimation::html_report;
+use estimation::liftover::*;
+use estimation::polish::*;
+use estimation::variation_graph::VariationGraph;
+use estimation::strain_assignment::StrainSites;
 use model::variants::*;
 use std::str;
 use std::path::Path;
@@ -7,6 +22,9 @@ use std::io::prelude::*;
 use rayon::prelude::*;
 use std::sync::{Arc, Mutex};
 use std::fs::File;
+use bio::io::gff::Record;
+use bio_types::strand;
+use serde_json::Value;
 use dbscan::fuzzy;
 use kodama::{Method, linkage};
 use itertools::{Itertools};
@@ -21,9 +39,6 @@ pub enum VariantMatrix {
         variances: HashMap<i32, Vec<f64>>,
         // TID, Position, Base, Var Depth, Total Depth
         all_variants: HashMap<i32, HashMap<i64, HashMap<Variant, Base>>>,
-        // Placeholder hashmap for the depths of each contig for a sample
-        // Deleted after use
-        depths: HashMap<i32, Vec<i32>>,
         contigs: HashMap<i32, Vec<u8>>,
         target_names: HashMap<i32, String>,
         target_lengths: HashMap<i32, f64>,
@@ -38,6 +53,12 @@ pub enum VariantMatrix {
         geom_mean_dep: Vec<f64>,
         geom_mean_frq: Vec<f64>,
         pred_variants: HashMap<usize, HashMap<i32, HashMap<i64, HashMap<fuzzy::Category, HashSet<Variant>>>>>,
+        // TID, Position, Variant, one annotation per overlapping gene
+        annotations: HashMap<i32, HashMap<i64, HashMap<Variant, Vec<VariantAnnotation>>>>,
+        // TID, Sample, Position depth
+        sample_depths: HashMap<i32, Vec<Vec<i32>>>,
+        // Gene ID, Sample, dN/dS
+        gene_dnds: HashMap<String, HashMap<usize, f64>>,
 //        pred_variants_all: HashMap<usize, HashMap<i32, HashMap<i32, HashSet<String>>>>,
     }
 }
@@ -49,7 +70,6 @@ impl VariantMatrix {
             variances: HashMap::new(),
             average_genotypes: HashMap::new(),
             all_variants: HashMap::new(),
-            depths: HashMap::new(),
             contigs: HashMap::new(),
             target_names: HashMap::new(),
             target_lengths: HashMap::new(),
@@ -64,10 +84,26 @@ impl VariantMatrix {
             geom_mean_dep: Vec::new(),
             geom_mean_frq: Vec::new(),
             pred_variants: HashMap::new(),
+            annotations: HashMap::new(),
+            sample_depths: HashMap::new(),
+            gene_dnds: HashMap::new(),
         }
     }
 }
 
+/// Sequences of one strain, made by applying the strain's variants to each reference contig
+#[derive(Debug, Clone)]
+pub struct StrainGenome {
+    pub strain: usize,
+    /// Name and sequence of each contig, in the order of the reference
+    pub contigs: Vec<(String, Vec<u8>)>,
+    /// Edits made to each contig, by tid, in the same order as contigs
+    pub edits: Vec<(i32, Vec<SequenceEdit>)>,
+    pub liftovers: Vec<Liftover>,
+    /// Genes lifted over from the reference, empty when no genes were given
+    pub genes: Vec<Record>,
+}
+
 pub trait VariantMatrixFunctions {
     fn setup(&mut self);
 
@@ -92,9 +128,23 @@ pub trait VariantMatrixFunctions {
     /// Perform fuzzy DBSCAN clustering using proportionality
     fn run_fuzzy_scan(&mut self, e_min: f64, e_max: f64, pts_min: f64, pts_max: f64, phi: f64);
 
-    /// Takes clusters from DBSCAN and linkage method and writes variants to file as genotype
+    /// Applies the variants of each strain found by DBSCAN and linkage clustering to the
+    /// reference, lifting genes over onto each strain when genes are given
+    fn strain_genomes(&self,
+                      gff_map: &HashMap<String, Vec<Record>>,
+                      codon_table: &CodonTable) -> Vec<StrainGenome>;
+
+    /// Takes clusters from DBSCAN and linkage method and writes variants to file as genotype.
+    /// Each strain's coordinates are mapped from the reference by {output_prefix}_strain_N.chain
+    /// and, when genes are given, its genes are lifted to {output_prefix}_strain_N.gff
     fn generate_genotypes(&mut self,
-                          output_prefix: &str);
+                          output_prefix: &str,
+                          gff_map: &HashMap<String, Vec<Record>>,
+                          codon_table: &CodonTable);
+
+    /// Allele of each strain at every site carrying a strain variant, using the same variant
+    /// at each site as the strain genomes
+    fn strain_sites(&self) -> StrainSites;
 
     /// Connects fuzzy DBSCAN clusters based on shared read information
     fn linkage_clustering(clusters: &Vec<Vec<fuzzy::Assignment>>,
@@ -113,6 +163,84 @@ pub trait VariantMatrixFunctions {
 
     fn print_variant_stats(&self, output_prefix: &str);
 
+    /// Variant statistics of each sample on each contig, ordered by contig
+    fn contig_reports(&self) -> Vec<ContigReport>;
+
+    /// Annotate every variant with its consequence on the overlapping gene
+    fn annotate_variants(&mut self, gene_index: &GeneIndex, codon_table: &CodonTable);
+
+    /// Prints each variant observed in each sample to stdout, along with the allele
+    /// frequency mode it belongs to in that sample
+    fn print_variants(&self, max_components: usize);
+
+    /// Writes the variants and their annotations that make up each strain
+    fn print_strain_variants(&self, output_prefix: &str);
+
+    /// Writes nucleotide diversity, Watterson's theta and Tajima's D for each sample
+    /// across each contig, gene and window
+    fn print_diversity(&self, output_prefix: &str,
+                       gff_map: &HashMap<String, Vec<Record>>,
+                       parameters: &DiversityParameters);
+
+    /// Writes conANI, popANI and Hudson's FST between each pair of samples for each contig
+    /// and across the genome, using only sites with at least min_depth reads in both samples
+    fn print_pairwise_differentiation(&self, output_prefix: &str, min_depth: i32);
+
+    /// Writes r² and D′ between pairs of SNVs observed on the same reads, along with
+    /// the decay of linkage with distance along each contig
+    fn print_linkage(&self, output_prefix: &str, parameters: &LinkageParameters);
+
+    /// Writes an alignment of variable SNV sites containing the majority allele of each
+    /// sample and the allele of each strain, along with SNP distances between them and
+    /// a neighbour-joining tree. Sample alleles below min_depth are masked
+    fn print_snp_alignment(&self, output_prefix: &str, min_depth: i32);
+
+    /// Writes the frequency trajectory of each variant through the time points of each
+    /// subject, classifying them as sweeping, fixed, lost or stable, along with genes
+    /// containing clustered sweeps and a summary of each subject
+    fn print_trajectories(&self, output_prefix: &str,
+                          metadata: &HashMap<String, SampleMetadata>,
+                          parameters: &TrajectoryParameters);
+
+    /// Tests each variant for a difference in allele frequency between each pair of sample
+    /// groups with a Cochran-Mantel-Haenszel test across strata, using samples with at least
+    /// min_depth reads at the site. q-values are Benjamini-Hochberg corrected per comparison
+    fn print_differential_frequencies(&self, output_prefix: &str,
+                                      groups: &HashMap<String, SampleGroup>,
+                                      min_depth: i32);
+
+    /// Stores the population dN/dS of each gene calculated from a sample
+    fn add_gene_dnds(&mut self, sample_idx: usize, dnds: Vec<(String, f64)>);
+
+    /// Merges samples sharing a name, such as the short and long read libraries of a sample
+    /// in a sample sheet, into one sample placed where the first of them was. Coverages and
+    /// depths are summed and the dN/dS of each gene averaged over the merged samples
+    fn merge_samples_by_name(&mut self);
+
+    /// Contig statistics, genes and strains of a run along with its parameters
+    fn report(&self, mode: &str, parameters: &BTreeMap<String, Value>,
+              gff_map: &HashMap<String, Vec<Record>>) -> LorikeetReport;
+
+    /// Writes a self-contained HTML report to {output_prefix}_report.html with sample QC,
+    /// variant density, allele frequency spectra, dN/dS, clustering and strain abundances
+    fn write_html_report(&self, output_prefix: &str, mode: &str);
+
+    /// Writes the allele frequency histogram and the unfolded and folded site frequency
+    /// spectra of each sample on each contig and across the genome, along with the
+    /// frequency modes of each sample and the number of strains they suggest
+    fn print_frequency_spectra(&self, output_prefix: &str, parameters: &SpectrumParameters);
+
+    /// Gaussian mixture of the allele frequencies of each sample, with up to max_components
+    /// modes chosen by BIC
+    fn frequency_mixtures(&self, max_components: usize) -> Vec<Option<GaussianMixture>>;
+
+    /// Polishes each contig with the alleles passing the thresholds in parameters, pooling
+    /// reads across samples of the same technology. Samples from index short_samples onwards
+    /// are long read samples. Writes the polished contigs to {output_prefix}_polished.fna,
+    /// each edit to {output_prefix}_polish_edits.vcf and the mapping of reference coordinates
+    /// onto the polished contigs to {output_prefix}_polished.chain, returning the number of edits
+    fn polish_genomes(&self, output_prefix: &str, parameters: &PolishParameters,
+                      short_samples: usize) -> usize;
 
 }
 
@@ -148,12 +276,14 @@ impl VariantMatrixFunctions for VariantMatrix {
             VariantMatrix::VariantContigMatrix {
                 ref mut sample_names,
                 ref mut all_variants,
-                ref mut depths,
+                ref sample_depths,
                 ..
             } => {
                 sample_names[sample_idx] = sample_name;
 
-                for (tid, depth) in depths.iter() {
+                for (tid, depths) in sample_depths.iter() {
+                    let depth = &depths[sample_idx];
+
                     // Initialize contig id in variant hashmap
                     let contig_variants = all_variants.entry(*tid)
                         .or_insert(HashMap::new());
@@ -181,7 +311,6 @@ impl VariantMatrixFunctions for VariantMatrix {
                         }
                     }
                 }
-                *depths = HashMap::new();
             }
         }
     }
@@ -218,7 +347,7 @@ impl VariantMatrixFunctions for VariantMatrix {
                 ref mut target_names,
                 ref mut target_lengths,
                 ref mut variances,
-                ref mut depths,
+                ref mut sample_depths,
                 ..
             } => {
                 match variant_stats {
@@ -247,8 +376,9 @@ impl VariantMatrixFunctions for VariantMatrix {
                         target_names.entry(tid)
                             .or_insert(str::from_utf8(&target_name).unwrap().to_string());
                         target_lengths.entry(tid).or_insert(target_len);
-                        // copy across depths
-                        depths.entry(tid).or_insert(depth);
+                        // Keep this sample's depths for the diversity statistics
+                        sample_depths.entry(tid)
+                            .or_insert(vec![Vec::new(); sample_count])[sample_idx] = depth;
                         contigs.entry(tid).or_insert(contig);
                         
                     }
@@ -547,138 +677,156 @@ impl VariantMatrixFunctions for VariantMatrix {
         }
     }
 
-    fn generate_genotypes(&mut self, output_prefix: &str) {
+    fn strain_genomes(&self,
+                      gff_map: &HashMap<String, Vec<Record>>,
+                      codon_table: &CodonTable) -> Vec<StrainGenome> {
         match self {
             VariantMatrix::VariantContigMatrix {
                 target_names,
                 contigs,
-                ref mut pred_variants,
+                pred_variants,
                 ..
             } => {
 
-                pred_variants.par_iter().for_each(|(strain_index, genotype)|{
-                    let file_name = format!("{}_strain_{}.fna", output_prefix.to_string(), strain_index);
+                let mut strain_genomes: Vec<StrainGenome> = pred_variants
+                    .par_iter().map(|(strain_index, genotype)|{
                     debug!("Genotype {:?}", genotype);
 
+                    let mut multivariant_sites = 0;
+                    let mut tot_variations = 0;
+                    let mut strain_genome = StrainGenome {
+                        strain: *strain_index,
+                        contigs: Vec::new(),
+                        edits: Vec::new(),
+                        liftovers: Vec::new(),
+                        genes: Vec::new(),
+                    };
+
+                    // Generate the variant genome
+                    for tid in contigs.keys().sorted() {
+                        let original_contig = &contigs[tid];
+                        let mut variants = Vec::new();
+                        if let Some(tid_genotype) = genotype.get(tid) {
+                            for (pos, categories) in tid_genotype.iter() {
+                                let hash = strain_variants(categories)
+                                    .cloned().unwrap_or(HashSet::new());
+                                if hash.len() > 1 {
+                                    multivariant_sites += 1;
+                                    debug!("Multi hash {:?}", hash)
+                                }
+                                if let Some(var) = hash.into_iter().next() {
+                                    variants.push((*pos as usize, var));
+                                }
+                            }
+                        }
+                        let (contig, edits) = apply_variants(original_contig, variants);
+                        tot_variations += edits.len();
+
+                        let contig_name = &target_names[tid];
+                        let strain_name = format!("{}_strain_{}", contig_name, strain_index);
+                        let liftover = Liftover::from_edits(
+                            contig_name, original_contig.len(), &strain_name, &edits);
+                        if let Some(genes) = gff_map.get(contig_name) {
+                            strain_genome.genes.extend(lift_genes(
+                                genes, &liftover, original_contig, &contig, codon_table));
+                        }
+                        strain_genome.contigs.push((strain_name, contig));
+                        strain_genome.edits.push((*tid, edits));
+                        strain_genome.liftovers.push(liftover);
+                    }
+                    debug!("{} Multivariant sites and single variant sites {} for Strain {}",
+                          multivariant_sites, tot_variations, strain_index);
+                    strain_genome
+                }).collect();
+                strain_genomes.sort_by_key(|strain_genome| strain_genome.strain);
+                strain_genomes
+            }
+        }
+    }
+
+    fn generate_genotypes(&mut self, output_prefix: &str,
+                          gff_map: &HashMap<String, Vec<Record>>,
+                          codon_table: &CodonTable) {
+        let strain_genomes = self.strain_genomes(gff_map, codon_table);
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                target_names,
+                contigs,
+                ..
+            } => {
+
+                strain_genomes.par_iter().for_each(|strain_genome| {
+                    let strain_index = strain_genome.strain;
+                    let file_name = format!("{}_strain_{}.fna", output_prefix.to_string(), strain_index);
                     let file_path = Path::new(&file_name);
 
                     // Open haplotype file or create one
                     let mut file_open = File::create(file_path)
                         .expect("No Read or Write Permission in current directory");
 
-                    let mut genotype = genotype.clone();
-                    let mut multivariant_sites = 0;
-                    let mut tot_variations = 0;
-
-                    // Generate the variant genome
-                    for (tid, original_contig) in contigs.iter() {
-                        let mut contig = String::new();
+                    for ((strain_name, contig), (_tid, edits)) in strain_genome.contigs.iter()
+                        .zip(strain_genome.edits.iter()) {
+                        writeln!(file_open, ">{}\t#variants_{}",
+                                 strain_name,
+                                 edits.len()).expect("Unable to write to file");
 
-                        let mut skip_n = 0;
-                        let mut skip_cnt = 0;
-//                            let char_cnt = 0;
-                        let mut variations = 0;
-
-                        for (pos, base) in original_contig.iter().enumerate() {
-                            if skip_cnt < skip_n {
-                                skip_cnt += 1;
-                            } else {
-                                skip_n = 0;
-                                skip_cnt = 0;
-                                if genotype.contains_key(&tid) {
-                                    let tid_genotype = genotype.get_mut(&tid).unwrap();
-
-                                    if tid_genotype.contains_key(&(pos as i64)) {
-                                        let categories = &genotype[tid][&(pos as i64)];
-                                        let mut hash = HashSet::new();
-                                        if categories.contains_key(&fuzzy::Category::Core) {
-                                            hash = categories[&fuzzy::Category::Core].clone();
-                                        } else if categories.contains_key(&fuzzy::Category::Border) {
-                                            hash = categories[&fuzzy::Category::Border].clone();
-                                        } else {
-                                            hash = categories[&fuzzy::Category::Noise].clone();
-                                        }
+                        for line in contig.chunks(60) {
+                            file_open.write_all(line).expect("Unable to write to file");
+                            file_open.write_all(b"\n").expect("Unable to write to file");
+                        }
+                    }
+                    write_chain_file(&format!("{}_strain_{}.chain", output_prefix, strain_index),
+                                     &strain_genome.liftovers);
+                    if !gff_map.is_empty() {
+                        write_gff(&format!("{}_strain_{}.gff", output_prefix, strain_index),
+                                  &strain_genome.genes);
+                    }
+                });
 
-                                        let mut max_var = Variant::None;
+                // Graph of the reference with each strain as a path through it
+                let mut graph = VariationGraph::new();
+                for tid in contigs.keys().sorted() {
+                    let contig_name = &target_names[tid];
+                    let haplotypes: Vec<(String, Vec<SequenceEdit>)> = strain_genomes.iter()
+                        .filter_map(|strain_genome| {
+                            strain_genome.edits.iter().find(|(edit_tid, _)| edit_tid == tid)
+                                .map(|(_, edits)| (
+                                    format!("{}_strain_{}", contig_name, strain_genome.strain),
+                                    edits.clone()))
+                        }).collect();
+                    graph.add_contig(contig_name, &contigs[tid], &haplotypes);
+                }
+                graph.write_gfa(&format!("{}_variation_graph.gfa", output_prefix));
+            }
+        }
+    }
 
-                                        for var in hash.iter() {
-                                            // If there are two variants possible for
-                                            // a single site and one is the reference
-                                            // we will choose the reference
-                                            if max_var == Variant::None {
-                                                max_var = var.clone();
-                                            }
-                                        }
-                                        if hash.len() > 1 {
-                                            multivariant_sites += 1;
-                                            debug!("Multi hash {:?} {:?}", hash, max_var)
-                                        }
-                                        match max_var {
-                                            Variant::Deletion(size) => {
-                                                // Skip the next n bases but rescue the reference prefix
-                                                debug!("DEL {:?}", size);
-
-                                                skip_n = size - 1;
-                                                skip_cnt = 0;
-//                                                let first_byte = max_var.as_bytes()[0];
-//                                                contig = contig + str::from_utf8(
-//                                                    &[first_byte]).unwrap();
-                                                variations += 1;
-                                            },
-                                            Variant::Insertion(alt) => {
-                                                debug!("INS {:?}", alt);
-
-                                                // Insertions have a reference prefix that needs to be removed
-                                                let removed_first_base = str::from_utf8(
-                                                    &alt[1..]).unwrap();
-                                                contig = contig + removed_first_base;
-                                                variations += 1;
-                                            },
-                                            Variant::None => {
-                                                contig = contig + str::from_utf8(&[*base]).unwrap();
-                                            },
-                                            Variant::MNV(alt) => {
-                                                debug!("MNV {:?}", alt);
-                                                skip_n = alt.len() as u32 - 1;
-                                                skip_cnt = 0;
-                                                let removed_first_base = str::from_utf8(
-                                                    &alt[1..]).unwrap();
-                                                contig = contig + removed_first_base;
-                                                variations += 1;
-                                            },
-                                            Variant::SNV(alt) => {
-                                                debug!("SNV {:?}", alt);
-
-                                                contig = contig + str::from_utf8(&[alt]).unwrap();
-                                                variations += 1;
-                                            },
-                                            _ => {
-                                                contig = contig + str::from_utf8(&[*base]).unwrap();
-                                            }
-                                        }
-                                    } else {
-                                        contig = contig + str::from_utf8(&[*base]).unwrap();
-                                    }
-                                } else {
-                                    contig = str::from_utf8(&original_contig)
-                                        .expect("Can't convert to str").to_string();
-                                }
+    fn strain_sites(&self) -> StrainSites {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                contigs,
+                pred_variants,
+                ..
+            } => {
+                let strains: Vec<usize> = pred_variants.keys().cloned().sorted().collect();
+                let mut sites: HashMap<i32, BTreeMap<i64, Vec<Variant>>> = HashMap::new();
+                for (strain_idx, strain_index) in strains.iter().enumerate() {
+                    for (tid, tid_genotype) in pred_variants[strain_index].iter() {
+                        for (pos, categories) in tid_genotype.iter() {
+                            if let Some(var) = strain_variants(categories)
+                                .and_then(|hash| hash.iter().next()) {
+                                let alleles = sites.entry(*tid).or_default()
+                                    .entry(*pos).or_insert(vec![Variant::None; strains.len()]);
+                                alleles[strain_idx] = var.clone();
                             }
-                        };
-                        writeln!(file_open, ">{}_strain_{}\t#variants_{}",
-                                 target_names[tid],
-                                 strain_index,
-                                 variations).expect("Unable to write to file");
-
-                        for line in contig.as_bytes().to_vec()[..].chunks(60).into_iter() {
-                            file_open.write(line).unwrap();
-                            file_open.write(b"\n").unwrap();
-                        };
-                        tot_variations += variations;
+                        }
                     }
-                    debug!("{} Multivariant sites and single variant sites {} for Strain {}",
-                          multivariant_sites, tot_variations, strain_index);
-                });
+                }
+                StrainSites {
+                    strains,
+                    sites,
+                    references: contigs.clone(),
+                }
             }
         }
     }
@@ -889,11 +1037,8 @@ impl VariantMatrixFunctions for VariantMatrix {
     fn print_variant_stats(&self, output_prefix: &str) {
         match self {
             VariantMatrix::VariantContigMatrix {
-                target_names,
-                target_lengths,
                 sample_names,
-                variant_counts,
-                variant_sums,
+                annotations,
                 ..
             } => {
                 let file_name = output_prefix.to_string()
@@ -914,15 +1059,72 @@ impl VariantMatrixFunctions for VariantMatrix {
                            &sample_name, &sample_name, &sample_name,
                            &sample_name, &sample_name, &sample_name).unwrap();
                 }
+                // Consequence counts are only reported when a GFF was provided
+                let consequences = Consequence::all();
+                if annotations.len() > 0 {
+                    for consequence in consequences.iter() {
+                        write!(file_open, "\t{}", consequence).unwrap();
+                    }
+                }
                 write!(file_open, "\n").unwrap();
-                for (tid, contig_name) in target_names.iter() {
-                    let contig_len =  target_lengths[tid];
-                    write!(file_open, "{}\t{}", contig_name, contig_len).unwrap();
-                    for (sample_idx, _sample_name) in sample_names.iter().enumerate() {
-                        let ten_kbs = contig_len / 10000.;
-                        let total_variants = variant_counts[&sample_idx][tid] as f64;
-                        if total_variants > 0. {
-                            let var_ten_kbs = total_variants / ten_kbs;
+                for contig in self.contig_reports() {
+                    write!(file_open, "{}\t{}", contig.name, contig.length).unwrap();
+                    for stats in contig.samples.iter() {
+                        if stats.variants > 0 {
+                            write!(file_open,
+                                     "\t{:.3}\t{}\t{:.3}\t{:.3}\t{:.3}\t{:.3}",
+                                     stats.subs_per_10kb, stats.variants,
+                                     stats.mean_ref_abundance, stats.ref_std_dev,
+                                     stats.mean_var_abundance, stats.var_std_dev).unwrap();
+                        } else {
+                            write!(file_open,
+                                     "\t{}\t{}\t{}\t{}\t{}\t{}",
+                                     0., 0., 0., 0., 0., 0.,).unwrap();
+                        }
+                    }
+                    if let Some(counts) = contig.consequences {
+                        for consequence in consequences.iter() {
+                            write!(file_open, "\t{}", counts[&consequence.to_string()]).unwrap();
+                        }
+                    }
+                    write!(file_open, "\n").unwrap();
+                }
+            }
+        }
+    }
+
+    fn contig_reports(&self) -> Vec<ContigReport> {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                target_names,
+                target_lengths,
+                sample_names,
+                variant_counts,
+                variant_sums,
+                annotations,
+                ..
+            } => {
+                let mut reports = Vec::new();
+                for tid in target_names.keys().sorted() {
+                    let contig_len = target_lengths[tid];
+                    let ten_kbs = contig_len / 10000.;
+                    let mut samples = Vec::new();
+                    for (sample_idx, sample_name) in sample_names.iter().enumerate() {
+                        let total_variants = match variant_counts.get(&sample_idx)
+                            .and_then(|counts| counts.get(tid)) {
+                            Some(count) => *count,
+                            None => 0,
+                        };
+                        let mut stats = SampleContigStats {
+                            sample: sample_name.clone(),
+                            subs_per_10kb: 0.,
+                            variants: total_variants,
+                            mean_ref_abundance: 0.,
+                            ref_std_dev: 0.,
+                            mean_var_abundance: 0.,
+                            var_std_dev: 0.,
+                        };
+                        if total_variants > 0 {
                             let sample_sums = &variant_sums[&sample_idx][tid];
 
 //                            let var_ratios = sample_sums[0]
@@ -947,21 +1149,1494 @@ impl VariantMatrixFunctions for VariantMatrix {
                                 {(*x - refr_ratios_mean).powf(2.)}).collect::<Vec<f64>>().iter().sum::<f64>();
                             ref_std = (ref_std / (sample_sums[1].len()) as f64).powf(1./2.);
 
-                            writeln!(file_open,
-                                     "\t{:.3}\t{}\t{:.3}\t{:.3}\t{:.3}\t{:.3}",
-                                     var_ten_kbs, total_variants,
-                                     refr_ratios_mean, ref_std,
-                                     var_ratios_mean, var_std).unwrap();
-                        } else {
-                            writeln!(file_open,
-                                     "\t{}\t{}\t{}\t{}\t{}\t{}",
-                                     0., 0., 0., 0., 0., 0.,).unwrap();
+                            stats.subs_per_10kb = total_variants as f64 / ten_kbs;
+                            stats.mean_ref_abundance = refr_ratios_mean;
+                            stats.ref_std_dev = ref_std;
+                            stats.mean_var_abundance = var_ratios_mean;
+                            stats.var_std_dev = var_std;
+                        }
+                        samples.push(stats);
+                    }
+                    // Consequence counts are only reported when a GFF was provided
+                    let consequences = if annotations.len() > 0 {
+                        let mut counts: BTreeMap<String, usize> = Consequence::all().iter()
+                            .map(|consequence| (consequence.to_string(), 0)).collect();
+                        if let Some(contig_annotations) = annotations.get(tid) {
+                            for (_pos, variant_annotations) in contig_annotations.iter() {
+                                for annotation in variant_annotations.values().flatten() {
+                                    *counts.entry(annotation.consequence.to_string()).or_insert(0) += 1;
+                                }
+                            }
+                        }
+                        Some(counts)
+                    } else {
+                        None
+                    };
+                    reports.push(ContigReport {
+                        name: target_names[tid].clone(),
+                        length: contig_len,
+                        samples,
+                        consequences,
+                    });
+                }
+                reports
+            }
+        }
+    }
+
+    fn annotate_variants(&mut self, gene_index: &GeneIndex, codon_table: &CodonTable) {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                all_variants,
+                contigs,
+                target_names,
+                ref mut annotations,
+                ..
+            } => {
+                let annotated = Arc::new(Mutex::new(HashMap::new()));
+                all_variants.par_iter().for_each(|(tid, variant_abundances)| {
+                    let contig_name = match target_names.get(tid) {
+                        Some(name) => name,
+                        None => return,
+                    };
+                    let ref_sequence = match contigs.get(tid) {
+                        Some(sequence) => sequence,
+                        None => return,
+                    };
+                    let mut contig_annotations = HashMap::new();
+                    for (position, hash) in variant_abundances.iter() {
+                        for (variant, _base) in hash.iter() {
+                            if variant == &Variant::None {
+                                continue
+                            }
+                            let variant_annotations = gene_index.annotate(contig_name,
+                                                                          *position,
+                                                                          variant,
+                                                                          ref_sequence,
+                                                                          codon_table);
+                            contig_annotations.entry(*position)
+                                .or_insert(HashMap::new())
+                                .insert(variant.clone(), variant_annotations);
+                        }
+                    }
+                    let mut annotated = annotated.lock().unwrap();
+                    annotated.insert(*tid, contig_annotations);
+                });
+                *annotations = annotated.lock().unwrap().clone();
+                debug!("Annotated variants on {} contigs", annotations.len());
+            }
+        }
+    }
+
+    fn print_variants(&self, max_components: usize) {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                all_variants,
+                contigs,
+                target_names,
+                sample_names,
+                annotations,
+                ..
+            } => {
+                let mixtures = self.frequency_mixtures(max_components);
+                let stdout = std::io::stdout();
+                let mut print_stream = stdout.lock();
+                for tid in all_variants.keys().sorted() {
+                    let variant_abundances = &all_variants[tid];
+                    let ref_sequence = match contigs.get(tid) {
+                        Some(sequence) => sequence,
+                        None => continue,
+                    };
+                    for position in variant_abundances.keys().sorted() {
+                        let hash = &variant_abundances[position];
+                        let genotypes = hash.keys()
+                            .filter(|variant| **variant != Variant::None).count();
+                        for (sample_idx, sample_name) in sample_names.iter().enumerate() {
+                            for (variant, base) in hash.iter() {
+                                if variant == &Variant::None || base.depth[sample_idx] <= 0 {
+                                    continue
+                                }
+                                let vaf_cluster = match &mixtures[sample_idx] {
+                                    Some(mixture) => mixture.assign(base.allele_frequency(sample_idx)).to_string(),
+                                    None => "-".to_string(),
+                                };
+                                // One row for each gene the variant falls in
+                                for annotation in annotation_columns(annotations, *tid, *position, variant) {
+                                    writeln!(print_stream, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                                             sample_name,
+                                             target_names[tid],
+                                             position,
+                                             variant,
+                                             reference_allele(ref_sequence, *position, variant),
+                                             base.depth[sample_idx],
+                                             base.totaldepth[sample_idx],
+                                             genotypes,
+                                             vaf_cluster,
+                                             annotation).expect("Unable to write to stream");
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn print_strain_variants(&self, output_prefix: &str) {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                target_names,
+                contigs,
+                pred_variants,
+                annotations,
+                ..
+            } => {
+                pred_variants.par_iter().for_each(|(strain_index, genotype)| {
+                    let file_name = format!("{}_strain_{}_variants.tsv",
+                                            output_prefix.to_string(), strain_index);
+                    let file_path = Path::new(&file_name);
+                    let mut file_open = File::create(file_path)
+                        .expect("No Read or Write Permission in current directory");
+                    writeln!(file_open, "contigName\tpos\tvariant\treference\tcategory\t{}",
+                             ANNOTATION_HEADER).expect("Unable to write to file");
+
+                    for tid in genotype.keys().sorted() {
+                        let ref_sequence = match contigs.get(tid) {
+                            Some(sequence) => sequence,
+                            None => continue,
+                        };
+                        for position in genotype[tid].keys().sorted() {
+                            for (category, variants) in genotype[tid][position].iter() {
+                                for variant in variants.iter() {
+                                    if variant == &Variant::None {
+                                        continue
+                                    }
+                                    for annotation in annotation_columns(annotations, *tid, *position, variant) {
+                                        writeln!(file_open, "{}\t{}\t{}\t{}\t{:?}\t{}",
+                                                 target_names[tid],
+                                                 position,
+                                                 variant,
+                                                 reference_allele(ref_sequence, *position, variant),
+                                                 category,
+                                                 annotation).expect("Unable to write to file");
+                                    }
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+        }
+    }
+
+    fn print_diversity(&self, output_prefix: &str,
+                       gff_map: &HashMap<String, Vec<Record>>,
+                       parameters: &DiversityParameters) {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                all_variants,
+                target_names,
+                target_lengths,
+                sample_names,
+                sample_depths,
+                ..
+            } => {
+                let file_name = format!("{}_diversity.tsv", output_prefix);
+                let file_path = Path::new(&file_name);
+                let mut file_open = File::create(file_path)
+                    .expect("No Read or Write Permission in current directory");
+                writeln!(file_open, "sampleName\tregionType\tcontigName\tregion\tstart\tend\t{}",
+                         DIVERSITY_HEADER).expect("Unable to write to file");
+
+                let tids: Vec<&i32> = target_names.keys().sorted().collect();
+                let contig_rows: Vec<Vec<String>> = tids.par_iter().map(|&tid| {
+                    let contig_name = &target_names[tid];
+                    let contig_len = target_lengths[tid] as usize;
+
+                    // Segments of multi-segment CDS share an ID and are treated as one gene
+                    let mut genes: Vec<(String, Vec<(usize, usize)>)> = Vec::new();
+                    let mut gene_indices: HashMap<String, usize> = HashMap::new();
+                    if let Some(records) = gff_map.get(contig_name) {
+                        for record in records.iter() {
+                            let id = gene_id(record);
+                            let segment = (*record.start() as usize - 1, *record.end() as usize);
+                            match gene_indices.get(&id) {
+                                Some(idx) => genes[*idx].1.push(segment),
+                                None => {
+                                    gene_indices.insert(id.clone(), genes.len());
+                                    genes.push((id, vec![segment]));
+                                }
+                            }
+                        }
+                    }
+
+                    let mut rows = Vec::new();
+                    for (sample_idx, sample_name) in sample_names.iter().enumerate() {
+                        let depths = sample_depths.get(tid)
+                            .map(|depths| &depths[sample_idx])
+                            .filter(|depths| depths.len() > 0)
+                            .cloned()
+                            .unwrap_or(vec![0; contig_len]);
+                        let sites = contig_sites(&depths, all_variants.get(tid), sample_idx);
+
+                        let statistics = DiversityStatistics::from_sites(
+                            sites.iter(), parameters.min_depth);
+                        rows.push(format!("{}\tcontig\t{}\t{}\t{}\t{}\t{}",
+                                          sample_name, contig_name, contig_name, 1, sites.len(),
+                                          statistics.to_tsv(parameters.min_breadth)));
+
+                        for (id, segments) in genes.iter() {
+                            let segments: Vec<(usize, usize)> = segments.iter()
+                                .map(|(start, end)| (*start, std::cmp::min(*end, sites.len())))
+                                .filter(|(start, end)| start < end)
+                                .collect();
+                            if segments.len() == 0 {
+                                continue
+                            }
+                            let statistics = DiversityStatistics::from_sites(
+                                segments.iter().flat_map(|(start, end)| sites[*start..*end].iter()),
+                                parameters.min_depth);
+                            rows.push(format!("{}\tgene\t{}\t{}\t{}\t{}\t{}",
+                                              sample_name, contig_name, id,
+                                              segments.iter().map(|segment| segment.0).min().unwrap() + 1,
+                                              segments.iter().map(|segment| segment.1).max().unwrap(),
+                                              statistics.to_tsv(parameters.min_breadth)));
+                        }
+
+                        for (start, end) in windows(sites.len(), parameters.window_size,
+                                                    parameters.window_step) {
+                            let statistics = DiversityStatistics::from_sites(
+                                sites[start..end].iter(), parameters.min_depth);
+                            rows.push(format!("{}\twindow\t{}\t{}:{}-{}\t{}\t{}\t{}",
+                                              sample_name, contig_name, contig_name, start + 1, end,
+                                              start + 1, end,
+                                              statistics.to_tsv(parameters.min_breadth)));
                         }
                     }
+                    rows
+                }).collect();
+
+                for rows in contig_rows.iter() {
+                    for row in rows.iter() {
+                        writeln!(file_open, "{}", row).expect("Unable to write to file");
+                    }
+                }
+            }
+        }
+    }
+
+    fn print_pairwise_differentiation(&self, output_prefix: &str, min_depth: i32) {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                all_variants,
+                contigs,
+                target_names,
+                sample_names,
+                sample_depths,
+                ..
+            } => {
+                let sample_count = sample_names.len();
+                if sample_count < 2 {
+                    info!("Fewer than two samples, skipping pairwise differentiation");
+                    return
+                }
+                let pairs: Vec<(usize, usize)> = (0..sample_count)
+                    .flat_map(|a| ((a + 1)..sample_count).map(move |b| (a, b)))
+                    .collect();
+
+                let tids: Vec<&i32> = target_names.keys().sorted().collect();
+                // Comparison of each pair of samples on each contig, in the order of tids
+                let contig_comparisons: Vec<Vec<PairwiseComparison>> = tids.par_iter().map(|&tid| {
+                    let mut comparisons = vec![PairwiseComparison::new(); pairs.len()];
+                    let (depths, reference) = match (sample_depths.get(tid), contigs.get(tid)) {
+                        (Some(depths), Some(reference)) => (depths, reference),
+                        _ => return comparisons,
+                    };
+                    let contig_variants = all_variants.get(tid);
+                    for (pos, ref_base) in reference.iter().enumerate() {
+                        let position_variants = contig_variants
+                            .and_then(|variants| variants.get(&(pos as i64)));
+                        let site_depths: Vec<i32> = depths.iter()
+                            .map(|depths| *depths.get(pos).unwrap_or(&0))
+                            .collect();
+                        let mut alleles: Vec<Option<Vec<(u8, f64)>>> = vec![None; sample_count];
+                        for (pair_idx, (a, b)) in pairs.iter().enumerate() {
+                            if site_depths[*a] < min_depth || site_depths[*b] < min_depth {
+                                continue
+                            }
+                            for idx in [*a, *b].iter() {
+                                if alleles[*idx].is_none() {
+                                    alleles[*idx] = Some(site_alleles(*ref_base, position_variants, *idx));
+                                }
+                            }
+                            comparisons[pair_idx].add_site(
+                                alleles[*a].as_ref().unwrap(), site_depths[*a],
+                                alleles[*b].as_ref().unwrap(), site_depths[*b]);
+                        }
+                    }
+                    comparisons
+                }).collect();
+
+                let file_name = format!("{}_pairwise.tsv", output_prefix);
+                let file_path = Path::new(&file_name);
+                let mut file_open = File::create(file_path)
+                    .expect("No Read or Write Permission in current directory");
+                writeln!(file_open, "sampleA\tsampleB\tcontigName\t{}", PAIRWISE_HEADER)
+                    .expect("Unable to write to file");
+
+                let mut genome = vec![PairwiseComparison::new(); pairs.len()];
+                for (pair_idx, (a, b)) in pairs.iter().enumerate() {
+                    for (tid, comparisons) in tids.iter().zip(contig_comparisons.iter()) {
+                        writeln!(file_open, "{}\t{}\t{}\t{}",
+                                 sample_names[*a], sample_names[*b], target_names[*tid],
+                                 comparisons[pair_idx].to_tsv()).expect("Unable to write to file");
+                        genome[pair_idx].combine(&comparisons[pair_idx]);
+                    }
+                    writeln!(file_open, "{}\t{}\tgenome\t{}",
+                             sample_names[*a], sample_names[*b],
+                             genome[pair_idx].to_tsv()).expect("Unable to write to file");
+                }
+
+                // Genome wide sample by sample matrix of each statistic
+                let statistics: [(&str, fn(&PairwiseComparison) -> Option<f64>, f64); 3] = [
+                    ("conANI", PairwiseComparison::con_ani, 1.),
+                    ("popANI", PairwiseComparison::pop_ani, 1.),
+                    ("fst", PairwiseComparison::fst, 0.),
+                ];
+                for (name, statistic, diagonal) in statistics.iter() {
+                    let file_name = format!("{}_{}.tsv", output_prefix, name);
+                    let file_path = Path::new(&file_name);
+                    let mut file_open = File::create(file_path)
+                        .expect("No Read or Write Permission in current directory");
+                    writeln!(file_open, "sampleName\t{}", sample_names.join("\t"))
+                        .expect("Unable to write to file");
+
+                    let mut matrix = vec![vec![Some(*diagonal); sample_count]; sample_count];
+                    for (pair_idx, (a, b)) in pairs.iter().enumerate() {
+                        let value = statistic(&genome[pair_idx]);
+                        matrix[*a][*b] = value;
+                        matrix[*b][*a] = value;
+                    }
+                    for (sample_idx, row) in matrix.into_iter().enumerate() {
+                        writeln!(file_open, "{}\t{}", sample_names[sample_idx],
+                                 row.into_iter().map(format_statistic).join("\t"))
+                            .expect("Unable to write to file");
+                    }
+                }
+            }
+        }
+    }
+
+    fn print_linkage(&self, output_prefix: &str, parameters: &LinkageParameters) {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                all_variants,
+                target_names,
+                ..
+            } => {
+                let has_reads = all_variants.values()
+                    .flat_map(|positions| positions.values())
+                    .flat_map(|variants| variants.values())
+                    .any(|base| base.reads.len() > 0);
+                if !has_reads {
+                    info!("No reads have been assigned to variants, skipping linkage");
+                    return
+                }
+
+                let tids: Vec<&i32> = all_variants.keys().sorted().collect();
+                let contig_rows: Vec<(Vec<String>, Vec<String>)> = tids.par_iter().map(|&tid| {
+                    let contig_name = &target_names[tid];
+                    let positions = &all_variants[tid];
+
+                    // SNV sites with the reads carrying the alternate allele and the reads
+                    // carrying any other allele at the same position
+                    let mut sites: Vec<(i64, &Variant, &HashSet<i64>, HashSet<i64>)> = Vec::new();
+                    for position in positions.keys().sorted() {
+                        let variants = &positions[position];
+                        for (variant, base) in variants.iter() {
+                            if let Variant::SNV(_) = variant {
+                                if base.reads.len() == 0 {
+                                    continue
+                                }
+                                let other: HashSet<i64> = variants.iter()
+                                    .filter(|(other_variant, _)| *other_variant != variant)
+                                    .flat_map(|(_, other_base)| other_base.reads.iter().cloned())
+                                    .collect();
+                                sites.push((*position, variant, &base.reads, other));
+                            }
+                        }
+                    }
+
+                    let mut rows = Vec::new();
+                    let mut decay = LinkageDecay::new(parameters.bin_size);
+                    for (idx, (pos_a, variant_a, alt_a, other_a)) in sites.iter().enumerate() {
+                        for (pos_b, variant_b, alt_b, other_b) in sites[idx + 1..].iter() {
+                            let distance = pos_b - pos_a;
+                            if distance > parameters.max_distance {
+                                break
+                            } else if distance == 0 {
+                                continue
+                            }
+                            let counts = HaplotypeCounts::from_reads(alt_a, other_a, alt_b, other_b);
+                            if counts.total() < parameters.min_reads {
+                                continue
+                            }
+                            rows.push(format!("{}\t{}\t{}\t{}\t{}\t{}",
+                                              contig_name, pos_a, variant_a, pos_b, variant_b,
+                                              counts.to_tsv(distance)));
+                            decay.add(distance, &counts);
+                        }
+                    }
+                    let decay_rows = decay.rows().into_iter()
+                        .map(|row| format!("{}\t{}", contig_name, row))
+                        .collect();
+                    (rows, decay_rows)
+                }).collect();
+
+                let file_name = format!("{}_linkage.tsv", output_prefix);
+                let file_path = Path::new(&file_name);
+                let mut file_open = File::create(file_path)
+                    .expect("No Read or Write Permission in current directory");
+                writeln!(file_open, "contigName\tposition1\tvariant1\tposition2\tvariant2\t{}",
+                         LINKAGE_HEADER).expect("Unable to write to file");
+                for (rows, _) in contig_rows.iter() {
+                    for row in rows.iter() {
+                        writeln!(file_open, "{}", row).expect("Unable to write to file");
+                    }
+                }
+
+                let file_name = format!("{}_ld_decay.tsv", output_prefix);
+                let file_path = Path::new(&file_name);
+                let mut file_open = File::create(file_path)
+                    .expect("No Read or Write Permission in current directory");
+                writeln!(file_open, "contigName\t{}", DECAY_HEADER).expect("Unable to write to file");
+                for (_, decay_rows) in contig_rows.iter() {
+                    for row in decay_rows.iter() {
+                        writeln!(file_open, "{}", row).expect("Unable to write to file");
+                    }
                 }
             }
         }
     }
+
+    fn print_snp_alignment(&self, output_prefix: &str, min_depth: i32) {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                all_variants,
+                contigs,
+                target_names,
+                sample_names,
+                sample_depths,
+                pred_variants,
+                ..
+            } => {
+                let strain_indices: Vec<&usize> = pred_variants.keys().sorted().collect();
+                let mut names: Vec<String> = sample_names.clone();
+                names.extend(strain_indices.iter().map(|strain| format!("strain_{}", strain)));
+                if names.is_empty() {
+                    warn!("No samples or strains to align, skipping SNP alignment");
+                    return
+                }
+                let mut sequences: Vec<Vec<u8>> = vec![Vec::new(); names.len()];
+                let mut site_count = 0;
+
+                let file_name = format!("{}_snp_positions.tsv", output_prefix);
+                let file_path = Path::new(&file_name);
+                let mut positions_file = File::create(file_path)
+                    .expect("No Read or Write Permission in current directory");
+                writeln!(positions_file, "column\tcontigName\tposition\treference")
+                    .expect("Unable to write to file");
+
+                for tid in all_variants.keys().sorted() {
+                    let ref_sequence = match contigs.get(tid) {
+                        Some(sequence) => sequence,
+                        None => continue,
+                    };
+                    for position in all_variants[tid].keys().sorted() {
+                        let variants = &all_variants[tid][position];
+                        let ref_base = match ref_sequence.get(*position as usize) {
+                            Some(base) => base.to_ascii_uppercase(),
+                            None => continue,
+                        };
+                        if !variants.keys().any(|variant| match variant {
+                            Variant::SNV(_) => true,
+                            _ => false,
+                        }) {
+                            continue
+                        }
+
+                        // Majority allele of each sample, masking sites below min_depth
+                        let mut column: Vec<u8> = (0..sample_names.len()).map(|sample_idx| {
+                            let depth = sample_depths.get(tid)
+                                .and_then(|depths| depths.get(sample_idx))
+                                .and_then(|depths| depths.get(*position as usize))
+                                .cloned()
+                                .unwrap_or(0);
+                            if depth < min_depth {
+                                phylogeny::MASKED
+                            } else {
+                                consensus(&site_alleles(ref_base, Some(variants), sample_idx))
+                                    .unwrap_or(phylogeny::MASKED)
+                            }
+                        }).collect();
+
+                        // Allele of each strain, which is the reference unless it carries an SNV here
+                        column.extend(strain_indices.iter().map(|strain| {
+                            match pred_variants[*strain].get(tid)
+                                .and_then(|genotype| genotype.get(position))
+                                .and_then(|categories| strain_variants(categories))
+                                .and_then(|variants| variants.iter().filter_map(|variant| match variant {
+                                    Variant::SNV(alt) => Some(*alt),
+                                    _ => None,
+                                }).min()) {
+                                Some(alt) => alt,
+                                None => ref_base,
+                            }
+                        }));
+
+                        if !phylogeny::is_variable(&column) {
+                            continue
+                        }
+                        writeln!(positions_file, "{}\t{}\t{}\t{}",
+                                 site_count + 1, target_names[tid], position, ref_base as char)
+                            .expect("Unable to write to file");
+                        site_count += 1;
+                        for (sequence, allele) in sequences.iter_mut().zip(column.into_iter()) {
+                            sequence.push(allele);
+                        }
+                    }
+                }
+
+                let file_name = format!("{}_snp_alignment.fna", output_prefix);
+                let file_path = Path::new(&file_name);
+                let mut file_open = File::create(file_path)
+                    .expect("No Read or Write Permission in current directory");
+                for (name, sequence) in names.iter().zip(sequences.iter()) {
+                    writeln!(file_open, ">{}", name).expect("Unable to write to file");
+                    for line in sequence.chunks(60) {
+                        file_open.write(line).unwrap();
+                        file_open.write(b"\n").unwrap();
+                    }
+                }
+
+                let distances = phylogeny::snp_distances(&sequences);
+                let file_name = format!("{}_snp_distances.tsv", output_prefix);
+                let file_path = Path::new(&file_name);
+                let mut file_open = File::create(file_path)
+                    .expect("No Read or Write Permission in current directory");
+                writeln!(file_open, "name\t{}", names.join("\t")).expect("Unable to write to file");
+                for (name, row) in names.iter().zip(distances.iter()) {
+                    writeln!(file_open, "{}\t{}", name, row.iter().join("\t"))
+                        .expect("Unable to write to file");
+                }
+
+                let distances: Vec<Vec<f64>> = distances.iter()
+                    .map(|row| row.iter().map(|distance| *distance as f64).collect())
+                    .collect();
+                let file_name = format!("{}_snp_tree.nwk", output_prefix);
+                let file_path = Path::new(&file_name);
+                let mut file_open = File::create(file_path)
+                    .expect("No Read or Write Permission in current directory");
+                writeln!(file_open, "{}", phylogeny::neighbour_joining(&names, &distances))
+                    .expect("Unable to write to file");
+                info!("Wrote SNP alignment of {} sites across {} samples and strains",
+                      site_count, names.len());
+            }
+        }
+    }
+
+    fn print_trajectories(&self, output_prefix: &str,
+                          metadata: &HashMap<String, SampleMetadata>,
+                          parameters: &TrajectoryParameters) {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                all_variants,
+                target_names,
+                sample_names,
+                sample_depths,
+                annotations,
+                ..
+            } => {
+                // Samples of each subject ordered by time point
+                let mut subjects: BTreeMap<String, Vec<(f64, usize)>> = BTreeMap::new();
+                for (sample_idx, sample_name) in sample_names.iter().enumerate() {
+                    match match_sample(sample_name, metadata) {
+                        Ok(sample) => subjects.entry(sample.subject.clone())
+                            .or_insert(Vec::new()).push((sample.time, sample_idx)),
+                        Err(e) => {
+                            error!("{}", e);
+                            std::process::exit(1)
+                        },
+                    }
+                }
+                for samples in subjects.values_mut() {
+                    samples.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                }
+                if !subjects.values().any(|samples| samples.len() > 1) {
+                    info!("No subject has more than one time point, skipping trajectories");
+                    return
+                }
+
+                let file_name = format!("{}_trajectories.tsv", output_prefix);
+                let file_path = Path::new(&file_name);
+                let mut trajectory_file = File::create(file_path)
+                    .expect("No Read or Write Permission in current directory");
+                writeln!(trajectory_file, "subject\tcontigName\tposition\tvariant\tgeneId\tclass\
+                                           \ttimePoints\tinitialFrequency\tfinalFrequency\ttrajectory")
+                    .expect("Unable to write to file");
+
+                let file_name = format!("{}_gene_sweeps.tsv", output_prefix);
+                let file_path = Path::new(&file_name);
+                let mut gene_file = File::create(file_path)
+                    .expect("No Read or Write Permission in current directory");
+                writeln!(gene_file, "subject\tcontigName\tgeneId\t{}\tflagged",
+                         TRAJECTORY_COUNTS_HEADER).expect("Unable to write to file");
+
+                let file_name = format!("{}_subject_summary.tsv", output_prefix);
+                let file_path = Path::new(&file_name);
+                let mut summary_file = File::create(file_path)
+                    .expect("No Read or Write Permission in current directory");
+                writeln!(summary_file, "subject\tsamples\tfirstTimePoint\tlastTimePoint\t{}\tflaggedGenes",
+                         TRAJECTORY_COUNTS_HEADER).expect("Unable to write to file");
+
+                for (subject, samples) in subjects.iter() {
+                    let mut subject_counts = TrajectoryCounts::default();
+                    let mut gene_counts: BTreeMap<(String, String), TrajectoryCounts> = BTreeMap::new();
+                    if samples.len() > 1 {
+                        for tid in all_variants.keys().sorted() {
+                            let depths = match sample_depths.get(tid) {
+                                Some(depths) => depths,
+                                None => continue,
+                            };
+                            for position in all_variants[tid].keys().sorted() {
+                                let variants = &all_variants[tid][position];
+                                let ordered_variants = variants.keys()
+                                    .filter(|variant| **variant != Variant::None)
+                                    .sorted_by_key(|variant| variant.to_string());
+                                for variant in ordered_variants {
+                                    let base = &variants[variant];
+                                    let trajectory: Vec<(f64, f64)> = samples.iter()
+                                        .filter(|(_, sample_idx)| {
+                                            depths[*sample_idx].get(*position as usize)
+                                                .map(|depth| *depth >= parameters.min_depth)
+                                                .unwrap_or(false)
+                                        })
+                                        .map(|(time, sample_idx)| (*time, base.allele_frequency(*sample_idx)))
+                                        .collect();
+                                    if trajectory.len() < 2
+                                        || trajectory.iter().all(|(_, frequency)| *frequency == 0.) {
+                                        continue
+                                    }
+
+                                    let class = classify(&trajectory, parameters.min_change);
+                                    subject_counts.add(class);
+                                    let genes = variant_genes(annotations, *tid, *position, variant);
+                                    for gene in genes.iter() {
+                                        gene_counts.entry((target_names[tid].clone(), gene.clone()))
+                                            .or_insert(TrajectoryCounts::default()).add(class);
+                                    }
+                                    let gene = if genes.len() > 0 { Some(genes.join(",")) } else { None };
+                                    writeln!(trajectory_file, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{}",
+                                             subject, target_names[tid], position, variant,
+                                             gene.unwrap_or("-".to_string()), class, trajectory.len(),
+                                             trajectory[0].1, trajectory[trajectory.len() - 1].1,
+                                             trajectory.iter()
+                                                 .map(|(time, frequency)| format!("{}:{:.4}", time, frequency))
+                                                 .join(","))
+                                        .expect("Unable to write to file");
+                                }
+                            }
+                        }
+                    }
+
+                    // Genes where sweeps cluster are likely targets of selection
+                    let mut flagged_genes = 0;
+                    for ((contig_name, gene), counts) in gene_counts.iter() {
+                        let flagged = counts.sweeps() >= parameters.min_gene_sweeps;
+                        if flagged {
+                            flagged_genes += 1;
+                        }
+                        writeln!(gene_file, "{}\t{}\t{}\t{}\t{}",
+                                 subject, contig_name, gene, counts.to_tsv(), flagged)
+                            .expect("Unable to write to file");
+                    }
+                    writeln!(summary_file, "{}\t{}\t{}\t{}\t{}\t{}",
+                             subject, samples.len(), samples[0].0, samples[samples.len() - 1].0,
+                             subject_counts.to_tsv(), flagged_genes)
+                        .expect("Unable to write to file");
+                }
+            }
+        }
+    }
+
+    fn print_differential_frequencies(&self, output_prefix: &str,
+                                      groups: &HashMap<String, SampleGroup>,
+                                      min_depth: i32) {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                all_variants,
+                target_names,
+                sample_names,
+                sample_depths,
+                annotations,
+                ..
+            } => {
+                let sample_groups: Vec<&SampleGroup> = sample_names.iter()
+                    .map(|sample_name| match match_sample(sample_name, groups) {
+                        Ok(group) => group,
+                        Err(e) => {
+                            error!("{}", e);
+                            std::process::exit(1)
+                        },
+                    }).collect();
+                let group_names: Vec<&String> = sample_groups.iter()
+                    .map(|group| &group.group)
+                    .unique().sorted().collect();
+                if group_names.len() < 2 {
+                    info!("Fewer than two sample groups, skipping differential allele frequencies");
+                    return
+                }
+
+                let file_name = format!("{}_differential_frequencies.tsv", output_prefix);
+                let file_path = Path::new(&file_name);
+                let mut file_open = File::create(file_path)
+                    .expect("No Read or Write Permission in current directory");
+                writeln!(file_open, "groupA\tgroupB\tcontigName\tposition\tvariant\tgeneId\
+                                     \tsamplesA\tsamplesB\tmeanFrequencyA\tmeanFrequencyB\
+                                     \tfrequencyDifference\t{}\tqValue", CMH_HEADER)
+                    .expect("Unable to write to file");
+
+                for (group_idx, group_a) in group_names.iter().enumerate() {
+                    for group_b in group_names[group_idx + 1..].iter() {
+                        let strata = stratify_samples(&sample_groups, group_a, group_b);
+                        for (stratum, samples_a, samples_b) in strata.iter() {
+                            if samples_a.is_empty() || samples_b.is_empty() {
+                                warn!("Stratum {} does not have samples of both {} and {}, so \
+                                       its samples are not part of the test between them",
+                                      stratum, group_a, group_b);
+                            }
+                        }
+                        // Rows and p-values are held until every site is tested so the
+                        // q-values can be corrected across the comparison
+                        let mut rows = Vec::new();
+                        let mut p_values = Vec::new();
+                        for tid in all_variants.keys().sorted() {
+                            let depths = match sample_depths.get(tid) {
+                                Some(depths) => depths,
+                                None => continue,
+                            };
+                            for position in all_variants[tid].keys().sorted() {
+                                let variants = &all_variants[tid][position];
+                                let ordered_variants = variants.keys()
+                                    .filter(|variant| **variant != Variant::None)
+                                    .sorted_by_key(|variant| variant.to_string());
+                                for variant in ordered_variants {
+                                    let base = &variants[variant];
+                                    let depth = |sample_idx: usize| depths.get(sample_idx)
+                                        .and_then(|depths| depths.get(*position as usize))
+                                        .cloned().unwrap_or(0);
+                                    // One table for each stratum, pooling the reads of the
+                                    // covered samples of each group
+                                    let mut tables = Vec::new();
+                                    let mut frequencies_a = Vec::new();
+                                    let mut frequencies_b = Vec::new();
+                                    for (_, samples_a, samples_b) in strata.iter() {
+                                        let mut table = StratumTable::default();
+                                        for (in_group_a, samples) in [(true, samples_a), (false, samples_b)].iter() {
+                                            for sample_idx in samples.iter() {
+                                                let sample_depth = depth(*sample_idx);
+                                                if sample_depth < min_depth {
+                                                    continue
+                                                }
+                                                let frequency = base.allele_frequency(*sample_idx);
+                                                table.add_sample(*in_group_a, sample_depth, frequency);
+                                                if *in_group_a {
+                                                    frequencies_a.push(frequency);
+                                                } else {
+                                                    frequencies_b.push(frequency);
+                                                }
+                                            }
+                                        }
+                                        tables.push(table);
+                                    }
+                                    if frequencies_a.len() == 0 || frequencies_b.len() == 0 {
+                                        continue
+                                    }
+                                    let test = match cmh_test(&tables) {
+                                        Some(test) => test,
+                                        None => continue,
+                                    };
+                                    let mean_a = frequencies_a.iter().sum::<f64>() / frequencies_a.len() as f64;
+                                    let mean_b = frequencies_b.iter().sum::<f64>() / frequencies_b.len() as f64;
+                                    let genes = variant_genes(annotations, *tid, *position, variant);
+                                    let gene = if genes.len() > 0 { genes.join(",") } else { "-".to_string() };
+                                    rows.push(format!("{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:.4}\t{:.4}\t{:.4}\t{}",
+                                                      group_a, group_b, target_names[tid], position,
+                                                      variant, gene, frequencies_a.len(), frequencies_b.len(),
+                                                      mean_a, mean_b, mean_a - mean_b, test.to_tsv()));
+                                    p_values.push(test.p_value);
+                                }
+                            }
+                        }
+
+                        let q_values = benjamini_hochberg(&p_values);
+                        for (row, q_value) in rows.iter().zip(q_values.iter()) {
+                            writeln!(file_open, "{}\t{:.4e}", row, q_value)
+                                .expect("Unable to write to file");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn add_gene_dnds(&mut self, sample_idx: usize, dnds: Vec<(String, f64)>) {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                ref mut gene_dnds,
+                ..
+            } => {
+                for (gene, value) in dnds.into_iter() {
+                    gene_dnds.entry(gene).or_insert(HashMap::new()).insert(sample_idx, value);
+                }
+            }
+        }
+    }
+
+    fn merge_samples_by_name(&mut self) {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                ref mut coverages,
+                ref mut average_genotypes,
+                ref mut variances,
+                ref mut all_variants,
+                ref mut sample_names,
+                ref mut sample_depths,
+                ref mut gene_dnds,
+                ..
+            } => {
+                // Column of each sample once merged, in the order of first appearance
+                let mut merged_names: Vec<String> = Vec::new();
+                let columns: Vec<usize> = sample_names.iter().map(|name| {
+                    match merged_names.iter().position(|merged| merged == name) {
+                        Some(column) => column,
+                        None => {
+                            merged_names.push(name.clone());
+                            merged_names.len() - 1
+                        },
+                    }
+                }).collect();
+                let merged_count = merged_names.len();
+                if merged_count == sample_names.len() {
+                    return
+                }
+                info!("Merging {} libraries into {} samples", sample_names.len(), merged_count);
+
+                for values in coverages.values_mut().chain(average_genotypes.values_mut())
+                    .chain(variances.values_mut()) {
+                    let mut merged = vec![0.; merged_count];
+                    for (sample_idx, value) in values.iter().enumerate() {
+                        merged[columns[sample_idx]] += value;
+                    }
+                    *values = merged;
+                }
+                for depths in sample_depths.values_mut() {
+                    let mut merged: Vec<Vec<i32>> = vec![Vec::new(); merged_count];
+                    for (sample_idx, depth) in depths.iter().enumerate() {
+                        let merged_depth = &mut merged[columns[sample_idx]];
+                        if merged_depth.len() < depth.len() {
+                            merged_depth.resize(depth.len(), 0);
+                        }
+                        for (pos, position_depth) in depth.iter().enumerate() {
+                            merged_depth[pos] += position_depth;
+                        }
+                    }
+                    *depths = merged;
+                }
+                for contig_variants in all_variants.values_mut() {
+                    for position_variants in contig_variants.values_mut() {
+                        for base in position_variants.values_mut() {
+                            base.merge_samples(&columns, merged_count);
+                        }
+                    }
+                }
+                for sample_dnds in gene_dnds.values_mut() {
+                    let mut merged: HashMap<usize, (f64, usize)> = HashMap::new();
+                    for (sample_idx, value) in sample_dnds.iter() {
+                        let entry = merged.entry(columns[*sample_idx]).or_insert((0., 0));
+                        entry.0 += value;
+                        entry.1 += 1;
+                    }
+                    *sample_dnds = merged.into_iter()
+                        .map(|(column, (total, count))| (column, total / count as f64))
+                        .collect();
+                }
+                *sample_names = merged_names;
+            }
+        }
+    }
+
+    fn report(&self, mode: &str, parameters: &BTreeMap<String, Value>,
+              gff_map: &HashMap<String, Vec<Record>>) -> LorikeetReport {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                target_names,
+                contigs,
+                sample_names,
+                pred_variants,
+                annotations,
+                gene_dnds,
+                ..
+            } => {
+                let mut report = LorikeetReport::new(mode, parameters.clone());
+                report.samples = sample_names.clone();
+                report.contigs = self.contig_reports();
+
+                // Variants of each gene, counted from their annotations
+                let mut gene_counts: HashMap<String, (usize, BTreeMap<String, usize>)> = HashMap::new();
+                for (_tid, contig_annotations) in annotations.iter() {
+                    for (_pos, variant_annotations) in contig_annotations.iter() {
+                        for annotation in variant_annotations.values().flatten() {
+                            if let Some(gene) = &annotation.gene_id {
+                                let counts = gene_counts.entry(gene.clone())
+                                    .or_insert((0, BTreeMap::new()));
+                                counts.0 += 1;
+                                *counts.1.entry(annotation.consequence.to_string()).or_insert(0) += 1;
+                            }
+                        }
+                    }
+                }
+                for contig in gff_map.keys().sorted() {
+                    for segments in group_segments(&gff_map[contig]) {
+                        let gene = segments[0];
+                        let id = gene_id(gene);
+                        let (variants, consequences) = gene_counts.get(&id).cloned()
+                            .unwrap_or((0, BTreeMap::new()));
+                        let dnds = gene_dnds.get(&id).map(|samples| {
+                            samples.iter()
+                                .map(|(sample_idx, value)| (sample_names[*sample_idx].clone(), *value))
+                                .collect()
+                        });
+                        report.genes.push(GeneReport {
+                            contig: contig.clone(),
+                            start: segments.iter().map(|segment| *segment.start()).min().unwrap(),
+                            end: segments.iter().map(|segment| *segment.end()).max().unwrap(),
+                            strand: match gene.strand() {
+                                Some(strand::Strand::Reverse) => "-".to_string(),
+                                Some(strand::Strand::Forward) => "+".to_string(),
+                                _ => ".".to_string(),
+                            },
+                            id,
+                            variants,
+                            consequences,
+                            dnds,
+                        });
+                    }
+                }
+
+                for strain in pred_variants.keys().sorted() {
+                    let genotype = &pred_variants[strain];
+                    let mut variants = Vec::new();
+                    for tid in genotype.keys().sorted() {
+                        let ref_sequence = match contigs.get(tid) {
+                            Some(sequence) => sequence,
+                            None => continue,
+                        };
+                        for position in genotype[tid].keys().sorted() {
+                            let categories = &genotype[tid][position];
+                            for category in categories.keys().sorted_by_key(|category| format!("{:?}", category)) {
+                                let ordered_variants = categories[category].iter()
+                                    .filter(|variant| **variant != Variant::None)
+                                    .sorted_by_key(|variant| variant.to_string());
+                                for variant in ordered_variants {
+                                    let variant_annotations: Vec<Option<&VariantAnnotation>> =
+                                        match annotations.get(tid)
+                                            .and_then(|contig| contig.get(position))
+                                            .and_then(|variants| variants.get(variant)) {
+                                            Some(variant_annotations) => variant_annotations.iter().map(Some).collect(),
+                                            None => vec![None],
+                                        };
+                                    // One entry for each gene the variant falls in
+                                    for annotation in variant_annotations {
+                                        variants.push(StrainVariant {
+                                            contig: target_names[tid].clone(),
+                                            position: *position,
+                                            variant: variant.to_string(),
+                                            reference: reference_allele(ref_sequence, *position, variant),
+                                            category: format!("{:?}", category),
+                                            gene_id: annotation.and_then(|annotation| annotation.gene_id.clone()),
+                                            consequence: annotation
+                                                .map(|annotation| annotation.consequence.to_string()),
+                                        });
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    report.strains.push(StrainReport {
+                        id: *strain,
+                        variants,
+                    });
+                }
+                report
+            }
+        }
+    }
+
+    fn write_html_report(&self, output_prefix: &str, mode: &str) {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                coverages,
+                all_variants,
+                target_names,
+                target_lengths,
+                sample_names,
+                variant_info,
+                geom_mean_var,
+                geom_mean_dep,
+                geom_mean_frq,
+                pred_variants,
+                sample_depths,
+                gene_dnds,
+                ..
+            } => {
+                let mut report = html_report::HtmlReport::new(&format!("Lorikeet {} report", mode));
+                let tids: Vec<&i32> = target_names.keys().sorted().collect();
+                let total_length: f64 = tids.iter().map(|tid| target_lengths[*tid]).sum();
+
+                // Allele frequencies of the variants observed in each sample
+                let mut frequencies = vec![Vec::new(); sample_names.len()];
+                let mut variant_sites = vec![0; sample_names.len()];
+                for tid in tids.iter() {
+                    let contig_variants = match all_variants.get(*tid) {
+                        Some(contig_variants) => contig_variants,
+                        None => continue,
+                    };
+                    for (_position, variants) in contig_variants.iter() {
+                        let mut observed = vec![false; sample_names.len()];
+                        for (variant, base) in variants.iter() {
+                            if variant == &Variant::None {
+                                continue
+                            }
+                            for sample_idx in 0..sample_names.len() {
+                                if base.depth[sample_idx] > 0 {
+                                    frequencies[sample_idx].push(base.allele_frequency(sample_idx));
+                                    observed[sample_idx] = true;
+                                }
+                            }
+                        }
+                        for (sample_idx, observed) in observed.into_iter().enumerate() {
+                            if observed {
+                                variant_sites[sample_idx] += 1;
+                            }
+                        }
+                    }
+                }
+
+                let mut rows = Vec::new();
+                for (sample_idx, sample_name) in sample_names.iter().enumerate() {
+                    let mut covered_bases = 0;
+                    let mut weighted_coverage = 0.;
+                    for tid in tids.iter() {
+                        // Samples without coverage of a contig have no entry for it
+                        let coverage = coverages.get(*tid)
+                            .and_then(|contig_coverages| contig_coverages.get(sample_idx))
+                            .cloned().unwrap_or(0.);
+                        weighted_coverage += coverage * target_lengths[*tid];
+                        if let Some(depths) = sample_depths.get(*tid)
+                            .and_then(|depths| depths.get(sample_idx)) {
+                            covered_bases += depths.iter().filter(|depth| **depth > 0).count();
+                        }
+                    }
+                    let mut sample_frequencies = frequencies[sample_idx].clone();
+                    sample_frequencies.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                    let median = if sample_frequencies.len() > 0 {
+                        format!("{:.3}", sample_frequencies[sample_frequencies.len() / 2])
+                    } else {
+                        "NA".to_string()
+                    };
+                    let (coverage, breadth) = if total_length > 0. {
+                        (weighted_coverage / total_length, covered_bases as f64 / total_length)
+                    } else {
+                        (0., 0.)
+                    };
+                    rows.push(vec![sample_name.clone(), format!("{:.3}", coverage),
+                                   format!("{:.3}", breadth), variant_sites[sample_idx].to_string(),
+                                   median]);
+                }
+                report.add_section("Sample QC", html_report::table(
+                    &["Sample", "Mean coverage", "Breadth", "Variant sites",
+                      "Median allele frequency"], &rows));
+
+                // Variant sites seen in any sample per 10 kb of each contig
+                let mut density = String::new();
+                if tids.len() > 0 {
+                    let mut labels = Vec::new();
+                    let mut values = Vec::new();
+                    for tid in tids.iter() {
+                        let sites = all_variants.get(*tid).map(|contig_variants| {
+                            contig_variants.values()
+                                .filter(|variants| variants.keys()
+                                    .any(|variant| variant != &Variant::None))
+                                .count()
+                        }).unwrap_or(0);
+                        labels.push(target_names[*tid].clone());
+                        values.push(sites as f64 / (target_lengths[*tid] / 10000.));
+                    }
+                    density.push_str(&html_report::bar_chart(
+                        &labels, &values, "Contig", "Variant sites per 10 kb"));
+                } else {
+                    density.push_str(&html_report::note("No contigs had variants recorded"));
+                }
+                report.add_section("Variant density", density);
+
+                let mut spectra = String::new();
+                for (sample_idx, sample_name) in sample_names.iter().enumerate() {
+                    spectra.push_str(&format!("<h3>{}</h3>\n", html_report::escape_html(sample_name)));
+                    spectra.push_str(&html_report::histogram_chart(
+                        &frequencies[sample_idx], 20, 0., 1., "Allele frequency"));
+                }
+                report.add_section("Allele frequency spectra", spectra);
+
+                let mut dnds = String::new();
+                if gene_dnds.len() > 0 {
+                    for (sample_idx, sample_name) in sample_names.iter().enumerate() {
+                        let values: Vec<f64> = gene_dnds.values()
+                            .filter_map(|samples| samples.get(&sample_idx).cloned())
+                            .collect();
+                        dnds.push_str(&format!("<h3>{}</h3>\n", html_report::escape_html(sample_name)));
+                        dnds.push_str(&html_report::histogram_chart(&values, 30, 0., 3., "dN/dS"));
+                    }
+                    dnds.push_str(&html_report::note("Genes with dN/dS above 3 are counted in the last bin"));
+                } else {
+                    dnds.push_str(&html_report::note("dN/dS is calculated by lorikeet evolve"));
+                }
+                report.add_section("dN/dS", dnds);
+
+                let mut clustering = String::new();
+                let mut abundances = String::new();
+                if pred_variants.len() > 0 {
+                    clustering.push_str(&html_report::table(
+                        &["Clustered variants", "Geometric mean variant depth",
+                          "Geometric mean depth", "Geometric mean frequency"],
+                        &[vec![variant_info.len().to_string(),
+                               format!("{:.3?}", geom_mean_var), format!("{:.3?}", geom_mean_dep),
+                               format!("{:.3?}", geom_mean_frq)]]));
+                    let mut cluster_rows = Vec::new();
+                    let mut abundance_rows = Vec::new();
+                    for strain in pred_variants.keys().sorted() {
+                        let mut categories = HashMap::new();
+                        let mut strain_frequencies = vec![Vec::new(); sample_names.len()];
+                        for (tid, positions) in pred_variants[strain].iter() {
+                            for (position, position_categories) in positions.iter() {
+                                for category in position_categories.keys() {
+                                    *categories.entry(*category).or_insert(0) += 1;
+                                }
+                                let bases = match all_variants.get(tid)
+                                    .and_then(|contig_variants| contig_variants.get(position)) {
+                                    Some(bases) => bases,
+                                    None => continue,
+                                };
+                                for variant in strain_variants(position_categories).into_iter().flatten() {
+                                    if variant == &Variant::None {
+                                        continue
+                                    }
+                                    if let Some(base) = bases.get(variant) {
+                                        for sample_idx in 0..sample_names.len() {
+                                            if base.totaldepth[sample_idx] > 0 {
+                                                strain_frequencies[sample_idx]
+                                                    .push(base.allele_frequency(sample_idx));
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        cluster_rows.push(vec![
+                            format!("strain_{}", strain),
+                            categories.get(&fuzzy::Category::Core).unwrap_or(&0).to_string(),
+                            categories.get(&fuzzy::Category::Border).unwrap_or(&0).to_string(),
+                            categories.get(&fuzzy::Category::Noise).unwrap_or(&0).to_string(),
+                        ]);
+                        let mut row = vec![format!("strain_{}", strain)];
+                        for sample_frequencies in strain_frequencies.iter() {
+                            row.push(if sample_frequencies.len() > 0 {
+                                format!("{:.3}", sample_frequencies.iter().sum::<f64>()
+                                    / sample_frequencies.len() as f64)
+                            } else {
+                                "NA".to_string()
+                            });
+                        }
+                        abundance_rows.push(row);
+                    }
+                    clustering.push_str(&html_report::table(
+                        &["Strain", "Core sites", "Border sites", "Noise sites"], &cluster_rows));
+
+                    let mut header = vec!["Strain"];
+                    header.extend(sample_names.iter().map(|sample_name| sample_name.as_str()));
+                    abundances.push_str(&html_report::note(
+                        "Mean frequency of the variants assigned to each strain in each sample"));
+                    abundances.push_str(&html_report::table(&header, &abundance_rows));
+                    for (sample_idx, sample_name) in sample_names.iter().enumerate() {
+                        let labels: Vec<String> = abundance_rows.iter().map(|row| row[0].clone()).collect();
+                        let values: Vec<f64> = abundance_rows.iter()
+                            .map(|row| row[sample_idx + 1].parse().unwrap_or(0.)).collect();
+                        abundances.push_str(&format!("<h3>{}</h3>\n", html_report::escape_html(sample_name)));
+                        abundances.push_str(&html_report::bar_chart(
+                            &labels, &values, "Strain", "Mean frequency"));
+                    }
+                } else {
+                    clustering.push_str(&html_report::note("Strains are clustered by lorikeet genotype"));
+                    abundances.push_str(&html_report::note("Strains are clustered by lorikeet genotype"));
+                }
+                report.add_section("Clustering diagnostics", clustering);
+                report.add_section("Strain abundances", abundances);
+
+                report.write(output_prefix);
+            }
+        }
+    }
+
+    fn print_frequency_spectra(&self, output_prefix: &str, parameters: &SpectrumParameters) {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                all_variants,
+                target_names,
+                sample_names,
+                ..
+            } => {
+                let file_name = format!("{}_frequency_spectra.tsv", output_prefix);
+                let mut spectra_file = File::create(Path::new(&file_name))
+                    .expect("No Read or Write Permission in current directory");
+                writeln!(spectra_file, "sample\tcontigName\t{}", SPECTRUM_HEADER)
+                    .expect("Unable to write to file");
+
+                let mut genome_spectra = vec![FrequencySpectra::new(parameters.bins); sample_names.len()];
+                for tid in all_variants.keys().sorted() {
+                    let contig_name = match target_names.get(tid) {
+                        Some(name) => name,
+                        None => continue,
+                    };
+                    let mut contig_spectra = vec![FrequencySpectra::new(parameters.bins); sample_names.len()];
+                    for (_position, variants) in all_variants[tid].iter() {
+                        for (sample_idx, spectra) in contig_spectra.iter_mut().enumerate() {
+                            let frequencies: Vec<f64> = variants.iter()
+                                .filter(|(variant, base)| **variant != Variant::None
+                                    && base.depth[sample_idx] > 0)
+                                .map(|(_, base)| base.allele_frequency(sample_idx))
+                                .collect();
+                            spectra.add_site(&frequencies);
+                        }
+                    }
+                    for (sample_idx, spectra) in contig_spectra.iter().enumerate() {
+                        for row in spectra.rows() {
+                            writeln!(spectra_file, "{}\t{}\t{}", sample_names[sample_idx], contig_name, row)
+                                .expect("Unable to write to file");
+                        }
+                        genome_spectra[sample_idx].combine(spectra);
+                    }
+                }
+                for (sample_idx, spectra) in genome_spectra.iter().enumerate() {
+                    for row in spectra.rows() {
+                        writeln!(spectra_file, "{}\tall\t{}", sample_names[sample_idx], row)
+                            .expect("Unable to write to file");
+                    }
+                }
+
+                let file_name = format!("{}_vaf_clusters.tsv", output_prefix);
+                let mut cluster_file = File::create(Path::new(&file_name))
+                    .expect("No Read or Write Permission in current directory");
+                writeln!(cluster_file, "sample\t{}", MIXTURE_HEADER)
+                    .expect("Unable to write to file");
+                for (sample_idx, mixture) in self.frequency_mixtures(parameters.max_components)
+                    .iter().enumerate() {
+                    let columns = match mixture {
+                        Some(mixture) => mixture.to_tsv(),
+                        None => "0\t0\t0\tNA\tNA\tNA".to_string(),
+                    };
+                    writeln!(cluster_file, "{}\t{}", sample_names[sample_idx], columns)
+                        .expect("Unable to write to file");
+                }
+            }
+        }
+    }
+
+    fn frequency_mixtures(&self, max_components: usize) -> Vec<Option<GaussianMixture>> {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                all_variants,
+                sample_names,
+                ..
+            } => {
+                (0..sample_names.len()).into_par_iter().map(|sample_idx| {
+                    let frequencies: Vec<f64> = all_variants.values()
+                        .flat_map(|positions| positions.values())
+                        .flat_map(|variants| variants.iter())
+                        .filter(|(variant, base)| **variant != Variant::None
+                            && base.depth[sample_idx] > 0)
+                        .map(|(_, base)| base.allele_frequency(sample_idx))
+                        .collect();
+                    select_mixture(&frequencies, max_components)
+                }).collect()
+            }
+        }
+    }
+
+    fn polish_genomes(&self, output_prefix: &str, parameters: &PolishParameters,
+                      short_samples: usize) -> usize {
+        match self {
+            VariantMatrix::VariantContigMatrix {
+                all_variants,
+                contigs,
+                target_names,
+                sample_depths,
+                ..
+            } => {
+                let tids: Vec<&i32> = contigs.keys().sorted().collect();
+                let polished: Vec<(Vec<u8>, Vec<PolishEdit>)> = tids.par_iter().map(|tid| {
+                    let reference = &contigs[*tid];
+                    // Reads covering each position pooled across the short and long read samples
+                    let mut short_depths = vec![0; reference.len()];
+                    let mut long_depths = vec![0; reference.len()];
+                    if let Some(depths) = sample_depths.get(*tid) {
+                        for (sample_idx, sample_depths) in depths.iter().enumerate() {
+                            let pooled_depths = if sample_idx < short_samples {
+                                &mut short_depths
+                            } else {
+                                &mut long_depths
+                            };
+                            for (pos, depth) in sample_depths.iter().enumerate().take(reference.len()) {
+                                pooled_depths[pos] += depth;
+                            }
+                        }
+                    }
+
+                    let mut edits = Vec::new();
+                    if let Some(contig_variants) = all_variants.get(*tid) {
+                        for (position, variants) in contig_variants.iter() {
+                            let pos = *position as usize;
+                            if pos >= reference.len() {
+                                continue
+                            }
+                            // Alleles supported by the samples in the given range
+                            let evidence = |samples: std::ops::Range<usize>, depth: i32| SiteEvidence {
+                                alleles: variants.iter()
+                                    .filter(|(variant, _)| match variant {
+                                        Variant::None | Variant::SV(_) => false,
+                                        _ => true,
+                                    })
+                                    .map(|(variant, base)| {
+                                        let depths: Vec<i32> = (0..base.depth.len())
+                                            .filter(|sample_idx| samples.contains(sample_idx))
+                                            .map(|sample_idx| base.allele_depth(sample_idx))
+                                            .filter(|depth| *depth > 0).collect();
+                                        AlleleSupport {
+                                            variant: variant.clone(),
+                                            depth: depths.iter().sum(),
+                                            samples: depths.len(),
+                                        }
+                                    })
+                                    .filter(|allele| allele.depth > 0)
+                                    .collect(),
+                                depth,
+                            };
+                            let short = evidence(0..short_samples, short_depths[pos]);
+                            let long = evidence(short_samples..std::usize::MAX, long_depths[pos]);
+                            if let Some(edit) = choose_hybrid_edit(pos, &short, &long, parameters) {
+                                edits.push(edit);
+                            }
+                        }
+                    }
+
+                    let mask: Vec<bool> = match parameters.mask_depth {
+                        Some(mask_depth) => short_depths.iter().zip(long_depths.iter())
+                            .map(|(short, long)| short + long < mask_depth).collect(),
+                        None => Vec::new(),
+                    };
+                    apply_edits(reference, edits, &mask)
+                }).collect();
+
+                let file_name = format!("{}_polished.fna", output_prefix);
+                let mut fasta_file = File::create(Path::new(&file_name))
+                    .expect("No Read or Write Permission in current directory");
+                let file_name = format!("{}_polish_edits.vcf", output_prefix);
+                let mut vcf_file = File::create(Path::new(&file_name))
+                    .expect("No Read or Write Permission in current directory");
+                let header: Vec<(String, usize)> = tids.iter()
+                    .map(|tid| (target_names[*tid].clone(), contigs[*tid].len())).collect();
+                write!(vcf_file, "{}", vcf_header(&header)).expect("Unable to write to file");
+
+                let mut total_edits = 0;
+                let mut liftovers = Vec::with_capacity(tids.len());
+                for (tid, (sequence, edits)) in tids.iter().zip(polished.iter()) {
+                    let reference = &contigs[*tid];
+                    let sequence_edits: Vec<SequenceEdit> = edits.iter().map(|edit| {
+                        let (start, end) = edit.span(reference.len());
+                        SequenceEdit { start, end, replacement: edit.replacement(reference) }
+                    }).collect();
+                    liftovers.push(Liftover::from_edits(
+                        &target_names[*tid], reference.len(), &target_names[*tid], &sequence_edits));
+
+                    writeln!(fasta_file, ">{}", target_names[*tid]).expect("Unable to write to file");
+                    for line in sequence.chunks(60) {
+                        fasta_file.write_all(line).expect("Unable to write to file");
+                        fasta_file.write_all(b"\n").expect("Unable to write to file");
+                    }
+                    for edit in edits.iter() {
+                        writeln!(vcf_file, "{}", edit.to_vcf(&target_names[*tid], &contigs[*tid]))
+                            .expect("Unable to write to file");
+                    }
+                    total_edits += edits.len();
+                }
+                write_chain_file(&format!("{}_polished.chain", output_prefix), &liftovers);
+                info!("Applied {} edits across {} contigs", total_edits, tids.len());
+                total_edits
+            }
+        }
+    }
+}
+
+/// Variants assigned to a strain at a position, preferring core over border and noise assignments
+fn strain_variants(categories: &HashMap<fuzzy::Category, HashSet<Variant>>) -> Option<&HashSet<Variant>> {
+    categories.get(&fuzzy::Category::Core)
+        .or(categories.get(&fuzzy::Category::Border))
+        .or(categories.get(&fuzzy::Category::Noise))
+}
+
+/// The reference bases replaced by a variant
+pub fn reference_allele(ref_sequence: &Vec<u8>, pos: i64, variant: &Variant) -> String {
+    let pos = pos as usize;
+    if pos >= ref_sequence.len() {
+        return "N".to_string()
+    }
+    let (start, end) = variant_span(pos, variant, ref_sequence.len());
+    String::from_utf8_lossy(&ref_sequence[start..end]).to_string()
+}
+
+/// Annotation columns of a variant, one entry for each gene it falls in, or dashes if the
+/// variant was not annotated
+fn annotation_columns(annotations: &HashMap<i32, HashMap<i64, HashMap<Variant, Vec<VariantAnnotation>>>>,
+                      tid: i32, pos: i64, variant: &Variant) -> Vec<String> {
+    match annotations.get(&tid)
+        .and_then(|contig| contig.get(&pos))
+        .and_then(|variants| variants.get(variant)) {
+        Some(variant_annotations) if variant_annotations.len() > 0 => {
+            variant_annotations.iter().map(|annotation| annotation.to_tsv()).collect()
+        },
+        _ => vec![vec!["-"; 7].join("\t")],
+    }
+}
+
+/// Identifiers of the genes a variant falls in or lies upstream of
+fn variant_genes(annotations: &HashMap<i32, HashMap<i64, HashMap<Variant, Vec<VariantAnnotation>>>>,
+                 tid: i32, pos: i64, variant: &Variant) -> Vec<String> {
+    annotations.get(&tid)
+        .and_then(|contig| contig.get(&pos))
+        .and_then(|variants| variants.get(variant))
+        .map(|variant_annotations| variant_annotations.iter()
+            .filter_map(|annotation| annotation.gene_id.clone())
+            .collect())
+        .unwrap_or(Vec::new())
 }
 
 /// Add read count entry to cluster hashmap
@@ -0,0 +1,205 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::Path;
+use estimation::liftover::SequenceEdit;
+
+/// Node of the graph holding either a piece of a reference contig or an alternative allele
+#[derive(Debug, Clone, PartialEq)]
+struct Segment {
+    sequence: Vec<u8>,
+    contig: String,
+    /// Start of the reference bases covered by this segment
+    offset: usize,
+    /// 0 for reference segments and 1 for allele segments
+    rank: usize,
+}
+
+/// Variation graph of reference contigs and the haplotypes derived from them. Each contig is
+/// split at the boundaries of every edit, each distinct edit becomes an allele segment, and
+/// the reference and each haplotype are kept as paths through the segments
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct VariationGraph {
+    segments: Vec<Segment>,
+    links: BTreeSet<(usize, usize)>,
+    paths: Vec<(String, Vec<usize>)>,
+}
+
+impl VariationGraph {
+    pub fn new() -> VariationGraph {
+        VariationGraph {
+            segments: Vec::new(),
+            links: BTreeSet::new(),
+            paths: Vec::new(),
+        }
+    }
+
+    fn add_segment(&mut self, sequence: Vec<u8>, contig: &str, offset: usize, rank: usize) -> usize {
+        self.segments.push(Segment {
+            sequence,
+            contig: contig.to_string(),
+            offset,
+            rank,
+        });
+        // GFA segment ids are 1-based
+        self.segments.len()
+    }
+
+    fn add_path(&mut self, name: String, path: Vec<usize>) {
+        for pair in path.windows(2) {
+            self.links.insert((pair[0], pair[1]));
+        }
+        self.paths.push((name, path));
+    }
+
+    /// Adds a contig along with the haplotypes derived from it, each given as a name and the
+    /// sorted, non-overlapping edits made to the contig as returned by apply_variants
+    pub fn add_contig(&mut self, name: &str, reference: &[u8],
+                      haplotypes: &[(String, Vec<SequenceEdit>)]) {
+        if reference.is_empty() {
+            return
+        }
+        let mut breakpoints = BTreeSet::new();
+        breakpoints.insert(0);
+        breakpoints.insert(reference.len());
+        for (_, edits) in haplotypes.iter() {
+            for edit in edits.iter() {
+                breakpoints.insert(edit.start);
+                breakpoints.insert(edit.end);
+            }
+        }
+
+        // Reference segments keyed by the position they start at
+        let mut pieces = BTreeMap::new();
+        let breakpoints: Vec<usize> = breakpoints.into_iter().collect();
+        for bounds in breakpoints.windows(2) {
+            let id = self.add_segment(
+                reference[bounds[0]..bounds[1]].to_vec(), name, bounds[0], 0);
+            pieces.insert(bounds[0], id);
+        }
+        self.add_path(name.to_string(), pieces.values().cloned().collect());
+
+        let mut alleles = HashMap::new();
+        for (haplotype, edits) in haplotypes.iter() {
+            let mut path = Vec::new();
+            let mut cursor = 0;
+            for edit in edits.iter() {
+                path.extend(pieces.range(cursor..edit.start).map(|(_, id)| *id));
+                let key = (edit.start, edit.end, edit.replacement.clone());
+                let id = match alleles.get(&key) {
+                    Some(id) => *id,
+                    None => {
+                        let id = self.add_segment(edit.replacement.clone(), name, edit.start, 1);
+                        alleles.insert(key, id);
+                        id
+                    }
+                };
+                path.push(id);
+                cursor = edit.end;
+            }
+            path.extend(pieces.range(cursor..).map(|(_, id)| *id));
+            self.add_path(haplotype.clone(), path);
+        }
+    }
+
+    /// Sequence spelled out by a path
+    pub fn path_sequence(&self, name: &str) -> Option<Vec<u8>> {
+        self.paths.iter().find(|(path_name, _)| path_name == name).map(|(_, path)| {
+            path.iter()
+                .flat_map(|id| self.segments[id - 1].sequence.iter().cloned())
+                .collect()
+        })
+    }
+
+    /// GFA 1.0 representation of the graph. Segments carry rGFA tags giving the contig and
+    /// offset they come from, and are joined with blunt links
+    pub fn to_gfa(&self) -> String {
+        let mut gfa = "H\tVN:Z:1.0\n".to_string();
+        for (idx, segment) in self.segments.iter().enumerate() {
+            gfa.push_str(&format!("S\t{}\t{}\tLN:i:{}\tSN:Z:{}\tSO:i:{}\tSR:i:{}\n",
+                                  idx + 1,
+                                  String::from_utf8_lossy(&segment.sequence),
+                                  segment.sequence.len(),
+                                  segment.contig,
+                                  segment.offset,
+                                  segment.rank));
+        }
+        for (from, to) in self.links.iter() {
+            gfa.push_str(&format!("L\t{}\t+\t{}\t+\t0M\n", from, to));
+        }
+        for (name, path) in self.paths.iter() {
+            let steps: Vec<String> = path.iter().map(|id| format!("{}+", id)).collect();
+            gfa.push_str(&format!("P\t{}\t{}\t*\n", name, steps.join(",")));
+        }
+        gfa
+    }
+
+    pub fn write_gfa(&self, file_name: &str) {
+        let mut file_open = File::create(Path::new(file_name))
+            .expect("No Read or Write Permission in current directory");
+        file_open.write_all(self.to_gfa().as_bytes()).expect("Unable to write to file");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use estimation::liftover::apply_variants;
+    use model::variants::Variant;
+
+    #[test]
+    fn test_variation_graph_paths() {
+        let reference = b"ACGTACGTACGT";
+        let strains = vec![
+            vec![(1, Variant::SNV(b'T')), (5, Variant::Deletion(2))],
+            vec![(1, Variant::SNV(b'T')), (9, Variant::Insertion(b"GG".to_vec()))],
+        ];
+        let mut haplotypes = Vec::new();
+        let mut sequences = Vec::new();
+        for (idx, variants) in strains.into_iter().enumerate() {
+            let (sequence, edits) = apply_variants(reference, variants);
+            haplotypes.push((format!("contig_1_strain_{}", idx), edits));
+            sequences.push(sequence);
+        }
+        let mut graph = VariationGraph::new();
+        graph.add_contig("contig_1", reference, &haplotypes);
+
+        assert_eq!(graph.path_sequence("contig_1").unwrap(), reference.to_vec());
+        for (idx, sequence) in sequences.iter().enumerate() {
+            assert_eq!(&graph.path_sequence(&format!("contig_1_strain_{}", idx)).unwrap(),
+                       sequence);
+        }
+        // The shared SNV is a single allele segment
+        let alleles = graph.segments.iter().filter(|segment| segment.rank == 1).count();
+        assert_eq!(alleles, 3);
+        for (from, to) in graph.links.iter() {
+            assert!(*from <= graph.segments.len() && *to <= graph.segments.len());
+        }
+    }
+
+    #[test]
+    fn test_variation_graph_gfa() {
+        let reference = b"ACGTA";
+        let (_, edits) = apply_variants(reference, vec![
+            (1, Variant::SNV(b'T')), (2, Variant::SNV(b'C'))]);
+        let mut graph = VariationGraph::new();
+        graph.add_contig("contig_1", reference, &[("strain_0".to_string(), edits)]);
+
+        assert_eq!(graph.to_gfa(),
+                   "H\tVN:Z:1.0\n\
+                    S\t1\tA\tLN:i:1\tSN:Z:contig_1\tSO:i:0\tSR:i:0\n\
+                    S\t2\tC\tLN:i:1\tSN:Z:contig_1\tSO:i:1\tSR:i:0\n\
+                    S\t3\tG\tLN:i:1\tSN:Z:contig_1\tSO:i:2\tSR:i:0\n\
+                    S\t4\tTA\tLN:i:2\tSN:Z:contig_1\tSO:i:3\tSR:i:0\n\
+                    S\t5\tT\tLN:i:1\tSN:Z:contig_1\tSO:i:1\tSR:i:1\n\
+                    S\t6\tC\tLN:i:1\tSN:Z:contig_1\tSO:i:2\tSR:i:1\n\
+                    L\t1\t+\t2\t+\t0M\n\
+                    L\t1\t+\t5\t+\t0M\n\
+                    L\t2\t+\t3\t+\t0M\n\
+                    L\t3\t+\t4\t+\t0M\n\
+                    L\t5\t+\t6\t+\t0M\n\
+                    L\t6\t+\t4\t+\t0M\n\
+                    P\tcontig_1\t1+,2+,3+,4+\t*\n\
+                    P\tstrain_0\t1+,5+,6+,4+\t*\n");
+    }
+}
@@ -9,10 +9,6 @@ pub fn check_for_samtools() {
     self::check_for_external_command_presence("samtools", "which samtools");
 }
 
-pub fn check_for_prokka() {
-    self::check_for_external_command_presence("prokka", "which prokka");
-}
-
 pub fn check_for_prodigal() {
     self::check_for_external_command_presence("prodigal", "which prodigal");
 }
@@ -21,6 +21,7 @@ extern crate env_logger;
 extern crate nix;
 extern crate tempdir;
 extern crate tempfile;
+extern crate md5;
 extern crate rand;
 extern crate rayon;
 extern crate rust_htslib;
@@ -35,6 +36,9 @@ extern crate strum_macros;
 extern crate derive_builder;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate toml;
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
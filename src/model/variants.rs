@@ -1,5 +1,6 @@
 
 use std::cmp::Ordering;
+use std::fmt;
 use std::fmt::Debug;
 use std::ops::{Deref, Range};
 use std::collections::{HashSet, HashMap};
@@ -215,6 +216,24 @@ impl Variant {
     }
 }
 
+impl fmt::Display for Variant {
+    /// Prints variants in the same shorthand used throughout lorikeet's output,
+    /// with deletions as runs of N and the reference allele as R
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Variant::SNV(alt) => write!(f, "{}", alt as char),
+            &Variant::MNV(ref alt) | &Variant::Insertion(ref alt) => {
+                write!(f, "{}", String::from_utf8_lossy(alt))
+            },
+            &Variant::Deletion(length) => {
+                write!(f, "{}", std::iter::repeat("N").take(length as usize).collect::<String>())
+            },
+            &Variant::SV(_) => write!(f, "SV"),
+            &Variant::None => write!(f, "R"),
+        }
+    }
+}
+
 /// The filter tag given to the locus
 #[derive(Clone, Debug, Eq, PartialEq, Hash)]
 pub enum Filter {
@@ -319,6 +338,28 @@ impl Base {
         self.totaldepth[sample_idx] = depth
     }
 
+    /// Frequency of this allele in a sample, preferring the AF reported by the variant
+    /// caller and falling back to the allele's share of the total depth
+    pub fn allele_frequency(&self, sample_idx: usize) -> f64 {
+        if self.af[sample_idx] > 0. {
+            self.af[sample_idx].min(1.)
+        } else if self.totaldepth[sample_idx] > 0 {
+            (self.depth[sample_idx] as f64 / self.totaldepth[sample_idx] as f64).min(1.)
+        } else {
+            0.
+        }
+    }
+
+    /// Reads supporting this allele in a sample, estimated from the depth of the site and the
+    /// allele frequency reported by the variant caller
+    pub fn allele_depth(&self, sample_idx: usize) -> i32 {
+        if self.af[sample_idx] > 0. {
+            (self.af[sample_idx].min(1.) * self.depth[sample_idx] as f64).round() as i32
+        } else {
+            self.depth[sample_idx]
+        }
+    }
+
     pub fn new(pos: i64, refr: Vec<u8>, sample_count: usize) -> Base {
         Base {
             pos,
@@ -369,17 +410,27 @@ impl Base {
                 // Populate Base struct with known info tags
                 base.variant = variant.clone();
                 base.filters[sample_idx] = filter_hash.clone();
-                base.depth[sample_idx] = record.info(b"DP").integer().unwrap().unwrap()[0];
-                base.truedepth[sample_idx] = record.info(b"TD").integer().unwrap().unwrap()[0];
-                base.physicalcov[sample_idx] = record.info(b"PC").integer().unwrap().unwrap()[0];
-                base.baseq[sample_idx] = record.info(b"BQ").integer().unwrap().unwrap()[0];
-                base.mapq[sample_idx] = record.info(b"MQ").integer().unwrap().unwrap()[0];
-                base.conf[sample_idx] = record.info(b"QD").integer().unwrap().unwrap()[0];
-                base.ic[sample_idx] = record.info(b"IC").integer().unwrap().unwrap()[0];
-                base.dc[sample_idx] = record.info(b"DC").integer().unwrap().unwrap()[0];
-                base.xc[sample_idx] = record.info(b"XC").integer().unwrap().unwrap()[0];
-                base.ac[sample_idx] = record.info(b"AC").integer().unwrap().unwrap()[0];
-                base.af[sample_idx] = record.info(b"AF").float().unwrap().unwrap()[0] as f64;
+                base.af[sample_idx] = info_float(record, b"AF").unwrap_or(0.) as f64;
+                base.depth[sample_idx] = match info_integer(record, b"DP") {
+                    Some(depth) => depth,
+                    // Sniffles reports the reads supporting a variant and its frequency
+                    // rather than the depth of the site
+                    None => match info_integer(record, b"RE") {
+                        Some(support) if base.af[sample_idx] > 0. =>
+                            (support as f64 / base.af[sample_idx]).round() as i32,
+                        Some(support) => support,
+                        None => 0,
+                    },
+                };
+                base.truedepth[sample_idx] = info_integer(record, b"TD").unwrap_or(0);
+                base.physicalcov[sample_idx] = info_integer(record, b"PC").unwrap_or(0);
+                base.baseq[sample_idx] = info_integer(record, b"BQ").unwrap_or(0);
+                base.mapq[sample_idx] = info_integer(record, b"MQ").unwrap_or(0);
+                base.conf[sample_idx] = info_integer(record, b"QD").unwrap_or(0);
+                base.ic[sample_idx] = info_integer(record, b"IC").unwrap_or(0);
+                base.dc[sample_idx] = info_integer(record, b"DC").unwrap_or(0);
+                base.xc[sample_idx] = info_integer(record, b"XC").unwrap_or(0);
+                base.ac[sample_idx] = info_integer(record, b"AC").unwrap_or(0);
                 bases.push(base);
             };
             Some(bases)
@@ -391,6 +442,82 @@ impl Base {
     pub fn assign_read(&mut self, read_id: i64) {
         self.reads.insert(read_id);
     }
+
+    /// Merges the samples of this site into merged_count samples, sample_idx being merged
+    /// into columns[sample_idx]. Read counts are summed, qualities averaged over the depth of
+    /// each sample and the allele frequency recomputed from the pooled reads
+    pub fn merge_samples(&mut self, columns: &[usize], merged_count: usize) {
+        let mut merged = Base::new(self.pos, self.refr.clone(), merged_count);
+        merged.variant = self.variant.clone();
+        merged.reads = self.reads.clone();
+        // Reads supporting the allele and reads covering the site in each merged sample
+        let mut allele_reads = vec![0; merged_count];
+        let mut site_reads = vec![0; merged_count];
+        for (sample_idx, &column) in columns.iter().enumerate() {
+            merged.filters[column].extend(self.filters[sample_idx].iter().cloned());
+            merged.truedepth[column] += self.truedepth[sample_idx];
+            merged.totaldepth[column] += self.totaldepth[sample_idx];
+            merged.physicalcov[column] += self.physicalcov[sample_idx];
+            merged.baseq[column] += self.baseq[sample_idx] * self.depth[sample_idx];
+            merged.mapq[column] += self.mapq[sample_idx] * self.depth[sample_idx];
+            merged.conf[column] += self.conf[sample_idx] * self.depth[sample_idx];
+            merged.depth[column] += self.depth[sample_idx];
+            merged.ic[column] += self.ic[sample_idx];
+            merged.dc[column] += self.dc[sample_idx];
+            merged.xc[column] += self.xc[sample_idx];
+            merged.ac[column] += self.ac[sample_idx];
+            allele_reads[column] += self.allele_depth(sample_idx);
+            site_reads[column] += if self.af[sample_idx] > 0. {
+                self.depth[sample_idx]
+            } else {
+                self.totaldepth[sample_idx]
+            };
+        }
+        for column in 0..merged_count {
+            if merged.depth[column] > 0 {
+                merged.baseq[column] /= merged.depth[column];
+                merged.mapq[column] /= merged.depth[column];
+                merged.conf[column] /= merged.depth[column];
+            }
+            // The pooled frequency is kept as an AF of the reads covering the site, so that
+            // allele_frequency and allele_depth both count the reads of every library
+            if allele_reads[column] > 0 && site_reads[column] > 0 {
+                merged.af[column] = allele_reads[column] as f64 / site_reads[column] as f64;
+                merged.depth[column] = site_reads[column];
+            } else {
+                merged.depth[column] = 0;
+            }
+            merged.freq[column] = merged.allele_frequency(column);
+        }
+        for (nucs, merged_nucs) in vec![(&self.nucs, &mut merged.nucs),
+                                        (&self.pernucs, &mut merged.pernucs)].into_iter() {
+            for (nucleotide, counts) in nucs.iter() {
+                let merged_counts = merged_nucs.entry(*nucleotide)
+                    .or_insert(vec![0; merged_count]);
+                for (sample_idx, count) in counts.iter().enumerate() {
+                    merged_counts[columns[sample_idx]] += count;
+                }
+            }
+        }
+        *self = merged;
+    }
+}
+
+/// First value of an integer INFO tag, if the record has it. Pilon and sniffles report
+/// different tags
+fn info_integer(record: &mut bcf::Record, tag: &[u8]) -> Option<i32> {
+    match record.info(tag).integer() {
+        Ok(Some(values)) => values.get(0).cloned(),
+        _ => None,
+    }
+}
+
+/// First value of a float INFO tag, if the record has it
+fn info_float(record: &mut bcf::Record, tag: &[u8]) -> Option<f32> {
+    match record.info(tag).float() {
+        Ok(Some(values)) => values.get(0).cloned(),
+        _ => None,
+    }
 }
 
 /// Collect variants from a given ´bcf::Record`.
@@ -581,5 +708,29 @@ pub fn collect_variants(
 
 #[cfg(test)]
 mod tests {
-
+    use super::*;
+
+    #[test]
+    fn test_merge_samples() {
+        // Short reads called by pilon, with the depth of the site and an AF, and long reads
+        // of the same sample without an AF, merged into the first sample
+        let mut base = Base::new(10, b"A".to_vec(), 3);
+        base.variant = Variant::SNV(b'T');
+        base.depth = vec![20, 8, 5];
+        base.totaldepth = vec![20, 10, 5];
+        base.af = vec![0.5, 0., 0.2];
+        base.baseq = vec![30, 10, 20];
+        base.filters[1].insert(Filter::PASS);
+        base.nucs.insert('T', vec![10, 8, 1]);
+
+        base.merge_samples(&[0, 1, 0], 2);
+        assert_eq!(base.totaldepth, vec![25, 10]);
+        assert_eq!(base.depth, vec![25, 10]);
+        assert_eq!(base.allele_depth(0), 11);
+        assert!((base.allele_frequency(0) - 11. / 25.).abs() < 1e-9);
+        assert!((base.allele_frequency(1) - 0.8).abs() < 1e-9);
+        assert_eq!(base.baseq[0], 28);
+        assert!(base.filters[1].contains(&Filter::PASS));
+        assert_eq!(base.nucs[&'T'], vec![11, 8]);
+    }
 }